@@ -0,0 +1,45 @@
+//! core_emulation: Run a Master System program from a byte slice, without
+//! the `host_clock` feature, so nothing keeps time with the host's clock.
+//!
+//! Build it with `cargo run --example core_emulation --no-default-features
+//! --features std` to check that the emulation still works that way. For the
+//! Z80 and memory without `std` at all, see `no_std_core`.
+
+extern crate euphrates;
+
+use std::sync::Arc;
+
+use euphrates::hardware::z80::Reg8;
+use euphrates::systems::sms::SmsBuilder;
+
+fn main() {
+    let mut rom = vec![0u8; 0x8000];
+    let program = [
+        0x3E, 0x12, // ld a, 0x12
+        0x06, 0x34, // ld b, 0x34
+        0x80, // add a, b
+        0x32, 0x00, 0xC0, // ld (0xC000), a
+        0x18, 0xFE, // jr -2
+    ];
+    rom[..program.len()].copy_from_slice(&program);
+
+    let mut sms = SmsBuilder::new()
+        .frequency(None)
+        .build_from_rom(Arc::new(rom.into_boxed_slice()))
+        .unwrap();
+    for _ in 0..4 {
+        sms.step().unwrap();
+    }
+    assert_eq!(sms.z80().reg8(Reg8::A), 0x46);
+    assert_eq!(sms.memory().read(0xC000), 0x46);
+
+    // and a few frames
+    for _ in 0..3 {
+        sms.run_frame(Default::default()).unwrap();
+    }
+    println!(
+        "{} cycles, A = 0x{:0>2X}",
+        sms.z80().cycles(),
+        sms.z80().reg8(Reg8::A)
+    );
+}
@@ -0,0 +1,109 @@
+//! no_std_core: Run a Z80 program out of a Master System memory map, using
+//! only `core` and `alloc`.
+//!
+//! It's a library rather than a program, since a `no_std` program would need
+//! its own entry point and panic handler. Check that the core still builds and
+//! runs without `std` with
+//! `cargo test --example no_std_core --no-default-features`.
+
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(not(test))]
+extern crate alloc;
+extern crate euphrates;
+
+#[cfg(not(test))]
+use alloc::{boxed::Box, sync::Arc, vec};
+#[cfg(test)]
+use std::sync::Arc;
+
+use euphrates::hardware::io16::Io16;
+use euphrates::hardware::sms_memory::{self, SmsMemoryMapper, SmsMemoryState};
+use euphrates::hardware::z80::{Reg16, Z80Internal, Z80Irq, Z80Run, Z80RunImpler, Z80State};
+use euphrates::memo::NothingInbox;
+
+/// IO with nothing attached.
+struct NoIo;
+
+impl Io16 for NoIo {
+    fn input(&mut self, _address: u16) -> u8 {
+        0xFF
+    }
+
+    fn output(&mut self, _address: u16, _value: u8) {}
+}
+
+/// Nothing ever interrupts.
+struct NoIrq;
+
+impl Z80Irq for NoIrq {
+    fn requesting_mi(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn requesting_nmi(&mut self) -> bool {
+        false
+    }
+
+    fn take_nmi(&mut self) {}
+}
+
+/// Put `program` at the start of a 32 KiB Sega mapped ROM, and run `count`
+/// instructions of it.
+pub fn run_program(program: &[u8], count: usize) -> (Z80State, SmsMemoryState) {
+    let mut rom = vec![0u8; 0x8000];
+    rom[..program.len()].copy_from_slice(program);
+    let mut memory = SmsMemoryState {
+        rom: Arc::new(rom.into_boxed_slice()),
+        system_ram: Default::default(),
+        main_cartridge_ram: Default::default(),
+        half_cartridge_ram: Default::default(),
+        pages: Default::default(),
+        mapper: SmsMemoryMapper::Sega,
+        ram_fill: Default::default(),
+        first_slot: Box::new([]),
+    };
+    sms_memory::default_mappings(&mut memory);
+
+    let mut z80 = Z80State::default();
+    z80.set_reg16(Reg16::SP, 0xDFEE);
+    {
+        let mut run = Z80RunImpler {
+            z80: &mut z80,
+            memory: &mut memory,
+            io: &mut NoIo,
+            irq: &mut NoIrq,
+            inbox: &mut NothingInbox::default(),
+        };
+        for _ in 0..count {
+            run.step();
+        }
+    }
+    (z80, memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euphrates::hardware::memory16::Memory16;
+    use euphrates::hardware::z80::Reg8;
+
+    #[test]
+    fn add_and_store() {
+        let program = [
+            0x3E, 0x12, // ld a, 0x12
+            0x06, 0x34, // ld b, 0x34
+            0x80, // add a, b
+            0x32, 0x00, 0xC0, // ld (0xC000), a
+            0xF5, // push af
+        ];
+        let (z80, mut memory) = run_program(&program, 5);
+        assert_eq!(z80.reg8(Reg8::A), 0x46);
+        assert_eq!(z80.reg16(Reg16::PC), program.len() as u16);
+        assert_eq!(memory.read(0xC000), 0x46);
+        // system RAM is mirrored at 0xE000
+        assert_eq!(memory.read(0xE000), 0x46);
+        assert_eq!(memory.read(0xDFED), 0x46);
+    }
+}
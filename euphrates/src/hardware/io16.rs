@@ -7,4 +7,14 @@ pub trait Io16 {
 
     /// Output a byte at the given `address`.
     fn output(&mut self, address: u16, value: u8);
+
+    /// How many extra cycles must the CPU wait after an access at `address`?
+    ///
+    /// `cycles` is the CPU's cycle count at the time of the access. Devices
+    /// which stall the CPU while they're busy can override this; by default
+    /// there is no wait.
+    #[inline]
+    fn wait_cycles(&mut self, _address: u16, _cycles: u64) -> u32 {
+        0
+    }
 }
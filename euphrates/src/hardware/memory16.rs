@@ -20,3 +20,97 @@ impl Memory16 for [u8; 0x10000] {
         self[logical_address as usize] = value
     }
 }
+
+/// Something that wants to hear about memory accesses, like a tracer or a cheat
+/// finder.
+///
+/// This is lighter weight than getting `Memo`s from an `Inbox`, and sees every
+/// access rather than just those a memo is made for.
+pub trait MemoryWatch {
+    /// `value` was read from `logical_address`.
+    fn on_read(&mut self, logical_address: u16, value: u8);
+
+    /// `value` was written to `logical_address`.
+    fn on_write(&mut self, logical_address: u16, value: u8);
+}
+
+/// A `Memory16` that tells `watch` about every access to `memory`.
+pub struct WatchedMemory16Impler<'a, M: 'a + ?Sized, W: 'a + ?Sized> {
+    pub memory: &'a mut M,
+    pub watch: &'a mut W,
+}
+
+impl<'a, M: 'a, W: 'a> Memory16 for WatchedMemory16Impler<'a, M, W>
+where
+    M: Memory16 + ?Sized,
+    W: MemoryWatch + ?Sized,
+{
+    #[inline]
+    fn read(&mut self, logical_address: u16) -> u8 {
+        let value = self.memory.read(logical_address);
+        self.watch.on_read(logical_address, value);
+        value
+    }
+
+    #[inline]
+    fn write(&mut self, logical_address: u16, value: u8) {
+        self.memory.write(logical_address, value);
+        self.watch.on_write(logical_address, value);
+    }
+}
+
+/// A cheat code, like those for the Game Genie.
+///
+/// While a cheat is in effect, reads from `address` give `value` instead of
+/// whatever is there. If there's a `compare` byte, this only happens when
+/// the byte that's really there is `compare`; since different ROM banks can
+/// be mapped to the same logical address, that's how a cheat can pick out
+/// just one of them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+pub struct Cheat {
+    /// A logical address.
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+/// A memory map that knows which logical addresses are currently mapped to
+/// ROM.
+pub trait RomMemory16 {
+    fn is_rom(&self, logical_address: u16) -> bool;
+}
+
+/// A `Memory16` that applies `cheats` to reads from `memory`.
+///
+/// Cheats patch ROM, so they only apply to addresses `memory` says are mapped
+/// to ROM at the time of the read.
+///
+/// `cheats` must be sorted by address.
+pub struct CheatMemory16Impler<'a, M: 'a + ?Sized> {
+    pub memory: &'a mut M,
+    pub cheats: &'a [Cheat],
+}
+
+impl<'a, M: 'a> Memory16 for CheatMemory16Impler<'a, M>
+where
+    M: Memory16 + RomMemory16 + ?Sized,
+{
+    #[inline]
+    fn read(&mut self, logical_address: u16) -> u8 {
+        let value = self.memory.read(logical_address);
+        let start = self.cheats.partition_point(|c| c.address < logical_address);
+        let cheat = self.cheats[start..]
+            .iter()
+            .take_while(|c| c.address == logical_address)
+            .find(|c| c.compare.is_none() || c.compare == Some(value));
+        match cheat {
+            Some(c) if self.memory.is_rom(logical_address) => c.value,
+            _ => value,
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, logical_address: u16, value: u8) {
+        self.memory.write(logical_address, value)
+    }
+}
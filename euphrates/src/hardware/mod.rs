@@ -2,11 +2,19 @@
 
 pub mod io16;
 pub mod memory16;
+#[cfg(feature = "std")]
 pub mod sms_io;
+#[cfg(feature = "std")]
 pub mod sms_irq;
 pub mod sms_memory;
+#[cfg(feature = "std")]
 pub mod sms_player_input;
+#[cfg(feature = "std")]
 pub mod sms_roms;
+#[cfg(feature = "std")]
 pub mod sms_vdp;
+#[cfg(feature = "std")]
 pub mod sn76489;
+#[cfg(feature = "std")]
+pub mod ym2413;
 pub mod z80;
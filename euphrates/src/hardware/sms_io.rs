@@ -1,17 +1,296 @@
 //! The IO system of the Sega Master System.
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use super::io16::Io16;
-use super::sms_player_input::SmsPlayerInput;
+use super::sms_memory::{memory_control_bits, OPEN_BUS};
+use super::sms_player_input::{joypad_b_bits, ControllerPort, PortDevice, SmsPlayerInput};
 use super::sms_vdp::{SmsVdpInterface, SmsVdpInternal};
 use super::sn76489::Sn76489Interface;
+use super::ym2413::Ym2413Interface;
+
+/// How many lines above or below its aim point can a light phaser see the
+/// beam?
+///
+/// A real phaser's sensor picks up light from a small patch of the screen, not
+/// a single pixel.
+pub const LIGHT_PHASER_RADIUS: u16 = 4;
+
+/// How many cycles does the Z80 wait for a VDP port access during active
+/// display, when `accurate_vdp_access` is on?
+///
+/// While it's drawing the active display, the VDP only services the CPU in
+/// occasional free access slots. This is an approximation of the average wait
+/// for one of them.
+pub const VDP_ACTIVE_DISPLAY_WAIT: u32 = 10;
+
+// The TL, TR, and TH lines, as laid out by `SmsIo16Impler::port_lines`.
+const LINE_TL: u8 = 1 << 4;
+const LINE_TR: u8 = 1 << 5;
+const LINE_TH: u8 = 1 << 6;
+
+/// What the IO system remembers about the controller ports between accesses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SmsIoState {
+    /// The level the console is driving each port's TH line to, or `None` if
+    /// the port's TH line is an input, as it is at power on.
+    pub th_output: [Option<bool>; 2],
+
+    /// The SC-3000 PPI's port C, whose low 3 bits select the keyboard row.
+    ///
+    /// At power on the PPI's outputs float high, selecting row 7, the
+    /// joypads, so SG-1000 games which never touch the PPI still work.
+    pub ppi_port_c: u8,
+
+    // Set when a write to the IO control register raises a TH line, so the
+    // H counter is latched once we know the cycle of the write
+    latch_h_pending: bool,
+}
+
+impl Default for SmsIoState {
+    #[inline]
+    fn default() -> Self {
+        SmsIoState {
+            th_output: Default::default(),
+            ppi_port_c: 0xFF,
+            latch_h_pending: false,
+        }
+    }
+}
+
+/// Is the raster currently passing the point the light phaser in `port` is
+/// aimed at?
+///
+/// The VDP only draws a line at a time, so we consider the beam to pass the
+/// aim point whenever the current line is within `LIGHT_PHASER_RADIUS` of it.
+/// We also assume the phaser is aimed at something bright.
+fn light_phaser_sees_beam<V>(
+    vdp: &V,
+    player_input: &SmsPlayerInput,
+    port_devices: [PortDevice; 2],
+    port: ControllerPort,
+) -> bool
+where
+    V: SmsVdpInternal + ?Sized,
+{
+    if port_devices[port as usize] != PortDevice::LightPhaser {
+        return false;
+    }
+    let phaser = player_input.light_phaser(port);
+    let v = vdp.v();
+    let y = phaser.y as u16;
+    v < vdp.active_lines() && v + LIGHT_PHASER_RADIUS >= y && v <= y + LIGHT_PHASER_RADIUS
+}
+
+/// Latch the H counter for any light phaser the beam is passing.
+///
+/// Call this each time the VDP finishes a line. When the beam passes a
+/// phaser's aim point, its sensor pulls TH low, which latches the H counter at
+/// the phaser's x coordinate, whether or not the game is reading the port.
+pub fn latch_light_phasers<V>(
+    vdp: &mut V,
+    player_input: &SmsPlayerInput,
+    port_devices: [PortDevice; 2],
+) where
+    V: SmsVdpInternal + ?Sized,
+{
+    for &port in [ControllerPort::A, ControllerPort::B].iter() {
+        if light_phaser_sees_beam(vdp, player_input, port_devices, port) {
+            let x = player_input.light_phaser(port).x;
+            vdp.set_h(x as u16);
+        }
+    }
+}
 
 pub struct SmsIo16Impler<'a, V: 'a + ?Sized, S: 'a + ?Sized> {
     pub vdp: Rc<RefCell<&'a mut V>>,
     pub sn76489: &'a mut S,
     pub player_input: SmsPlayerInput,
+    pub io_state: &'a mut SmsIoState,
+
+    /// The YM2413 on IO ports 0xF0 through 0xF2, if there is one.
+    pub ym2413: Option<&'a mut dyn Ym2413Interface>,
+
+    /// What's plugged into controller ports A and B.
+    pub port_devices: [PortDevice; 2],
+
+    /// Should VDP port accesses during active display cost extra cycles?
+    ///
+    /// Accesses during vblank, hblank, or with the display disabled are always
+    /// free.
+    pub accurate_vdp_access: bool,
+
+    /// Is this an SC-3000, with its keyboard read through a PPI at IO ports
+    /// 0xDC through 0xDF in place of the controller ports?
+    pub sc3000_keyboard: bool,
+
+    /// The memory control register, written through IO port 0x3E. See
+    /// `memory_control_bits` and `ControlledMemory16Impler`.
+    pub memory_control: &'a Cell<u8>,
+}
+
+impl<'a, V: 'a, S: 'a> SmsIo16Impler<'a, V, S>
+where
+    V: SmsVdpInternal + ?Sized,
+    S: ?Sized,
+{
+    /// What does reading `address` give when nothing drives the data bus?
+    ///
+    /// On the original Master System, the bus keeps the last byte the Z80
+    /// read, which is the last byte of the `in` instruction. For `in a, (n)`
+    /// that's `n`, the low byte of the port address, so we use that for every
+    /// `in`; it's wrong only for the rarely used `in r, (c)`. The Master
+    /// System 2 and Game Gear pull the bus high, giving `OPEN_BUS`.
+    pub fn open_bus(&self, address: u16) -> u8 {
+        use hardware::sms_vdp::Kind;
+        match self.vdp.borrow().kind() {
+            Kind::Sms => address as u8,
+            _ => OPEN_BUS,
+        }
+    }
+
+    /// Is the raster currently passing the point a light phaser is aimed at?
+    ///
+    /// See `light_phaser_sees_beam`.
+    fn light_phaser_sees_beam(&self, port: ControllerPort) -> bool {
+        light_phaser_sees_beam(
+            &**self.vdp.borrow(),
+            &self.player_input,
+            self.port_devices,
+            port,
+        )
+    }
+
+    /// The levels of `port`'s lines, as bits 0 through 6: up, down, left,
+    /// right, TL, TR, and TH. That's the layout port A's lines have in IO port
+    /// 0xDC, with TH added.
+    ///
+    /// A paddle's TR line says which nibble of its position it's sending. On
+    /// a Japanese console, the paddle toggles it on its own at about 8 KHz; we
+    /// toggle it every line. Export consoles instead select the nibble by
+    /// driving the paddle's TH line, and TR follows.
+    fn port_lines(&self, port: ControllerPort) -> u8 {
+        match self.port_devices[port as usize] {
+            PortDevice::Joypad => {
+                let a = self.player_input.joypad_a();
+                let b = self.player_input.joypad_b();
+                match port {
+                    ControllerPort::A => a & 0x3F | b & joypad_b_bits::A_TH,
+                    ControllerPort::B => a >> 6 | (b & 0x0F) << 2 | (b & joypad_b_bits::B_TH) >> 1,
+                }
+            }
+            PortDevice::LightPhaser => {
+                let mut lines = 0x7F;
+                if self.player_input.light_phaser(port).trigger {
+                    lines &= !LINE_TL;
+                }
+                if self.light_phaser_sees_beam(port) {
+                    lines &= !LINE_TH;
+                }
+                lines
+            }
+            PortDevice::Paddle => {
+                let paddle = self.player_input.paddle(port);
+                let high = match self.io_state.th_output[port as usize] {
+                    Some(th) => th,
+                    None => self.vdp.borrow().v() & 1 != 0,
+                };
+                let mut lines = LINE_TH;
+                if high {
+                    lines |= paddle.position >> 4 | LINE_TR;
+                } else {
+                    lines |= paddle.position & 0xF;
+                }
+                if !paddle.button {
+                    lines |= LINE_TL;
+                }
+                lines
+            }
+            PortDevice::None => 0x7F,
+        }
+    }
+
+    /// Read from the SC-3000's PPI. Its ports A and B read the keyboard row
+    /// selected by port C, laid out as in `Sc3000Key`; row 7 is the joypads,
+    /// laid out as in IO ports 0xDC and 0xDD. The unused high bits of port B
+    /// read high.
+    fn ppi_input(&self, address: u16) -> u8 {
+        let row = self.io_state.ppi_port_c & 7;
+        let columns = if row == 7 {
+            let a = self.port_lines(ControllerPort::A);
+            let b = self.port_lines(ControllerPort::B);
+            (a & 0x3F | b << 6) as u16 | (b as u16 >> 2 & 0x0F) << 8
+        } else {
+            self.player_input.keyboard_row(row)
+        };
+        match address & 3 {
+            0 => columns as u8,
+            1 => (columns >> 8) as u8 | 0xF0,
+            2 => self.io_state.ppi_port_c,
+            _ => 0xFF,
+        }
+    }
+
+    /// Write to the SC-3000's PPI. Only port C, directly or through the
+    /// control register's bit set/reset, does anything.
+    fn ppi_output(&mut self, address: u16, value: u8) {
+        match address & 3 {
+            2 => self.io_state.ppi_port_c = value,
+            3 if value & 0x80 == 0 => {
+                let bit = 1 << (value >> 1 & 7);
+                if value & 1 != 0 {
+                    self.io_state.ppi_port_c |= bit;
+                } else {
+                    self.io_state.ppi_port_c &= !bit;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The level of `port`'s TH line, whether the console or the device is
+    /// driving it.
+    fn th_level(&self, port: ControllerPort) -> bool {
+        match self.io_state.th_output[port as usize] {
+            Some(level) => level,
+            None => self.port_lines(port) & LINE_TH != 0,
+        }
+    }
+
+    /// Where in its current line is the VDP at Z80 cycle `cycles`, in VDP
+    /// cycles (that is, pixels) from 0 to 341?
+    ///
+    /// By the time the Z80 runs, the VDP has already drawn the line the Z80
+    /// is executing during, and that line ended at the VDP's `cycles`.
+    fn h_at(&self, cycles: u64) -> u16 {
+        // the Z80 runs at 2/3 the speed of the VDP, and there are 342 VDP
+        // cycles per line
+        let h = (3 * cycles / 2 + 342).saturating_sub(self.vdp.borrow().cycles());
+        h.min(341) as u16
+    }
+
+    /// Is the VDP drawing the active display at Z80 cycle `cycles`?
+    ///
+    /// By the time the Z80 runs, the VDP has already drawn the line the Z80
+    /// is executing during, so that line is the one before `v`, and it ended
+    /// at the VDP's `cycles`.
+    fn in_active_display(&self, cycles: u64) -> bool {
+        let vdp = self.vdp.borrow();
+        if !vdp.display_visible() {
+            return false;
+        }
+        let line = match vdp.v() {
+            0 => vdp.total_lines() - 1,
+            v => v - 1,
+        };
+        if line >= vdp.active_lines() {
+            // vblank
+            return false;
+        }
+        // past 256, we're in hblank
+        self.h_at(cycles) < 256
+    }
 }
 
 impl<'a, V: 'a, S: 'a> Io16 for SmsIo16Impler<'a, V, S>
@@ -22,22 +301,35 @@ where
     fn input(&mut self, address: u16) -> u8 {
         use hardware::sms_vdp::Kind;
 
+        if address as u8 == 0xF2 {
+            if let Some(ref ym2413) = self.ym2413 {
+                return ym2413.read_control();
+            }
+        }
+
+        if self.sc3000_keyboard && address & 0b11000000 == 0b11000000 {
+            return self.ppi_input(address);
+        }
+
         let masked = (address & 0b11000001) as u8;
+        if masked & 0b11000000 == 0b11000000
+            && self.memory_control.get() & memory_control_bits::IO_DISABLE != 0
+        {
+            // the controller ports are disconnected
+            return self.open_bus(address);
+        }
         let value = match masked {
             0b00000000 => {
                 match (self.vdp.borrow().kind(), self.player_input.pause()) {
                     (Kind::Gg, true) => 0,
                     (Kind::Gg, false) => 0x80,
-                    // This is what the SMS 2 does. In the original SMS, reads
-                    // give the last byte of the instruction which read the
-                    // port. I'm not implementing that for now or hopefully
-                    // ever.
-                    _ => 0xFF,
+                    // nothing's there
+                    _ => self.open_bus(address),
                 }
             }
             0b00000001 => {
                 // ditto
-                0xFF
+                self.open_bus(address)
             }
             0b01000000 => {
                 // V counter
@@ -57,11 +349,22 @@ where
             }
             0b11000000 => {
                 // IO port A/B register
-                self.player_input.joypad_a()
+                let a = self.port_lines(ControllerPort::A);
+                let b = self.port_lines(ControllerPort::B);
+                a & 0x3F | b << 6
             }
             0b11000001 => {
                 // IO port B register
-                self.player_input.joypad_b()
+                let b = self.port_lines(ControllerPort::B);
+                let reset_cont = joypad_b_bits::RESET | joypad_b_bits::CONT;
+                let mut value = b >> 2 & 0x0F | self.player_input.joypad_b() & reset_cont;
+                if self.th_level(ControllerPort::A) {
+                    value |= joypad_b_bits::A_TH;
+                }
+                if self.th_level(ControllerPort::B) {
+                    value |= joypad_b_bits::B_TH;
+                }
+                value
             }
             _ => {
                 unreachable!("Missing IO address in input");
@@ -72,16 +375,53 @@ where
     }
 
     fn output(&mut self, address: u16, value: u8) {
+        use hardware::sms_vdp::Kind;
+
+        if let Some(ref mut ym2413) = self.ym2413 {
+            match address as u8 {
+                0xF0 => ym2413.write_address(value),
+                0xF1 => ym2413.write_data(value),
+                0xF2 => ym2413.write_control(value),
+                _ => {}
+            }
+            if address as u8 >= 0xF0 && address as u8 <= 0xF2 {
+                return;
+            }
+        }
+
+        if self.sc3000_keyboard && address & 0b11000000 == 0b11000000 {
+            self.ppi_output(address, value);
+            return;
+        }
+
         let masked = (address & 0b11000001) as u8;
 
         match masked {
+            0b00000000 if address as u8 == 0x06 && self.vdp.borrow().kind() == Kind::Gg => {
+                // Game Gear stereo control
+                self.sn76489.write_stereo(value);
+            }
             0b00000000 => {
-                // This is supposed to write to the IO system's memory control.
-                // It doesn't seem necessary to emulate this.
+                // memory control
+                self.memory_control.set(value);
             }
             0b00000001 => {
-                // This is supposed to write to the IO system's IO control. It
-                // doesn't seem necessary to emulate this.
+                // IO control. Bits 1 and 3 say whether the TH lines of ports A
+                // and B are inputs, and bits 5 and 7 give their levels as
+                // outputs. Raising a TH line latches the H counter. The
+                // corresponding bits for the TR lines aren't emulated.
+                for &port in [ControllerPort::A, ControllerPort::B].iter() {
+                    let shift = 2 * port as u8;
+                    let was_high = self.th_level(port);
+                    self.io_state.th_output[port as usize] = if value & 2 << shift != 0 {
+                        None
+                    } else {
+                        Some(value & 0x20 << shift != 0)
+                    };
+                    if !was_high && self.th_level(port) {
+                        self.io_state.latch_h_pending = true;
+                    }
+                }
             }
             0b01000000 =>
                 // SN76489 write
@@ -98,4 +438,397 @@ where
             _ => {}
         }
     }
+
+    fn wait_cycles(&mut self, address: u16, cycles: u64) -> u32 {
+        if self.io_state.latch_h_pending {
+            self.io_state.latch_h_pending = false;
+            let h = self.h_at(cycles);
+            self.vdp.borrow_mut().set_h(h);
+        }
+
+        let masked = (address & 0b11000001) as u8;
+        if self.accurate_vdp_access
+            && masked & 0b11000000 == 0b10000000
+            && self.in_active_display(cycles)
+        {
+            VDP_ACTIVE_DISPLAY_WAIT
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hardware::sms_irq::{SmsPauseInterruptState, SmsZ80IrqImpler};
+    use hardware::sms_memory::MEMORY_CONTROL_CARTRIDGE;
+    use hardware::sms_player_input::{joypad_a_bits, sc3000_keys, LightPhaserInput, PaddleInput};
+    use hardware::sms_vdp::{Kind, SmsVdpState};
+    use hardware::sn76489::FakeSn76489;
+    use hardware::z80::{Reg16, Reg8, Z80Internal, Z80Run, Z80RunImpler, Z80State};
+    use memo::NothingInbox;
+
+    /// What an `SmsIo16Impler` borrows, for `io` to build one from.
+    struct Parts {
+        vdp: SmsVdpState,
+        sn76489: FakeSn76489,
+        io_state: SmsIoState,
+        memory_control: Cell<u8>,
+    }
+
+    impl Default for Parts {
+        fn default() -> Self {
+            Parts {
+                vdp: Default::default(),
+                sn76489: FakeSn76489,
+                io_state: Default::default(),
+                memory_control: Cell::new(MEMORY_CONTROL_CARTRIDGE),
+            }
+        }
+    }
+
+    impl Parts {
+        /// An `SmsIo16Impler` with `player_input` and `port_devices`, with no
+        /// YM2413 and the other settings off.
+        fn io<'a>(
+            &'a mut self,
+            player_input: SmsPlayerInput,
+            port_devices: [PortDevice; 2],
+        ) -> SmsIo16Impler<'a, SmsVdpState, FakeSn76489> {
+            SmsIo16Impler {
+                vdp: Rc::new(RefCell::new(&mut self.vdp)),
+                sn76489: &mut self.sn76489,
+                player_input,
+                io_state: &mut self.io_state,
+                ym2413: None,
+                port_devices,
+                accurate_vdp_access: false,
+                sc3000_keyboard: false,
+                memory_control: &self.memory_control,
+            }
+        }
+    }
+
+    #[test]
+    fn light_phaser() {
+        let mut player_input = SmsPlayerInput::default();
+        player_input.set_light_phaser(
+            ControllerPort::A,
+            LightPhaserInput {
+                x: 100,
+                y: 50,
+                trigger: true,
+            },
+        );
+        let mut parts = Parts::default();
+        let mut io = parts.io(player_input, [PortDevice::LightPhaser, PortDevice::Joypad]);
+
+        // trigger held: TL reads low
+        assert_eq!(io.input(0xDC) & joypad_a_bits::JOYPAD1_A, 0);
+
+        // raster far from the aim point: TH reads high and H doesn't latch
+        io.vdp.borrow_mut().set_v(10);
+        latch_light_phasers(&mut **io.vdp.borrow_mut(), &io.player_input, io.port_devices);
+        assert_ne!(io.input(0xDD) & joypad_b_bits::A_TH, 0);
+        assert_eq!(io.input(0x7F), 0);
+
+        // raster at the aim point: TH reads low and H latches, as the line is
+        // drawn rather than when port 0xDD is read
+        io.vdp.borrow_mut().set_v(50);
+        assert_eq!(io.input(0x7F), 0);
+        latch_light_phasers(&mut **io.vdp.borrow_mut(), &io.player_input, io.port_devices);
+        assert_eq!(io.input(0x7F), 50);
+        assert_eq!(io.input(0xDD) & joypad_b_bits::A_TH, 0);
+
+        // trigger released
+        io.player_input.set_light_phaser(
+            ControllerPort::A,
+            LightPhaserInput {
+                x: 100,
+                y: 50,
+                trigger: false,
+            },
+        );
+        assert_ne!(io.input(0xDC) & joypad_a_bits::JOYPAD1_A, 0);
+    }
+
+    #[test]
+    fn light_phaser_in_window() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_kind(Kind::Gg);
+        let phaser = LightPhaserInput::in_window(&vdp, 10, 20, true);
+        assert_eq!(phaser.x, 58);
+        assert_eq!(phaser.y, 44);
+        assert_eq!(LightPhaserInput::in_window(&vdp, -100, 500, false).y, 0xFF);
+
+        let mut player_input = SmsPlayerInput::default();
+        player_input.set_light_phaser(ControllerPort::A, phaser);
+        let mut parts = Parts {
+            vdp,
+            ..Default::default()
+        };
+        let mut io = parts.io(player_input, [PortDevice::LightPhaser, PortDevice::Joypad]);
+
+        io.vdp.borrow_mut().set_v(44);
+        latch_light_phasers(&mut **io.vdp.borrow_mut(), &io.player_input, io.port_devices);
+        assert_eq!(io.input(0xDD) & joypad_b_bits::A_TH, 0);
+        assert_eq!(io.input(0x7E), 44);
+        assert_eq!(io.input(0x7F), 58 / 2);
+    }
+
+    #[test]
+    fn port_devices() {
+        let mut player_input = SmsPlayerInput::default();
+        player_input.set_paddle(
+            ControllerPort::A,
+            PaddleInput {
+                position: 0xA5,
+                button: true,
+            },
+        );
+        player_input.set_joypad_a(!joypad_a_bits::JOYPAD2_UP);
+        player_input.set_joypad_b(!joypad_b_bits::JOYPAD2_B);
+        let mut parts = Parts::default();
+        let mut io = parts.io(player_input, [PortDevice::Paddle, PortDevice::Joypad]);
+
+        // TR low: the paddle's low nibble, with its button (TL) held
+        let low = io.input(0xDC);
+        assert_eq!(low & 0x3F, 0x05);
+
+        // TR high: the high nibble
+        io.vdp.borrow_mut().set_v(1);
+        let high = io.input(0xDC);
+        assert_eq!(high & 0x3F, 0x2A);
+        assert_eq!((high & 0xF) << 4 | low & 0xF, 0xA5);
+
+        // port B's joypad, with up and B held, is unaffected
+        for &value in [low, high].iter() {
+            assert_eq!(value & 0xC0, joypad_a_bits::JOYPAD2_DOWN);
+        }
+        assert_eq!(io.input(0xDD), !joypad_b_bits::JOYPAD2_B);
+
+        // with nothing plugged in, everything reads high
+        io.port_devices = [PortDevice::None, PortDevice::None];
+        io.player_input = SmsPlayerInput::default();
+        assert_eq!(io.input(0xDC), 0xFF);
+        assert_eq!(io.input(0xDD), 0xFF);
+    }
+
+    #[test]
+    fn paddle_th() {
+        let mut parts = Parts::default();
+        let mut io = parts.io(Default::default(), [PortDevice::Joypad, PortDevice::Paddle]);
+
+        for &position in [0x00, 0x3C, 0xA5, 0xFF].iter() {
+            io.player_input.set_paddle(
+                ControllerPort::B,
+                PaddleInput {
+                    position,
+                    button: false,
+                },
+            );
+            let mut nibbles = Vec::new();
+            for &th in [false, true, false, true].iter() {
+                io.io_state.th_output[ControllerPort::B as usize] = Some(th);
+                // port B's up, down, left, and right are split across 0xDC and
+                // 0xDD
+                let lines = io.input(0xDC) >> 6 | (io.input(0xDD) & 0x0F) << 2;
+                nibbles.push(lines & 0xF);
+            }
+            assert_eq!(nibbles[0], nibbles[2]);
+            assert_eq!(nibbles[1], nibbles[3]);
+            assert_eq!(nibbles[1] << 4 | nibbles[0], position);
+        }
+    }
+
+    #[test]
+    fn io_control() {
+        let mut parts = Parts::default();
+        parts.vdp.set_cycles(342);
+        let mut io = parts.io(Default::default(), Default::default());
+        let th = joypad_b_bits::A_TH | joypad_b_bits::B_TH;
+
+        // TH lines are inputs, pulled high by the joypads
+        assert_eq!(io.input(0xDD) & th, th);
+
+        // TH lines as outputs, driven low
+        io.output(0x3F, 0x05);
+        io.wait_cycles(0x3F, 10);
+        assert_eq!(io.input(0xDD) & th, 0);
+        assert_eq!(io.input(0x7F), 0);
+
+        // only port A's TH driven high: the H counter latches
+        io.output(0x3F, 0x25);
+        io.wait_cycles(0x3F, 100);
+        assert_eq!(io.input(0xDD) & th, joypad_b_bits::A_TH);
+        assert_eq!(io.input(0x7F), 75);
+
+        // port B's TH back to an input: it rises, and H latches again
+        io.output(0x3F, 0x2D);
+        io.wait_cycles(0x3F, 40);
+        assert_eq!(io.input(0xDD) & th, th);
+        assert_eq!(io.input(0x7F), 30);
+
+        // no rising TH line, so no latch
+        io.output(0x3F, 0xFF);
+        io.wait_cycles(0x3F, 60);
+        assert_eq!(io.input(0x7F), 30);
+    }
+
+    #[test]
+    fn sc3000_keyboard() {
+        let mut player_input = SmsPlayerInput::default();
+        player_input.set_key_pressed(sc3000_keys::K, true);
+        player_input.set_key_pressed(sc3000_keys::SHIFT, true);
+        player_input.set_joypad_a(!joypad_a_bits::JOYPAD1_UP);
+        let mut parts = Parts::default();
+        let mut io = parts.io(player_input, Default::default());
+        io.sc3000_keyboard = true;
+
+        // at power on, row 7: the joypads
+        assert_eq!(io.input(0xDC), !joypad_a_bits::JOYPAD1_UP);
+
+        // row 0 has K in column 6
+        io.output(0xDE, 0x00);
+        assert_eq!(io.input(0xDC), !(1 << 6));
+        assert_eq!(io.input(0xDD), 0xFF);
+
+        // select row 6 with bit set/reset: SHIFT is column 11
+        io.output(0xDF, 0x03);
+        io.output(0xDF, 0x05);
+        assert_eq!(io.input(0xDE), 0x06);
+        assert_eq!(io.input(0xDC), 0xFF);
+        assert_eq!(io.input(0xDD), !(1 << 3));
+
+        io.player_input.set_key_pressed(sc3000_keys::SHIFT, false);
+        assert_eq!(io.input(0xDD), 0xFF);
+    }
+
+    #[test]
+    fn memory_control() {
+        let mut player_input = SmsPlayerInput::default();
+        player_input.set_joypad_a(!joypad_a_bits::JOYPAD1_UP);
+        let mut parts = Parts::default();
+        parts.vdp.set_v(33);
+        let mut io = parts.io(player_input, Default::default());
+        assert_eq!(io.input(0xDC), !joypad_a_bits::JOYPAD1_UP);
+
+        // disabling the IO chip disconnects the controller ports, but not the
+        // VDP
+        let disabled = MEMORY_CONTROL_CARTRIDGE | memory_control_bits::IO_DISABLE;
+        io.output(0x3E, disabled);
+        assert_eq!(io.memory_control.get(), disabled);
+        assert_eq!(io.input(0xDC), OPEN_BUS);
+        assert_eq!(io.input(0xDD), OPEN_BUS);
+        assert_eq!(io.input(0x7E), 33);
+
+        io.output(0x3E, MEMORY_CONTROL_CARTRIDGE);
+        assert_eq!(io.input(0xDC), !joypad_a_bits::JOYPAD1_UP);
+    }
+
+    #[test]
+    fn open_bus() {
+        fn read(kind: Kind, address: u16) -> u8 {
+            let mut parts = Parts::default();
+            parts.vdp.set_kind(kind);
+            parts
+                .memory_control
+                .set(MEMORY_CONTROL_CARTRIDGE | memory_control_bits::IO_DISABLE);
+            let mut io = parts.io(Default::default(), Default::default());
+            io.input(address)
+        }
+
+        // the original Master System reads back the port's low byte, as the
+        // last byte of `in a, (n)`
+        assert_eq!(read(Kind::Sms, 0x21), 0x21);
+        assert_eq!(read(Kind::Sms, 0x1234), 0x34);
+        assert_eq!(read(Kind::Sms, 0xDC), 0xDC);
+        assert_eq!(read(Kind::Sms2, 0x21), OPEN_BUS);
+        assert_eq!(read(Kind::Sms2, 0xDC), OPEN_BUS);
+
+        // and through a real `in`, on the bus with A in the high byte
+        let mut pause_irq = SmsPauseInterruptState::default();
+        let mut memory = [0u8; 0x10000];
+        memory[..4].copy_from_slice(&[
+            0x3E, 0x99, // ld a, 0x99
+            0xDB, 0x15, // in a, (0x15)
+        ]);
+        let mut parts = Parts::default();
+        parts.vdp.set_kind(Kind::Sms);
+        let mut io = parts.io(Default::default(), Default::default());
+        let mut irq = SmsZ80IrqImpler {
+            pause_interrupt: &mut pause_irq,
+            vdp: io.vdp.clone(),
+        };
+        let mut inbox = NothingInbox::default();
+        let mut z80 = Z80State::default();
+        for _ in 0..2 {
+            Z80RunImpler {
+                z80: &mut z80,
+                memory: &mut memory,
+                io: &mut io,
+                irq: &mut irq,
+                inbox: &mut inbox,
+            }.step();
+        }
+        assert_eq!(z80.reg8(Reg8::A), 0x15);
+    }
+
+    /// How many cycles do four `out (0xBE), a` instructions take, with the VDP
+    /// having just drawn the line before `v`, starting `h` pixels into it?
+    fn vdp_write_cycles(v: u16, h: u64) -> u64 {
+        let mut parts = Parts::default();
+        // display enabled
+        parts.vdp.set_register(1, 0x40);
+        parts.vdp.set_v(v);
+        parts.vdp.set_cycles(342 - h);
+        let mut pause_irq = SmsPauseInterruptState::default();
+        let mut memory = [0u8; 0x10000];
+        for i in 0..4 {
+            memory[2 * i] = 0xD3;
+            memory[2 * i + 1] = 0xBE;
+        }
+        let mut z80 = Z80State::default();
+        let mut io = parts.io(Default::default(), Default::default());
+        io.accurate_vdp_access = true;
+        let mut irq = SmsZ80IrqImpler {
+            vdp: io.vdp.clone(),
+            pause_interrupt: &mut pause_irq,
+        };
+        let mut inbox = NothingInbox::default();
+        while z80.reg16(Reg16::PC) < 8 {
+            let target = z80.cycles() + 1;
+            Z80RunImpler {
+                z80: &mut z80,
+                memory: &mut memory,
+                io: &mut io,
+                irq: &mut irq,
+                inbox: &mut inbox,
+            }.run(target);
+        }
+        z80.cycles()
+    }
+
+    #[test]
+    fn vdp_access_free_in_blanking() {
+        let vblank = vdp_write_cycles(200, 0);
+        let active = vdp_write_cycles(11, 0);
+        assert_eq!(vblank, 4 * 11);
+        assert_eq!(active, 4 * (11 + VDP_ACTIVE_DISPLAY_WAIT as u64));
+    }
+
+    #[test]
+    fn vdp_access_free_in_hblank() {
+        // the four writes take 44 Z80 cycles, or 66 pixels, so starting at
+        // pixel 260 they all land in hblank, before the line ends at 342
+        let hblank = vdp_write_cycles(11, 260);
+        assert_eq!(hblank, 4 * 11);
+
+        // starting at pixel 230, the first write lands in the active display
+        // and waits, which pushes the rest into hblank
+        let straddling = vdp_write_cycles(11, 230);
+        assert_eq!(straddling, 4 * 11 + VDP_ACTIVE_DISPLAY_WAIT as u64);
+    }
 }
@@ -26,6 +26,10 @@ impl Default for SmsPauseInterruptState {
 }
 
 impl SmsPauseInterruptState {
+    /// Tell whether the pause button is held.
+    ///
+    /// An NMI is only requested when the button goes from released to held,
+    /// so holding it down doesn't repeat the NMI.
     #[inline]
     pub fn pause_pressed(&mut self, x: bool) {
         if x && *self == SmsPauseInterruptState::Free {
@@ -1,8 +1,12 @@
 //! Memory maps for the Sega Master System.
 
 use std;
-use std::cell::UnsafeCell;
+#[cfg(not(feature = "std"))]
+use std::boxed::Box;
+use std::cell::{Cell, UnsafeCell};
 use std::sync::Arc;
+#[cfg(not(feature = "std"))]
+use std::vec::Vec;
 
 use super::memory16::*;
 
@@ -11,6 +15,16 @@ pub enum SmsMemoryMapper {
     Sega,
     Codemasters,
     Sg1000(usize),
+
+    /// Used by some Korean games; a single register at 0xA000 selects the
+    /// ROM page in slot 2.
+    Korean,
+
+    /// Used by multicarts like the 4 Pak All Action, which hold several
+    /// games in one ROM. Registers at 0x3FFE, 0x7FFF, and 0xBFFF select the
+    /// ROM pages in slots 0, 1, and 2, and the register for slot 0 also
+    /// selects which game slot 2's pages come from. See `reg_multicart`.
+    Multicart,
 }
 
 impl Default for SmsMemoryMapper {
@@ -78,6 +92,50 @@ mod _impl0 {
 
 }
 
+/// A physical location in the memory of the Master System.
+///
+/// This is where a logical address actually ends up, given the current
+/// mappings of the four slots. See `SmsMemory::describe_address`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MemoryLocation {
+    /// The byte at `offset` within the 16 KiB page `page` of ROM.
+    Rom { page: u8, offset: u16 },
+
+    /// The byte at the given index of the system RAM.
+    SystemRam(u16),
+
+    /// The byte at the given index of the 0, 16, or 32 KiB of cartridge RAM
+    /// used in the Sega Memory Mapper.
+    MainCartridgeRam(u16),
+
+    /// The byte at the given index of the 8 KiB of cartridge RAM used in the
+    /// Codemasters Memory Mapper.
+    HalfCartridgeRam(u16),
+}
+
+impl MemoryLocation {
+    /// If this is a location in ROM, its index into the whole ROM.
+    #[inline]
+    pub fn rom_index(&self) -> Option<usize> {
+        match *self {
+            MemoryLocation::Rom { page, offset } => Some(page as usize * 0x4000 + offset as usize),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MemoryLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use self::MemoryLocation::*;
+        match self {
+            Rom { page, offset } => format_args!("Rom({:>02X}):{:>04X}", page, offset).fmt(f),
+            SystemRam(x) => format_args!("SystemRam:{:>04X}", x).fmt(f),
+            MainCartridgeRam(x) => format_args!("MainCartridgeRam:{:>04X}", x).fmt(f),
+            HalfCartridgeRam(x) => format_args!("HalfCartridgeRam:{:>04X}", x).fmt(f),
+        }
+    }
+}
+
 /// On-cartridge RAM, which can be dynamically allocated if needed.
 ///
 /// This is for cartridges using the Sega Memory Mapper, which have 0, 16, or 32
@@ -90,6 +148,9 @@ pub enum MainCartridgeRam {
 }
 
 mod _impl1 {
+    #[cfg(not(feature = "std"))]
+    use std::boxed::Box;
+
     impl Default for super::MainCartridgeRam {
         fn default() -> Self {
             super::MainCartridgeRam::Zero
@@ -112,6 +173,60 @@ mod _impl1 {
     impl Eq for super::MainCartridgeRam {}
 }
 
+/// What RAM contains when the console is powered on.
+///
+/// Real RAM powers on with unpredictable contents, which some games end up
+/// depending on. Emulating that with `Seeded` keeps runs reproducible.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum RamFill {
+    Zero,
+    Ones,
+    /// Every byte is this value.
+    Pattern(u8),
+    /// Pseudorandom bytes, the same for the same seed.
+    Seeded(u64),
+}
+
+impl Default for RamFill {
+    #[inline]
+    fn default() -> Self {
+        RamFill::Zero
+    }
+}
+
+impl RamFill {
+    pub fn fill(self, ram: &mut [u8]) {
+        use self::RamFill::*;
+        match self {
+            Zero => fill_bytes(ram, 0),
+            Ones => fill_bytes(ram, 0xFF),
+            Pattern(x) => fill_bytes(ram, x),
+            Seeded(seed) => {
+                // xorshift64*, which must not start at 0
+                let mut state = seed ^ 0x9E37_79B9_7F4A_7C15;
+                if state == 0 {
+                    state = 1;
+                }
+                for chunk in ram.chunks_mut(8) {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    let x = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+                    for (i, byte) in chunk.iter_mut().enumerate() {
+                        *byte = (x >> (8 * i)) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn fill_bytes(ram: &mut [u8], x: u8) {
+    for byte in ram.iter_mut() {
+        *byte = x;
+    }
+}
+
 fn reg_sega<T>(memory: &mut T, address: u16, value: u8)
 where
     T: SmsMemory + ?Sized,
@@ -162,41 +277,119 @@ where
     match address {
         0x0000 => memory.map_page(0, Rom(page)),
         0x4000 => memory.map_page(1, Rom(page)),
-        0x8000 => memory.map_page(2, Rom(page)),
+        0x8000 => {
+            // Bit 7 maps the 8 KiB of on-cartridge RAM into 0xA000-0xBFFF;
+            // the rest of the value still selects the ROM page for
+            // 0x8000-0x9FFF.
+            let page = (value & 0x7F) % rom_pages as u8;
+            if value & 0x80 != 0 {
+                memory.map_page(2, HalfCartridgeRam(page))
+            } else {
+                memory.map_page(2, Rom(page))
+            }
+        }
+        _ => {}
+    }
+}
+
+fn reg_korean<T>(memory: &mut T, address: u16, value: u8)
+where
+    T: SmsMemory + ?Sized,
+{
+    use self::MemoryPage::*;
+    if address == 0xA000 {
+        memory.map_page(2, Rom(value));
+    }
+}
+
+/// The multicart mapper's registers are all in ROM, so writing them doesn't
+/// touch RAM:
+///
+/// * 0x3FFE: the ROM page in slot 0. Bits 4 and 5 also select the game, a
+///   256 KiB region of ROM.
+/// * 0x7FFF: the ROM page in slot 1.
+/// * 0xBFFF: the ROM page in slot 2, counting from the beginning of the game
+///   selected by 0x3FFE.
+///
+/// So a game selected by writing its first page to 0x3FFE appears at slot 0,
+/// and can then bank its own pages into slot 2 as if it were alone on the
+/// cartridge.
+fn reg_multicart<T>(memory: &mut T, address: u16, value: u8)
+where
+    T: SmsMemory + ?Sized,
+{
+    use self::MemoryPage::*;
+    match address {
+        0x3FFE => memory.map_page(0, Rom(value)),
+        0x7FFF => memory.map_page(1, Rom(value)),
+        0xBFFF => {
+            let game = match memory.page(0) {
+                Rom(x) => x & 0x30,
+                _ => 0,
+            };
+            memory.map_page(2, Rom(game.wrapping_add(value)))
+        }
         _ => {}
     }
 }
 
 pub fn default_mappings<M>(memory: &mut M)
+where
+    M: SmsMemory,
+{
+    let kib = match memory.mapper() {
+        SmsMemoryMapper::Sg1000(x) => std::cmp::max(1, x),
+        _ => 8,
+    };
+    memory.set_system_ram_kib(kib);
+    reset_mappings(memory);
+}
+
+/// Map the pages the way they are at power on, leaving RAM alone.
+///
+/// This is what a reset does to the mapper; see `Sms::reset`.
+pub fn reset_mappings<M>(memory: &mut M)
 where
     M: SmsMemory,
 {
     use self::MemoryPage::*;
+    memory.map_page(0, Rom(0));
+    memory.map_page(1, Rom(1));
     match memory.mapper() {
-        SmsMemoryMapper::Sega => {
-            memory.set_system_ram_kib(8);
-            memory.map_page(0, Rom(0));
-            memory.map_page(1, Rom(1));
-            memory.map_page(2, Rom(2));
-            memory.map_page(3, SystemRam);
-        }
-        SmsMemoryMapper::Codemasters => {
-            memory.set_system_ram_kib(8);
-            memory.map_page(0, Rom(0));
-            memory.map_page(1, Rom(1));
-            memory.map_page(2, Rom(0));
-            memory.map_page(3, SystemRam);
-        }
-        SmsMemoryMapper::Sg1000(x) => {
-            use self::MemoryPage::*;
-            use std::cmp::max;
-            let kib = max(1, x);
-            memory.set_system_ram_kib(kib);
-            memory.map_page(0, Rom(0));
-            memory.map_page(1, Rom(1));
-            memory.map_page(2, Rom(2));
-            memory.map_page(3, SystemRam);
-        }
+        SmsMemoryMapper::Codemasters => memory.map_page(2, Rom(0)),
+        _ => memory.map_page(2, Rom(2)),
+    }
+    memory.map_page(3, SystemRam);
+}
+
+/// Fill system and cartridge RAM as at power on.
+///
+/// Like `SmsMemoryState::fill_ram`, but for any `SmsMemory`.
+pub fn fill_ram<M>(memory: &mut M, fill: RamFill)
+where
+    M: SmsMemory + ?Sized,
+{
+    memory.set_cartridge_ram_fill(fill);
+
+    let mut ram = vec![0u8; memory.system_ram_len()];
+    fill.fill(&mut ram);
+    for (i, &x) in ram.iter().enumerate() {
+        memory.system_ram_write(i, x);
+    }
+
+    // each 16 KiB page is filled separately, as in `fill_ram`
+    let mut ram = vec![0u8; memory.main_cartridge_ram_len()];
+    for page in ram.chunks_mut(0x4000) {
+        fill.fill(page);
+    }
+    for (i, &x) in ram.iter().enumerate() {
+        memory.main_cartridge_ram_write(i, x);
+    }
+
+    let mut ram = vec![0u8; memory.half_cartridge_ram_len()];
+    fill.fill(&mut ram);
+    for (i, &x) in ram.iter().enumerate() {
+        memory.half_cartridge_ram_write(i, x);
     }
 }
 
@@ -258,6 +451,69 @@ pub trait SmsMemory: Memory16 {
     /// Panics if `index` is greater than the length of the RAM.
     fn half_cartridge_ram_write(&mut self, index: usize, value: u8);
 
+    /// Make sure at least `len` bytes of main cartridge RAM are allocated.
+    ///
+    /// `len` should be 0x4000 or 0x8000. Any RAM already allocated keeps its
+    /// contents.
+    fn allocate_main_cartridge_ram(&mut self, len: usize);
+
+    /// Make sure the 8 KiB of half cartridge RAM is allocated.
+    fn allocate_half_cartridge_ram(&mut self);
+
+    /// Fill cartridge RAM allocated from now on with `fill`, rather than
+    /// zeros. RAM already allocated is left alone.
+    fn set_cartridge_ram_fill(&mut self, fill: RamFill);
+
+    /// The contents of the cartridge RAM, suitable for saving to a `.sav`
+    /// file.
+    ///
+    /// This is the 16 or 32 KiB of main cartridge RAM if any has been
+    /// allocated, or else the 8 KiB of half cartridge RAM if that has been
+    /// allocated. If the game hasn't used any cartridge RAM, this is `None`.
+    fn save_cartridge_ram(&self) -> Option<Vec<u8>> {
+        let main_len = self.main_cartridge_ram_len();
+        let half_len = self.half_cartridge_ram_len();
+        if main_len != 0 {
+            Some(
+                (0..main_len)
+                    .map(|i| self.main_cartridge_ram_read(i))
+                    .collect(),
+            )
+        } else if half_len != 0 {
+            Some(
+                (0..half_len)
+                    .map(|i| self.half_cartridge_ram_read(i))
+                    .collect(),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Load cartridge RAM saved by `save_cartridge_ram`.
+    ///
+    /// `data` must be 8, 16, or 32 KiB; the RAM it's loaded into is decided
+    /// by its size. Main cartridge RAM is allocated as needed, but if more is
+    /// already allocated than `data` fills, the rest is left alone.
+    fn load_cartridge_ram(&mut self, data: &[u8]) -> Result<(), SmsMemoryLoadError> {
+        match data.len() {
+            0x2000 => {
+                self.allocate_half_cartridge_ram();
+                for (i, &x) in data.iter().enumerate() {
+                    self.half_cartridge_ram_write(i, x);
+                }
+            }
+            0x4000 | 0x8000 => {
+                self.allocate_main_cartridge_ram(data.len());
+                for (i, &x) in data.iter().enumerate() {
+                    self.main_cartridge_ram_write(i, x);
+                }
+            }
+            len => return Err(SmsMemoryLoadError::InvalidCartridgeRamSize(len)),
+        }
+        Ok(())
+    }
+
     /// How much system RAM, in bytes?
     ///
     /// (This is always a multiple of 0x400.)
@@ -313,6 +569,36 @@ pub trait SmsMemory: Memory16 {
     /// Should be memory safe but panic if `page` refers to a ROM page that
     /// doesn't exist.
     fn map_page_impl(&mut self, slot: u8, page: MemoryPage);
+
+    /// Where does the logical address `logical` point, given the current
+    /// mappings?
+    fn describe_address(&self, logical: u16) -> MemoryLocation {
+        use self::MemoryLocation as L;
+        use self::MemoryPage::*;
+        let slot = (logical >> 14) as u8;
+        let address = logical & 0x3FFF;
+        match self.page(slot) {
+            SystemRam => L::SystemRam(address % self.system_ram_len() as u16),
+            FirstCartridgeRam(_) => L::MainCartridgeRam(address),
+            SecondCartridgeRam(_) => L::MainCartridgeRam(address + 0x4000),
+            HalfCartridgeRam(page) => if address < 0x2000 {
+                L::Rom {
+                    page,
+                    offset: address,
+                }
+            } else {
+                L::HalfCartridgeRam(address - 0x2000)
+            },
+            Rom(page) => L::Rom {
+                page,
+                offset: address,
+            },
+            RomButFirstKiB(page) => L::Rom {
+                page: if address < 0x400 { 0 } else { page },
+                offset: address,
+            },
+        }
+    }
 }
 
 /// Captures the state of the memory in the Master System.
@@ -335,12 +621,46 @@ pub struct SmsMemoryState {
     pub half_cartridge_ram: Option<Box<[u8; 0x2000]>>,
     pub pages: [MemoryPage; 4],
     pub mapper: SmsMemoryMapper,
+
+    /// What cartridge RAM is filled with when a game first maps it in.
+    ///
+    /// This is a setting rather than part of the console's state, so it isn't
+    /// saved, and doesn't count in comparing or hashing states; a loaded
+    /// `SmsMemoryState` has `RamFill::Zero`.
+    pub ram_fill: RamFill,
+
+    /// A copy of what slot 0 reads while it's mapped to `RomButFirstKiB`: the
+    /// first KiB of ROM, then the rest of the mapped page. That way reads
+    /// don't have to check for the first KiB.
+    ///
+    /// `map_page_impl` and `rom_write` keep this up to date. Like `ram_fill`,
+    /// it isn't saved or compared; deserializing and `fix_first_slot` rebuild
+    /// it.
+    pub first_slot: Box<[u8]>,
 }
 
 mod _impl2 {
     use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::hash::{Hash, Hasher};
+    use std::mem::transmute;
     use std::sync::Arc;
 
+    /// A `T` that every other one equals, and that hashes to nothing.
+    #[derive(Default)]
+    #[repr(transparent)]
+    struct Ignored<T>(T);
+
+    impl<T> PartialEq for Ignored<T> {
+        fn eq(&self, _rhs: &Self) -> bool {
+            true
+        }
+    }
+
+    impl<T> Hash for Ignored<T> {
+        fn hash<H: Hasher>(&self, _state: &mut H) {}
+    }
+
     #[derive(Hash, PartialEq, Serialize, Deserialize)]
     struct SmsMemoryStateDerive {
         pub rom: Arc<Box<[u8]>>,
@@ -349,19 +669,69 @@ mod _impl2 {
         pub half_cartridge_ram: Option<Box<[[[u8; 0x20]; 0x10]; 0x10]>>,
         pub pages: [super::MemoryPage; 4],
         pub mapper: SmsMemoryMapper,
+        #[serde(skip)]
+        pub ram_fill: Ignored<RamFill>,
+        #[serde(skip)]
+        pub first_slot: Ignored<Box<[u8]>>,
+    }
+
+    impl<'de> Deserialize<'de> for super::SmsMemoryState {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let derive = SmsMemoryStateDerive::deserialize(deserializer)?;
+            let mut state: super::SmsMemoryState = unsafe { transmute(derive) };
+            state.fix_first_slot();
+            Ok(state)
+        }
+    }
+
+    impl Serialize for super::SmsMemoryState {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            // assert that types are the same size
+            let _ = transmute::<super::SmsMemoryState, SmsMemoryStateDerive>;
+            let derive: &SmsMemoryStateDerive = unsafe { transmute(self) };
+            derive.serialize(serializer)
+        }
     }
 
-    impl_serde_via!{super::SmsMemoryState, SmsMemoryStateDerive}
     impl_hash_via!{super::SmsMemoryState, SmsMemoryStateDerive}
     impl_partial_eq_via!{super::SmsMemoryState, SmsMemoryStateDerive}
     impl Eq for super::SmsMemoryState {}
 }
 
 impl SmsMemoryState {
+    /// Fill the system RAM, and any cartridge RAM, as at power on.
+    ///
+    /// Cartridge RAM is only allocated when a game first maps it in, so this
+    /// also sets `ram_fill`, for it to be filled the same way then. Call this
+    /// after `default_mappings`, so there is system RAM to fill.
+    pub fn fill_ram(&mut self, fill: RamFill) {
+        use self::MainCartridgeRam::*;
+        self.ram_fill = fill;
+        fill.fill(&mut self.system_ram);
+        match self.main_cartridge_ram {
+            Zero => {}
+            One(ref mut page) => fill.fill(&mut page[..]),
+            Two(ref mut page0, ref mut page1) => {
+                fill.fill(&mut page0[..]);
+                fill.fill(&mut page1[..]);
+            }
+        }
+        if let Some(ref mut ram) = self.half_cartridge_ram {
+            fill.fill(&mut ram[..]);
+        }
+    }
+
     /// Are the mapped ROM pages in this `SmsMemoryState` valid?
     ///
     /// That is, are they smaller than the total number of pages in the ROM?
     pub fn check_valid(&self) -> Option<SmsMemoryLoadError> {
+        use self::MemoryPage::*;
         use self::SmsMemoryLoadError::*;
         let rom_len = self.rom.len();
         let rom_pages = rom_len / 0x4000;
@@ -371,7 +741,7 @@ impl SmsMemoryState {
 
         for (slot, page) in self.pages.iter().enumerate() {
             match page {
-                MemoryPage::Rom(p) | MemoryPage::RomButFirstKiB(p) if *p > rom_pages as u8 => {
+                Rom(p) | RomButFirstKiB(p) | HalfCartridgeRam(p) if *p as usize >= rom_pages => {
                     return Some(InvalidRomPageSelected {
                         slot: slot as u8,
                         selected: *p,
@@ -385,16 +755,54 @@ impl SmsMemoryState {
         None
     }
 
+    /// Copy into `first_slot` what slot 0 reads, if it's mapped to
+    /// `RomButFirstKiB`.
+    ///
+    /// Call this after building an `SmsMemoryState` by hand, unless it goes
+    /// through `map_page` anyway. A page past the end of the ROM is left for
+    /// `check_valid` to report.
+    pub fn fix_first_slot(&mut self) {
+        if self.rom.len() < 0x400 {
+            return;
+        }
+        let mut first_slot = vec![0u8; 0x4000].into_boxed_slice();
+        first_slot[..0x400].copy_from_slice(&self.rom[..0x400]);
+        self.first_slot = first_slot;
+        self.remap_first_slot();
+    }
+
+    /// After slot 0 is remapped, copy the rest of the newly mapped page after
+    /// the first KiB in `first_slot`, which stays put.
+    fn remap_first_slot(&mut self) {
+        if let MemoryPage::RomButFirstKiB(page) = self.pages[0] {
+            let start = page as usize * 0x4000;
+            if self.first_slot.len() != 0x4000 {
+                self.fix_first_slot();
+            } else if start + 0x4000 <= self.rom.len() {
+                self.first_slot[0x400..].copy_from_slice(&self.rom[start + 0x400..start + 0x4000]);
+            }
+        }
+    }
+
     fn ensure_half_page(&mut self) {
         if let None = self.half_cartridge_ram {
-            self.half_cartridge_ram = Some(Box::new([0u8; 0x2000]));
+            let mut ram = Box::new([0u8; 0x2000]);
+            self.ram_fill.fill(&mut ram[..]);
+            self.half_cartridge_ram = Some(ram);
         }
     }
 
+    /// A newly allocated page of main cartridge RAM.
+    fn new_page(&self) -> Box<[u8; 0x4000]> {
+        let mut page = Box::new([0u8; 0x4000]);
+        self.ram_fill.fill(&mut page[..]);
+        page
+    }
+
     fn ensure_one_page(&mut self) {
         use self::MainCartridgeRam::*;
         if let Zero = self.main_cartridge_ram {
-            self.main_cartridge_ram = One(Box::new([0u8; 0x4000]));
+            self.main_cartridge_ram = One(self.new_page());
         }
     }
 
@@ -403,7 +811,7 @@ impl SmsMemoryState {
         use std::mem::swap;
         match &self.main_cartridge_ram {
             Zero => {
-                self.main_cartridge_ram = Two(Box::new([0u8; 0x4000]), Box::new([0u8; 0x4000]));
+                self.main_cartridge_ram = Two(self.new_page(), self.new_page());
             }
             One(_) => {
                 let mut fake_ram = Zero;
@@ -412,7 +820,7 @@ impl SmsMemoryState {
                     One(x) => x,
                     _ => unreachable!(),
                 };
-                self.main_cartridge_ram = Two(first_page, Box::new([0u8; 0x4000]));
+                self.main_cartridge_ram = Two(first_page, self.new_page());
             }
             _ => {}
         }
@@ -421,10 +829,11 @@ impl SmsMemoryState {
 
 impl SmsMemoryLoad for SmsMemoryState {
     #[inline(always)]
-    fn load(state: SmsMemoryState) -> Result<Self, SmsMemoryLoadError> {
+    fn load(mut state: SmsMemoryState) -> Result<Self, SmsMemoryLoadError> {
         if let Some(e) = state.check_valid() {
             Err(e)
         } else {
+            state.fix_first_slot();
             Ok(state)
         }
     }
@@ -457,13 +866,7 @@ impl Memory16 for SmsMemoryState {
                 }
             }
             Rom(x) => self.rom_read(address + x as usize * 0x4000),
-            RomButFirstKiB(x) => {
-                if address < 0x400 {
-                    self.rom_read(address)
-                } else {
-                    self.rom_read(address + x as usize * 0x4000)
-                }
-            }
+            RomButFirstKiB(_) => self.first_slot[address],
         }
     }
 
@@ -505,10 +908,120 @@ where
     match memory.mapper() {
         SmsMemoryMapper::Sega => reg_sega(memory, logical_address, value),
         SmsMemoryMapper::Codemasters => reg_codemasters(memory, logical_address, value),
+        SmsMemoryMapper::Korean => reg_korean(memory, logical_address, value),
+        SmsMemoryMapper::Multicart => reg_multicart(memory, logical_address, value),
         SmsMemoryMapper::Sg1000(_) => {}
     }
 }
 
+/// Bit flags for the memory control register, written through IO port 0x3E.
+///
+/// Each bit disables something when it's set.
+pub mod memory_control_bits {
+    pub const EXPANSION_DISABLE: u8 = 0b10000000;
+    pub const CARTRIDGE_DISABLE: u8 = 0b01000000;
+    pub const CARD_DISABLE: u8 = 0b00100000;
+    pub const RAM_DISABLE: u8 = 0b00010000;
+    pub const BIOS_DISABLE: u8 = 0b00001000;
+    pub const IO_DISABLE: u8 = 0b00000100;
+}
+
+/// The memory control register at power on, with the BIOS mapped in and the
+/// cartridge out.
+pub const MEMORY_CONTROL_BIOS: u8 = memory_control_bits::EXPANSION_DISABLE
+    | memory_control_bits::CARTRIDGE_DISABLE
+    | memory_control_bits::CARD_DISABLE;
+
+/// The memory control register as the BIOS leaves it when it hands off to
+/// the cartridge. Without a BIOS, we start here.
+pub const MEMORY_CONTROL_CARTRIDGE: u8 = memory_control_bits::EXPANSION_DISABLE
+    | memory_control_bits::CARD_DISABLE
+    | memory_control_bits::BIOS_DISABLE;
+
+/// What reads from disabled memory return.
+///
+/// With nothing driving the data bus, it's pulled high. (The original Master
+/// System instead reads back whatever was last on the bus; for IO ports, see
+/// `SmsIo16Impler::open_bus`.)
+pub const OPEN_BUS: u8 = 0xFF;
+
+/// A `Memory16` that applies the memory control register to `memory`.
+///
+/// While the BIOS is enabled, `bios` is mapped over the cartridge's ROM. It's
+/// paged by the same mapper registers as the cartridge, so a BIOS bigger than
+/// 48 KiB can reach the rest of itself, and it's mirrored if it's smaller
+/// than the pages selected.
+///
+/// While the cartridge is disabled, the rest of the first 48 KiB reads as
+/// `OPEN_BUS` and ignores writes. While RAM is disabled, so does the last 16
+/// KiB, except that the mapper still sees writes to its registers there.
+pub struct ControlledMemory16Impler<'a, M: 'a + ?Sized> {
+    pub memory: &'a mut M,
+    pub bios: Option<&'a [u8]>,
+    pub memory_control: &'a Cell<u8>,
+}
+
+impl<'a, M: 'a> Memory16 for ControlledMemory16Impler<'a, M>
+where
+    M: Memory16 + SmsMemory + ?Sized,
+{
+    #[inline]
+    fn read(&mut self, logical_address: u16) -> u8 {
+        use self::memory_control_bits::*;
+        let control = self.memory_control.get();
+        if logical_address < 0xC000 {
+            if let Some(bios) = self.bios {
+                if control & BIOS_DISABLE == 0 {
+                    let location = self.memory.describe_address(logical_address);
+                    if let Some(index) = location.rom_index() {
+                        return bios[index % bios.len()];
+                    }
+                }
+            }
+            if control & CARTRIDGE_DISABLE != 0 {
+                return OPEN_BUS;
+            }
+        } else if control & RAM_DISABLE != 0 {
+            return OPEN_BUS;
+        }
+        self.memory.read(logical_address)
+    }
+
+    #[inline]
+    fn write(&mut self, logical_address: u16, value: u8) {
+        use self::memory_control_bits::*;
+        let control = self.memory_control.get();
+        if logical_address < 0xC000 {
+            if control & CARTRIDGE_DISABLE == 0 {
+                self.memory.write(logical_address, value);
+            }
+        } else if control & RAM_DISABLE == 0 {
+            self.memory.write(logical_address, value);
+        } else {
+            memory_register_check(self.memory, logical_address, value);
+        }
+    }
+}
+
+impl<'a, M: 'a> RomMemory16 for ControlledMemory16Impler<'a, M>
+where
+    M: SmsMemory + ?Sized,
+{
+    #[inline]
+    fn is_rom(&self, logical_address: u16) -> bool {
+        use self::memory_control_bits::*;
+        let control = self.memory_control.get();
+        let bios_mapped = self.bios.is_some() && control & BIOS_DISABLE == 0;
+        if control & CARTRIDGE_DISABLE != 0 && !bios_mapped {
+            return false;
+        }
+        self.memory
+            .describe_address(logical_address)
+            .rom_index()
+            .is_some()
+    }
+}
+
 impl SmsMemory for SmsMemoryState {
     fn set_system_ram_kib(&mut self, kib: usize) {
         let len = kib * 0x400;
@@ -538,6 +1051,9 @@ impl SmsMemory for SmsMemoryState {
     #[inline(always)]
     fn map_page_impl(&mut self, slot: u8, page: MemoryPage) {
         self.pages[slot as usize] = page;
+        if slot == 0 {
+            self.remap_first_slot();
+        }
     }
     #[inline(always)]
     fn rom_read(&self, index: usize) -> u8 {
@@ -547,6 +1063,17 @@ impl SmsMemory for SmsMemoryState {
     #[inline(always)]
     fn rom_write(&mut self, index: usize, value: u8) {
         Arc::make_mut(&mut self.rom)[index] = value;
+        // and the same byte in `first_slot`, if slot 0 reads it from there
+        if let MemoryPage::RomButFirstKiB(page) = self.pages[0] {
+            let start = page as usize * 0x4000;
+            if self.first_slot.len() == 0x4000 {
+                if index < 0x400 {
+                    self.first_slot[index] = value;
+                } else if start + 0x400 <= index && index < start + 0x4000 {
+                    self.first_slot[index - start] = value;
+                }
+            }
+        }
     }
 
     #[inline(always)]
@@ -613,9 +1140,25 @@ impl SmsMemory for SmsMemoryState {
         }
     }
 
+    fn allocate_main_cartridge_ram(&mut self, len: usize) {
+        if len > 0x4000 {
+            self.ensure_two_pages();
+        } else if len > 0 {
+            self.ensure_one_page();
+        }
+    }
+
+    fn allocate_half_cartridge_ram(&mut self) {
+        self.ensure_half_page();
+    }
+
+    fn set_cartridge_ram_fill(&mut self, fill: RamFill) {
+        self.ram_fill = fill;
+    }
+
     #[inline(always)]
     fn system_ram_len(&self) -> usize {
-        self.system_ram.len() * 0x400
+        self.system_ram.len()
     }
 
     #[inline(always)]
@@ -641,7 +1184,8 @@ mod sms_memory_load_error {
     use super::*;
 
     /// Error generated by `SmsMemoryLoad`.
-    #[derive(Debug, Fail)]
+    #[derive(Debug)]
+    #[cfg_attr(feature = "std", derive(Fail))]
     pub enum SmsMemoryLoadError {
         /// The ROM size is not valid.
         ///
@@ -651,24 +1195,55 @@ mod sms_memory_load_error {
         /// * It's bigger than 0x400000 (there are 8 bits to select a page, and each
         ///   16 bit logical address has 14 bits to select an offset within the
         ///   slot, leaving an effective 22 bit address).
-        #[fail(
-            display = "Invalid ROM size 0x{:x} (should be a positive multiple of 0x4000, no bigger than 0x400000)",
-            _0
-        )]
         InvalidRomSize(usize),
 
-        #[fail(
-            display = "Slot {} selected ROM page {}, but found only {} pages", slot, selected, found
-        )]
         InvalidRomPageSelected { slot: u8, selected: u8, found: u8 },
 
-        #[fail(display = "IO error while reading ROM file {}: {}", filename, io_error)]
+        /// Cartridge RAM must be 0x2000, 0x4000, or 0x8000 bytes.
+        InvalidCartridgeRamSize(usize),
+
+        #[cfg(feature = "std")]
         Io {
             filename: String,
             #[cause]
             io_error: std::io::Error,
         },
     }
+
+    // Written out rather than with `#[fail(display)]`, so that it's there
+    // without the `std` feature too.
+    impl std::fmt::Display for SmsMemoryLoadError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            use self::SmsMemoryLoadError::*;
+            match self {
+                InvalidRomSize(size) => write!(
+                    f,
+                    "Invalid ROM size 0x{:x} (should be a positive multiple of 0x4000, no bigger than 0x400000)",
+                    size
+                ),
+                InvalidRomPageSelected {
+                    slot,
+                    selected,
+                    found,
+                } => write!(
+                    f,
+                    "Slot {} selected ROM page {}, but found only {} pages",
+                    slot, selected, found
+                ),
+                InvalidCartridgeRamSize(size) => write!(
+                    f,
+                    "Invalid cartridge RAM size 0x{:x} (should be 0x2000, 0x4000, or 0x8000)",
+                    size
+                ),
+                #[cfg(feature = "std")]
+                Io { filename, io_error } => write!(
+                    f,
+                    "IO error while reading ROM file {}: {}",
+                    filename, io_error
+                ),
+            }
+        }
+    }
 }
 
 pub use self::sms_memory_load_error::SmsMemoryLoadError;
@@ -707,6 +1282,8 @@ pub trait SmsMemoryLoad: Sized {
             half_cartridge_ram: Default::default(),
             pages: Default::default(),
             mapper: Default::default(),
+            ram_fill: Default::default(),
+            first_slot: Default::default(),
         };
         return Self::load(state);
     }
@@ -715,8 +1292,9 @@ pub trait SmsMemoryLoad: Sized {
 /// An implementation of `SmsMemory` using pointer manipulation to map logical
 /// memory addresses to physical memory addresses.
 ///
-/// This is likely faster than the manual dispatch of `SmsMemoryState`, but I
-/// haven't done a comparison yet.
+/// Every 1 KiB of the address space gets its own pointer, so a read is just an
+/// index and a dereference, with no dispatch on the page or special case for
+/// the first KiB.
 pub struct PointerSmsMemory {
     state: UnsafeCell<SmsMemoryState>,
     scrap: Arc<[u8; 0x400]>,
@@ -871,7 +1449,7 @@ impl PointerSmsMemory {
 
         match page {
             SystemRam => {
-                let kib = state.system_ram.len();
+                let kib = state.system_ram.len() / 0x400;
                 for i in 0..16 {
                     let offset = (i % kib) * 0x400;
                     let ptr: *mut u8 =
@@ -913,7 +1491,7 @@ impl PointerSmsMemory {
                     _ => unreachable!(),
                 };
                 for i in 0..8 {
-                    let p0 = unsafe { ptr0.offset(i as isize * 0x400 + 0x2000) };
+                    let p0 = unsafe { ptr0.offset(i as isize * 0x400) };
                     minislots[i] = p0;
                     write_minislots[i] = scrap_ptr;
                     let p1 = unsafe { ptr1.offset(i as isize * 0x400) };
@@ -940,7 +1518,7 @@ impl PointerSmsMemory {
                 }
             }
         }
-        state.pages[slot as usize] = page;
+        state.map_page_impl(slot, page);
     }
 
     #[inline(always)]
@@ -1060,6 +1638,20 @@ impl SmsMemory for PointerSmsMemory {
         self.state_mut().half_cartridge_ram_write(index, value)
     }
 
+    fn allocate_main_cartridge_ram(&mut self, len: usize) {
+        self.state_mut().allocate_main_cartridge_ram(len);
+        self.reset_pointers();
+    }
+
+    fn allocate_half_cartridge_ram(&mut self) {
+        self.state_mut().allocate_half_cartridge_ram();
+        self.reset_pointers();
+    }
+
+    fn set_cartridge_ram_fill(&mut self, fill: RamFill) {
+        self.state_mut().ram_fill = fill;
+    }
+
     #[inline]
     fn system_ram_len(&self) -> usize {
         self.state().system_ram_len()
@@ -1080,3 +1672,494 @@ impl SmsMemory for PointerSmsMemory {
         self.state().state()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    #[cfg(feature = "std")]
+    use save;
+    use utilities;
+
+    /// A ROM of `pages` 16 KiB pages, where each byte is distinct from the
+    /// byte at the same offset in any other page.
+    fn rom(pages: usize) -> Arc<Box<[u8]>> {
+        let rom: Vec<u8> = (0..pages * 0x4000)
+            .map(|i| (i / 0x4000) as u8 ^ i as u8)
+            .collect();
+        Arc::new(rom.into_boxed_slice())
+    }
+
+    fn state(pages: usize, mapper: SmsMemoryMapper) -> SmsMemoryState {
+        let mut state = SmsMemoryState {
+            rom: rom(pages),
+            system_ram: Default::default(),
+            main_cartridge_ram: Default::default(),
+            half_cartridge_ram: Default::default(),
+            pages: Default::default(),
+            mapper,
+            ram_fill: Default::default(),
+            first_slot: Default::default(),
+        };
+        default_mappings(&mut state);
+        state
+    }
+
+    #[test]
+    fn describe_address() {
+        use self::MemoryLocation::*;
+
+        let mut memory = state(8, SmsMemoryMapper::Sega);
+        memory.write(0xFFFD, 5);
+        memory.write(0xFFFF, 3);
+        assert_eq!(memory.describe_address(0x0100), Rom { page: 0, offset: 0x100 });
+        assert_eq!(memory.describe_address(0x0500), Rom { page: 5, offset: 0x500 });
+        assert_eq!(memory.describe_address(0x5000), Rom { page: 1, offset: 0x1000 });
+        assert_eq!(memory.describe_address(0x9234), Rom { page: 3, offset: 0x1234 });
+        assert_eq!(memory.describe_address(0xC123), SystemRam(0x123));
+        assert_eq!(memory.describe_address(0xE123), SystemRam(0x123));
+
+        memory.write(0xFFFC, 0x08);
+        assert_eq!(memory.describe_address(0x8010), MainCartridgeRam(0x10));
+        memory.write(0xFFFC, 0x0C);
+        assert_eq!(memory.describe_address(0x8010), MainCartridgeRam(0x4010));
+
+        let mut memory = state(8, SmsMemoryMapper::Codemasters);
+        memory.map_page(2, MemoryPage::HalfCartridgeRam(2));
+        assert_eq!(memory.describe_address(0x8100), Rom { page: 2, offset: 0x100 });
+        assert_eq!(memory.describe_address(0xA100), HalfCartridgeRam(0x100));
+    }
+
+    #[test]
+    fn reads_match_description() {
+        let mut state = state(8, SmsMemoryMapper::Sega);
+        state.write(0xFFFD, 5);
+        state.write(0xFFFE, 6);
+        state.write(0xFFFF, 3);
+        state.map_page(2, MemoryPage::HalfCartridgeRam(2));
+        let mut pointer = PointerSmsMemory::from(state.clone());
+        for &address in [0x0100u16, 0x0500, 0x5000, 0x8100, 0x9FFF].iter() {
+            let index = state.describe_address(address).rom_index().unwrap();
+            let expected = state.rom[index];
+            assert_eq!(state.read(address), expected);
+            assert_eq!(pointer.read(address), expected);
+        }
+    }
+
+    #[test]
+    fn codemasters_ram() {
+        fn check<M: SmsMemory>(memory: &mut M) {
+            use self::MemoryLocation::*;
+
+            memory.write(0x8000, 0x83);
+            assert_eq!(memory.page(2), MemoryPage::HalfCartridgeRam(3));
+            assert_eq!(memory.describe_address(0x8100), Rom { page: 3, offset: 0x100 });
+            assert_eq!(memory.describe_address(0xA100), HalfCartridgeRam(0x100));
+            memory.write(0xA100, 0x12);
+            memory.write(0xBFFF, 0x34);
+            assert_eq!(memory.read(0xA100), 0x12);
+            assert_eq!(memory.read(0xBFFF), 0x34);
+
+            // switch the RAM out
+            memory.write(0x8000, 0x04);
+            assert_eq!(memory.page(2), MemoryPage::Rom(4));
+            assert_eq!(memory.read(0xA100), memory.rom_read(4 * 0x4000 + 0x2100));
+            memory.write(0xA100, 0x56);
+
+            // and back in, on a different ROM page
+            memory.write(0x8000, 0x85);
+            assert_eq!(memory.read(0x8100), memory.rom_read(5 * 0x4000 + 0x100));
+            assert_eq!(memory.read(0xA100), 0x12);
+            assert_eq!(memory.read(0xBFFF), 0x34);
+            assert_eq!(memory.half_cartridge_ram_read(0x100), 0x12);
+        }
+
+        let state = state(8, SmsMemoryMapper::Codemasters);
+        check(&mut PointerSmsMemory::from(state.clone()));
+        let mut state = state;
+        check(&mut state);
+        let mut pointer = PointerSmsMemory::from(state.clone());
+        assert_eq!(pointer.read(0xA100), 0x12);
+    }
+
+    #[test]
+    fn pointer_system_ram() {
+        // 8 KiB of system RAM, mirrored across the 16 KiB of slot 3
+        let mut pointer = PointerSmsMemory::from(state(8, SmsMemoryMapper::Sega));
+        pointer.write(0xC123, 0x12);
+        pointer.write(0xFDFF, 0x34);
+        assert_eq!(pointer.read(0xE123), 0x12);
+        assert_eq!(pointer.read(0xDDFF), 0x34);
+        assert_eq!(pointer.system_ram_read(0x123), 0x12);
+        assert_eq!(pointer.system_ram_read(0x1DFF), 0x34);
+    }
+
+    #[test]
+    fn cartridge_ram() {
+        fn check<M: SmsMemory>(memory: &mut M) {
+            assert_eq!(memory.save_cartridge_ram(), None);
+
+            // turn on the first page of cartridge RAM in slot 2
+            memory.write(0xFFFC, 0x08);
+            memory.write(0x8000, 0x12);
+            memory.write(0xBFFF, 0x34);
+            let saved = memory.save_cartridge_ram().unwrap();
+            assert_eq!(saved.len(), 0x4000);
+            assert_eq!(saved[0], 0x12);
+            assert_eq!(saved[0x3FFF], 0x34);
+
+            let mut fresh = state(8, SmsMemoryMapper::Sega);
+            fresh.load_cartridge_ram(&saved).unwrap();
+            assert_eq!(fresh.save_cartridge_ram(), Some(saved.clone()));
+            let mut pointer = PointerSmsMemory::from(state(8, SmsMemoryMapper::Sega));
+            pointer.load_cartridge_ram(&saved).unwrap();
+            pointer.write(0xFFFC, 0x08);
+            assert_eq!(pointer.read(0x8000), 0x12);
+            assert_eq!(pointer.read(0xBFFF), 0x34);
+
+            match memory.load_cartridge_ram(&[0u8; 0x3000]) {
+                Err(SmsMemoryLoadError::InvalidCartridgeRamSize(0x3000)) => {}
+                x => panic!("unexpected result {:?}", x),
+            }
+
+            // loading 32 KiB allocates the second page
+            let mut big = vec![0u8; 0x8000];
+            big[0x4000] = 0x56;
+            memory.load_cartridge_ram(&big).unwrap();
+            memory.write(0xFFFC, 0x0C);
+            assert_eq!(memory.read(0x8000), 0x56);
+            assert_eq!(memory.save_cartridge_ram(), Some(big));
+        }
+
+        check(&mut state(8, SmsMemoryMapper::Sega));
+        check(&mut PointerSmsMemory::from(state(8, SmsMemoryMapper::Sega)));
+
+        let mut memory = state(8, SmsMemoryMapper::Codemasters);
+        memory.write(0x8000, 0x80);
+        memory.write(0xA000, 0x78);
+        let saved = memory.save_cartridge_ram().unwrap();
+        assert_eq!(saved.len(), 0x2000);
+        let mut pointer = PointerSmsMemory::from(state(8, SmsMemoryMapper::Codemasters));
+        pointer.load_cartridge_ram(&saved).unwrap();
+        pointer.write(0x8000, 0x80);
+        assert_eq!(pointer.read(0xA000), 0x78);
+    }
+
+    /// Check that `memory` survives both a trip through `SmsMemoryState` and,
+    /// with the `std` feature, a trip through serialization.
+    fn assert_round_trip<M>(memory: &mut M)
+    where
+        M: SmsMemory + SmsMemoryLoad,
+    {
+        let state = memory.state();
+
+        let mut loaded = M::load(state.clone()).unwrap();
+        assert!(loaded.state() == state);
+        for address in 0..0x10000 {
+            assert_eq!(loaded.read(address as u16), memory.read(address as u16));
+        }
+
+        #[cfg(feature = "std")]
+        {
+            let bytes = save::serialize(&state).unwrap();
+            let deserialized: SmsMemoryState = save::deserialize(&bytes).unwrap();
+            assert!(deserialized == state);
+            let mut deserialized = M::load(deserialized).unwrap();
+            for address in 0..0x10000 {
+                assert_eq!(deserialized.read(address as u16), memory.read(address as u16));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_sega() {
+        fn check<M: SmsMemory + SmsMemoryLoad>(mut memory: M) {
+            assert_round_trip(&mut memory);
+
+            memory.write(0xFFFD, 5);
+            memory.write(0xFFFE, 6);
+            memory.write(0xFFFF, 7);
+            memory.write(0xC000, 0x12);
+            assert_round_trip(&mut memory);
+
+            // cartridge RAM in slot 2, then its second page, then slot 3
+            memory.write(0xFFFC, 0x08);
+            memory.write(0x8000, 0x34);
+            memory.write(0xFFFC, 0x0C);
+            memory.write(0x8000, 0x56);
+            assert_eq!(memory.main_cartridge_ram_len(), 0x8000);
+            assert_round_trip(&mut memory);
+            memory.write(0xFFFC, 0x10);
+            assert_eq!(memory.page(3), MemoryPage::FirstCartridgeRam(0));
+            assert_round_trip(&mut memory);
+        }
+
+        check(state(8, SmsMemoryMapper::Sega));
+        check(PointerSmsMemory::from(state(8, SmsMemoryMapper::Sega)));
+    }
+
+    #[test]
+    fn round_trip_codemasters() {
+        fn check<M: SmsMemory + SmsMemoryLoad>(mut memory: M) {
+            assert_round_trip(&mut memory);
+
+            memory.write(0x0000, 3);
+            memory.write(0x4000, 4);
+            memory.write(0x8000, 5);
+            assert_round_trip(&mut memory);
+
+            memory.write(0x8000, 0x86);
+            memory.write(0xA000, 0x78);
+            assert_round_trip(&mut memory);
+
+            // the RAM should still be saved while it's switched out
+            memory.write(0x8000, 0x02);
+            assert_round_trip(&mut memory);
+            let mut loaded = M::load(memory.state()).unwrap();
+            loaded.write(0x8000, 0x80);
+            assert_eq!(loaded.read(0xA000), 0x78);
+        }
+
+        check(state(8, SmsMemoryMapper::Codemasters));
+        check(PointerSmsMemory::from(state(8, SmsMemoryMapper::Codemasters)));
+    }
+
+    #[test]
+    fn load_checks_rom_pages() {
+        let mut memory = state(8, SmsMemoryMapper::Sega);
+        memory.pages[1] = MemoryPage::Rom(7);
+        assert!(SmsMemoryState::load(memory.clone()).is_ok());
+        memory.pages[1] = MemoryPage::Rom(8);
+        assert!(SmsMemoryState::load(memory.clone()).is_err());
+        assert!(PointerSmsMemory::load(memory.clone()).is_err());
+        memory.pages[1] = MemoryPage::HalfCartridgeRam(8);
+        assert!(PointerSmsMemory::load(memory).is_err());
+    }
+
+    #[test]
+    fn korean() {
+        fn check<M: SmsMemory + SmsMemoryLoad>(mut memory: M) {
+            assert_eq!(memory.read(0x8123), memory.rom_read(2 * 0x4000 + 0x123));
+
+            memory.write(0xA000, 5);
+            assert_eq!(memory.page(2), MemoryPage::Rom(5));
+            assert_eq!(memory.read(0x8123), memory.rom_read(5 * 0x4000 + 0x123));
+            assert_eq!(memory.read(0xA123), memory.rom_read(5 * 0x4000 + 0x2123));
+
+            // the page number wraps around the size of the ROM
+            memory.write(0xA000, 11);
+            assert_eq!(memory.page(2), MemoryPage::Rom(3));
+
+            // nothing else is a register
+            memory.write(0xFFFF, 6);
+            memory.write(0x8000, 6);
+            assert_eq!(memory.page(2), MemoryPage::Rom(3));
+            assert_eq!(memory.page(0), MemoryPage::Rom(0));
+            assert_eq!(memory.page(1), MemoryPage::Rom(1));
+
+            assert_round_trip(&mut memory);
+        }
+
+        check(state(8, SmsMemoryMapper::Korean));
+        check(PointerSmsMemory::from(state(8, SmsMemoryMapper::Korean)));
+    }
+
+    #[test]
+    fn multicart() {
+        // two games of 256 KiB each
+        fn check<M: SmsMemory + SmsMemoryLoad>(mut memory: M) {
+            assert_eq!(memory.page(0), MemoryPage::Rom(0));
+
+            // the first game banks its page 3 into slot 2
+            memory.write(0xBFFF, 3);
+            assert_eq!(memory.page(2), MemoryPage::Rom(3));
+
+            // select the second game
+            memory.write(0x3FFE, 0x10);
+            memory.write(0x7FFF, 0x11);
+            assert_eq!(memory.page(0), MemoryPage::Rom(0x10));
+            assert_eq!(memory.read(0x0123), memory.rom_read(0x10 * 0x4000 + 0x123));
+            assert_eq!(memory.read(0x4123), memory.rom_read(0x11 * 0x4000 + 0x123));
+
+            // and now slot 2's register counts from the second game
+            memory.write(0xBFFF, 3);
+            assert_eq!(memory.page(2), MemoryPage::Rom(0x13));
+            assert_eq!(memory.read(0x8123), memory.rom_read(0x13 * 0x4000 + 0x123));
+
+            // back to the first game
+            memory.write(0x3FFE, 0);
+            assert_eq!(memory.read(0x0123), memory.rom_read(0x123));
+            memory.write(0xBFFF, 3);
+            assert_eq!(memory.page(2), MemoryPage::Rom(3));
+
+            // the Sega mapper's registers are just RAM
+            memory.write(0xFFFE, 5);
+            assert_eq!(memory.page(1), MemoryPage::Rom(0x11));
+            assert_eq!(memory.read(0xFFFE), 5);
+
+            assert_round_trip(&mut memory);
+        }
+
+        let memory = state(0x20, SmsMemoryMapper::Multicart);
+        check(memory.clone());
+        check(PointerSmsMemory::from(memory));
+    }
+
+    #[test]
+    fn ram_fill() {
+        fn filled(fill: RamFill) -> Box<[u8]> {
+            let mut state = state(8, SmsMemoryMapper::Sega);
+            state.fill_ram(fill);
+            state.system_ram
+        }
+
+        // the default is what there was before
+        assert_eq!(
+            filled(RamFill::default()),
+            state(8, SmsMemoryMapper::Sega).system_ram
+        );
+        assert!(filled(RamFill::Ones).iter().all(|&x| x == 0xFF));
+        assert!(filled(RamFill::Pattern(0xA5)).iter().all(|&x| x == 0xA5));
+
+        let seeded = filled(RamFill::Seeded(1));
+        assert_eq!(seeded.len(), 0x2000);
+        assert_eq!(seeded, filled(RamFill::Seeded(1)));
+        assert_ne!(seeded, filled(RamFill::Seeded(2)));
+        // and it's not all one value
+        assert!(seeded.iter().any(|&x| x != seeded[0]));
+        let zero_seed = filled(RamFill::Seeded(0));
+        assert!(zero_seed.iter().any(|&x| x != 0));
+
+        // it's a setting, not part of the state
+        let mut state0 = state(8, SmsMemoryMapper::Sega);
+        let state1 = state0.clone();
+        state0.ram_fill = RamFill::Ones;
+        assert!(state0 == state1);
+        let hash = |state: &SmsMemoryState| {
+            let mut hasher = DefaultHasher::new();
+            state.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&state0), hash(&state1));
+    }
+
+    #[test]
+    fn first_kib_stays_put() {
+        fn check<M: SmsMemory>(memory: &mut M) {
+            let rom = rom(8);
+            for &page in [3u8, 0, 7, 1].iter() {
+                memory.write(0xFFFD, page);
+                for address in 0..0x4000u16 {
+                    let expected = if address < 0x400 {
+                        rom[address as usize]
+                    } else {
+                        rom[page as usize * 0x4000 + address as usize]
+                    };
+                    assert_eq!(memory.read(address), expected, "page {}", page);
+                }
+            }
+            // writing ROM shows up on both sides of the first KiB
+            memory.rom_write(0x10, 0xAB);
+            memory.rom_write(0x4000 + 0x410, 0xCD);
+            assert_eq!(memory.read(0x10), 0xAB);
+            assert_eq!(memory.read(0x410), 0xCD);
+        }
+
+        let state = state(8, SmsMemoryMapper::Sega);
+        check(&mut PointerSmsMemory::from(state.clone()));
+        check(&mut state.clone());
+
+        // a deserialized state reads the same, even without `load`
+        #[cfg(feature = "std")]
+        {
+            let mut state = state.clone();
+            state.write(0xFFFD, 5);
+            let bytes = save::serialize(&state).unwrap();
+            let mut state2: SmsMemoryState = save::deserialize(&bytes).unwrap();
+            for address in 0..0x4000u16 {
+                assert_eq!(state2.read(address), state.read(address));
+            }
+        }
+    }
+
+    /// Read the whole address space `rounds` times, with the mapper cycling
+    /// through ROM pages, returning a checksum so the reads can't be skipped.
+    fn read_everything<M: SmsMemory>(memory: &mut M, rounds: usize) -> u64 {
+        let mut sum = 0u64;
+        for round in 0..rounds {
+            memory.write(0xFFFE, (round % 8) as u8);
+            memory.write(0xFFFF, ((round + 3) % 8) as u8);
+            for address in 0..=0xFFFFu16 {
+                sum = sum.wrapping_add(memory.read(address) as u64);
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn memory_control() {
+        use self::memory_control_bits::*;
+
+        let mut state = state(4, SmsMemoryMapper::Sega);
+        let control = Cell::new(MEMORY_CONTROL_CARTRIDGE);
+        let mut memory = ControlledMemory16Impler {
+            memory: &mut state,
+            bios: None,
+            memory_control: &control,
+        };
+        assert_eq!(memory.read(0x4010), 0x11);
+        memory.write(0xC000, 0x12);
+
+        // with the cartridge disabled, ROM and cartridge RAM are open bus
+        control.set(MEMORY_CONTROL_CARTRIDGE | CARTRIDGE_DISABLE);
+        assert_eq!(memory.read(0x0001), OPEN_BUS);
+        assert_eq!(memory.read(0x4010), OPEN_BUS);
+        memory.write(0xFFFC, 0x08);
+        memory.write(0x8000, 0x34);
+        assert_eq!(memory.read(0x8000), OPEN_BUS);
+        assert_eq!(memory.read(0xC000), 0x12);
+
+        // with RAM disabled, the mapper still sees its registers
+        control.set(MEMORY_CONTROL_CARTRIDGE | RAM_DISABLE);
+        assert_eq!(memory.read(0x8000), 0);
+        assert_eq!(memory.read(0xC000), OPEN_BUS);
+        memory.write(0xC000, 0x56);
+        memory.write(0xFFFE, 3);
+        assert_eq!(memory.read(0x4010), 0x13);
+
+        control.set(MEMORY_CONTROL_CARTRIDGE);
+        assert_eq!(memory.read(0xC000), 0x12);
+    }
+
+    #[test]
+    fn pointer_reads_match() {
+        let state = state(8, SmsMemoryMapper::Sega);
+        let mut pointer = PointerSmsMemory::from(state.clone());
+        let mut state = state;
+        assert_eq!(
+            read_everything(&mut state, 16),
+            read_everything(&mut pointer, 16)
+        );
+    }
+
+    /// Compare read throughput of the two `SmsMemory` implementations. See
+    /// `utilities::print_throughput`.
+    #[test]
+    #[ignore]
+    fn read_throughput() {
+        const ROUNDS: usize = 2000;
+        let reads = (ROUNDS * 0x10000) as u64;
+        let state = state(32, SmsMemoryMapper::Sega);
+        let mut pointer = PointerSmsMemory::from(state.clone());
+        utilities::print_throughput("PointerSmsMemory", "reads", reads, || {
+            read_everything(&mut pointer, ROUNDS)
+        });
+        let mut state = state.clone();
+        utilities::print_throughput("SmsMemoryState", "reads", reads, || {
+            read_everything(&mut state, ROUNDS)
+        });
+    }
+}
@@ -1,5 +1,7 @@
 //! The status of player input on the Sega Master System.
 
+use super::sms_vdp::{self, SmsVdpInternal};
+
 /// Bit flags for Joypad Port A.
 ///
 /// Note that a button press is indicated by a flag *not* being set.
@@ -28,6 +30,185 @@ pub mod joypad_b_bits {
     pub const JOYPAD2_LEFT: u8 = 0b00000001;
 }
 
+/// One of the two controller ports.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ControllerPort {
+    A,
+    B,
+}
+
+/// What's plugged into a controller port.
+///
+/// Each port has seven lines: up, down, left, right, TL, TR, and TH. How
+/// they're spread across the bits of IO ports 0xDC and 0xDD is described in
+/// `joypad_a_bits` and `joypad_b_bits`; what each device does with them is
+/// described with its input type.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum PortDevice {
+    /// An ordinary joypad, read from `SmsPlayerInput::joypad_a` and
+    /// `SmsPlayerInput::joypad_b`.
+    Joypad,
+
+    /// A light phaser, read from `SmsPlayerInput::light_phaser`.
+    LightPhaser,
+
+    /// A paddle, read from `SmsPlayerInput::paddle`.
+    Paddle,
+
+    /// Nothing at all. Every line reads high.
+    None,
+}
+
+impl Default for PortDevice {
+    #[inline]
+    fn default() -> Self {
+        PortDevice::Joypad
+    }
+}
+
+/// A light phaser.
+///
+/// The phaser's trigger is read on its port's TL line (for port A, the same
+/// bit as `joypad_a_bits::JOYPAD1_A`), and its light sensor drives its port's
+/// TH line (for port A, `joypad_b_bits::A_TH`).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct LightPhaserInput {
+    /// The screen column the phaser is aimed at.
+    pub x: u8,
+
+    /// The screen line the phaser is aimed at.
+    pub y: u8,
+
+    /// Is the trigger being held?
+    pub trigger: bool,
+}
+
+impl LightPhaserInput {
+    /// A phaser aimed at `(x, y)` in the displayed part of `vdp`'s screen.
+    ///
+    /// This is for a UI aiming with a mouse: on the Game Gear, where only the
+    /// center of the screen is displayed, `(0, 0)` is the top left of the
+    /// LCD rather than of the full screen. Points off the screen are clamped
+    /// to its edges.
+    pub fn in_window<V>(vdp: &V, x: i32, y: i32, trigger: bool) -> Self
+    where
+        V: SmsVdpInternal + ?Sized,
+    {
+        let (y_start, _, x_start, _) = sms_vdp::display_window(vdp);
+        let clamp = |n: i32| n.clamp(0, 0xFF) as u8;
+        LightPhaserInput {
+            x: clamp(x + x_start as i32),
+            y: clamp(y + y_start as i32),
+            trigger,
+        }
+    }
+}
+
+/// A paddle, like the HPD-200.
+///
+/// The paddle's button is read on its port's TL line. Its position is sent a
+/// nibble at a time on the up, down, left, and right lines: the low nibble
+/// while its TR line is low, and the high nibble while it's high. On an export
+/// console, which drives the paddle's TH line, TH selects the nibble the same
+/// way, and TR follows it.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PaddleInput {
+    /// How far the knob is turned, from 0 (fully left) to 255.
+    pub position: u8,
+
+    /// Is the button being held?
+    pub button: bool,
+}
+
+/// A key on the SC-3000's keyboard, by its position in the keyboard matrix.
+///
+/// The matrix has 7 rows of 12 columns. A row is selected by the low 3 bits
+/// of the PPI's port C (IO port 0xDE), and its columns are then read from bits
+/// 0 through 7 of IO port 0xDC and bits 0 through 3 of IO port 0xDD. Selecting
+/// row 7 reads the joypads instead.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Sc3000Key {
+    pub row: u8,
+    pub column: u8,
+}
+
+/// The keys of the SC-3000's keyboard.
+pub mod sc3000_keys {
+    use super::Sc3000Key;
+
+    const fn key(row: u8, column: u8) -> Sc3000Key {
+        Sc3000Key { row, column }
+    }
+
+    pub const DIGIT_1: Sc3000Key = key(0, 0);
+    pub const Q: Sc3000Key = key(0, 1);
+    pub const A: Sc3000Key = key(0, 2);
+    pub const Z: Sc3000Key = key(0, 3);
+    pub const ENG_DIERS: Sc3000Key = key(0, 4);
+    pub const COMMA: Sc3000Key = key(0, 5);
+    pub const K: Sc3000Key = key(0, 6);
+    pub const I: Sc3000Key = key(0, 7);
+    pub const DIGIT_8: Sc3000Key = key(0, 8);
+
+    pub const DIGIT_2: Sc3000Key = key(1, 0);
+    pub const W: Sc3000Key = key(1, 1);
+    pub const S: Sc3000Key = key(1, 2);
+    pub const X: Sc3000Key = key(1, 3);
+    pub const SPACE: Sc3000Key = key(1, 4);
+    pub const PERIOD: Sc3000Key = key(1, 5);
+    pub const L: Sc3000Key = key(1, 6);
+    pub const O: Sc3000Key = key(1, 7);
+    pub const DIGIT_9: Sc3000Key = key(1, 8);
+
+    pub const DIGIT_3: Sc3000Key = key(2, 0);
+    pub const E: Sc3000Key = key(2, 1);
+    pub const D: Sc3000Key = key(2, 2);
+    pub const C: Sc3000Key = key(2, 3);
+    pub const HOME_CLR: Sc3000Key = key(2, 4);
+    pub const SLASH: Sc3000Key = key(2, 5);
+    pub const SEMICOLON: Sc3000Key = key(2, 6);
+    pub const P: Sc3000Key = key(2, 7);
+    pub const DIGIT_0: Sc3000Key = key(2, 8);
+
+    pub const DIGIT_4: Sc3000Key = key(3, 0);
+    pub const R: Sc3000Key = key(3, 1);
+    pub const F: Sc3000Key = key(3, 2);
+    pub const V: Sc3000Key = key(3, 3);
+    pub const INS_DEL: Sc3000Key = key(3, 4);
+    pub const PI: Sc3000Key = key(3, 5);
+    pub const COLON: Sc3000Key = key(3, 6);
+    pub const AT: Sc3000Key = key(3, 7);
+    pub const MINUS: Sc3000Key = key(3, 8);
+
+    pub const DIGIT_5: Sc3000Key = key(4, 0);
+    pub const T: Sc3000Key = key(4, 1);
+    pub const G: Sc3000Key = key(4, 2);
+    pub const B: Sc3000Key = key(4, 3);
+    pub const DOWN: Sc3000Key = key(4, 5);
+    pub const RIGHT_BRACKET: Sc3000Key = key(4, 6);
+    pub const LEFT_BRACKET: Sc3000Key = key(4, 7);
+    pub const CARET: Sc3000Key = key(4, 8);
+
+    pub const DIGIT_6: Sc3000Key = key(5, 0);
+    pub const Y: Sc3000Key = key(5, 1);
+    pub const H: Sc3000Key = key(5, 2);
+    pub const N: Sc3000Key = key(5, 3);
+    pub const LEFT: Sc3000Key = key(5, 5);
+    pub const CR: Sc3000Key = key(5, 6);
+    pub const YEN: Sc3000Key = key(5, 8);
+    pub const FUNC: Sc3000Key = key(5, 11);
+
+    pub const DIGIT_7: Sc3000Key = key(6, 0);
+    pub const U: Sc3000Key = key(6, 1);
+    pub const J: Sc3000Key = key(6, 2);
+    pub const M: Sc3000Key = key(6, 3);
+    pub const RIGHT: Sc3000Key = key(6, 5);
+    pub const UP: Sc3000Key = key(6, 6);
+    pub const GRAPH: Sc3000Key = key(6, 9);
+    pub const CTRL: Sc3000Key = key(6, 10);
+    pub const SHIFT: Sc3000Key = key(6, 11);
+}
+
 /// What buttons are being pressed this frame?
 ///
 /// Since button presses are indicated by flags *not* being set,
@@ -43,6 +224,17 @@ pub struct SmsPlayerInput {
 
     /// The pause button.
     pause: bool,
+
+    /// Light phasers in ports A and B, used if the port has a
+    /// `PortDevice::LightPhaser`.
+    light_phaser: [LightPhaserInput; 2],
+
+    /// Paddles in ports A and B, used if the port has a `PortDevice::Paddle`.
+    paddle: [PaddleInput; 2],
+
+    /// The SC-3000's keyboard matrix, one row per element, with bit n for the
+    /// key in column n. As with the joypads, a pressed key's bit is *not* set.
+    keyboard: [u16; 7],
 }
 
 impl Default for SmsPlayerInput {
@@ -52,6 +244,9 @@ impl Default for SmsPlayerInput {
             joypad_a: 0xFF,
             joypad_b: 0xFF,
             pause: false,
+            light_phaser: Default::default(),
+            paddle: Default::default(),
+            keyboard: [0xFFFF; 7],
         }
     }
 }
@@ -86,4 +281,85 @@ impl SmsPlayerInput {
     pub fn set_pause(&mut self, x: bool) {
         self.pause = x
     }
+
+    #[inline]
+    pub fn light_phaser(&self, port: ControllerPort) -> LightPhaserInput {
+        self.light_phaser[port as usize]
+    }
+
+    #[inline]
+    pub fn set_light_phaser(&mut self, port: ControllerPort, x: LightPhaserInput) {
+        self.light_phaser[port as usize] = x
+    }
+
+    #[inline]
+    pub fn paddle(&self, port: ControllerPort) -> PaddleInput {
+        self.paddle[port as usize]
+    }
+
+    #[inline]
+    pub fn set_paddle(&mut self, port: ControllerPort, x: PaddleInput) {
+        self.paddle[port as usize] = x
+    }
+
+    /// Row `row` of the keyboard matrix, with a pressed key's bit not set.
+    ///
+    /// Panics if `row > 6`.
+    #[inline]
+    pub fn keyboard_row(&self, row: u8) -> u16 {
+        self.keyboard[row as usize]
+    }
+
+    #[inline]
+    pub fn key_pressed(&self, key: Sc3000Key) -> bool {
+        self.keyboard[key.row as usize] & 1 << key.column == 0
+    }
+
+    #[inline]
+    pub fn set_key_pressed(&mut self, key: Sc3000Key, pressed: bool) {
+        let bit = 1 << key.column;
+        if pressed {
+            self.keyboard[key.row as usize] &= !bit;
+        } else {
+            self.keyboard[key.row as usize] |= bit;
+        }
+    }
+}
+
+/// `SmsPlayerInput` as it was laid out in version 1 of saved states,
+/// `Recording`s and movies, before the SC-3000 keyboard.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SmsPlayerInputV1 {
+    joypad_a: u8,
+    joypad_b: u8,
+    pause: bool,
+    light_phaser: [LightPhaserInput; 2],
+    paddle: [PaddleInput; 2],
+}
+
+impl From<SmsPlayerInputV1> for SmsPlayerInput {
+    /// No keys are pressed on the keyboard.
+    fn from(x: SmsPlayerInputV1) -> Self {
+        SmsPlayerInput {
+            joypad_a: x.joypad_a,
+            joypad_b: x.joypad_b,
+            pause: x.pause,
+            light_phaser: x.light_phaser,
+            paddle: x.paddle,
+            keyboard: [0xFFFF; 7],
+        }
+    }
+}
+
+impl From<SmsPlayerInput> for SmsPlayerInputV1 {
+    /// The keyboard is dropped.
+    fn from(x: SmsPlayerInput) -> Self {
+        SmsPlayerInputV1 {
+            joypad_a: x.joypad_a,
+            joypad_b: x.joypad_b,
+            pause: x.pause,
+            light_phaser: x.light_phaser,
+            paddle: x.paddle,
+        }
+    }
 }
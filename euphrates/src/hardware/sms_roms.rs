@@ -1,10 +1,16 @@
 //! Functions for dealing with Sega Master System ROM images.
 
+use std::fmt;
 use std::fs::File;
 use std::io::Error as IoError;
 use std::io::Read;
 use std::path::Path;
 
+use flate2::Crc;
+use sha1_smol::Sha1;
+
+use hardware::sms_memory::SmsMemoryMapper;
+use hardware::sms_vdp::TvSystem;
 
 // This superfluous module with the `allow` attribute is necessary until the
 // `fail` crate begins using `dyn trait` syntax
@@ -33,11 +39,43 @@ impl From<IoError> for SmsRomError {
     }
 }
 
+/// Does this ROM image start with the 512 byte header some old copiers
+/// prepended to their dumps?
+///
+/// There's nothing useful in the header, so we just go by the length.
+pub fn has_copier_header(rom: &[u8]) -> bool {
+    rom.len() > 0x200 && rom.len() % 0x2000 == 0x200
+}
+
+/// Pad a ROM whose number of 16 KiB pages isn't a power of two up to the next
+/// power of two, so that selecting a page modulo the number of pages is
+/// well defined.
+///
+/// The extra pages mirror the ones half the padded size below them, so that
+/// in a 48 KiB ROM, page 3 is a copy of page 1. ROMs whose length isn't a
+/// multiple of 0x4000, or whose number of pages is already a power of two,
+/// are returned unchanged.
+pub fn mirror_to_power_of_two(rom: Box<[u8]>) -> Box<[u8]> {
+    let pages = rom.len() / 0x4000;
+    if rom.len() % 0x4000 != 0 || pages == 0 || pages.is_power_of_two() {
+        return rom;
+    }
+    let half = pages.next_power_of_two() / 2;
+    let mut padded = rom.into_vec();
+    for page in pages..2 * half {
+        let start = (page - half) * 0x4000;
+        padded.extend_from_within(start..start + 0x4000);
+    }
+    padded.into_boxed_slice()
+}
+
 /// If the ROM does not have the right length, fix that. Our ROMs will always
 /// have a length a multiple of 0x4000.
 ///
 /// This function:
 /// * strips off a 512 byte header, if present
+/// * mirrors ROMs up to a power of two number of pages, as in
+///   `mirror_to_power_of_two`
 /// * does ROM mirroring of `0x2000` byte slices (it just puts two copies of the
 ///   ROM image into a `[u8; 0x4000]`).
 /// * pads ROMs shorter than `0x2000` bytes
@@ -52,11 +90,10 @@ pub fn format(rom: Box<[u8]>) -> Result<Box<[u8]>, SmsRomError> {
     }
 
     if len % 0x4000 == 0 {
-        return Ok(rom);
+        return Ok(mirror_to_power_of_two(rom));
     }
 
-    if len % 0x2000 == 0x200 {
-        // it's got a 512 byte header we need to remove
+    if has_copier_header(&rom) {
         return format(rom[0x200..].to_vec().into_boxed_slice());
     }
 
@@ -94,3 +131,389 @@ where
     drop(file);
     format(buf.into_boxed_slice())
 }
+
+/// Does this ROM have a Codemasters header?
+///
+/// Codemasters ROMs have a header at 0x7FE0 with the number of 16 KiB banks,
+/// a date, a checksum at 0x7FE6, and at 0x7FE8 the checksum's complement
+/// (0x10000 minus the checksum). The checksum and complement summing to
+/// 0x10000 is what we look for.
+fn has_codemasters_header(rom: &[u8]) -> bool {
+    if rom.len() < 0x8000 {
+        return false;
+    }
+    let checksum = rom[0x7FE6] as u32 | (rom[0x7FE7] as u32) << 8;
+    let complement = rom[0x7FE8] as u32 | (rom[0x7FE9] as u32) << 8;
+    checksum + complement == 0x10000
+}
+
+/// Guess which memory mapper a ROM was made for.
+///
+/// ROMs in `GAME_DATABASE` get the mapper listed there. Otherwise, ROMs with
+/// a Codemasters header get `SmsMemoryMapper::Codemasters`. Everything else
+/// gets `SmsMemoryMapper::Sega`: most of those will have the `TMR SEGA`
+/// signature at 0x7FF0, but even those that don't are more likely to be meant
+/// for the Sega mapper than anything else.
+pub fn detect_mapper(rom: &[u8]) -> SmsMemoryMapper {
+    detect_mapper_in(GAME_DATABASE, rom)
+}
+
+/// Like `detect_mapper`, but looking in `database` instead of
+/// `GAME_DATABASE`.
+pub fn detect_mapper_in(database: &[GameInfo], rom: &[u8]) -> SmsMemoryMapper {
+    if let Some(info) = find_game(database, &rom_fingerprint(rom)) {
+        info.mapper
+    } else if has_codemasters_header(rom) {
+        SmsMemoryMapper::Codemasters
+    } else {
+        SmsMemoryMapper::Sega
+    }
+}
+
+/// Where a `TMR SEGA` header can be, in the order `header` prefers them.
+///
+/// The BIOS looks at 0x7FF0, but ROMs smaller than 32 KiB put their header at
+/// the end of the ROM instead, and some ROMs have one in more than one place.
+pub const HEADER_OFFSETS: [usize; 3] = [0x7FF0, 0x3FF0, 0x1FF0];
+
+/// The `TMR SEGA` header of a ROM.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct RomHeader {
+    /// Where the header was found; one of `HEADER_OFFSETS`.
+    pub offset: usize,
+
+    pub checksum: u16,
+
+    /// The product code, decoded from its 2.5 BCD bytes.
+    pub product_code: u32,
+
+    pub version: u8,
+
+    /// The region code: 3 is a Japanese Master System, 4 an export Master
+    /// System, and 5 through 7 Game Gears.
+    pub region: u8,
+
+    /// The size code, saying how much of the ROM the checksum covers.
+    pub size: u8,
+}
+
+impl RomHeader {
+    /// The header at `offset`, if `rom` has the `TMR SEGA` signature there.
+    pub fn at(rom: &[u8], offset: usize) -> Option<RomHeader> {
+        if rom.len() < offset + 0x10 || &rom[offset..offset + 8] != b"TMR SEGA" {
+            return None;
+        }
+        let h = &rom[offset..offset + 0x10];
+        fn bcd(x: u8) -> u32 {
+            (x >> 4) as u32 * 10 + (x & 0xF) as u32
+        }
+        Some(RomHeader {
+            offset,
+            checksum: h[0xA] as u16 | (h[0xB] as u16) << 8,
+            product_code: bcd(h[0xC]) + 100 * bcd(h[0xD]) + 10000 * (h[0xE] >> 4) as u32,
+            version: h[0xE] & 0xF,
+            region: h[0xF] >> 4,
+            size: h[0xF] & 0xF,
+        })
+    }
+}
+
+fn iter_headers<'a>(rom: &'a [u8]) -> impl Iterator<Item = RomHeader> + 'a {
+    HEADER_OFFSETS
+        .iter()
+        .filter_map(move |&offset| RomHeader::at(rom, offset))
+}
+
+/// Every `TMR SEGA` header in `rom`, in the order of `HEADER_OFFSETS`.
+pub fn headers(rom: &[u8]) -> Vec<RomHeader> {
+    iter_headers(rom).collect()
+}
+
+/// The header the BIOS would use: the first in `HEADER_OFFSETS` that `rom`
+/// has.
+pub fn header(rom: &[u8]) -> Option<RomHeader> {
+    iter_headers(rom).next()
+}
+
+/// Hashes identifying a ROM image.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct RomFingerprint {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+pub fn rom_fingerprint(rom: &[u8]) -> RomFingerprint {
+    RomFingerprint {
+        crc32: crc32(rom),
+        sha1: Sha1::from(rom).digest().bytes(),
+    }
+}
+
+/// What we know about a particular game's ROM image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct GameInfo {
+    pub title: &'static str,
+
+    /// Identifies the ROM; both hashes must match.
+    pub fingerprint: RomFingerprint,
+
+    /// The mapper the game needs.
+    pub mapper: SmsMemoryMapper,
+
+    /// The TV system of the region the game was released in.
+    pub tv_system: TvSystem,
+}
+
+/// Games whose ROMs we recognize, for `lookup_game` and `detect_mapper`.
+///
+/// Fingerprints are of the ROM as it's loaded, after `format`. Only games
+/// that `detect_mapper` would otherwise get wrong really need to be here.
+pub static GAME_DATABASE: &[GameInfo] = &[];
+
+/// Find the game with this fingerprint in `database`.
+pub fn find_game<'a>(
+    database: &'a [GameInfo],
+    fingerprint: &RomFingerprint,
+) -> Option<&'a GameInfo> {
+    database
+        .iter()
+        .find(|info| info.fingerprint == *fingerprint)
+}
+
+/// Find `rom` in `GAME_DATABASE`.
+pub fn lookup_game(rom: &[u8]) -> Option<&'static GameInfo> {
+    find_game(GAME_DATABASE, &rom_fingerprint(rom))
+}
+
+/// The CRC32 of `rom`, as used by ROM databases and movies to identify it.
+pub fn crc32(rom: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(rom);
+    crc.sum()
+}
+
+/// The checksum in a ROM's `TMR SEGA` header doesn't match the ROM's contents.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ChecksumMismatch {
+    /// The checksum stored in the header.
+    pub expected: u16,
+
+    /// The checksum of the ROM's contents.
+    pub found: u16,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ROM checksum mismatch: header has 0x{:0>4X}, but the ROM sums to 0x{:0>4X}",
+            self.expected, self.found
+        )
+    }
+}
+
+/// Compare the checksum in the ROM's header to its contents, as the export
+/// SMS BIOS does.
+///
+/// The header is at 0x7FF0 and begins with `TMR SEGA`. The checksum is the
+/// 16 bit sum of the ROM's bytes, stored little endian at 0x7FFA, and the low
+/// nibble of 0x7FFF says how many bytes it covers. The header itself is never
+/// included.
+///
+/// Returns `None` if the checksum is right, and also if there is no header or
+/// it claims to cover more than the whole ROM, since there's nothing to check.
+pub fn verify_checksum(rom: &[u8]) -> Option<ChecksumMismatch> {
+    if rom.len() < 0x8000 || &rom[0x7FF0..0x7FF8] != b"TMR SEGA" {
+        return None;
+    }
+
+    let len = match rom[0x7FFF] & 0xF {
+        0xA => 0x1FF0,
+        0xB => 0x3FF0,
+        0xC => 0x7FF0,
+        0xD => 0xC000,
+        0xE => 0x10000,
+        0xF => 0x20000,
+        0x0 => 0x40000,
+        0x1 => 0x80000,
+        0x2 => 0x100000,
+        _ => return None,
+    };
+    if len > rom.len() {
+        return None;
+    }
+
+    let found = rom[..len]
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| !(0x7FF0..0x8000).contains(&i))
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+    let expected = rom[0x7FFA] as u16 | (rom[0x7FFB] as u16) << 8;
+
+    if found == expected {
+        None
+    } else {
+        Some(ChecksumMismatch { expected, found })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn fingerprint() {
+        let fingerprint = rom_fingerprint(b"abc");
+        assert_eq!(fingerprint.crc32, 0x352441C2);
+        assert_eq!(
+            fingerprint.sha1,
+            [
+                0xA9, 0x99, 0x3E, 0x36, 0x47, 0x06, 0x81, 0x6A, 0xBA, 0x3E, 0x25, 0x71, 0x78, 0x50,
+                0xC2, 0x6C, 0x9C, 0xD0, 0xD8, 0x9D,
+            ]
+        );
+
+        // a ROM with a Codemasters header that's really for the Korean mapper
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x7FE6] = 0x01;
+        rom[0x7FE9] = 0xFF;
+        rom[0x7FE8] = 0xFF;
+        assert_eq!(detect_mapper(&rom), SmsMemoryMapper::Codemasters);
+        let database = [GameInfo {
+            title: "Test Game",
+            fingerprint: rom_fingerprint(&rom),
+            mapper: SmsMemoryMapper::Korean,
+            tv_system: TvSystem::Pal,
+        }];
+        let info = find_game(&database, &rom_fingerprint(&rom)).unwrap();
+        assert_eq!(info.title, "Test Game");
+        assert_eq!(detect_mapper_in(&database, &rom), SmsMemoryMapper::Korean);
+
+        rom[0] = 1;
+        assert_eq!(find_game(&database, &rom_fingerprint(&rom)), None);
+        assert_eq!(
+            detect_mapper_in(&database, &rom),
+            SmsMemoryMapper::Codemasters
+        );
+    }
+
+    #[test]
+    fn power_of_two() {
+        // 48 KiB, with each byte the number of its page
+        let rom: Vec<u8> = (0..0xC000).map(|i| (i / 0x4000) as u8).collect();
+        let padded = format(rom.into_boxed_slice()).unwrap();
+        assert_eq!(padded.len(), 0x10000);
+        assert!(padded[0xC000..].iter().all(|&x| x == 1));
+        assert!(padded[0x8000..0xC000].iter().all(|&x| x == 2));
+
+        // 5 pages mirror up to 8
+        let rom: Vec<u8> = (0..0x14000).map(|i| (i / 0x4000) as u8).collect();
+        let padded = mirror_to_power_of_two(rom.into_boxed_slice());
+        let pages: Vec<u8> = padded.chunks(0x4000).map(|page| page[0]).collect();
+        assert_eq!(pages, vec![0, 1, 2, 3, 4, 1, 2, 3]);
+
+        let rom = vec![0u8; 0x20000].into_boxed_slice();
+        assert_eq!(mirror_to_power_of_two(rom.clone()), rom);
+    }
+
+    #[test]
+    fn detect() {
+        let mut rom = vec![0u8; 0x20000];
+        assert_eq!(detect_mapper(&rom), SmsMemoryMapper::Sega);
+
+        rom[0x7FF0..0x7FF8].copy_from_slice(b"TMR SEGA");
+        assert_eq!(detect_mapper(&rom), SmsMemoryMapper::Sega);
+
+        // checksum 0x1234, complement 0xEDCC
+        rom[0x7FE0] = 8;
+        rom[0x7FE6..0x7FEA].copy_from_slice(&[0x34, 0x12, 0xCC, 0xED]);
+        assert_eq!(detect_mapper(&rom), SmsMemoryMapper::Codemasters);
+
+        // a bad complement
+        rom[0x7FE8] = 0xCD;
+        assert_eq!(detect_mapper(&rom), SmsMemoryMapper::Sega);
+
+        // too small to have a header at all
+        assert_eq!(detect_mapper(&rom[..0x4000]), SmsMemoryMapper::Sega);
+    }
+
+    #[test]
+    fn header_offsets() {
+        let mut rom = vec![0u8; 0x4000];
+        assert_eq!(header(&rom), None);
+
+        // a 16 KiB ROM has its header at 0x3FF0
+        rom[0x3FF0..0x4000].copy_from_slice(&[
+            b'T', b'M', b'R', b' ', b'S', b'E', b'G', b'A', 0, 0, 0x34, 0x12, 0x26, 0x70, 0x52,
+            0x4A,
+        ]);
+        let h = header(&rom).unwrap();
+        assert_eq!(h.offset, 0x3FF0);
+        assert_eq!(h.checksum, 0x1234);
+        assert_eq!(h.product_code, 57026);
+        assert_eq!(h.version, 2);
+        assert_eq!(h.region, 4);
+        assert_eq!(h.size, 0xA);
+
+        // with another at 0x1FF0, both are reported, but 0x3FF0 is preferred
+        rom[0x1FF0..0x1FF8].copy_from_slice(b"TMR SEGA");
+        let all = headers(&rom);
+        assert_eq!(
+            all.iter().map(|h| h.offset).collect::<Vec<_>>(),
+            vec![0x3FF0, 0x1FF0]
+        );
+        assert_eq!(header(&rom).unwrap().offset, 0x3FF0);
+
+        // and 0x7FF0 beats both
+        let mut rom = rom.clone();
+        rom.resize(0x8000, 0);
+        rom[0x7FF0..0x7FF8].copy_from_slice(b"TMR SEGA");
+        assert_eq!(headers(&rom).len(), 3);
+        assert_eq!(header(&rom).unwrap().offset, 0x7FF0);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut rom = vec![0u8; 0x10000];
+        assert_eq!(verify_checksum(&rom), None);
+
+        rom[0x7FF0..0x7FF8].copy_from_slice(b"TMR SEGA");
+        rom[0x7FFF] = 0x4E; // 64 KiB
+        rom[0x0000] = 0x12;
+        rom[0x7FEF] = 0x34;
+        rom[0xFFFF] = 0xF0;
+        // the header isn't part of the sum
+        rom[0x7FF8] = 0x55;
+        rom[0x7FFA..0x7FFC].copy_from_slice(&[0x36, 0x01]);
+        assert_eq!(verify_checksum(&rom), None);
+
+        rom[0x7FFA] = 0x37;
+        assert_eq!(
+            verify_checksum(&rom),
+            Some(ChecksumMismatch {
+                expected: 0x0137,
+                found: 0x0136,
+            })
+        );
+
+        // only the first 32 KiB are covered now
+        rom[0x7FFF] = 0x4C;
+        assert_eq!(
+            verify_checksum(&rom),
+            Some(ChecksumMismatch {
+                expected: 0x0137,
+                found: 0x0046,
+            })
+        );
+
+        // claims to be 128 KiB, bigger than the ROM
+        rom[0x7FFF] = 0x4F;
+        assert_eq!(verify_checksum(&rom), None);
+    }
+}
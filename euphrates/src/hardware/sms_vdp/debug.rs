@@ -26,9 +26,13 @@ where
     let name_table = ((v.register(2) & 0xF) as u16) << 10;
     let color_table = (v.register(3) as u16) << 6;
 
-    graphics
-        .set_resolution(256, 192)
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
+    graphics.set_resolution(256, 192).map_err(|error| {
+        SmsVdpGraphicsError::ResolutionUnsupported {
+            width: 256,
+            height: 192,
+            error,
+        }
+    })?;
 
     for tile_x in 0..32 {
         for tile_y in 0..24 {
@@ -50,9 +54,7 @@ where
         }
     }
 
-    graphics
-        .render()
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))
+    graphics.render().map_err(SmsVdpGraphicsError::RenderFailed)
 }
 
 fn draw_tiles_graphics2<V, G>(v: &V, graphics: &mut G) -> Result<(), SmsVdpGraphicsError>
@@ -64,9 +66,13 @@ where
     let name_table = ((v.register(2) & 0xF) as u16) << 10;
     let color_table = ((v.register(3) & 0x80) as u16) << 6;
 
-    graphics
-        .set_resolution(256, 192)
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
+    graphics.set_resolution(256, 192).map_err(|error| {
+        SmsVdpGraphicsError::ResolutionUnsupported {
+            width: 256,
+            height: 192,
+            error,
+        }
+    })?;
 
     for tile_y in 0..24 {
         let third = tile_y / 8;
@@ -91,9 +97,7 @@ where
         }
     }
 
-    graphics
-        .render()
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))
+    graphics.render().map_err(SmsVdpGraphicsError::RenderFailed)
 }
 
 fn draw_tiles_mode4<V, G>(v: &V, graphics: &mut G) -> Result<(), SmsVdpGraphicsError>
@@ -109,7 +113,11 @@ where
     let height = 8 * vert_tile_count;
     graphics
         .set_resolution(256, height as u32)
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
+        .map_err(|error| SmsVdpGraphicsError::ResolutionUnsupported {
+            width: 256,
+            height: height as u32,
+            error,
+        })?;
     for tile_x in 0..32 {
         for tile_y in 0..vert_tile_count {
             let current_tile_address = v.name_table_address() + 2 * (32 * tile_y + tile_x);
@@ -188,7 +196,5 @@ where
         }
     }
 
-    graphics
-        .render()
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))
+    graphics.render().map_err(SmsVdpGraphicsError::RenderFailed)
 }
@@ -1,6 +1,8 @@
+use std::path::Path;
+
 use failure::Error;
 
-use host_multimedia::{SimpleColor, SimpleGraphics};
+use host_multimedia::{self, SimpleColor, SimpleGraphics};
 use utilities;
 
 use super::*;
@@ -13,8 +15,42 @@ mod sms_vdp_graphics_error {
 
     #[derive(Debug, Fail)]
     pub enum SmsVdpGraphicsError {
-        #[fail(display = "graphics error {}", _0)]
-        Graphics(Error),
+        /// The `SimpleGraphics` failed to take the resolution the VDP draws
+        /// at.
+        #[fail(display = "resolution {}x{} unsupported: {}", width, height, error)]
+        ResolutionUnsupported {
+            width: u32,
+            height: u32,
+            error: Error,
+        },
+
+        /// The VDP would paint the pixel `(x, y)`, outside the `SimpleGraphics`'
+        /// resolution of `width` by `height`.
+        #[fail(
+            display = "painting ({}, {}) is out of bounds of {}x{} graphics",
+            x, y, width, height
+        )]
+        PaintOutOfBounds {
+            x: u32,
+            y: u32,
+            width: u32,
+            height: u32,
+        },
+
+        /// The `SimpleGraphics` failed to render a finished frame.
+        #[fail(display = "rendering failed: {}", _0)]
+        RenderFailed(Error),
+
+        /// Saving a screenshot with `SmsVdpGraphics::save_png` failed.
+        #[fail(display = "screenshot failed: {}", _0)]
+        ScreenshotFailed(Error),
+
+        /// The mode bits `m1` through `m4` don't select any mode we can draw.
+        #[fail(
+            display = "invalid graphics mode bits {}, {}, {}, {}",
+            m1, m2, m3, m4
+        )]
+        InvalidMode { m1: bool, m2: bool, m3: bool, m4: bool },
     }
 }
 
@@ -24,9 +60,118 @@ pub trait SmsVdpGraphics {
     fn draw_line(&mut self) -> Result<(), SmsVdpGraphicsError>;
 }
 
+/// Graphics the VDP draws frames to, which can look back at the frame drawn so
+/// far.
+pub trait SmsVdpFrame {
+    /// A fingerprint of the frame drawn so far. See
+    /// `host_multimedia::frame_hash`.
+    fn frame_hash(&self) -> u64;
+
+    /// Save the frame drawn so far as a PNG image at `path`. See
+    /// `host_multimedia::save_png`.
+    fn save_png(&self, path: &Path) -> Result<(), SmsVdpGraphicsError>;
+}
+
+impl<G> SmsVdpFrame for G
+where
+    G: SimpleGraphics,
+{
+    fn frame_hash(&self) -> u64 {
+        host_multimedia::frame_hash(self)
+    }
+
+    fn save_png(&self, path: &Path) -> Result<(), SmsVdpGraphicsError> {
+        host_multimedia::save_png(self, path).map_err(SmsVdpGraphicsError::ScreenshotFailed)
+    }
+}
+
+/// Set the resolution of `graphics`, and make sure the line `y`, `width`
+/// pixels wide, fits in it.
+///
+/// `SimpleGraphics::paint` can't fail, so this is how drawing out of bounds
+/// is caught.
+pub fn set_line_resolution<G>(
+    graphics: &mut G,
+    width: u32,
+    height: u32,
+    y: u32,
+) -> Result<(), SmsVdpGraphicsError>
+where
+    G: SimpleGraphics + ?Sized,
+{
+    graphics.set_resolution(width, height).map_err(|error| {
+        SmsVdpGraphicsError::ResolutionUnsupported {
+            width,
+            height,
+            error,
+        }
+    })?;
+    let (actual_width, actual_height) = graphics.resolution();
+    if y >= actual_height || width > actual_width {
+        return Err(SmsVdpGraphicsError::PaintOutOfBounds {
+            x: if y >= actual_height { 0 } else { actual_width },
+            y,
+            width: actual_width,
+            height: actual_height,
+        });
+    }
+    Ok(())
+}
+
 pub struct SmsVdpGraphicsImpler<'a, V: 'a, G: 'a> {
     pub graphics: &'a mut G,
     pub vdp: &'a mut V,
+
+    /// Draw a border in the backdrop color around the active display, as a TV
+    /// shows it, rather than just the active display? See
+    /// `draw_line_overscan`.
+    ///
+    /// The Game Gear's LCD has no border, so there this does nothing.
+    pub overscan: bool,
+}
+
+/// How many pixels wide the border is on either side of the active display
+/// with `overscan`.
+///
+/// A TV shows a bit more than this, but how much varies from set to set.
+pub const OVERSCAN_WIDTH: u32 = 16;
+
+/// How many lines tall the border is above and below the active display with
+/// `overscan`.
+pub const OVERSCAN_HEIGHT: u32 = 16;
+
+/// A `SimpleGraphics` within the border of another, so the functions drawing
+/// the active display can draw as if there were no border.
+struct Overscan<'a, G: 'a + ?Sized>(&'a mut G);
+
+impl<'a, G: 'a + ?Sized> SimpleGraphics for Overscan<'a, G>
+where
+    G: SimpleGraphics,
+{
+    fn set_resolution(&mut self, width: u32, height: u32) -> Result<(), Error> {
+        self.0
+            .set_resolution(width + 2 * OVERSCAN_WIDTH, height + 2 * OVERSCAN_HEIGHT)
+    }
+
+    fn resolution(&self) -> (u32, u32) {
+        let (width, height) = self.0.resolution();
+        (
+            width.saturating_sub(2 * OVERSCAN_WIDTH),
+            height.saturating_sub(2 * OVERSCAN_HEIGHT),
+        )
+    }
+
+    fn paint(&mut self, x: u32, y: u32, color: SimpleColor) {
+        self.0.paint(x + OVERSCAN_WIDTH, y + OVERSCAN_HEIGHT, color)
+    }
+
+    fn get(&self, x: u32, y: u32) -> SimpleColor {
+        self.0.get(x + OVERSCAN_WIDTH, y + OVERSCAN_HEIGHT)
+    }
+
+    fn render(&mut self) -> Result<(), Error> {
+        self.0.render()
+    }
 }
 
 #[inline]
@@ -49,28 +194,134 @@ pub fn gg_color_to_simple_color(color: u16) -> SimpleColor {
     }
 }
 
+/// Convert a CRAM entry to a `SimpleColor`.
+///
+/// Game Gear CRAM entries are 12 bits (4 bits each for red, green, and blue),
+/// while Master System entries are 6 bits (2 bits each).
+#[inline]
+pub fn cram_color_to_simple_color(kind: Kind, color: u16) -> SimpleColor {
+    if kind == Kind::Gg {
+        gg_color_to_simple_color(color)
+    } else {
+        vdp_color_to_simple_color(color as u8)
+    }
+}
+
+/// The part of the VDP's output actually visible on screen, as
+/// `(y_start, y_end, x_start, x_end)`.
+///
+/// The Game Gear's LCD shows only the 160x144 pixels in the center of the
+/// Master System's 256x192 display.
+pub fn display_window<V>(vdp: &V) -> (u16, u16, usize, usize)
+where
+    V: SmsVdpInternal + ?Sized,
+{
+    if vdp.kind() == Kind::Gg {
+        (24, 168, 48, 208)
+    } else {
+        (0, vdp.active_lines(), 0, 256)
+    }
+}
+
 impl<'a, V: 'a, G: 'a> SmsVdpGraphics for SmsVdpGraphicsImpler<'a, V, G>
 where
     V: SmsVdpInternal,
     G: SimpleGraphics,
 {
     fn draw_line(&mut self) -> Result<(), SmsVdpGraphicsError> {
-        match (self.vdp.m1(), self.vdp.m2(), self.vdp.m3(), self.vdp.m4()) {
-            (_, _, _, true) => draw_line_mode4(self),
-            (false, false, false, _) => draw_line_graphics1(self),
-            (false, true, false, _) => draw_line_graphics2(self),
-            _ => {
-                eprintln!(
-                    "Invalid or unimplemented graphics mode {}, {}, {}, {}",
-                    self.vdp.m1(),
-                    self.vdp.m2(),
-                    self.vdp.m3(),
-                    self.vdp.m4()
-                );
-                Ok(())
+        if self.overscan && self.vdp.kind() != Kind::Gg {
+            draw_line_overscan(self)
+        } else {
+            draw_line_active(self)
+        }
+    }
+}
+
+/// Draw the part of the line in the active display, in whatever mode the VDP
+/// is in.
+pub fn draw_line_active<'a, V, G>(
+    s: &mut SmsVdpGraphicsImpler<'a, V, G>,
+) -> Result<(), SmsVdpGraphicsError>
+where
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
+{
+    match s.vdp.mode() {
+        Mode::Mode4 => draw_line_mode4(s),
+        Mode::Graphics1 => draw_line_graphics1(s),
+        Mode::Graphics2 => draw_line_graphics2(s),
+        Mode::Multicolor => draw_line_multicolor(s),
+        Mode::Text => draw_line_text(s),
+        Mode::Invalid => Err(SmsVdpGraphicsError::InvalidMode {
+            m1: s.vdp.m1(),
+            m2: s.vdp.m2(),
+            m3: s.vdp.m3(),
+            m4: s.vdp.m4(),
+        }),
+    }
+}
+
+/// The color of the backdrop: the border, and whatever shows through
+/// transparent pixels.
+pub fn backdrop_color<V>(vdp: &V) -> SimpleColor
+where
+    V: SmsVdpInternal,
+{
+    let index = vdp.backdrop_color_index();
+    if vdp.mode() == Mode::Mode4 {
+        cram_color_to_simple_color(vdp.kind(), vdp.cram(16 + index as u16))
+    } else {
+        TMS9918_PALETTE[index as usize]
+    }
+}
+
+/// Draw the line with a border of `OVERSCAN_WIDTH` pixels on either side and
+/// `OVERSCAN_HEIGHT` lines above and below the active display.
+///
+/// The lines below are the ones right after the active display, and the
+/// lines above are the last ones of the frame, so the frame is rendered with
+/// the border as it will be at the top of the next one.
+pub fn draw_line_overscan<'a, V, G>(
+    s: &mut SmsVdpGraphicsImpler<'a, V, G>,
+) -> Result<(), SmsVdpGraphicsError>
+where
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
+{
+    let v = s.vdp.v() as u32;
+    let active_lines = s.vdp.active_lines() as u32;
+    let total_lines = s.vdp.total_lines() as u32;
+    let width = 256 + 2 * OVERSCAN_WIDTH;
+    let height = active_lines + 2 * OVERSCAN_HEIGHT;
+
+    let y = if v < active_lines + OVERSCAN_HEIGHT {
+        Some(v + OVERSCAN_HEIGHT)
+    } else if v + OVERSCAN_HEIGHT >= total_lines {
+        Some(v + OVERSCAN_HEIGHT - total_lines)
+    } else {
+        None
+    };
+
+    if let Some(y) = y {
+        set_line_resolution(s.graphics, width, height, y)?;
+        let color = backdrop_color(s.vdp);
+        if v < active_lines {
+            for x in 0..OVERSCAN_WIDTH {
+                s.graphics.paint(x, y, color);
+                s.graphics.paint(width - 1 - x, y, color);
+            }
+        } else {
+            for x in 0..width {
+                s.graphics.paint(x, y, color);
             }
         }
     }
+
+    draw_line_active(&mut SmsVdpGraphicsImpler {
+        graphics: &mut Overscan(s.graphics),
+        vdp: s.vdp,
+        overscan: false,
+    })
 }
 
 /// The actual palette of the TMS9918.
@@ -275,36 +526,39 @@ pub static TMS9918_PALETTE_SMS: [SimpleColor; 16] = [
     },
 ];
 
-pub fn draw_sprites_tms<'a, V: 'a, G: 'a>(
-    s: &mut SmsVdpGraphicsImpler<'a, V, G>,
-) -> Result<(), SmsVdpGraphicsError>
+/// Evaluate the TMS9918 sprites on line `v`, setting the sprite overflow and
+/// collision flags and the fifth sprite number, and giving the color of each
+/// pixel a sprite covers.
+///
+/// This is the part of drawing sprites that changes the VDP's state, so it
+/// runs even when nothing is painted.
+pub fn evaluate_sprites_tms<V>(vdp: &mut V, v: u16) -> [Option<SimpleColor>; 256]
 where
-    V: SmsVdpInternal,
-    G: SimpleGraphics,
+    V: SmsVdpInternal + ?Sized,
 {
-    let sprites_large = s.vdp.register(1) & 2 != 0;
-    let sprites_zoom = s.vdp.register(1) & 1 != 0;
-    let sprite_size = match (sprites_large, sprites_zoom) {
-        (true, true) => 32,
-        (false, false) => 8,
-        _ => 16,
-    };
+    let sprites_large = vdp.tall_sprites();
+    let sprites_zoom = vdp.zoomed_sprites();
+    let sprite_size = vdp.tms_sprite_size();
 
-    let v = s.vdp.v();
-
-    let sprite_pattern_table = ((s.vdp.register(6) & 0x7) as u16) << 11;
-    let sprite_attribute_table = ((s.vdp.register(5) & 0x7F) as u16) << 7;
+    let sprite_pattern_table = vdp.tms_sprite_pattern_table_address();
+    let sprite_attribute_table = vdp.tms_sprite_attribute_table_address();
 
     let mut sprites_on_line = 0;
 
-    let mut line = [false; 256];
+    let mut line: [Option<SimpleColor>; 256] = [None; 256];
+
+    // The fifth sprite number is latched along with the overflow flag, until
+    // the status register is read. Until then, later lines leave it alone.
+    let overflowed = vdp.status_flags() & SPRITE_OVERFLOW_FLAG != 0;
+    let mut last_sprite = 31;
 
     for i in 0..32 {
-        let y = s.vdp.vram(sprite_attribute_table + 4 * i).wrapping_add(1) as u16;
+        let y = vdp.vram(sprite_attribute_table + 4 * i).wrapping_add(1) as u16;
         if y == 0xD1 {
+            last_sprite = i;
             break;
         }
-        let x = s.vdp.vram(sprite_attribute_table + 4 * i + 1) as u16;
+        let x = vdp.vram(sprite_attribute_table + 4 * i + 1) as u16;
         let sprite_line = v.wrapping_sub(y);
         if sprite_line >= sprite_size {
             continue;
@@ -312,11 +566,12 @@ where
 
         sprites_on_line += 1;
         if sprites_on_line > 4 {
-            let mut status = s.vdp.status_flags() & 0xE0;
-            status |= i as u8;
-            status |= SPRITE_OVERFLOW_FLAG;
-            s.vdp.set_status_flags(status);
-            return Ok(());
+            // only four sprites are drawn on a line
+            if !overflowed {
+                vdp.trigger_sprite_overflow();
+                vdp.set_sprite_number(i as u8);
+            }
+            return line;
         }
 
         let sprite_y = if sprites_zoom {
@@ -324,55 +579,42 @@ where
         } else {
             sprite_line
         };
-        let name = s.vdp.vram(sprite_attribute_table + 4 * i + 2) as u16
+        let name = vdp.vram(sprite_attribute_table + 4 * i + 2) as u16
             & if sprites_large { 0xFC } else { 0xFF };
 
-        let last_byte = s.vdp.vram(sprite_attribute_table + 4 * i + 3);
+        let last_byte = vdp.vram(sprite_attribute_table + 4 * i + 3);
         let early_clock = last_byte & 0x80 != 0;
         let color = last_byte & 0xF;
         let color1 = TMS9918_PALETTE[color as usize];
 
         let line_pattern_index = sprite_pattern_table + name * 8 + sprite_y;
 
-        let pattern = s.vdp.vram(line_pattern_index);
+        let pattern = vdp.vram(line_pattern_index);
         let pattern2 = if sprites_large {
-            Some(s.vdp.vram(line_pattern_index + 16))
+            Some(vdp.vram(line_pattern_index + 16))
         } else {
             None
         };
 
-        let mut render_pattern = |mut pattern: u8, mut screen_x: u16| {
-            let mut draw = |x| {
-                if line[x as usize] {
-                    s.vdp.trigger_sprite_collision();
-                    return;
+        let zoom = if sprites_zoom { 2 } else { 1 };
+        let mut render_pattern = |pattern: u8, screen_x: u16| {
+            for j in 0..8 {
+                if pattern & (0x80 >> j) == 0 {
+                    continue;
                 }
-                line[x as usize] = true;
-                s.graphics.paint(x as u32, v as u32, color1);
-            };
-            if sprites_zoom {
-                for _ in 0..8 {
-                    // FIX
-                    if screen_x >= 256 {
+                for k in 0..zoom {
+                    // an early clocked sprite can begin off the left edge of
+                    // the screen, where `screen_x` wraps around
+                    let x = screen_x.wrapping_add(zoom * j + k) as usize;
+                    if x >= 256 {
+                        // pixels off the screen don't collide
                         continue;
                     }
-                    if pattern & 0x80 != 0 {
-                        draw(screen_x);
-                        draw(screen_x + 1);
-                    }
-                    pattern <<= 1;
-                    screen_x = screen_x.wrapping_add(2);
-                }
-            } else {
-                for _ in 0..8 {
-                    if screen_x >= 256 {
+                    if line[x].is_some() {
+                        vdp.trigger_sprite_collision();
                         continue;
                     }
-                    if pattern & 0x80 != 0 {
-                        draw(screen_x);
-                    }
-                    pattern <<= 1;
-                    screen_x = screen_x.wrapping_add(1);
+                    line[x] = Some(color1);
                 }
             }
         };
@@ -384,34 +626,68 @@ where
         }
     }
 
-    Ok(())
+    if !overflowed {
+        vdp.set_sprite_number(last_sprite as u8);
+    }
+
+    line
 }
 
-pub fn draw_line_graphics1<'a, V: 'a, G: 'a>(
+pub fn draw_sprites_tms<'a, V, G>(
     s: &mut SmsVdpGraphicsImpler<'a, V, G>,
 ) -> Result<(), SmsVdpGraphicsError>
+where
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
+{
+    let v = s.vdp.v();
+    let line = evaluate_sprites_tms(s.vdp, v);
+    for (x, color) in line.iter().enumerate() {
+        if let Some(color) = *color {
+            s.graphics.paint(x as u32, v as u32, color);
+        }
+    }
+    Ok(())
+}
+
+/// The color to paint for TMS9918 color `index`.
+///
+/// Color 0 is transparent, so the backdrop color shows through.
+fn tms_color<V>(vdp: &V, index: u8) -> SimpleColor
 where
     V: SmsVdpInternal,
-    G: SimpleGraphics,
 {
-    let pattern_table = ((s.vdp.register(4) & 0x7) as u16) << 11;
-    let name_table = ((s.vdp.register(2) & 0xF) as u16) << 10;
-    let color_table = (s.vdp.register(3) as u16) << 6;
+    if index == 0 {
+        TMS9918_PALETTE[vdp.backdrop_color_index() as usize]
+    } else {
+        TMS9918_PALETTE[index as usize & 0xF]
+    }
+}
 
+/// Handle everything about drawing a line in a TMS9918 mode except the
+/// background itself.
+///
+/// Returns `None` if there's nothing more to do, or otherwise `Some(v)`, where
+/// `v` is the line we should draw.
+fn tms_line_start<'a, V, G>(
+    s: &mut SmsVdpGraphicsImpler<'a, V, G>,
+) -> Result<Option<u16>, SmsVdpGraphicsError>
+where
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
+{
     let v = s.vdp.v();
 
     if v >= 192 {
         if v + 1 == s.vdp.total_lines() {
             s.graphics
                 .render()
-                .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
+                .map_err(SmsVdpGraphicsError::RenderFailed)?;
         }
-        return Ok(());
+        return Ok(None);
     }
 
-    s.graphics
-        .set_resolution(256, 192)
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
+    set_line_resolution(s.graphics, 256, 192, v as u32)?;
 
     if !s.vdp.display_visible() {
         for x in 0..256 {
@@ -425,18 +701,37 @@ where
                 },
             );
         }
-        return Ok(());
+        return Ok(None);
     }
 
+    Ok(Some(v))
+}
+
+pub fn draw_line_graphics1<'a, V, G>(
+    s: &mut SmsVdpGraphicsImpler<'a, V, G>,
+) -> Result<(), SmsVdpGraphicsError>
+where
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
+{
+    let v = match tms_line_start(s)? {
+        None => return Ok(()),
+        Some(v) => v,
+    };
+
+    let pattern_table = s.vdp.tms_pattern_table_address();
+    let name_table = s.vdp.tms_name_table_address();
+    let color_table = s.vdp.tms_color_table_address();
+
     let tile_y = v / 8;
     let tile_line = v % 8;
     for tile_x in 0..32 {
         let name = s.vdp.vram(name_table + tile_y * 32 + tile_x) as u16;
         let color_entry = name / 8;
         let color = s.vdp.vram(color_table + color_entry as u16);
-        let color0 = TMS9918_PALETTE[color as usize & 0xF];
-        let color1 = TMS9918_PALETTE[color as usize >> 4];
-        let mut pattern = s.vdp.vram(pattern_table + name + tile_line);
+        let color0 = tms_color(s.vdp, color & 0xF);
+        let color1 = tms_color(s.vdp, color >> 4);
+        let mut pattern = s.vdp.vram(pattern_table + name * 8 + tile_line);
         for i in 0..8 {
             s.graphics.paint(
                 tile_x as u32 * 8 + i,
@@ -450,73 +745,232 @@ where
     draw_sprites_tms(s)
 }
 
-pub fn draw_line_graphics2<'a, V: 'a, G: 'a>(
+pub fn draw_line_graphics2<'a, V, G>(
     s: &mut SmsVdpGraphicsImpler<'a, V, G>,
 ) -> Result<(), SmsVdpGraphicsError>
 where
-    V: SmsVdpInternal,
-    G: SimpleGraphics,
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
 {
-    let pattern_table = ((s.vdp.register(4) & 4) as u16) << 11;
-    let name_table = ((s.vdp.register(2) & 0xF) as u16) << 10;
-    let color_table = ((s.vdp.register(3) & 0x80) as u16) << 6;
+    let v = match tms_line_start(s)? {
+        None => return Ok(()),
+        Some(v) => v,
+    };
 
-    let v = s.vdp.v();
+    let pattern_table = s.vdp.tms_pattern_table_address();
+    let name_table = s.vdp.tms_name_table_address();
+    let color_table = s.vdp.tms_color_table_address();
 
-    if v >= 192 {
-        if v + 1 == s.vdp.total_lines() {
-            s.graphics
-                .render()
-                .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
-        }
-        return Ok(());
-    }
+    let tile_y = v / 8;
+    let tile_line = v % 8;
 
-    s.graphics
-        .set_resolution(256, 192)
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
+    // each third of the screen has its own patterns and colors
+    let third = v / 64;
+    let pattern_address = pattern_table + 2048 * third;
+    let color_address = color_table + 2048 * third;
 
-    if !s.vdp.display_visible() {
-        for x in 0..256 {
+    for tile_x in 0..32 {
+        let name = s.vdp.vram(name_table + tile_y * 32 + tile_x) as u16;
+        let color = s.vdp.vram(color_address + name * 8 + tile_line);
+        let color0 = tms_color(s.vdp, color & 0xF);
+        let color1 = tms_color(s.vdp, color >> 4);
+        let mut pattern = s.vdp.vram(pattern_address + name * 8 + tile_line);
+        for i in 0..8 {
             s.graphics.paint(
-                x,
+                tile_x as u32 * 8 + i,
                 v as u32,
-                SimpleColor {
-                    red: 0,
-                    green: 0,
-                    blue: 0,
-                },
+                if pattern & 0x80 == 0 { color0 } else { color1 },
             );
+            pattern <<= 1;
         }
-        return Ok(());
+    }
+
+    draw_sprites_tms(s)
+}
+
+/// In Text mode, the screen is 40 columns of 6 pixel wide tiles, with an 8
+/// pixel border of the backdrop color on each side. Only the top 6 bits of
+/// each pattern byte are drawn, all tiles share the colors in register 7, and
+/// there are no sprites.
+pub fn draw_line_text<'a, V, G>(
+    s: &mut SmsVdpGraphicsImpler<'a, V, G>,
+) -> Result<(), SmsVdpGraphicsError>
+where
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
+{
+    let v = match tms_line_start(s)? {
+        None => return Ok(()),
+        Some(v) => v,
+    };
+
+    let pattern_table = s.vdp.tms_pattern_table_address();
+    let name_table = s.vdp.tms_name_table_address();
+
+    let backdrop = tms_color(s.vdp, 0);
+    let color0 = backdrop;
+    let color1 = tms_color(s.vdp, s.vdp.register(7) >> 4);
+
+    for x in (0..8).chain(248..256) {
+        s.graphics.paint(x, v as u32, backdrop);
     }
 
     let tile_y = v / 8;
     let tile_line = v % 8;
+    for tile_x in 0..40 {
+        let name = s.vdp.vram(name_table + tile_y * 40 + tile_x) as u16;
+        let mut pattern = s.vdp.vram(pattern_table + name * 8 + tile_line);
+        for i in 0..6 {
+            s.graphics.paint(
+                8 + tile_x as u32 * 6 + i,
+                v as u32,
+                if pattern & 0x80 == 0 { color0 } else { color1 },
+            );
+            pattern <<= 1;
+        }
+    }
 
-    let third = v / 64;
-    let pattern_address = pattern_table + 2048 * third;
-    let color_address = color_table + 2048 * third;
+    Ok(())
+}
+
+/// In Multicolor mode, each name selects 8 bytes of the pattern table, each
+/// byte giving the colors of two 4x4 pixel blocks. Tile row `y` uses bytes
+/// `2 * (y % 4)` and `2 * (y % 4) + 1`, for its top and bottom halves.
+pub fn draw_line_multicolor<'a, V, G>(
+    s: &mut SmsVdpGraphicsImpler<'a, V, G>,
+) -> Result<(), SmsVdpGraphicsError>
+where
+    V: 'a + SmsVdpInternal,
+    G: 'a + SimpleGraphics,
+{
+    let v = match tms_line_start(s)? {
+        None => return Ok(()),
+        Some(v) => v,
+    };
+
+    let pattern_table = s.vdp.tms_pattern_table_address();
+    let name_table = s.vdp.tms_name_table_address();
+
+    let tile_y = v / 8;
+    let byte_index = 2 * (tile_y % 4) + (v % 8) / 4;
 
     for tile_x in 0..32 {
         let name = s.vdp.vram(name_table + tile_y * 32 + tile_x) as u16;
-        let color = s.vdp.vram(color_address + name * 8 + tile_line);
-        let color0 = TMS9918_PALETTE[color as usize & 0xF];
-        let color1 = TMS9918_PALETTE[color as usize >> 4];
-        let mut pattern = s.vdp.vram(pattern_address + name * 8 + tile_line);
+        let colors = s.vdp.vram(pattern_table + name * 8 + byte_index);
+        let left = tms_color(s.vdp, colors >> 4);
+        let right = tms_color(s.vdp, colors & 0xF);
         for i in 0..8 {
             s.graphics.paint(
                 tile_x as u32 * 8 + i,
                 v as u32,
-                if pattern & 0x80 == 0 { color0 } else { color1 },
+                if i < 4 { left } else { right },
             );
-            pattern <<= 1;
         }
     }
 
     draw_sprites_tms(s)
 }
 
+/// Evaluate the mode 4 sprites on line `v`, setting the sprite overflow and
+/// collision flags, and giving a line of palette indices with the sprites
+/// drawn in, and 0x80 wherever there's no sprite.
+///
+/// This is the part of drawing sprites that changes the VDP's state, so it
+/// runs even when nothing is painted.
+pub fn evaluate_sprites_mode4<V>(vdp: &mut V, v: u16) -> [u8; 256]
+where
+    V: SmsVdpInternal + ?Sized,
+{
+    use self::Resolution::*;
+
+    let mut line_buffer = [0x80u8; 256];
+
+    let sprite_height = vdp.sprite_height();
+    // zoomed sprites have each pixel doubled in both dimensions, but still
+    // only count once against the limit of 8 on a line
+    let zoom = if vdp.zoomed_sprites() { 2 } else { 1 };
+    let mut sprites_rendered = 0u8;
+    for i in 0..64 {
+        let sprite_y = unsafe { vdp.sprite_y(i) } as u16;
+        if sprite_y == 0xD1 && vdp.resolution() == Low {
+            break;
+        }
+
+        // which line of the sprite are we rendering?
+        let sprite_line = v.wrapping_sub(sprite_y) / zoom;
+        if sprite_line >= sprite_height {
+            continue;
+        }
+        if sprites_rendered == 8 {
+            // the ninth sprite on a line sets the overflow flag, and the VDP
+            // looks no further
+            vdp.trigger_sprite_overflow();
+            break;
+        }
+
+        let pattern_addr = unsafe { vdp.sprite_pattern_address(i) };
+
+        let palette_indices: [u8; 8] =
+            unsafe { vdp.pattern_address_to_palette_indices(pattern_addr, sprite_line) };
+        let sprite_x = unsafe { vdp.sprite_x(i) } as isize;
+        // with `shift_sprites`, a sprite can start off the left edge of the
+        // screen
+        let shift_x = if vdp.shift_sprites() { 8 } else { 0 };
+        let zoom_x = zoom as usize;
+        for (j, &index) in palette_indices.iter().enumerate() {
+            if index == 0 {
+                // transparent pixels neither show nor collide
+                continue;
+            }
+            for k in 0..zoom_x {
+                // Sprites collide anywhere on the line, even outside the Game
+                // Gear's window, but not off the edges of the screen.
+                let render_x = sprite_x + (zoom_x * j + k) as isize - shift_x;
+                if render_x < 0 {
+                    continue;
+                }
+                if render_x >= 256 {
+                    break;
+                }
+                let render_x = render_x as usize;
+                if line_buffer[render_x] != 0x80 {
+                    vdp.trigger_sprite_collision();
+                    continue;
+                }
+                line_buffer[render_x] = index + 16;
+            }
+        }
+        sprites_rendered += 1;
+    }
+
+    line_buffer
+}
+
+/// Evaluate the sprites on the line the VDP is at, if drawing the line would,
+/// without painting anything.
+///
+/// This sets the sprite overflow and collision flags just as drawing the line
+/// would, for graphics like `FakeSmsGraphics` that skip the drawing.
+pub fn evaluate_sprites<V>(vdp: &mut V)
+where
+    V: SmsVdpInternal + ?Sized,
+{
+    let v = vdp.v();
+    if !vdp.display_visible() {
+        return;
+    }
+    let (display_y_start, display_y_end, _, _) = display_window(vdp);
+    match vdp.mode() {
+        Mode::Mode4 if v >= display_y_start && v < display_y_end => {
+            evaluate_sprites_mode4(vdp, v);
+        }
+        Mode::Graphics1 | Mode::Graphics2 | Mode::Multicolor if v < 192 => {
+            evaluate_sprites_tms(vdp, v);
+        }
+        _ => {}
+    }
+}
+
 pub fn draw_line_mode4<'a, V: 'a, G: 'a>(
     s: &mut SmsVdpGraphicsImpler<'a, V, G>,
 ) -> Result<(), SmsVdpGraphicsError>
@@ -524,16 +978,10 @@ where
     V: SmsVdpInternal,
     G: SimpleGraphics,
 {
-    use self::Resolution::*;
-
     let v = s.vdp.v();
 
     let (display_y_start, display_y_end, display_x_start, display_x_end) =
-        if s.vdp.kind() == Kind::Gg {
-            (24, 168, 48, 208)
-        } else {
-            (0, s.vdp.active_lines(), 0, 256)
-        };
+        display_window(s.vdp);
     let height = display_y_end - display_y_start;
     let width = display_x_end - display_x_start;
 
@@ -545,17 +993,15 @@ where
         if v + 1 == s.vdp.total_lines() {
             s.graphics
                 .render()
-                .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
+                .map_err(SmsVdpGraphicsError::RenderFailed)?;
         }
         return Ok(());
     }
 
-    s.graphics
-        .set_resolution(width as u32, height as u32)
-        .map_err(|e| SmsVdpGraphicsError::Graphics(e))?;
-
     let y = (v - display_y_start) as u32;
 
+    set_line_resolution(s.graphics, width as u32, height as u32, y)?;
+
     if !s.vdp.display_visible() {
         for x in 0..width {
             s.graphics.paint(
@@ -572,98 +1018,27 @@ where
     }
 
     let mut colors: [SimpleColor; 32] = Default::default();
+    let kind = s.vdp.kind();
 
-    if s.vdp.kind() == Kind::Gg {
-        for i in 0..32 {
-            colors[i] = gg_color_to_simple_color(s.vdp.cram(i as u16));
-        }
-    } else {
-        for i in 0..32 {
-            colors[i] = vdp_color_to_simple_color(s.vdp.cram(i as u16) as u8);
-        }
+    for (i, color) in colors.iter_mut().enumerate() {
+        *color = cram_color_to_simple_color(kind, s.vdp.cram(i as u16));
     }
 
-    let mut line_buffer = [0x80u8; 256];
-
-    // draw sprites
-    let sprite_height = if s.vdp.tall_sprites() { 16 } else { 8 };
-    let sprites_rendered = 0u8;
-    for i in 0..64 {
-        let sprite_y = unsafe { s.vdp.sprite_y(i) } as u16;
-        if sprite_y == 0xD1 && s.vdp.resolution() == Low {
-            break;
-        }
-
-        // which line of the sprite are we rendering?
-        let sprite_line = v.wrapping_sub(sprite_y) / if s.vdp.zoomed_sprites() { 2 } else { 1 };
-        if sprite_line >= sprite_height {
-            continue;
-        }
-        if sprites_rendered == 8 {
-            s.vdp.trigger_sprite_overflow();
-            break;
-        }
-
-        let pattern_addr = unsafe { s.vdp.sprite_pattern_address(i) };
-
-        let palette_indices: [u8; 8] = unsafe {
-            s.vdp
-                .pattern_address_to_palette_indices(pattern_addr, sprite_line)
-        };
-        let sprite_x = unsafe { s.vdp.sprite_x(i) } as usize;
-        let shift_x = if s.vdp.shift_sprites() { 8 } else { 0 };
-        for j in 0..8 {
-            let index = if s.vdp.zoomed_sprites() { 2 * j } else { j };
-            let render_x = sprite_x.wrapping_add(index).wrapping_sub(shift_x);
-            if render_x < display_x_start || render_x >= display_x_end {
-                break;
-            }
-            if line_buffer[render_x] != 0x80 {
-                s.vdp.trigger_sprite_collision();
-                continue;
-            }
-            if palette_indices[j] != 0 {
-                line_buffer[render_x] = palette_indices[j] + 16;
-            }
-            if s.vdp.zoomed_sprites() {
-                let render_x2 = render_x + 1;
-                if render_x2 < display_x_start || render_x2 >= display_x_end {
-                    break;
-                }
-                if line_buffer[render_x2] != 0x80 {
-                    s.vdp.trigger_sprite_collision();
-                    continue;
-                }
-                if palette_indices[j] != 0 {
-                    line_buffer[render_x2] = palette_indices[j] + 16;
-                }
-            }
-        }
-    }
+    let mut line_buffer = evaluate_sprites_mode4(s.vdp, v);
 
     // draw tiles
     let vert_scroll_locked = s.vdp.vert_scroll_locked();
 
-    let scroll_x = if s.vdp.horiz_scroll_locked() && v < 16 {
-        0
-    } else {
-        s.vdp.x_scroll()
-    };
+    let scroll_x = s.vdp.line_x_scroll(v);
     let pixel_offset_x = scroll_x & 7;
     let tile_offset_x = (-((scroll_x >> 3) as i16)) as u16;
 
-    let vert_tile_count = if s.vdp.resolution() == Low {
-        28u16
-    } else {
-        32u16
-    };
-    let vert_tile_height = 8 * vert_tile_count;
+    let vert_tile_height = 8 * s.vdp.name_table_rows();
 
     let scroll_y = s.vdp.y_scroll() as u16;
     let logical_y = (v + scroll_y as u16) % vert_tile_height;
     let pixel_offset_y = logical_y & 7;
     let tile_offset_y = logical_y >> 3;
-    let kind = s.vdp.kind();
 
     {
         let mut write_tile = |tile, tile_line, start_x| {
@@ -697,27 +1072,28 @@ where
             }
         };
 
-        // first, draw region 3/4
-        if kind != Kind::Gg && vert_scroll_locked {
-            for tile in 23..32 {
+        // with vertical scrolling locked, the rightmost 8 columns ignore
+        // `y_scroll`
+        for tile in 0..32 {
+            if vert_scroll_locked && tile >= 24 {
                 write_tile(
                     32 * (v >> 3) + (tile_offset_x.wrapping_add(tile)) % 32,
                     v & 7,
                     tile as usize * 8 + pixel_offset_x as usize,
-                )
+                );
+            } else {
+                write_tile(
+                    32 * tile_offset_y + (tile_offset_x.wrapping_add(tile)) % 32,
+                    pixel_offset_y,
+                    tile as usize * 8 + pixel_offset_x as usize,
+                );
             }
         }
-
-        // now draw region 1 or 2
-        for tile in 0..if vert_scroll_locked { 24 } else { 32 } {
-            write_tile(
-                32 * tile_offset_y + (tile_offset_x.wrapping_add(tile)) % 32,
-                pixel_offset_y,
-                tile as usize * 8 + pixel_offset_x as usize,
-            );
-        }
     }
 
+    // This comes after the tiles, so it covers whatever fine scrolling
+    // shifted under the first column, including the pixels left of the first
+    // tile that the tiles never reach
     if s.vdp.left_column_blank() {
         for i in 0..8 {
             line_buffer[i] = 16 + s.vdp.backdrop_color_index();
@@ -737,9 +1113,713 @@ where
          Deserialize)]
 pub struct FakeSmsGraphics;
 
-impl<'a, V: 'a> SmsVdpGraphics for SmsVdpGraphicsImpler<'a, V, FakeSmsGraphics> {
+impl<'a, V: 'a> SmsVdpGraphics for SmsVdpGraphicsImpler<'a, V, FakeSmsGraphics>
+where
+    V: SmsVdpInternal,
+{
+    /// Doesn't draw anything, but still evaluates sprites, so the VDP's
+    /// state is the same as if it had.
     #[inline]
     fn draw_line(&mut self) -> Result<(), SmsVdpGraphicsError> {
+        evaluate_sprites(self.vdp);
         Ok(())
     }
 }
+
+impl SmsVdpFrame for FakeSmsGraphics {
+    #[inline]
+    fn frame_hash(&self) -> u64 {
+        0
+    }
+
+    fn save_png(&self, _path: &Path) -> Result<(), SmsVdpGraphicsError> {
+        Err(SmsVdpGraphicsError::ScreenshotFailed(format_err!(
+            "FakeSmsGraphics has no pixels to save"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use host_multimedia::FakeGraphics;
+
+    /// Graphics that keep the pixels they're given.
+    struct PixelGraphics(Vec<SimpleColor>);
+
+    impl SimpleGraphics for PixelGraphics {
+        fn set_resolution(&mut self, width: u32, height: u32) -> Result<(), Error> {
+            assert_eq!((width, height), (256, 192));
+            Ok(())
+        }
+
+        fn resolution(&self) -> (u32, u32) {
+            (256, 192)
+        }
+
+        fn paint(&mut self, x: u32, y: u32, color: SimpleColor) {
+            self.0[(256 * y + x) as usize] = color;
+        }
+
+        fn get(&self, x: u32, y: u32) -> SimpleColor {
+            self.0[(256 * y + x) as usize]
+        }
+
+        fn render(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn frame_resolution(kind: Kind) -> (u32, u32) {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_kind(kind);
+        // mode 4, display on
+        vdp.set_register(0, 0x04);
+        vdp.set_register(1, 0x40);
+        let mut graphics = FakeGraphics::default();
+        for _ in 0..vdp.total_lines() {
+            line(&mut SmsVdpGraphicsImpler {
+                graphics: &mut graphics,
+                vdp: &mut vdp,
+                overscan: false,
+            }).unwrap();
+        }
+        graphics.resolution()
+    }
+
+    #[test]
+    fn game_gear_window() {
+        assert_eq!(frame_resolution(Kind::Sms2), (256, 192));
+        assert_eq!(frame_resolution(Kind::Gg), (160, 144));
+    }
+
+    #[test]
+    fn cram_colors() {
+        let white = SimpleColor {
+            red: 0xF0,
+            green: 0xF0,
+            blue: 0xF0,
+        };
+        assert_eq!(cram_color_to_simple_color(Kind::Gg, 0x0FFF), white);
+        let red = SimpleColor {
+            red: 0xC0,
+            green: 0,
+            blue: 0,
+        };
+        assert_eq!(cram_color_to_simple_color(Kind::Sms, 0x03), red);
+        // only the low 6 bits count on the Master System
+        assert_eq!(cram_color_to_simple_color(Kind::Sms, 0x0FC3), red);
+    }
+
+    #[test]
+    fn graphics2() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0x02); // Graphics 2
+        vdp.set_register(1, 0x40); // display on
+        vdp.set_register(2, 0x0E); // name table at 0x3800
+        vdp.set_register(3, 0xFF); // color table at 0x2000
+        vdp.set_register(4, 0x03); // pattern table at 0
+        vdp.set_register(5, 0x36); // sprite attribute table at 0x1B00
+        vdp.set_register(7, 0x04); // backdrop color 4
+        assert_eq!(vdp.mode(), Mode::Graphics2);
+
+        // no sprites
+        vdp.set_vram(0x1B00, 0xD0);
+
+        // tile 1 at the top left of the first and second thirds of the screen
+        vdp.set_vram(0x3800, 1);
+        vdp.set_vram(0x3800 + 32 * 8, 1);
+
+        // in the first third, tile 1's first line is 0xF0 with colors 6 on A
+        vdp.set_vram(0x0008, 0xF0);
+        vdp.set_vram(0x2008, 0x6A);
+
+        // in the second, it's 0x0F with colors 2 on transparent
+        vdp.set_vram(0x0808, 0x0F);
+        vdp.set_vram(0x2808, 0x20);
+
+        let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+        for &v in [0, 64].iter() {
+            vdp.set_v(v);
+            SmsVdpGraphicsImpler {
+                graphics: &mut graphics,
+                vdp: &mut vdp,
+                overscan: false,
+            }.draw_line()
+                .unwrap();
+        }
+
+        let expected0 = [6, 6, 6, 6, 0xA, 0xA, 0xA, 0xA];
+        let expected64 = [4, 4, 4, 4, 2, 2, 2, 2];
+        for x in 0..8 {
+            assert_eq!(graphics.get(x, 0), TMS9918_PALETTE[expected0[x as usize]]);
+            assert_eq!(
+                graphics.get(x, 64),
+                TMS9918_PALETTE[expected64[x as usize]]
+            );
+        }
+    }
+
+    #[test]
+    fn text() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(1, 0x50); // Text, display on
+        vdp.set_register(2, 0x0E); // name table at 0x3800
+        vdp.set_register(4, 0x01); // pattern table at 0x800
+        vdp.set_register(7, 0xF4); // white on backdrop color 4
+        assert_eq!(vdp.mode(), Mode::Text);
+
+        // tile 1 in the second column, whose first line is 0xA8
+        vdp.set_vram(0x3801, 1);
+        vdp.set_vram(0x0808, 0xA8);
+
+        let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+        vdp.set_v(0);
+        SmsVdpGraphicsImpler {
+            graphics: &mut graphics,
+            vdp: &mut vdp,
+            overscan: false,
+        }.draw_line()
+            .unwrap();
+
+        let expected = [0xF, 4, 0xF, 4, 0xF, 4];
+        for x in 0..256 {
+            let index = if x >= 14 && x < 20 {
+                expected[x as usize - 14]
+            } else {
+                4
+            };
+            assert_eq!(graphics.get(x, 0), TMS9918_PALETTE[index], "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn invalid_mode() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(1, 0x58); // m1 and m3, display on
+        assert_eq!(vdp.mode(), Mode::Invalid);
+
+        let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+        let result = SmsVdpGraphicsImpler {
+            graphics: &mut graphics,
+            vdp: &mut vdp,
+            overscan: false,
+        }.draw_line();
+        match result {
+            Err(SmsVdpGraphicsError::InvalidMode { m1: true, m3: true, .. }) => {}
+            x => panic!("expected InvalidMode, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn sprite_off_left_edge() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0x0C); // mode 4, shift sprites
+        vdp.set_register(1, 0x40); // display on
+        vdp.set_register(2, 0xFF); // name table at 0x3800
+        vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+        vdp.set_register(6, 0x00); // sprite patterns at 0
+
+        // sprite 0 on lines 10 through 17 at x = 4, using pattern 1
+        vdp.set_vram(0x3F00, 9);
+        vdp.set_vram(0x3F80, 4);
+        vdp.set_vram(0x3F81, 1);
+        // and no more sprites
+        vdp.set_vram(0x3F01, 0xD0);
+
+        // pattern 1's first line is all color 1
+        vdp.set_vram(32, 0xFF);
+        vdp.set_cram(17, 0x3F);
+
+        let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+        vdp.set_v(10);
+        SmsVdpGraphicsImpler {
+            graphics: &mut graphics,
+            vdp: &mut vdp,
+            overscan: false,
+        }.draw_line()
+            .unwrap();
+
+        // shifted left 8 pixels, only the last 4 are on screen
+        let white = vdp_color_to_simple_color(0x3F);
+        let black = vdp_color_to_simple_color(0);
+        for x in 0..256 {
+            let expected = if x < 4 { white } else { black };
+            assert_eq!(graphics.get(x, 10), expected, "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn scroll_locks() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0xC4); // mode 4, both scroll locks
+        vdp.set_register(1, 0x40); // display on
+        vdp.set_register(2, 0xFF); // name table at 0x3800
+        vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+        vdp.set_register(8, 8); // x scroll
+        vdp.set_y_scroll(8);
+
+        // no sprites
+        vdp.set_vram(0x3F00, 0xD0);
+
+        // patterns 1, 2, and 3 are solid colors 1, 2, and 3
+        for line in 0..8 {
+            vdp.set_vram(32 + 4 * line, 0xFF);
+            vdp.set_vram(64 + 4 * line + 1, 0xFF);
+            vdp.set_vram(96 + 4 * line, 0xFF);
+            vdp.set_vram(96 + 4 * line + 1, 0xFF);
+        }
+        let red = 0x03u8;
+        let green = 0x0Cu8;
+        let blue = 0x30u8;
+        vdp.set_cram(1, red as u16);
+        vdp.set_cram(2, green as u16);
+        vdp.set_cram(3, blue as u16);
+
+        // name table rows 0 and 2 are pattern 1 and 3; rows 1 and 3 are
+        // pattern 2, except for a pattern 1 in row 3's first column
+        for column in 0..32 {
+            vdp.set_vram(0x3800 + 2 * column, 1);
+            vdp.set_vram(0x3800 + 64 + 2 * column, 2);
+            vdp.set_vram(0x3800 + 128 + 2 * column, 3);
+            vdp.set_vram(0x3800 + 192 + 2 * column, 2);
+        }
+        vdp.set_vram(0x3800 + 192, 1);
+
+        let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+        for &v in [0, 16].iter() {
+            vdp.set_v(v);
+            SmsVdpGraphicsImpler {
+                graphics: &mut graphics,
+                vdp: &mut vdp,
+                overscan: false,
+            }.draw_line()
+                .unwrap();
+        }
+
+        // line 0 isn't scrolled horizontally, and its right columns aren't
+        // scrolled vertically
+        for x in 0..256 {
+            let expected = if x < 192 { green } else { red };
+            assert_eq!(
+                graphics.get(x, 0),
+                vdp_color_to_simple_color(expected),
+                "x = {}",
+                x
+            );
+        }
+
+        // line 16 is scrolled horizontally by a tile
+        for x in 0..256 {
+            let expected = match x {
+                8..=15 => red,
+                192..=255 => blue,
+                _ => green,
+            };
+            assert_eq!(
+                graphics.get(x, 16),
+                vdp_color_to_simple_color(expected),
+                "x = {}",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn sprite_zoom() {
+        // Draw 8x8 sprite 0, solid color 1, at (16, 10), returning which
+        // pixels it covers.
+        fn footprint(zoom: bool) -> Vec<(u32, u32)> {
+            let mut vdp = SmsVdpState::default();
+            vdp.set_register(0, 0x04); // mode 4
+            vdp.set_register(1, if zoom { 0x41 } else { 0x40 }); // display on
+            vdp.set_register(2, 0xFF); // name table at 0x3800
+            vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+            vdp.set_register(6, 0x00); // sprite patterns at 0
+
+            vdp.set_vram(0x3F00, 9);
+            vdp.set_vram(0x3F80, 16);
+            vdp.set_vram(0x3F81, 1);
+            vdp.set_vram(0x3F01, 0xD0);
+            for line in 0..8 {
+                vdp.set_vram(32 + 4 * line, 0xFF);
+            }
+            vdp.set_cram(17, 0x3F);
+
+            let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+            for v in 0..40 {
+                vdp.set_v(v);
+                SmsVdpGraphicsImpler {
+                    graphics: &mut graphics,
+                    vdp: &mut vdp,
+                    overscan: false,
+                }.draw_line()
+                    .unwrap();
+            }
+
+            let white = vdp_color_to_simple_color(0x3F);
+            let mut pixels = Vec::new();
+            for y in 0..40 {
+                for x in 0..256 {
+                    if graphics.get(x, y) == white {
+                        pixels.push((x, y));
+                    }
+                }
+            }
+            pixels
+        }
+
+        let unzoomed: Vec<(u32, u32)> = (10..18)
+            .flat_map(|y| (16..24).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(footprint(false), unzoomed);
+        let zoomed: Vec<(u32, u32)> = (10..26)
+            .flat_map(|y| (16..32).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(footprint(true), zoomed);
+    }
+
+    #[test]
+    fn sprite_collision() {
+        // Draw line 40 with 8x8 sprites on lines 40 through 47 at the given
+        // x coordinates with the given patterns, returning the VDP. Line 40
+        // is within the Game Gear's window.
+        fn mode4(kind: Kind, reg0: u8, sprites: &[(u8, u8)]) -> SmsVdpState {
+            let mut vdp = SmsVdpState::default();
+            vdp.set_kind(kind);
+            vdp.set_register(0, 0x04 | reg0); // mode 4
+            vdp.set_register(1, 0x40); // display on
+            vdp.set_register(2, 0xFF); // name table at 0x3800
+            vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+            vdp.set_register(6, 0x00); // sprite patterns at 0
+            for (i, &(x, pattern)) in sprites.iter().enumerate() {
+                vdp.set_vram(0x3F00 + i as u16, 39);
+                vdp.set_vram(0x3F80 + 2 * i as u16, x);
+                vdp.set_vram(0x3F81 + 2 * i as u16, pattern);
+            }
+            vdp.set_vram(0x3F00 + sprites.len() as u16, 0xD0);
+            // pattern 1 is solid, and pattern 2 has only its left half
+            for line in 0..8 {
+                vdp.set_vram(32 + 4 * line, 0xFF);
+                vdp.set_vram(64 + 4 * line, 0xF0);
+            }
+            draw(&mut vdp);
+            vdp
+        }
+
+        fn draw(vdp: &mut SmsVdpState) {
+            let mut graphics = FakeGraphics::default();
+            vdp.set_v(40);
+            SmsVdpGraphicsImpler {
+                graphics: &mut graphics,
+                vdp: &mut *vdp,
+                overscan: false,
+            }.draw_line()
+                .unwrap();
+        }
+
+        fn collided(vdp: &SmsVdpState) -> bool {
+            vdp.status_flags() & SPRITE_COLLISION_FLAG != 0
+        }
+
+        assert!(collided(&mode4(Kind::Sms2, 0, &[(100, 1), (104, 1)])));
+        assert!(!collided(&mode4(Kind::Sms2, 0, &[(100, 1), (108, 1)])));
+
+        // only transparent pixels of the second sprite overlap the first
+        assert!(!collided(&mode4(Kind::Sms2, 0, &[(104, 2), (100, 2)])));
+        assert!(collided(&mode4(Kind::Sms2, 0, &[(104, 2), (102, 2)])));
+
+        // shifted 8 pixels left, these overlap only off the screen
+        assert!(!collided(&mode4(Kind::Sms2, 0x08, &[(0, 1), (4, 1)])));
+        assert!(collided(&mode4(Kind::Sms2, 0x08, &[(4, 1), (8, 1)])));
+
+        // but outside the Game Gear's window, they still collide
+        assert!(collided(&mode4(Kind::Gg, 0, &[(10, 1), (14, 1)])));
+
+        // the flag stays set through lines without collisions until the
+        // status is read
+        let mut vdp = mode4(Kind::Sms2, 0, &[(100, 1), (104, 1)]);
+        vdp.set_vram(0x3F81, 0);
+        draw(&mut vdp);
+        assert!(collided(&vdp));
+        assert_eq!(
+            vdp.read_control() & SPRITE_COLLISION_FLAG,
+            SPRITE_COLLISION_FLAG
+        );
+        draw(&mut vdp);
+        assert!(!collided(&vdp));
+
+        // In Graphics 2, sprites can be early clocked 32 pixels left.
+        fn graphics2(sprites: &[(u8, bool)]) -> (SmsVdpState, PixelGraphics) {
+            let mut vdp = SmsVdpState::default();
+            vdp.set_register(0, 0x02); // Graphics 2
+            vdp.set_register(1, 0x40); // display on
+            vdp.set_register(2, 0x0E); // name table at 0x3800
+            vdp.set_register(5, 0x36); // sprite attribute table at 0x1B00
+            vdp.set_register(6, 0x07); // sprite patterns at 0x3800
+            for (i, &(x, early)) in sprites.iter().enumerate() {
+                let i = i as u16;
+                vdp.set_vram(0x1B00 + 4 * i, 9);
+                vdp.set_vram(0x1B01 + 4 * i, x);
+                vdp.set_vram(0x1B02 + 4 * i, 1);
+                vdp.set_vram(0x1B03 + 4 * i, if early { 0x8F } else { 0x0F });
+            }
+            vdp.set_vram(0x1B00 + 4 * sprites.len() as u16, 0xD0);
+            for line in 0..8 {
+                vdp.set_vram(0x3808 + line, 0xFF);
+            }
+            let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+            vdp.set_v(10);
+            SmsVdpGraphicsImpler {
+                graphics: &mut graphics,
+                vdp: &mut vdp,
+                overscan: false,
+            }.draw_line()
+                .unwrap();
+            (vdp, graphics)
+        }
+
+        assert!(collided(&graphics2(&[(100, false), (104, false)]).0));
+        assert!(!collided(&graphics2(&[(4, true), (8, true)]).0));
+
+        // an early clocked sprite partly off the screen still shows the rest
+        let (vdp, graphics) = graphics2(&[(28, true), (0, false)]);
+        assert!(collided(&vdp));
+        let white = TMS9918_PALETTE[15];
+        assert_eq!(graphics.get(3, 10), white);
+        assert_eq!(graphics.get(7, 10), white);
+        assert!(graphics.get(8, 10) != white);
+    }
+
+    #[test]
+    fn sprite_overflow() {
+        // Ten 8x8 sprites of solid color 1 side by side on lines 10 through
+        // 17, drawing line 10 and returning how many were drawn.
+        fn ten_sprites(vdp: &mut SmsVdpState) -> u32 {
+            let white = if vdp.mode() == Mode::Mode4 {
+                vdp_color_to_simple_color(0x3F)
+            } else {
+                TMS9918_PALETTE[15]
+            };
+            let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+            vdp.set_v(10);
+            SmsVdpGraphicsImpler {
+                graphics: &mut graphics,
+                vdp: &mut *vdp,
+                overscan: false,
+            }.draw_line()
+                .unwrap();
+            let pixels = (0..256)
+                .filter(|&x| graphics.get(x, 10) == white)
+                .count() as u32;
+            assert_eq!(pixels % 8, 0);
+            pixels / 8
+        }
+
+        // mode 4: eight are drawn
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0x04); // mode 4
+        vdp.set_register(1, 0x40); // display on
+        vdp.set_register(2, 0xFF); // name table at 0x3800
+        vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+        vdp.set_register(6, 0x00); // sprite patterns at 0
+        for i in 0..10 {
+            vdp.set_vram(0x3F00 + i, 9);
+            vdp.set_vram(0x3F80 + 2 * i, 16 * i as u8);
+            vdp.set_vram(0x3F81 + 2 * i, 1);
+        }
+        vdp.set_vram(0x3F0A, 0xD0);
+        for line in 0..8 {
+            vdp.set_vram(32 + 4 * line, 0xFF);
+        }
+        vdp.set_cram(17, 0x3F);
+        assert_eq!(ten_sprites(&mut vdp), 8);
+        assert_eq!(vdp.read_control() & 0x7F, SPRITE_OVERFLOW_FLAG);
+
+        // Graphics 2: four are drawn, and the fifth sprite's number is in the
+        // status register
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0x02); // Graphics 2
+        vdp.set_register(1, 0x40); // display on
+        vdp.set_register(2, 0x0E); // name table at 0x3800
+        vdp.set_register(5, 0x36); // sprite attribute table at 0x1B00
+        vdp.set_register(6, 0x07); // sprite patterns at 0x3800
+        for i in 0..10 {
+            vdp.set_vram(0x1B00 + 4 * i, 9);
+            vdp.set_vram(0x1B01 + 4 * i, 16 * i as u8);
+            vdp.set_vram(0x1B02 + 4 * i, 1);
+            vdp.set_vram(0x1B03 + 4 * i, 15);
+        }
+        vdp.set_vram(0x1B28, 0xD0);
+        for line in 0..8 {
+            vdp.set_vram(0x3808 + line, 0xFF);
+        }
+        assert_eq!(ten_sprites(&mut vdp), 4);
+        assert_eq!(
+            vdp.status_flags() & SPRITE_OVERFLOW_FLAG,
+            SPRITE_OVERFLOW_FLAG
+        );
+        assert_eq!(vdp.sprite_number(), 4);
+
+        // until the status is read, another line doesn't change it, even
+        // though sprite 5 is now the fifth on the line
+        vdp.set_vram(0x1B08, 0x50);
+        assert_eq!(ten_sprites(&mut vdp), 4);
+        assert_eq!(vdp.read_control() & 0x7F, SPRITE_OVERFLOW_FLAG | 4);
+
+        // once it's read, the next line sets it again
+        assert_eq!(ten_sprites(&mut vdp), 4);
+        assert_eq!(vdp.read_control() & 0x7F, SPRITE_OVERFLOW_FLAG | 5);
+
+        // without overflow, it's the terminating sprite's number
+        vdp.set_vram(0x1B10, 0xD0);
+        assert_eq!(ten_sprites(&mut vdp), 3);
+        assert_eq!(vdp.read_control() & 0x7F, 4);
+        vdp.set_vram(0x1B0C, 0xD0);
+        assert_eq!(ten_sprites(&mut vdp), 2);
+        assert_eq!(vdp.read_control() & 0x7F, 3);
+    }
+
+    #[test]
+    fn left_column_blank_scrolled() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0x24); // mode 4, left column blank
+        vdp.set_register(1, 0x40); // display on
+        vdp.set_register(2, 0xFF); // name table at 0x3800
+        vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+        vdp.set_register(7, 0x03); // backdrop color 3 of the sprite palette
+        vdp.set_vram(0x3F00, 0xD0); // no sprites
+
+        // tile 1 is solid color 1, and the top row of the name table
+        // alternates between tiles 0 and 1
+        for line in 0..8 {
+            vdp.set_vram(32 + 4 * line, 0xFF);
+        }
+        for column in 0..32 {
+            vdp.set_vram(0x3800 + 2 * column, column as u8 & 1);
+        }
+        vdp.set_cram(0, 0x30); // blue
+        vdp.set_cram(1, 0x0C); // green
+        vdp.set_cram(19, 0x03); // red
+        let red = vdp_color_to_simple_color(0x03);
+        let green = vdp_color_to_simple_color(0x0C);
+        let blue = vdp_color_to_simple_color(0x30);
+
+        // fine scrolling alone, both coarse and fine, and scrolling so far
+        // the name table wraps around
+        for &scroll in [0x05u8, 0x0D, 0xFD, 0xFF].iter() {
+            vdp.set_register(8, scroll);
+            let mut graphics = PixelGraphics(vec![Default::default(); 256 * 192]);
+            vdp.set_v(0);
+            SmsVdpGraphicsImpler {
+                graphics: &mut graphics,
+                vdp: &mut vdp,
+                overscan: false,
+            }.draw_line()
+                .unwrap();
+            for x in 0..8 {
+                assert_eq!(graphics.get(x, 0), red, "scroll {}, x {}", scroll, x);
+            }
+            for x in 8..256u32 {
+                let column = (x as u8).wrapping_sub(scroll) / 8;
+                let expected = if column & 1 != 0 { green } else { blue };
+                assert_eq!(graphics.get(x, 0), expected, "scroll {}, x {}", scroll, x);
+            }
+        }
+    }
+
+    #[test]
+    fn overscan() {
+        /// Graphics that keep their pixels as long as the resolution stays
+        /// the same.
+        #[derive(Default)]
+        struct KeepGraphics(u32, u32, Vec<SimpleColor>);
+
+        impl SimpleGraphics for KeepGraphics {
+            fn set_resolution(&mut self, width: u32, height: u32) -> Result<(), Error> {
+                if (width, height) != (self.0, self.1) {
+                    *self = KeepGraphics(
+                        width,
+                        height,
+                        vec![Default::default(); (width * height) as usize],
+                    );
+                }
+                Ok(())
+            }
+
+            fn resolution(&self) -> (u32, u32) {
+                (self.0, self.1)
+            }
+
+            fn paint(&mut self, x: u32, y: u32, color: SimpleColor) {
+                assert!(x < self.0 && y < self.1);
+                self.2[(self.0 * y + x) as usize] = color;
+            }
+
+            fn get(&self, x: u32, y: u32) -> SimpleColor {
+                self.2[(self.0 * y + x) as usize]
+            }
+
+            fn render(&mut self) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        fn frame(vdp: &mut SmsVdpState) -> KeepGraphics {
+            let mut graphics = KeepGraphics::default();
+            for _ in 0..vdp.total_lines() {
+                line(&mut SmsVdpGraphicsImpler {
+                    graphics: &mut graphics,
+                    vdp: &mut *vdp,
+                    overscan: true,
+                }).unwrap();
+            }
+            graphics
+        }
+
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0x24); // mode 4, left column blank
+        vdp.set_register(1, 0x40); // display on
+        vdp.set_register(2, 0xFF); // name table at 0x3800
+        vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+        vdp.set_register(7, 0x03); // backdrop color 3 of the sprite palette
+        vdp.set_vram(0x3F00, 0xD0); // no sprites
+        vdp.set_cram(0, 0x30); // blue
+        vdp.set_cram(19, 0x03); // red
+        let red = vdp_color_to_simple_color(0x03);
+        let blue = vdp_color_to_simple_color(0x30);
+        assert_eq!(backdrop_color(&vdp), red);
+
+        let graphics = frame(&mut vdp);
+        let (width, height) = (256 + 2 * OVERSCAN_WIDTH, 192 + 2 * OVERSCAN_HEIGHT);
+        assert_eq!(graphics.resolution(), (width, height));
+        for x in 0..width {
+            assert_eq!(graphics.get(x, 0), red);
+            assert_eq!(graphics.get(x, height - 1), red);
+        }
+        for y in 0..height {
+            assert_eq!(graphics.get(0, y), red);
+            assert_eq!(graphics.get(width - 1, y), red);
+        }
+
+        // the blank left column is the backdrop color too, right up against
+        // the border, and then the tiles begin
+        let y = OVERSCAN_HEIGHT + 10;
+        for x in 0..OVERSCAN_WIDTH + 8 {
+            assert_eq!(graphics.get(x, y), red);
+        }
+        assert_eq!(graphics.get(OVERSCAN_WIDTH + 8, y), blue);
+
+        // in a TMS9918 mode, the backdrop color is from its palette
+        vdp.set_register(0, 0x02); // Graphics 2
+        vdp.set_register(7, 0x04); // backdrop color 4
+        let graphics = frame(&mut vdp);
+        assert_eq!(graphics.get(0, 0), TMS9918_PALETTE[4]);
+
+        // and the Game Gear has no border
+        vdp.set_kind(Kind::Gg);
+        vdp.set_register(0, 0x04);
+        assert_eq!(frame(&mut vdp).resolution(), (160, 144));
+    }
+}
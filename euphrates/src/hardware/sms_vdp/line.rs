@@ -66,3 +66,93 @@ where
 
     return Ok(());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines on which interrupts were triggered in a frame.
+    #[derive(Debug, Eq, PartialEq)]
+    struct FrameInterrupts {
+        line: Vec<u16>,
+        frame: Vec<u16>,
+    }
+
+    /// Run `vdp` for a frame, starting from line 0, noting the line just
+    /// finished whenever a line or frame interrupt is triggered.
+    fn interrupts(vdp: &mut SmsVdpState) -> FrameInterrupts {
+        assert_eq!(vdp.v(), 0);
+        let mut result = FrameInterrupts {
+            line: Vec::new(),
+            frame: Vec::new(),
+        };
+        for _ in 0..vdp.total_lines() {
+            let v = vdp.v();
+            line(&mut SmsVdpGraphicsImpler {
+                graphics: &mut FakeSmsGraphics,
+                vdp: &mut *vdp,
+                overscan: false,
+            }).unwrap();
+            if vdp.line_interrupt_pending() {
+                result.line.push(v);
+                vdp.set_line_interrupt_pending(false);
+            }
+            let flags = vdp.status_flags();
+            if flags & FRAME_INTERRUPT_FLAG != 0 {
+                result.frame.push(v);
+                vdp.set_status_flags(flags & !FRAME_INTERRUPT_FLAG);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn line_interrupts() {
+        // The line counter is decremented going into each of lines 0 through
+        // 192, and reloaded from register 10 going into any other line. Since
+        // it's 0 at power on, the first frame begins with an interrupt.
+        fn check(reg10: u8, first: Vec<u16>, later: Vec<u16>) {
+            let mut vdp = SmsVdpState::default();
+            vdp.set_register(0, 0x04); // mode 4
+            vdp.set_register(10, reg10);
+            assert_eq!(vdp.active_lines(), 192);
+            assert_eq!(vdp.total_lines(), 262);
+            let frame = vec![192];
+            assert_eq!(
+                interrupts(&mut vdp),
+                FrameInterrupts {
+                    line: first,
+                    frame: frame.clone(),
+                },
+                "first frame with register 10 = {}",
+                reg10
+            );
+            for _ in 0..2 {
+                assert_eq!(
+                    interrupts(&mut vdp),
+                    FrameInterrupts {
+                        line: later.clone(),
+                        frame: frame.clone(),
+                    },
+                    "later frame with register 10 = {}",
+                    reg10
+                );
+            }
+        }
+
+        // every line, including the last line of the frame, going into line 0
+        let every: Vec<u16> = (0..192).chain(Some(261)).collect();
+        check(0, every.clone(), every);
+
+        // every other line
+        let every_other: Vec<u16> = (0..96).map(|i| 2 * i).collect();
+        check(1, every_other.clone(), every_other);
+
+        // after the first frame, counting starts going into line 0 again
+        check(49, vec![0, 50, 100, 150], vec![48, 98, 148]);
+
+        // the counter is reloaded before it can run out
+        check(200, vec![0], vec![]);
+        check(0xFF, vec![0], vec![]);
+    }
+}
@@ -50,6 +50,21 @@ impl Default for Kind {
     }
 }
 
+/// Which display mode do the mode select bits choose?
+///
+/// `Graphics1`, `Graphics2`, `Multicolor`, and `Text` are the legacy modes of
+/// the TMS9918 the VDP is based on. See `SmsVdpInternal::mode`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum Mode {
+    Graphics1,
+    Graphics2,
+    Multicolor,
+    Text,
+    Mode4,
+    /// Some combination of mode select bits not documented for the TMS9918.
+    Invalid,
+}
+
 /// Low, Medium or High resolution?
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum Resolution {
@@ -123,11 +123,7 @@ where
                 self.set_address(addr + 1);
             } else if code == 2 {
                 let which_reg = x & 0xF;
-                if which_reg < 11 {
-                    unsafe {
-                        self.set_register_unchecked(which_reg as u16, low_byte as u8);
-                    }
-                }
+                self.set_register(which_reg as u16, low_byte as u8);
             }
         } else {
             self.set_control_flag(true);
@@ -137,7 +133,7 @@ where
     }
 
     fn read_control(&mut self) -> u8 {
-        let current_status = self.status_flags();
+        let current_status = self.status_flags() | self.sprite_number();
         self.set_status_flags(0);
         self.set_control_flag(false);
         self.set_line_interrupt_pending(false);
@@ -174,3 +170,86 @@ where
         result as u8
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of `read_v` on each line of a frame.
+    fn v_counter(tv_system: TvSystem, reg0: u8, reg1: u8) -> Vec<u8> {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_kind(Kind::Sms2);
+        vdp.set_tv_system(tv_system);
+        vdp.set_register(0, reg0);
+        vdp.set_register(1, reg1);
+        (0..vdp.total_lines())
+            .map(|v| {
+                vdp.set_v(v);
+                vdp.read_v()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_v() {
+        use self::TvSystem::*;
+
+        let ntsc = v_counter(Ntsc, 0x04, 0x40);
+        assert_eq!(ntsc.len(), 262);
+        assert_eq!(&ntsc[0xD9..0xDD], &[0xD9, 0xDA, 0xD5, 0xD6]);
+        assert_eq!(ntsc[261], 0xFF);
+
+        let pal = v_counter(Pal, 0x04, 0x40);
+        assert_eq!(pal.len(), 313);
+        assert_eq!(&pal[0xF1..0xF5], &[0xF1, 0xF2, 0xBA, 0xBB]);
+        assert_eq!(pal[312], 0xFF);
+
+        // 224 lines
+        let ntsc = v_counter(Ntsc, 0x06, 0x50);
+        assert_eq!(&ntsc[0xE9..0xED], &[0xE9, 0xEA, 0xE5, 0xE6]);
+        let pal = v_counter(Pal, 0x06, 0x50);
+        assert_eq!(&pal[0xFF..0x105], &[0xFF, 0x00, 0x01, 0x02, 0xCA, 0xCB]);
+        assert_eq!(pal[312], 0xFF);
+    }
+
+    #[test]
+    fn v_counter_jumps() {
+        use self::TvSystem::*;
+
+        // (TV system, registers 0 and 1, and the values on either side of
+        // each place the counter doesn't just go up by 1)
+        type Jumps = &'static [(u8, u8)];
+        let cases: [(TvSystem, u8, u8, Jumps); 6] = [
+            (Ntsc, 0x04, 0x40, &[(0xDA, 0xD5)]),
+            (Ntsc, 0x06, 0x50, &[(0xEA, 0xE5)]),
+            (Ntsc, 0x06, 0x48, &[(0xFF, 0x00)]),
+            (Pal, 0x04, 0x40, &[(0xF2, 0xBA)]),
+            (Pal, 0x06, 0x50, &[(0xFF, 0x00), (0x02, 0xCA)]),
+            (Pal, 0x06, 0x48, &[(0xFF, 0x00), (0x0A, 0xD2)]),
+        ];
+        for &(tv_system, reg0, reg1, expected) in cases.iter() {
+            let values = v_counter(tv_system, reg0, reg1);
+            let jumps: Vec<(u8, u8)> = values
+                .windows(2)
+                .filter(|w| w[1] as u16 != w[0] as u16 + 1)
+                .map(|w| (w[0], w[1]))
+                .collect();
+            assert_eq!(
+                &jumps[..],
+                expected,
+                "{:?} {:0>2X} {:0>2X}",
+                tv_system,
+                reg0,
+                reg1
+            );
+            // and every frame ends at FF, but for NTSC 240 line mode
+            let last = if tv_system == Ntsc && reg1 == 0x48 {
+                0x05
+            } else {
+                0xFF
+            };
+            assert_eq!(values[0], 0);
+            assert_eq!(values[values.len() - 1], last);
+        }
+    }
+}
@@ -36,6 +36,17 @@ pub trait SmsVdpInternal {
     /// Set the status flags byte.
     fn set_status_flags(&mut self, _: u8);
 
+    /// The low 5 bits of the status register.
+    ///
+    /// In the TMS9918 modes, if the sprite overflow flag is set, this is the
+    /// number of the fifth sprite on the line where it was set. Otherwise it's
+    /// the number of the last sprite looked at on the most recent line. Mode 4
+    /// leaves it alone.
+    fn sprite_number(&self) -> u8;
+
+    /// Set the low 5 bits of the status register.
+    fn set_sprite_number(&mut self, _: u8);
+
     /// Flag indicating whether the control port has been written to.
     fn control_flag(&self) -> bool;
 
@@ -231,6 +242,90 @@ pub trait SmsVdpInternal {
         unsafe { self.register_unchecked(0) & (1 << 2) != 0 }
     }
 
+    /// Which display mode are we in?
+    ///
+    /// If `m4` is set, it's `Mode4`, whatever the other bits are; its
+    /// variations are described by `resolution`. Otherwise, we're in one of
+    /// the TMS9918 modes: `Graphics1` with no bits set, `Graphics2` with `m2`,
+    /// `Multicolor` with `m3`, and `Text` with `m1`.
+    #[inline]
+    fn mode(&self) -> Mode {
+        match (self.m4(), self.m3(), self.m2(), self.m1()) {
+            (true, _, _, _) => Mode::Mode4,
+            (false, false, false, false) => Mode::Graphics1,
+            (false, false, true, false) => Mode::Graphics2,
+            (false, true, false, false) => Mode::Multicolor,
+            (false, false, false, true) => Mode::Text,
+            _ => Mode::Invalid,
+        }
+    }
+
+    /// Where in VRAM is the name table in the TMS9918 modes?
+    ///
+    /// Bits 0 through 3 of register 2 form bits 10 through 13 of this address.
+    #[inline]
+    fn tms_name_table_address(&self) -> u16 {
+        unsafe { (self.register_unchecked(2) as u16 & 0xF) << 10 }
+    }
+
+    /// Where in VRAM is the pattern generator table in the TMS9918 modes?
+    ///
+    /// Bits 0 through 2 of register 4 form bits 11 through 13 of this address.
+    /// In `Graphics2` mode, the table is 6 KiB long, and only bit 2 counts.
+    #[inline]
+    fn tms_pattern_table_address(&self) -> u16 {
+        let reg4 = unsafe { self.register_unchecked(4) as u16 };
+        if self.mode() == Mode::Graphics2 {
+            (reg4 & 4) << 11
+        } else {
+            (reg4 & 7) << 11
+        }
+    }
+
+    /// Where in VRAM is the color table in the TMS9918 modes?
+    ///
+    /// Register 3 forms bits 6 through 13 of this address. In `Graphics2` mode,
+    /// the table is 6 KiB long, and only bit 7 counts.
+    #[inline]
+    fn tms_color_table_address(&self) -> u16 {
+        let reg3 = unsafe { self.register_unchecked(3) as u16 };
+        if self.mode() == Mode::Graphics2 {
+            (reg3 & 0x80) << 6
+        } else {
+            reg3 << 6
+        }
+    }
+
+    /// Where in VRAM is the sprite attribute table in the TMS9918 modes?
+    ///
+    /// Bits 0 through 6 of register 5 form bits 7 through 13 of this address.
+    #[inline]
+    fn tms_sprite_attribute_table_address(&self) -> u16 {
+        unsafe { (self.register_unchecked(5) as u16 & 0x7F) << 7 }
+    }
+
+    /// Where in VRAM is the sprite pattern table in the TMS9918 modes?
+    ///
+    /// Bits 0 through 2 of register 6 form bits 11 through 13 of this address.
+    #[inline]
+    fn tms_sprite_pattern_table_address(&self) -> u16 {
+        unsafe { (self.register_unchecked(6) as u16 & 7) << 11 }
+    }
+
+    /// How many screen pixels wide and tall is a sprite in the TMS9918 modes?
+    ///
+    /// Sprites are 8x8, or 16x16 if bit 1 of register 1 is set, and zooming
+    /// doubles that.
+    #[inline]
+    fn tms_sprite_size(&self) -> u16 {
+        let size = if self.tall_sprites() { 16 } else { 8 };
+        if self.zoomed_sprites() {
+            2 * size
+        } else {
+            size
+        }
+    }
+
     /// High, Medium, or Low resolution?
     ///
     /// The SMS VDP is always in Low resolution. The SMS2 and GG VDPs have
@@ -344,6 +439,18 @@ pub trait SmsVdpInternal {
         unsafe { self.register_unchecked(1) & (1 << 1) != 0 }
     }
 
+    /// How many lines of pattern does a sprite have in Mode 4?
+    ///
+    /// 16 if `tall_sprites`, and otherwise 8. This doesn't account for zoom.
+    #[inline]
+    fn sprite_height(&self) -> u16 {
+        if self.tall_sprites() {
+            16
+        } else {
+            8
+        }
+    }
+
     /// Are sprites zoomed (bit 0 of register 1)?
     ///
     /// Normal sprites are 8x8 pixels while tall ones are 8x16. Zooming them
@@ -373,6 +480,19 @@ pub trait SmsVdpInternal {
         }
     }
 
+    /// How many rows of tiles are in the name table?
+    ///
+    /// 28 in Low resolution, and 32 in Medium or High resolution. Vertical
+    /// scrolling wraps around after this many rows.
+    #[inline]
+    fn name_table_rows(&self) -> u16 {
+        if self.resolution() == Resolution::Low {
+            28
+        } else {
+            32
+        }
+    }
+
     /// Name table mask.
     ///
     /// Whenever looking up a value in the name table, you should AND your
@@ -395,6 +515,19 @@ pub trait SmsVdpInternal {
         unsafe { self.register_unchecked(8) }
     }
 
+    /// The horizontal scroll actually applied to `line`.
+    ///
+    /// This is `x_scroll`, except that it's 0 for the top 16 lines if
+    /// `horiz_scroll_locked`.
+    #[inline]
+    fn line_x_scroll(&self, line: u16) -> u8 {
+        if self.horiz_scroll_locked() && line < 16 {
+            0
+        } else {
+            self.x_scroll()
+        }
+    }
+
     /// Is the leftmost tile column blanked (bit 5 of register 0)? (Convenience
     /// method.)
     ///
@@ -671,6 +804,24 @@ pub trait SmsVdpInternal {
         unsafe { self.cram_unchecked(index) }
     }
 
+    /// The Color RAM entry at `index` as a 12 bit color, `0x0BGR`; panics for
+    /// `index > 31`.
+    ///
+    /// Game Gear entries already have this format, being written a byte at a
+    /// time through `cram_latch`. Master System entries only have 6 bits,
+    /// `00BBGGRR`, so each 2 bit component `c` is expanded to `5 * c`.
+    #[inline]
+    fn cram12(&self, index: u16) -> u16 {
+        let entry = self.cram(index);
+        if self.kind() == Kind::Gg {
+            return entry & 0x0FFF;
+        }
+        let red = entry & 3;
+        let green = (entry >> 2) & 3;
+        let blue = (entry >> 4) & 3;
+        5 * (red | green << 4 | blue << 8)
+    }
+
     /// Safely set value in the Color RAM; will panic for `index > 31`.
     #[inline]
     fn set_cram(&mut self, index: u16, value: u16) {
@@ -691,14 +842,33 @@ pub trait SmsVdpInternal {
         unsafe { self.register_unchecked(index) }
     }
 
-    /// Safely set the value of a numbered register; panics if `index > 10`.
+    /// All the numbered registers.
+    ///
+    /// The control port can address 16 registers, but only the first 11
+    /// exist; the last 5 entries are always 0.
+    #[inline]
+    fn registers(&self) -> [u8; 16] {
+        let mut registers = [0u8; 16];
+        for (i, register) in registers.iter_mut().take(11).enumerate() {
+            *register = unsafe { self.register_unchecked(i as u16) };
+        }
+        registers
+    }
+
+    /// Set the value of a numbered register, just as writing to the control
+    /// port would.
+    ///
+    /// Like the hardware, this ignores writes to the nonexistent registers 11
+    /// through 15. Panics if `index > 15`.
     #[inline]
     fn set_register(&mut self, index: u16, value: u8) {
-        if index > 10 {
+        if index > 15 {
             panic!("Register index {} out of bounds", index);
         }
-        unsafe {
-            self.set_register_unchecked(index, value);
+        if index < 11 {
+            unsafe {
+                self.set_register_unchecked(index, value);
+            }
         }
     }
 
@@ -877,6 +1047,16 @@ impl SmsVdpInternal for SmsVdpState {
         self.status_flags = low_bits | high_bits;
     }
 
+    #[inline]
+    fn sprite_number(&self) -> u8 {
+        self.status_flags & 0x1F
+    }
+
+    #[inline]
+    fn set_sprite_number(&mut self, x: u8) {
+        self.status_flags = (self.status_flags & 0xE0) | (x & 0x1F);
+    }
+
     #[inline]
     fn control_flag(&self) -> bool {
         self.other_flags & 1 != 0
@@ -1029,3 +1209,211 @@ impl SmsVdpInternal for SmsVdpState {
         *self.reg.get_unchecked_mut(index as usize) = value;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `vdp` for a frame, returning the lines on which a line interrupt
+    /// was triggered.
+    fn line_interrupts(vdp: &mut SmsVdpState) -> Vec<u16> {
+        let mut lines = Vec::new();
+        for _ in 0..vdp.total_lines() {
+            let v = vdp.v();
+            line(&mut SmsVdpGraphicsImpler {
+                graphics: &mut FakeSmsGraphics,
+                vdp: &mut *vdp,
+                overscan: false,
+            }).unwrap();
+            if vdp.line_interrupt_pending() {
+                lines.push(v);
+                vdp.set_line_interrupt_pending(false);
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn set_register_matches_control_port() {
+        let mut hardware = SmsVdpState::default();
+        hardware.write_control(0x10);
+        hardware.write_control(0x80);
+        hardware.write_control(49);
+        hardware.write_control(0x8A);
+        hardware.write_control(0xFF);
+        hardware.write_control(0x8F);
+
+        let mut setter = SmsVdpState::default();
+        setter.set_register(0, 0x10);
+        setter.set_register(10, 49);
+        setter.set_register(15, 0xFF);
+
+        // the control port writes also leave the address register set
+        setter.set_code_address(hardware.code_address());
+
+        assert!(hardware == setter);
+        assert_eq!(setter.registers()[10], 49);
+        assert_eq!(setter.registers()[15], 0);
+
+        let lines = line_interrupts(&mut hardware);
+        assert_eq!(lines, line_interrupts(&mut setter));
+        // the line counter starts at 0, so the first line reloads it
+        assert_eq!(lines, vec![0, 50, 100, 150]);
+        assert!(hardware == setter);
+    }
+
+    #[test]
+    fn cram12() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_kind(Kind::Gg);
+        // CRAM write starting at byte 2, which is entry 1
+        vdp.write_control(0x02);
+        vdp.write_control(0xC0);
+        vdp.write_data(0x5A);
+        // nothing's written until the high byte arrives
+        assert_eq!(vdp.cram12(1), 0);
+        vdp.write_data(0x03);
+        assert_eq!(vdp.cram12(1), 0x035A);
+
+        let mut vdp = SmsVdpState::default();
+        vdp.write_control(0x01);
+        vdp.write_control(0xC0);
+        vdp.write_data(0x39); // blue 3, green 2, red 1
+        assert_eq!(vdp.cram(1), 0x39);
+        assert_eq!(vdp.cram12(1), 0x0FA5);
+    }
+
+    #[test]
+    fn pattern_lines() {
+        let mut vdp = SmsVdpState::default();
+        // Each line of a mode 4 pattern is 4 bytes, one for each bitplane.
+        // Line `l` has pixel `l` in plane 0 and pixel `7 - l` in plane 1, and
+        // is filled in plane 2 for odd lines and plane 3 for the bottom half.
+        let address = 0x120;
+        for l in 0..8u16 {
+            let line_address = address + 4 * l;
+            vdp.set_vram(line_address, 0x80 >> l);
+            vdp.set_vram(line_address + 1, 0x01 << l);
+            vdp.set_vram(line_address + 2, if l % 2 == 1 { 0xFF } else { 0 });
+            vdp.set_vram(line_address + 3, if l >= 4 { 0xFF } else { 0 });
+        }
+        for l in 0..8u16 {
+            let indices = unsafe { vdp.pattern_address_to_palette_indices(address, l) };
+            let mut expected = [0u8; 8];
+            for x in 0..8u16 {
+                let mut index = 0;
+                if x == l {
+                    index |= 1;
+                }
+                if x == 7 - l {
+                    index |= 2;
+                }
+                if l % 2 == 1 {
+                    index |= 4;
+                }
+                if l >= 4 {
+                    index |= 8;
+                }
+                expected[x as usize] = index;
+            }
+            assert_eq!(indices, expected, "line {}", l);
+        }
+    }
+
+    #[test]
+    fn derived_addresses() {
+        let mut vdp = SmsVdpState::default();
+        vdp.set_kind(Kind::Sms2);
+        vdp.set_register(0, 0x44); // mode 4, horizontal scroll locked
+        vdp.set_register(1, 0x42); // display on, tall sprites
+        vdp.set_register(2, 0x0D); // name table at 0x3000
+        vdp.set_register(5, 0x7F); // sprite attribute table at 0x3F00
+        vdp.set_register(6, 0x04); // sprite patterns at 0x2000
+        vdp.set_register(8, 0x25); // x scroll
+        assert_eq!(vdp.mode(), Mode::Mode4);
+        assert_eq!(vdp.name_table_address(), 0x3000);
+        assert_eq!(vdp.name_table_rows(), 28);
+        assert_eq!(vdp.sprite_attribute_table_address(), 0x3F00);
+        assert_eq!(vdp.sprite_pattern_table_address(), 0x2000);
+        assert_eq!(vdp.sprite_height(), 16);
+        assert_eq!(vdp.line_x_scroll(15), 0);
+        assert_eq!(vdp.line_x_scroll(16), 0x25);
+
+        // 224 lines: the name table is taller, and always at 0x3700
+        vdp.set_register(0, 0x06);
+        vdp.set_register(1, 0x50);
+        assert_eq!(vdp.resolution(), Resolution::Medium);
+        assert_eq!(vdp.name_table_address(), 0x3700);
+        assert_eq!(vdp.name_table_rows(), 32);
+        assert_eq!(vdp.sprite_height(), 8);
+        assert_eq!(vdp.line_x_scroll(0), 0x25);
+
+        let mut vdp = SmsVdpState::default();
+        vdp.set_register(0, 0x02); // Graphics 2
+        vdp.set_register(1, 0x43); // display on, large zoomed sprites
+        vdp.set_register(2, 0x0E); // name table at 0x3800
+        vdp.set_register(3, 0xFF); // color table at 0x2000
+        vdp.set_register(4, 0x03); // pattern table at 0
+        vdp.set_register(5, 0x36); // sprite attribute table at 0x1B00
+        vdp.set_register(6, 0x07); // sprite patterns at 0x3800
+        assert_eq!(vdp.mode(), Mode::Graphics2);
+        assert_eq!(vdp.tms_name_table_address(), 0x3800);
+        assert_eq!(vdp.tms_color_table_address(), 0x2000);
+        assert_eq!(vdp.tms_pattern_table_address(), 0);
+        assert_eq!(vdp.tms_sprite_attribute_table_address(), 0x1B00);
+        assert_eq!(vdp.tms_sprite_pattern_table_address(), 0x3800);
+        assert_eq!(vdp.tms_sprite_size(), 32);
+    }
+
+    #[test]
+    fn active_lines() {
+        use self::Kind::*;
+        use self::TvSystem::*;
+
+        // mode 4, display on; and then the extra mode bits for 224 and 240
+        // lines
+        let modes = [(0x04, 0x40), (0x06, 0x50), (0x06, 0x48)];
+        let cases = [
+            (Sms2, Ntsc, modes[0], 192),
+            (Sms2, Ntsc, modes[1], 224),
+            (Sms2, Pal, modes[1], 224),
+            (Sms2, Pal, modes[2], 240),
+            (Gg, Ntsc, modes[1], 224),
+            // the original Master System VDP only has 192 lines
+            (Sms, Ntsc, modes[1], 192),
+            (Sms, Pal, modes[2], 192),
+        ];
+        for &(kind, tv_system, (reg0, reg1), lines) in cases.iter() {
+            let mut vdp = SmsVdpState::default();
+            vdp.set_kind(kind);
+            vdp.set_tv_system(tv_system);
+            vdp.set_register(0, reg0);
+            vdp.set_register(1, reg1);
+            assert_eq!(vdp.active_lines(), lines);
+
+            // the frame interrupt comes at the end of the line after the
+            // active display
+            let mut frame_interrupt_line = None;
+            for _ in 0..vdp.total_lines() {
+                let v = vdp.v();
+                line(&mut SmsVdpGraphicsImpler {
+                    graphics: &mut FakeSmsGraphics,
+                    vdp: &mut vdp,
+                    overscan: false,
+                }).unwrap();
+                if vdp.status_flags() & FRAME_INTERRUPT_FLAG != 0 {
+                    frame_interrupt_line = Some(v);
+                    break;
+                }
+            }
+            assert_eq!(
+                frame_interrupt_line,
+                Some(lines),
+                "{:?} {:?} {}",
+                kind,
+                tv_system,
+                lines
+            );
+        }
+    }
+}
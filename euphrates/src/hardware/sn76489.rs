@@ -7,13 +7,77 @@ use host_multimedia::SimpleAudio;
 /// The hardware interface for the SN76489 sound chip.
 pub trait Sn76489Interface {
     fn write(&mut self, data: u8);
+
+    /// Write to the Game Gear's stereo control register.
+    ///
+    /// Bits 4 through 7 turn on channels 0 through 3 in the left speaker, and
+    /// bits 0 through 3 turn them on in the right speaker.
+    fn write_stereo(&mut self, data: u8);
+
+    /// Silence channel `channel` (0 through 2 for the tones, 3 for noise) in
+    /// the audio output, or stop silencing it.
+    ///
+    /// This is for debugging and listening to individual channels; it has no
+    /// effect on the chip's registers.
+    fn set_channel_muted(&mut self, channel: u8, muted: bool);
+
+    /// Has `channel` been muted with `set_channel_muted`?
+    fn channel_muted(&self, channel: u8) -> bool;
 }
 
 pub trait Sn76489Audio {
-    fn queue(&mut self, target_cycles: u64) -> Result<(), Error>;
+    /// Queue audio samples until the SN76489 has caught up with the Z80 at
+    /// `z80_cycles`.
+    fn queue(&mut self, z80_cycles: u64) -> Result<(), Error>;
     fn hold(&mut self);
 }
 
+/// Which kind of shift register generates the noise channel?
+///
+/// The two differ in their widths and in which bits are tapped for white
+/// noise, and so in the sound of their noise.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum NoiseKind {
+    /// Sega's variant, in the Master System and Game Gear: 16 bits, with
+    /// bits 0 and 3 tapped.
+    Sms,
+
+    /// The original TI chip, in the SG-1000: 15 bits, with bits 0 and 1
+    /// tapped.
+    Sg1000,
+}
+
+impl Default for NoiseKind {
+    #[inline]
+    fn default() -> Self {
+        NoiseKind::Sms
+    }
+}
+
+impl NoiseKind {
+    /// The shift register's value when the noise channel is reset: just its
+    /// top bit.
+    #[inline]
+    pub fn reset_value(self) -> u16 {
+        match self {
+            NoiseKind::Sms => 0x8000,
+            NoiseKind::Sg1000 => 0x4000,
+        }
+    }
+
+    /// The bits XORed together to feed white noise back into the top bit.
+    #[inline]
+    fn taps(self) -> u16 {
+        match self {
+            NoiseKind::Sms => 0x0009,
+            NoiseKind::Sg1000 => 0x0003,
+        }
+    }
+}
+
+/// The usual clock divider: the SN76489 runs at 1/16 the speed of the Z80.
+pub const DEFAULT_CLOCK_DIVIDER: u16 = 16;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Sn76489State {
     // registers for the 4 different channels, in this order:
@@ -26,7 +90,28 @@ pub struct Sn76489State {
     pub linear_feedback: u16,
     pub counters: [u16; 4],
     pub polarity: [i8; 4],
+    /// SN76489 cycles; each is one audio sample. There's one of these every
+    /// `clock_divider` Z80 cycles.
     pub cycles: u64,
+    /// How many Z80 cycles per SN76489 cycle?
+    ///
+    /// This determines both the audio sample rate and, since tone periods are
+    /// counted in SN76489 cycles, the frequency of each tone: a tone register
+    /// value `n` makes a tone of `z80_frequency / (2 * n * clock_divider)` Hz.
+    /// Usually `DEFAULT_CLOCK_DIVIDER`.
+    pub clock_divider: u16,
+    /// The Game Gear's stereo control register. See
+    /// `Sn76489Interface::write_stereo`.
+    pub stereo: u8,
+    /// Bit `n` is set if channel `n` is muted. See
+    /// `Sn76489Interface::set_channel_muted`.
+    ///
+    /// Since it's not part of the chip's state, this isn't serialized.
+    #[serde(skip)]
+    pub muted: u8,
+    /// Which noise generator the chip has. Unlike `muted`, this is hardware,
+    /// so it's serialized.
+    pub noise_kind: NoiseKind,
 }
 
 pub trait HasSn76489State {
@@ -54,10 +139,33 @@ impl Default for Sn76489State {
             counters: [1, 1, 1, 1],
             polarity: [1, 1, 1, 1],
             cycles: 0,
+            clock_divider: DEFAULT_CLOCK_DIVIDER,
+            stereo: 0xFF,
+            muted: 0,
+            noise_kind: NoiseKind::Sms,
         }
     }
 }
 
+impl Sn76489State {
+    /// Shift the noise channel's shift register, returning the bit shifted
+    /// out.
+    ///
+    /// White noise feeds the tapped bits back into the top; periodic noise
+    /// feeds back just the bit shifted out.
+    pub fn shift_noise(&mut self) -> bool {
+        let lfsr = self.linear_feedback;
+        let feedback = if self.registers[6] & 4 != 0 {
+            (lfsr & self.noise_kind.taps()).count_ones() as u16 & 1
+        } else {
+            lfsr & 1
+        };
+        let top = self.noise_kind.reset_value().trailing_zeros();
+        self.linear_feedback = feedback << top | lfsr >> 1;
+        lfsr & 1 != 0
+    }
+}
+
 impl Sn76489Interface for Sn76489State {
     fn write(&mut self, data: u8) {
         if data & 0x80 != 0 {
@@ -77,9 +185,26 @@ impl Sn76489Interface for Sn76489State {
             }
         }
         if self.latch == 6 {
-            self.linear_feedback = 0x8000;
+            self.linear_feedback = self.noise_kind.reset_value();
         }
     }
+
+    fn write_stereo(&mut self, data: u8) {
+        self.stereo = data;
+    }
+
+    fn set_channel_muted(&mut self, channel: u8, muted: bool) {
+        debug_assert!(channel < 4);
+        if muted {
+            self.muted |= 1 << channel;
+        } else {
+            self.muted &= !(1 << channel);
+        }
+    }
+
+    fn channel_muted(&self, channel: u8) -> bool {
+        self.muted & 1 << channel != 0
+    }
 }
 
 macro_rules! min_nonzero {
@@ -105,7 +230,7 @@ pub struct Sn76489Impler<'a, Sn76489: 'a, Audio: 'a> {
 
 impl<'a, Audio: 'a> Sn76489Audio for Sn76489Impler<'a, FakeSn76489, Audio> {
     #[inline(always)]
-    fn queue(&mut self, _target_cycles: u64) -> Result<(), Error> {
+    fn queue(&mut self, _z80_cycles: u64) -> Result<(), Error> {
         Ok(())
     }
 
@@ -117,7 +242,8 @@ impl<'a, Audio: 'a> Sn76489Audio for Sn76489Impler<'a, Sn76489State, Audio>
 where
     Audio: SimpleAudio,
 {
-    fn queue(&mut self, target_cycles: u64) -> Result<(), Error> {
+    fn queue(&mut self, z80_cycles: u64) -> Result<(), Error> {
+        let target_cycles = z80_cycles / self.sn76489.clock_divider as u64;
         if self.sn76489.cycles >= target_cycles {
             return Ok(());
         }
@@ -147,12 +273,26 @@ where
             }
         }
 
-        let amplitudes: [i16; 4] = [
+        let mut amplitudes: [i16; 4] = [
             convert_volume(self.sn76489.registers[1]),
             convert_volume(self.sn76489.registers[3]),
             convert_volume(self.sn76489.registers[5]),
             convert_volume(self.sn76489.registers[7]),
         ];
+        for (j, amplitude) in amplitudes.iter_mut().enumerate() {
+            if self.sn76489.channel_muted(j as u8) {
+                *amplitude = 0;
+            }
+        }
+
+        // Multiply by these to silence channels turned off on either side.
+        let stereo = self.sn76489.stereo;
+        let mut left_on = [0i16; 4];
+        let mut right_on = [0i16; 4];
+        for j in 0..4 {
+            left_on[j] = (stereo >> (j + 4)) as i16 & 1;
+            right_on[j] = (stereo >> j) as i16 & 1;
+        }
 
         {
             let mut i: usize = 0;
@@ -161,7 +301,14 @@ where
                 let tone1 = self.sn76489.polarity[1] as i16 * amplitudes[1];
                 let tone2 = self.sn76489.polarity[2] as i16 * amplitudes[2];
                 let noise = self.sn76489.polarity[3] as i16 * amplitudes[3];
-                let sum = tone0 + tone1 + tone2 + noise;
+                let left = tone0 * left_on[0]
+                    + tone1 * left_on[1]
+                    + tone2 * left_on[2]
+                    + noise * left_on[3];
+                let right = tone0 * right_on[0]
+                    + tone1 * right_on[1]
+                    + tone2 * right_on[2]
+                    + noise * right_on[3];
                 debug_assert!(self.audio.buffer_len() <= u16::max_value() as usize);
                 let count = min_nonzero!(
                     (self.audio.buffer_len() - i) as u16,
@@ -172,7 +319,7 @@ where
                 );
                 let last_idx = count as usize + i;
                 for j in i..last_idx as usize {
-                    self.audio.buffer_set(j, sum);
+                    self.audio.buffer_set(j, left, right);
                 }
                 for j in 0..3 {
                     self.sn76489.counters[j] -= count;
@@ -193,20 +340,8 @@ where
                         2 => 0x80,
                         _ => 2 * self.sn76489.registers[4],
                     };
-                    let bit0 = 1 & self.sn76489.linear_feedback;
-                    let bit0_shifted = 1 << 15;
-                    self.sn76489.polarity[3] = 2 * (bit0 as i8) - 1;
-                    if self.sn76489.registers[6] & 4 != 0 {
-                        // white noise
-                        let bit3_shifted = (8 & self.sn76489.linear_feedback) << 12;
-                        let feed_bit = bit0_shifted ^ bit3_shifted;
-                        self.sn76489.linear_feedback =
-                            feed_bit | (self.sn76489.linear_feedback >> 1);
-                    } else {
-                        // "periodic noise"
-                        self.sn76489.linear_feedback =
-                            bit0_shifted | (self.sn76489.linear_feedback >> 1);
-                    }
+                    let bit = self.sn76489.shift_noise();
+                    self.sn76489.polarity[3] = 2 * (bit as i8) - 1;
                 }
                 i = last_idx;
             }
@@ -216,7 +351,7 @@ where
             self.audio.queue_buffer()?;
         }
 
-        self.queue(target_cycles)
+        self.queue(z80_cycles)
     }
 
     fn hold(&mut self) {
@@ -234,6 +369,17 @@ pub struct FakeSn76489;
 impl Sn76489Interface for FakeSn76489 {
     #[inline]
     fn write(&mut self, _data: u8) {}
+
+    #[inline]
+    fn write_stereo(&mut self, _data: u8) {}
+
+    #[inline]
+    fn set_channel_muted(&mut self, _channel: u8, _muted: bool) {}
+
+    #[inline]
+    fn channel_muted(&self, _channel: u8) -> bool {
+        false
+    }
 }
 
 impl HasSn76489State for FakeSn76489 {
@@ -245,3 +391,190 @@ impl HasSn76489State for FakeSn76489 {
         Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Audio that just remembers the samples it's been given.
+    #[derive(Default)]
+    struct RecordingAudio {
+        buffer: Vec<(i16, i16)>,
+        queued: Vec<(i16, i16)>,
+    }
+
+    impl SimpleAudio for RecordingAudio {
+        fn configure(&mut self, _frequency: u32, buffer_size: u16) -> Result<(), Error> {
+            self.buffer = vec![(0, 0); buffer_size as usize];
+            Ok(())
+        }
+
+        fn play(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn pause(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn buffer_len(&self) -> usize {
+            self.buffer.len()
+        }
+
+        fn buffer_set(&mut self, i: usize, left: i16, right: i16) {
+            self.buffer[i] = (left, right);
+        }
+
+        fn queue_buffer(&mut self) -> Result<(), Error> {
+            self.queued.extend_from_slice(&self.buffer);
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), Error> {
+            self.queued.clear();
+            Ok(())
+        }
+    }
+
+    fn tone0_samples(stereo: Option<u8>) -> Vec<(i16, i16)> {
+        tone0_samples_divider(stereo, DEFAULT_CLOCK_DIVIDER, 0x4000)
+    }
+
+    /// Tone 0 samples until Z80 cycle `z80_cycles`.
+    fn tone0_samples_divider(
+        stereo: Option<u8>,
+        clock_divider: u16,
+        z80_cycles: u64,
+    ) -> Vec<(i16, i16)> {
+        let mut sn76489 = Sn76489State {
+            clock_divider,
+            ..Default::default()
+        };
+        // tone 0 at period 0x40 and full volume
+        sn76489.write(0x80);
+        sn76489.write(0x04);
+        sn76489.write(0x90);
+        if let Some(x) = stereo {
+            sn76489.write_stereo(x);
+        }
+        let mut audio = RecordingAudio::default();
+        audio.configure(0, 0x100).unwrap();
+        Sn76489Impler {
+            sn76489: &mut sn76489,
+            audio: &mut audio,
+        }.queue(z80_cycles)
+            .unwrap();
+        audio.queued
+    }
+
+    #[test]
+    fn stereo() {
+        let mono = tone0_samples(None);
+        assert_eq!(mono.len(), 0x400);
+        assert!(mono.iter().any(|&(left, _)| left != 0));
+        assert!(mono.iter().all(|&(left, right)| left == right));
+
+        let left_only = tone0_samples(Some(0x10));
+        assert!(left_only.iter().all(|&(_, right)| right == 0));
+        let left: Vec<i16> = left_only.iter().map(|&(left, _)| left).collect();
+        let mono_left: Vec<i16> = mono.iter().map(|&(left, _)| left).collect();
+        assert_eq!(left, mono_left);
+    }
+
+    #[test]
+    fn clock_divider() {
+        fn changes(samples: &[(i16, i16)]) -> usize {
+            samples.windows(2).filter(|w| w[0] != w[1]).count()
+        }
+
+        let normal = tone0_samples_divider(None, 16, 0x10000);
+        let slow = tone0_samples_divider(None, 32, 0x10000);
+        assert_eq!(normal.len(), 0x1000);
+        assert_eq!(slow.len(), 0x800);
+        // a tone with period 0x40 changes polarity every 0x40 samples
+        assert_eq!(changes(&normal), 0x1000 / 0x40);
+        assert_eq!(changes(&slow), 0x800 / 0x40);
+    }
+
+    #[test]
+    fn muted_channels() {
+        fn samples(sn76489: &mut Sn76489State) -> Vec<(i16, i16)> {
+            let mut audio = RecordingAudio::default();
+            audio.configure(0, 0x100).unwrap();
+            Sn76489Impler {
+                sn76489,
+                audio: &mut audio,
+            }.queue(0x4000)
+                .unwrap();
+            audio.queued
+        }
+
+        // tones at periods 0x40, 0x30, and 0x20, and white noise, all at full
+        // volume
+        let writes = [0x80, 0x04, 0x90, 0xA0, 0x03, 0xB0, 0xC0, 0x02, 0xD0, 0xE4, 0xF0];
+        let mut all = Sn76489State::default();
+        for &x in writes.iter() {
+            all.write(x);
+        }
+        let mut only_tone0 = all;
+        for &x in [0xBF, 0xDF, 0xFF].iter() {
+            only_tone0.write(x);
+        }
+
+        for channel in 1..4 {
+            all.set_channel_muted(channel, true);
+        }
+        assert!(!all.channel_muted(0));
+        assert!(all.channel_muted(3));
+        let registers = all.registers;
+        let samples_all = samples(&mut all);
+        assert_eq!(all.registers, registers);
+        assert!(samples_all.iter().any(|&(left, _)| left != 0));
+        assert_eq!(samples_all, samples(&mut only_tone0));
+
+        all.set_channel_muted(2, false);
+        assert!(!all.channel_muted(2));
+    }
+
+    #[test]
+    fn noise_kinds() {
+        fn noise(kind: NoiseKind, white: bool) -> Sn76489State {
+            let mut sn76489 = Sn76489State {
+                noise_kind: kind,
+                ..Default::default()
+            };
+            sn76489.write(if white { 0xE4 } else { 0xE0 });
+            sn76489
+        }
+
+        fn bits(sn76489: &mut Sn76489State, count: usize) -> Vec<bool> {
+            (0..count).map(|_| sn76489.shift_noise()).collect()
+        }
+
+        // periodic noise is the reset bit going around the register
+        for &(kind, width) in [(NoiseKind::Sms, 16), (NoiseKind::Sg1000, 15)].iter() {
+            let mut sn76489 = noise(kind, false);
+            let expected: Vec<bool> = (0..3 * width).map(|i| i % width == width - 1).collect();
+            assert_eq!(bits(&mut sn76489, 3 * width), expected);
+        }
+
+        // white noise repeats with the periods of the two feedback polynomials
+        for &(kind, period) in [(NoiseKind::Sms, 57337), (NoiseKind::Sg1000, 32767)].iter() {
+            let mut sn76489 = noise(kind, true);
+            let start = sn76489.linear_feedback;
+            let mut count = 0;
+            loop {
+                sn76489.shift_noise();
+                count += 1;
+                if sn76489.linear_feedback == start {
+                    break;
+                }
+            }
+            assert_eq!(count, period);
+        }
+
+        let sms = bits(&mut noise(NoiseKind::Sms, true), 100);
+        let sg1000 = bits(&mut noise(NoiseKind::Sg1000, true), 100);
+        assert_ne!(sms, sg1000);
+    }
+}
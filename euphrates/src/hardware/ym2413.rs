@@ -0,0 +1,613 @@
+//! The YM2413, or OPLL, is the FM sound chip in the Japanese Sega Master
+//! System and in the FM Sound Unit for the Mark III.
+//!
+//! This is a model of the chip's registers, not of its circuitry: operators
+//! are computed with floating point sines and envelopes rather than the chip's
+//! log-sin and exponent tables, and the rhythm instruments are approximations.
+
+use std::f32::consts::PI;
+
+use failure::Error;
+
+use host_multimedia::SimpleAudio;
+
+/// How many Z80 cycles per YM2413 sample?
+///
+/// The YM2413 runs from the same 3.58 MHz clock as the Z80, and makes one
+/// sample every 72 cycles.
+pub const Z80_CYCLES_PER_SAMPLE: u32 = 72;
+
+/// Samples per second, with the NTSC clock.
+const SAMPLE_RATE: f32 = 3579545.0 / Z80_CYCLES_PER_SAMPLE as f32;
+
+/// The attenuation, in dB, at which an operator is silent.
+const SILENT: f32 = 48.0;
+
+/// How loud is each channel at full volume, compared to the SN76489?
+const CHANNEL_AMPLITUDE: f32 = 2048.0;
+
+/// How far, in radians, does a modulator at full volume shift its carrier's
+/// phase?
+const MODULATION_DEPTH: f32 = 4.0 * PI;
+
+/// The built in instruments 1 through 15.
+///
+/// Each instrument is 8 bytes laid out like the custom instrument in registers
+/// 0x00 through 0x07; see `Ym2413State`.
+const INSTRUMENTS: [[u8; 8]; 15] = [
+    [0x71, 0x61, 0x1E, 0x17, 0xD0, 0x78, 0x00, 0x17], // violin
+    [0x13, 0x41, 0x1A, 0x0D, 0xD8, 0xF7, 0x23, 0x13], // guitar
+    [0x13, 0x01, 0x99, 0x00, 0xF2, 0xC4, 0x11, 0x23], // piano
+    [0x31, 0x61, 0x0E, 0x07, 0xA8, 0x64, 0x70, 0x27], // flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE0, 0x76, 0x00, 0x28], // clarinet
+    [0x31, 0x22, 0x16, 0x05, 0xE0, 0x71, 0x00, 0x18], // oboe
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x10, 0x07], // trumpet
+    [0x23, 0x21, 0x2D, 0x14, 0xA2, 0x72, 0x00, 0x07], // organ
+    [0x61, 0x61, 0x1B, 0x06, 0x64, 0x65, 0x10, 0x17], // horn
+    [0x41, 0x61, 0x0B, 0x18, 0x85, 0xF7, 0x71, 0x07], // synthesizer
+    [0x13, 0x01, 0x83, 0x11, 0xFA, 0xE4, 0x10, 0x04], // harpsichord
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // vibraphone
+    [0x61, 0x50, 0x0C, 0x05, 0xC2, 0xF5, 0x20, 0x42], // synthesizer bass
+    [0x01, 0x01, 0x55, 0x03, 0xC9, 0x95, 0x03, 0x02], // acoustic bass
+    [0x61, 0x41, 0x89, 0x03, 0xF1, 0xE4, 0x40, 0x13], // electric guitar
+];
+
+/// The instruments used by channels 6, 7, and 8 in rhythm mode: bass drum,
+/// high hat and snare drum, and tom-tom and cymbal.
+const RHYTHM_INSTRUMENTS: [[u8; 8]; 3] = [
+    [0x01, 0x01, 0x18, 0x0F, 0xDF, 0xF8, 0x6A, 0x6D],
+    [0x01, 0x01, 0x00, 0x00, 0xC8, 0xD8, 0xA7, 0x48],
+    [0x05, 0x01, 0x00, 0x00, 0xF8, 0xAA, 0x59, 0x55],
+];
+
+/// Frequency multipliers, doubled so they're all integers.
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// The hardware interface for the YM2413.
+pub trait Ym2413Interface {
+    /// Select the register for the next `write_data`. This is IO port 0xF0.
+    fn write_address(&mut self, x: u8);
+
+    /// Write to the register selected by `write_address`. This is IO port
+    /// 0xF1.
+    fn write_data(&mut self, x: u8);
+
+    /// Write the audio control register, IO port 0xF2.
+    ///
+    /// Bit 0 turns on FM output. If it's set and bit 1 isn't, the SN76489 is
+    /// muted.
+    fn write_control(&mut self, x: u8);
+
+    /// Read the audio control register.
+    ///
+    /// Only its low 3 bits can be read back, which is how games detect the
+    /// YM2413.
+    fn read_control(&self) -> u8;
+}
+
+pub trait Ym2413Audio {
+    /// Run the YM2413 for `z80_cycles` more Z80 cycles, and give its output.
+    fn advance(&mut self, z80_cycles: u32) -> i16;
+}
+
+/// Where an operator is in its envelope.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// One of the two operators in each channel.
+///
+/// Its `attenuation` is compared and hashed bit for bit, so it can be `Eq`
+/// and `Hash`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Ym2413Operator {
+    /// How far through its waveform the operator is, in units of 1/2^19 of a
+    /// cycle.
+    pub phase: u32,
+    pub stage: EnvelopeStage,
+    /// The envelope's attenuation in dB, from 0 to `SILENT`.
+    pub attenuation: f32,
+}
+
+impl Default for Ym2413Operator {
+    fn default() -> Self {
+        Ym2413Operator {
+            phase: 0,
+            stage: EnvelopeStage::Release,
+            attenuation: SILENT,
+        }
+    }
+}
+
+/// Which byte of an instrument describes an operator: 0 for a modulator, 1
+/// for a carrier.
+type Slot = usize;
+
+impl Ym2413Operator {
+    fn key_on(&mut self) {
+        self.stage = EnvelopeStage::Attack;
+        self.phase = 0;
+    }
+
+    fn key_off(&mut self) {
+        self.stage = EnvelopeStage::Release;
+    }
+
+    /// Advance the envelope by one sample.
+    ///
+    /// `rks` is the rate key scale offset of the channel's frequency, and
+    /// `sustain` is the channel's sustain bit.
+    fn update_envelope(&mut self, instrument: &[u8; 8], slot: Slot, rks: u8, sustain: bool) {
+        let sustained_tone = instrument[slot] & 0x20 != 0;
+        let rate = match self.stage {
+            EnvelopeStage::Attack => instrument[4 + slot] >> 4,
+            EnvelopeStage::Decay => instrument[4 + slot] & 0xF,
+            EnvelopeStage::Sustain if sustained_tone => 0,
+            EnvelopeStage::Sustain => instrument[6 + slot] & 0xF,
+            EnvelopeStage::Release if sustain => 5,
+            EnvelopeStage::Release if sustained_tone => instrument[6 + slot] & 0xF,
+            EnvelopeStage::Release => 7,
+        };
+        if rate == 0 {
+            return;
+        }
+        let rks = if instrument[slot] & 0x10 != 0 {
+            rks
+        } else {
+            rks >> 2
+        };
+        let effective_rate = (4 * rate + rks).min(63) as f32;
+
+        // Going from full volume to silence takes about 20 seconds at rate 0,
+        // half as long for each step of 4 in the effective rate, and an
+        // eighth as long in the attack stage.
+        let seconds = 20.0 / 2f32.powf(effective_rate / 4.0);
+        let step = SILENT / (seconds * SAMPLE_RATE);
+
+        match self.stage {
+            EnvelopeStage::Attack => {
+                if rate == 15 {
+                    self.attenuation = 0.0;
+                } else {
+                    self.attenuation -= 8.0 * step;
+                }
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.attenuation += step;
+                let sustain_level = 3.0 * (instrument[6 + slot] >> 4) as f32;
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            _ => self.attenuation = (self.attenuation + step).min(SILENT),
+        }
+    }
+
+    /// Advance the phase by one sample.
+    ///
+    /// `vibrato` is the current frequency factor from the vibrato LFO.
+    fn update_phase(&mut self, instrument: &[u8; 8], slot: Slot, frequency: u32, vibrato: f32) {
+        let mut increment = (frequency * MULTIPLIERS[(instrument[slot] & 0xF) as usize]) >> 2;
+        if instrument[slot] & 0x40 != 0 {
+            increment = (increment as f32 * vibrato) as u32;
+        }
+        self.phase = (self.phase + increment) & 0x7FFFF;
+    }
+
+    /// The operator's waveform at its current phase, shifted by `phase_mod`
+    /// radians, from -1.0 to 1.0, before any attenuation.
+    fn wave(&self, instrument: &[u8; 8], slot: Slot, phase_mod: f32) -> f32 {
+        let angle = 2.0 * PI * self.phase as f32 / 0x80000 as f32 + phase_mod;
+        let value = angle.sin();
+        // bits 3 and 4 of byte 3 choose a half sine wave
+        if instrument[3] & (8 << slot) != 0 && value < 0.0 {
+            0.0
+        } else {
+            value
+        }
+    }
+
+    /// How much to scale the waveform by, given `attenuation` in dB on top of
+    /// the envelope and the tremolo LFO's attenuation `tremolo`.
+    fn amplitude(&self, instrument: &[u8; 8], slot: Slot, attenuation: f32, tremolo: f32) -> f32 {
+        let mut total = self.attenuation + attenuation;
+        if instrument[slot] & 0x80 != 0 {
+            total += tremolo;
+        }
+        if total >= SILENT {
+            0.0
+        } else {
+            10f32.powf(-total / 20.0)
+        }
+    }
+}
+
+/// The state of the YM2413.
+///
+/// The registers are:
+///
+/// - 0x00 through 0x07: the custom instrument, instrument 0. Bytes 0 and 1
+///   hold the modulator and carrier's tremolo (bit 7), vibrato (bit 6),
+///   sustained tone (bit 5), rate key scaling (bit 4), and frequency
+///   multiplier (bits 0 through 3). Byte 2 holds the modulator's key scale
+///   level (bits 6 and 7) and total level (bits 0 through 5). Byte 3 holds the
+///   carrier's key scale level (bits 6 and 7), half sine waves for the carrier
+///   (bit 4) and modulator (bit 3), and the modulator's feedback (bits 0
+///   through 2). Bytes 4 and 5 hold the modulator and carrier's attack rates
+///   (bits 4 through 7) and decay rates, and bytes 6 and 7 their sustain
+///   levels and release rates.
+/// - 0x0E: rhythm mode (bit 5), and keys for the bass drum (bit 4), snare
+///   drum, tom-tom, cymbal, and high hat (bit 0).
+/// - 0x10 through 0x18: the low 8 bits of each channel's frequency.
+/// - 0x20 through 0x28: each channel's sustain (bit 5), key (bit 4), block
+///   (bits 1 through 3), and frequency high bit.
+/// - 0x30 through 0x38: each channel's instrument (bits 4 through 7) and
+///   volume. In rhythm mode, these instead hold the bass drum's volume (low
+///   bits of 0x36) and the volumes of the high hat, snare drum, tom-tom, and
+///   cymbal (high and low bits of 0x37 and 0x38).
+///
+/// Key scale levels aren't emulated.
+///
+/// As with `Ym2413Operator`, `feedback` is compared and hashed bit for bit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ym2413State {
+    /// Registers 0x00 through 0x3F, 16 to a row.
+    pub registers: [[u8; 0x10]; 4],
+    pub address: u8,
+    pub control: u8,
+    /// The modulator and carrier of each channel, in that order.
+    pub operators: [Ym2413Operator; 18],
+    /// The last two outputs of each channel's modulator, for feedback.
+    pub feedback: [[f32; 2]; 9],
+    /// The rhythm instruments' noise generator.
+    pub noise: u32,
+    /// Samples generated so far, for the LFOs.
+    pub samples: u32,
+    /// Z80 cycles run since the last sample.
+    pub cycles: u32,
+    /// The last sample.
+    pub output: i16,
+}
+
+mod _impl {
+    use super::*;
+
+    #[derive(Hash, PartialEq)]
+    pub struct Ym2413OperatorDerive {
+        phase: u32,
+        stage: EnvelopeStage,
+        attenuation: u32,
+    }
+
+    impl_hash_via!{super::Ym2413Operator, Ym2413OperatorDerive}
+    impl_partial_eq_via!{super::Ym2413Operator, Ym2413OperatorDerive}
+    impl Eq for super::Ym2413Operator {}
+}
+
+mod _impl2 {
+    use super::_impl::Ym2413OperatorDerive;
+
+    #[derive(Hash, PartialEq)]
+    struct Ym2413StateDerive {
+        registers: [[u8; 0x10]; 4],
+        address: u8,
+        control: u8,
+        operators: [Ym2413OperatorDerive; 18],
+        feedback: [[u32; 2]; 9],
+        noise: u32,
+        samples: u32,
+        cycles: u32,
+        output: i16,
+    }
+
+    impl_hash_via!{super::Ym2413State, Ym2413StateDerive}
+    impl_partial_eq_via!{super::Ym2413State, Ym2413StateDerive}
+    impl Eq for super::Ym2413State {}
+}
+
+impl Default for Ym2413State {
+    fn default() -> Self {
+        Ym2413State {
+            registers: [[0; 0x10]; 4],
+            address: 0,
+            control: 0,
+            operators: [Default::default(); 18],
+            feedback: [[0.0; 2]; 9],
+            noise: 1,
+            samples: 0,
+            cycles: 0,
+            output: 0,
+        }
+    }
+}
+
+impl Ym2413State {
+    #[inline]
+    fn register(&self, address: u8) -> u8 {
+        self.registers[address as usize >> 4][address as usize & 0xF]
+    }
+
+    #[inline]
+    fn rhythm_mode(&self) -> bool {
+        self.register(0x0E) & 0x20 != 0
+    }
+
+    fn instrument(&self, channel: u8) -> [u8; 8] {
+        if channel >= 6 && self.rhythm_mode() {
+            return RHYTHM_INSTRUMENTS[channel as usize - 6];
+        }
+        match self.register(0x30 + channel) >> 4 {
+            0 => {
+                let mut custom = [0u8; 8];
+                custom.copy_from_slice(&self.registers[0][..8]);
+                custom
+            }
+            n => INSTRUMENTS[n as usize - 1],
+        }
+    }
+
+    /// A channel's frequency number shifted by its block.
+    #[inline]
+    fn frequency(&self, channel: u8) -> u32 {
+        let high = self.register(0x20 + channel);
+        let fnum = self.register(0x10 + channel) as u32 | (high as u32 & 1) << 8;
+        let block = high >> 1 & 7;
+        fnum << block
+    }
+
+    /// The rate key scale offset of a channel: its block and the high bit of
+    /// its frequency number.
+    #[inline]
+    fn rks(&self, channel: u8) -> u8 {
+        self.register(0x20 + channel) & 0xF
+    }
+
+    /// Which operators are keyed on.
+    fn keys(&self) -> [bool; 18] {
+        let mut keys = [false; 18];
+        for channel in 0..9 {
+            let key = self.register(0x20 + channel as u8) & 0x10 != 0;
+            keys[2 * channel] = key;
+            keys[2 * channel + 1] = key;
+        }
+        if self.rhythm_mode() {
+            let rhythm = self.register(0x0E);
+            // bass drum, high hat, snare drum, tom-tom, cymbal
+            for &(operator, bit) in [(12, 4), (13, 4), (14, 0), (15, 3), (16, 2), (17, 1)].iter() {
+                keys[operator] |= rhythm & 1 << bit != 0;
+            }
+        }
+        keys
+    }
+
+    /// Generate one sample.
+    fn sample(&mut self) -> i16 {
+        self.samples = self.samples.wrapping_add(1);
+        let time = self.samples as f32 / SAMPLE_RATE;
+        // tremolo is 4.8 dB at 3.7 Hz, and vibrato 14 cents at 6.4 Hz
+        let tremolo = 2.4 * (1.0 - (2.0 * PI * 3.7 * time).cos());
+        let vibrato = 2f32.powf(14.0 / 1200.0 * (2.0 * PI * 6.4 * time).sin());
+
+        // a 23 bit LFSR, as in the real chip
+        if self.noise & 1 != 0 {
+            self.noise ^= 0x800302;
+        }
+        self.noise >>= 1;
+        let noise = if self.noise & 1 != 0 { 1.0 } else { -1.0 };
+
+        let rhythm_mode = self.rhythm_mode();
+        let melodic_channels = if rhythm_mode { 6 } else { 9 };
+        let mut total = 0.0;
+
+        for channel in 0..9 {
+            let instrument = self.instrument(channel);
+            let frequency = self.frequency(channel);
+            let rks = self.rks(channel);
+            let sustain = self.register(0x20 + channel) & 0x20 != 0;
+            let index = 2 * channel as usize;
+            for slot in 0..2 {
+                let operator = &mut self.operators[index + slot];
+                operator.update_envelope(&instrument, slot, rks, sustain);
+                operator.update_phase(&instrument, slot, frequency, vibrato);
+            }
+
+            let volume = 3.0 * (self.register(0x30 + channel) & 0xF) as f32;
+            if channel < melodic_channels || channel == 6 {
+                let [modulator, carrier] = [self.operators[index], self.operators[index + 1]];
+                let feedback_level = instrument[3] & 7;
+                let feedback = if feedback_level == 0 {
+                    0.0
+                } else {
+                    let [a, b] = self.feedback[channel as usize];
+                    (a + b) / 2.0 * MODULATION_DEPTH / (1 << (7 - feedback_level)) as f32
+                };
+                let total_level = 0.75 * (instrument[2] & 0x3F) as f32;
+                let modulation = modulator.wave(&instrument, 0, feedback)
+                    * modulator.amplitude(&instrument, 0, total_level, tremolo);
+                self.feedback[channel as usize] = [self.feedback[channel as usize][1], modulation];
+                let output = carrier.wave(&instrument, 1, modulation * MODULATION_DEPTH)
+                    * carrier.amplitude(&instrument, 1, volume, tremolo);
+                total += if channel < melodic_channels {
+                    output
+                } else {
+                    // the bass drum is twice as loud
+                    2.0 * output
+                };
+            } else {
+                // the high hat and tom-tom have their volumes in the high bits
+                let high_volume = 3.0 * (self.register(0x30 + channel) >> 4) as f32;
+                let [modulator, carrier] = [self.operators[index], self.operators[index + 1]];
+                let (high, low) = if channel == 7 {
+                    // high hat and snare drum
+                    (noise, 0.5 * carrier.wave(&instrument, 1, 0.0) + 0.5 * noise)
+                } else {
+                    // tom-tom and cymbal
+                    (
+                        modulator.wave(&instrument, 0, 0.0),
+                        if carrier.phase & 0x40000 != 0 {
+                            noise
+                        } else {
+                            -noise
+                        },
+                    )
+                };
+                total += high * modulator.amplitude(&instrument, 0, high_volume, tremolo);
+                total += low * carrier.amplitude(&instrument, 1, volume, tremolo);
+            }
+        }
+
+        let output = total * CHANNEL_AMPLITUDE;
+        output.max(i16::MIN as f32).min(i16::MAX as f32) as i16
+    }
+}
+
+impl Ym2413Interface for Ym2413State {
+    fn write_address(&mut self, x: u8) {
+        self.address = x;
+    }
+
+    fn write_data(&mut self, x: u8) {
+        if self.address >= 0x40 {
+            return;
+        }
+        let keys = self.keys();
+        self.registers[self.address as usize >> 4][self.address as usize & 0xF] = x;
+        let new_keys = self.keys();
+        for i in 0..18 {
+            match (keys[i], new_keys[i]) {
+                (false, true) => self.operators[i].key_on(),
+                (true, false) => self.operators[i].key_off(),
+                _ => {}
+            }
+        }
+    }
+
+    fn write_control(&mut self, x: u8) {
+        self.control = x;
+    }
+
+    fn read_control(&self) -> u8 {
+        self.control & 7
+    }
+}
+
+impl Ym2413Audio for Ym2413State {
+    fn advance(&mut self, z80_cycles: u32) -> i16 {
+        self.cycles += z80_cycles;
+        while self.cycles >= Z80_CYCLES_PER_SAMPLE {
+            self.cycles -= Z80_CYCLES_PER_SAMPLE;
+            self.output = self.sample();
+        }
+        self.output
+    }
+}
+
+/// Mixes a YM2413's output into audio on its way to a `SimpleAudio`.
+///
+/// Use this as the `Audio` of a `Sn76489Impler`. Each sample it's given is
+/// taken to last `clock_divider` Z80 cycles, as each SN76489 sample does.
+pub struct Ym2413Impler<'a, Ym2413: 'a, Audio: 'a> {
+    pub ym2413: &'a mut Ym2413,
+    pub audio: &'a mut Audio,
+    pub clock_divider: u16,
+}
+
+impl<'a, Ym2413: 'a, Audio: 'a> SimpleAudio for Ym2413Impler<'a, Ym2413, Audio>
+where
+    Ym2413: Ym2413Interface + Ym2413Audio,
+    Audio: SimpleAudio,
+{
+    #[inline]
+    fn configure(&mut self, frequency: u32, buffer_size: u16) -> Result<(), Error> {
+        self.audio.configure(frequency, buffer_size)
+    }
+
+    #[inline]
+    fn play(&mut self) -> Result<(), Error> {
+        self.audio.play()
+    }
+
+    #[inline]
+    fn pause(&mut self) -> Result<(), Error> {
+        self.audio.pause()
+    }
+
+    #[inline]
+    fn buffer_len(&self) -> usize {
+        self.audio.buffer_len()
+    }
+
+    fn buffer_set(&mut self, i: usize, left: i16, right: i16) {
+        let fm = self.ym2413.advance(self.clock_divider as u32);
+        match self.ym2413.read_control() & 3 {
+            1 => self.audio.buffer_set(i, fm, fm),
+            3 => self
+                .audio
+                .buffer_set(i, left.saturating_add(fm), right.saturating_add(fm)),
+            _ => self.audio.buffer_set(i, left, right),
+        }
+    }
+
+    #[inline]
+    fn queue_buffer(&mut self) -> Result<(), Error> {
+        self.audio.queue_buffer()
+    }
+
+    #[inline]
+    fn clear(&mut self) -> Result<(), Error> {
+        self.audio.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_instrument() {
+        let mut ym2413 = Ym2413State::default();
+        let mut write = |address, value| {
+            ym2413.write_address(address);
+            ym2413.write_data(value);
+        };
+        // a plain sine carrier, modulated a little, with instant attack and
+        // no decay
+        for (address, &value) in [0x21, 0x21, 0x20, 0x00, 0xF0, 0xF0, 0x00, 0x00]
+            .iter()
+            .enumerate()
+        {
+            write(address as u8, value);
+        }
+        // channel 0: instrument 0 at full volume, about 440 Hz
+        write(0x30, 0x00);
+        write(0x10, 0x22);
+        write(0x20, 0x0B);
+
+        let samples: Vec<i16> = (0..1000).map(|_| ym2413.advance(72)).collect();
+        assert!(samples.iter().all(|&x| x == 0));
+
+        // key on
+        ym2413.write_address(0x20);
+        ym2413.write_data(0x1B);
+        let samples: Vec<i16> = (0..1000).map(|_| ym2413.advance(72)).collect();
+        assert!(samples.iter().any(|&x| x > 1000));
+        assert!(samples.iter().any(|&x| x < -1000));
+
+        // a quieter volume
+        ym2413.write_address(0x30);
+        ym2413.write_data(0x0A);
+        let quiet: Vec<i16> = (0..1000).map(|_| ym2413.advance(72)).collect();
+        let loudest = |v: &[i16]| v.iter().map(|x| (*x as i32).abs()).max().unwrap();
+        assert!(loudest(&quiet) > 0);
+        assert!(loudest(&quiet) < loudest(&samples) / 4);
+    }
+}
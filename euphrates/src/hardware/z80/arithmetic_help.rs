@@ -1,6 +1,5 @@
 use utilities;
 
-use hardware::io16::Io16;
 use hardware::memory16::Memory16;
 
 use super::*;
@@ -18,7 +17,7 @@ where
     let addr = BC.view(z);
     let hl = HL.view(z);
     let x = Address(hl).view(z);
-    z.io().output(addr, x);
+    z.io_output(addr, x);
     HL.change(z, hl.wrapping_add(inc));
 }
 
@@ -30,7 +29,7 @@ where
     let address_lo = arg.view(z);
     let address_hi = B.view(z);
     let address = utilities::to16(address_lo, address_hi);
-    let x = z.io().input(address);
+    let x = z.io_input(address);
 
     z.z80().set_parity(x);
     z.z80().set_sign(x);
@@ -47,7 +46,7 @@ where
     let b = B.view(z);
     let hl = HL.view(z);
     let addr = BC.view(z);
-    let x = z.io().input(addr);
+    let x = z.io_input(addr);
     Address(hl).change(z, x);
     B.change(z, b.wrapping_sub(1));
     HL.change(z, hl.wrapping_add(inc));
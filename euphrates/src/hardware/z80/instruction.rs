@@ -729,20 +729,229 @@ macro_rules! e_inst {
     }}
 }
 
-pub fn noprefix<Z>(z: &mut Z)
+// Each prefix group is written once, as a list of match arms in opcode order,
+// and expands to two functions: `$name`, which reads the opcode at PC and
+// `match`es on it, and `$table`, which gives a table of one function per
+// opcode for `DispatchTable`.
+macro_rules! instructions {
+    (
+        pub fn $name: ident, $table: ident($z: ident) { $($uses: item)* }
+        match { $($opcode: tt => $body: expr,)* }
+    ) => {
+        pub fn $name<Z>($z: &mut Z)
+        where
+            Z: Z80Emulator,
+        {
+            $($uses)*
+
+            let opcode = $z.read_pc();
+            $z.inc_pc();
+            match opcode {
+                $($opcode => $body,)*
+            }
+        }
+
+        pub fn $table<Z>() -> [fn(&mut Z); 256]
+        where
+            Z: Z80Emulator,
+        {
+            $($uses)*
+
+            [$(|$z| $body),*]
+        }
+    };
+
+    (
+        pub fn $name: ident, $table: ident($z: ident, $d: ident) { $($uses: item)* }
+        match { $($opcode: tt => $body: expr,)* }
+    ) => {
+        pub fn $name<Z>($z: &mut Z)
+        where
+            Z: Z80Emulator,
+        {
+            $($uses)*
+
+            let $d = $z.read_pc() as i8;
+            $z.inc_pc();
+            let opcode = $z.read_pc();
+            $z.inc_pc();
+            match opcode {
+                $($opcode => $body,)*
+            }
+        }
+
+        pub fn $table<Z>() -> [fn(&mut Z, i8); 256]
+        where
+            Z: Z80Emulator,
+        {
+            $($uses)*
+
+            [$(|$z, $d| $body),*]
+        }
+    };
+}
+
+/// The instructions of each prefix group, as tables indexed by opcode.
+///
+/// This is an alternative to the large `match`es in `noprefix`, `ed`, and the
+/// rest: executing an instruction is an indexed call rather than a jump
+/// through a `match`. The tables are big enough that they should be built
+/// once and reused.
+pub struct DispatchTable<Z> {
+    noprefix: [fn(&mut Z); 256],
+    ed: [fn(&mut Z); 256],
+    cb: [fn(&mut Z); 256],
+    dd: [fn(&mut Z); 256],
+    fd: [fn(&mut Z); 256],
+    ddcb: [fn(&mut Z, i8); 256],
+    fdcb: [fn(&mut Z, i8); 256],
+}
+
+impl<Z> DispatchTable<Z>
+where
+    Z: Z80Emulator,
+{
+    pub fn new() -> Self {
+        DispatchTable {
+            noprefix: noprefix_table(),
+            ed: ed_table(),
+            cb: cb_table(),
+            dd: dd_table(),
+            fd: fd_table(),
+            ddcb: ddcb_table(),
+            fdcb: fdcb_table(),
+        }
+    }
+}
+
+impl<Z> Default for DispatchTable<Z>
+where
+    Z: Z80Emulator,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A way to get from the opcode at PC to the code executing it.
+///
+/// `MatchDispatch` uses the functions `noprefix`, `ed`, and so on;
+/// `DispatchTable` uses its tables. Either way, each method reads the rest of
+/// the instruction (after any prefixes) and executes it.
+pub trait Dispatch<Z> {
+    fn noprefix(&self, z: &mut Z);
+    fn ed(&self, z: &mut Z);
+    fn cb(&self, z: &mut Z);
+    fn dd(&self, z: &mut Z);
+    fn fd(&self, z: &mut Z);
+    fn ddcb(&self, z: &mut Z);
+    fn fdcb(&self, z: &mut Z);
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MatchDispatch;
+
+impl<Z> Dispatch<Z> for MatchDispatch
 where
     Z: Z80Emulator,
 {
-    use self::instruction_traits::*;
+    #[inline]
+    fn noprefix(&self, z: &mut Z) {
+        noprefix(z)
+    }
+
+    #[inline]
+    fn ed(&self, z: &mut Z) {
+        ed(z)
+    }
 
-    use self::ConditionCode::*;
-    use self::Reg16::*;
-    use self::Reg8::*;
+    #[inline]
+    fn cb(&self, z: &mut Z) {
+        cb(z)
+    }
+
+    #[inline]
+    fn dd(&self, z: &mut Z) {
+        dd(z)
+    }
+
+    #[inline]
+    fn fd(&self, z: &mut Z) {
+        fd(z)
+    }
+
+    #[inline]
+    fn ddcb(&self, z: &mut Z) {
+        ddcb(z)
+    }
 
-    // z.inc_r(1); XXX
-    let pc = z.read_pc();
+    #[inline]
+    fn fdcb(&self, z: &mut Z) {
+        fdcb(z)
+    }
+}
+
+#[inline]
+fn read_opcode<Z>(z: &mut Z) -> usize
+where
+    Z: Z80Emulator,
+{
+    let opcode = z.read_pc();
     z.inc_pc();
-    match pc {
+    opcode as usize
+}
+
+impl<Z> Dispatch<Z> for DispatchTable<Z>
+where
+    Z: Z80Emulator,
+{
+    #[inline]
+    fn noprefix(&self, z: &mut Z) {
+        self.noprefix[read_opcode(z)](z)
+    }
+
+    #[inline]
+    fn ed(&self, z: &mut Z) {
+        self.ed[read_opcode(z)](z)
+    }
+
+    #[inline]
+    fn cb(&self, z: &mut Z) {
+        self.cb[read_opcode(z)](z)
+    }
+
+    #[inline]
+    fn dd(&self, z: &mut Z) {
+        self.dd[read_opcode(z)](z)
+    }
+
+    #[inline]
+    fn fd(&self, z: &mut Z) {
+        self.fd[read_opcode(z)](z)
+    }
+
+    #[inline]
+    fn ddcb(&self, z: &mut Z) {
+        let d = read_opcode(z) as u8 as i8;
+        self.ddcb[read_opcode(z)](z, d)
+    }
+
+    #[inline]
+    fn fdcb(&self, z: &mut Z) {
+        let d = read_opcode(z) as u8 as i8;
+        self.fdcb[read_opcode(z)](z, d)
+    }
+}
+
+instructions! {
+    pub fn noprefix, noprefix_table(z) {
+        use self::instruction_traits::*;
+
+        use self::ConditionCode::*;
+        use self::Reg16::*;
+        use self::Reg8::*;
+    }
+    match {
         0x00 => regular!{z,      4,  no,     Nop,     nop, () },
         0x01 => nn_inst!{z, nn, 10, mem,    Ld16,    ld16, ([BC] [nn]) },
         0x02 => regular!{z,      7, mem,      Ld,      ld, ([Address(BC)] [A]) },
@@ -759,7 +968,7 @@ where
         0x0D => regular!{z,      4, mem,     Dec,     dec, ([C]) },
         0x0E => n_inst! {z,  n,  7, mem,      Ld,      ld, ([C] [n]) },
         0x0F => regular!{z,      4, mem,    Rrca,    rrca, () },
-        0x10 => e_inst! {z,  e,  8,  no,    Djnz,    djnz, ([e]) },
+        0x10 => e_inst! {z,  e,  0,  no,    Djnz,    djnz, ([e]) },
         0x11 => nn_inst!{z, nn, 10, mem,    Ld16,    ld16, ([DE] [nn]) },
         0x12 => regular!{z,      7, mem,      Ld,      ld, ([Address(DE)] [A]) },
         0x13 => regular!{z,      6,  no,   Inc16,   inc16, ([DE]) },
@@ -937,7 +1146,7 @@ where
         0xBF => regular!{z,      4, mem,      Cp,     cp, ([A]) },
         0xC0 => regular!{z,      0, mem,   Retcc,  retcc, ([NZcc]) },
         0xC1 => regular!{z,     10, mem,     Pop,    pop, ([BC]) },
-        0xC2 => nn_inst!{z, nn, 10,  no,    Jpcc,   jpcc, ([NZcc] [nn]) },
+        0xC2 => nn_inst!{z, nn,  0,  no,    Jpcc,   jpcc, ([NZcc] [nn]) },
         0xC3 => nn_inst!{z, nn, 10, mem,      Jp,     jp, ([nn]) },
         0xC4 => nn_inst!{z, nn,  0, mem,  Callcc, callcc, ([NZcc] [nn]) },
         0xC5 => regular!{z,     11, mem,    Push,   push, ([BC]) },
@@ -945,7 +1154,7 @@ where
         0xC7 => regular!{z,     11, mem,     Rst,    rst, ([0x00]) },
         0xC8 => regular!{z,      0, mem,   Retcc,  retcc, ([Zcc]) },
         0xC9 => regular!{z,     10, mem,     Ret,    ret, () },
-        0xCA => nn_inst!{z, nn, 10,  no,    Jpcc,   jpcc, ([Zcc] [nn]) },
+        0xCA => nn_inst!{z, nn,  0,  no,    Jpcc,   jpcc, ([Zcc] [nn]) },
         0xCB => regular!{z,      0,  no,      Cb,     cb, () },
         0xCC => nn_inst!{z, nn,  0, mem,  Callcc, callcc, ([Zcc] [nn]) },
         0xCD => nn_inst!{z, nn,  0, mem,    Call,   call, ([nn]) },
@@ -953,7 +1162,7 @@ where
         0xCF => regular!{z,     11, mem,    Rst,     rst, ([0x08]) },
         0xD0 => regular!{z,      0, mem,  Retcc,   retcc, ([NCcc]) },
         0xD1 => regular!{z,     10, mem,    Pop,     pop, ([DE]) },
-        0xD2 => nn_inst!{z, nn, 10,  no,   Jpcc,    jpcc, ([NCcc] [nn]) },
+        0xD2 => nn_inst!{z, nn,  0,  no,   Jpcc,    jpcc, ([NCcc] [nn]) },
         0xD3 => n_inst! {z,  n, 11,  io,   OutN,   out_n, ([n] [A]) },
         0xD4 => nn_inst!{z, nn,  0, mem, Callcc,  callcc, ([NCcc] [nn]) },
         0xD5 => regular!{z,     11, mem,   Push,    push, ([DE]) },
@@ -961,7 +1170,7 @@ where
         0xD7 => regular!{z,     11, mem,    Rst,     rst, ([0x10]) },
         0xD8 => regular!{z,      0, mem,  Retcc,   retcc, ([Ccc]) },
         0xD9 => regular!{z,      4,  no,    Exx,     exx, () },
-        0xDA => nn_inst!{z, nn, 10,  no,   Jpcc,    jpcc, ([Ccc] [nn]) },
+        0xDA => nn_inst!{z, nn,  0,  no,   Jpcc,    jpcc, ([Ccc] [nn]) },
         0xDB => n_inst! {z,  n, 11,  io,    InN,    in_n, ([A] [n]) },
         0xDC => nn_inst!{z, nn,  0, mem, Callcc,  callcc, ([Ccc] [nn]) },
         0xDD => regular!{z,      4,  no,     Dd,      dd, () },
@@ -969,7 +1178,7 @@ where
         0xDF => regular!{z,     11, mem,    Rst,     rst, ([0x18]) },
         0xE0 => regular!{z,      0, mem,  Retcc,   retcc, ([POcc]) },
         0xE1 => regular!{z,     10, mem,    Pop,     pop, ([HL]) },
-        0xE2 => nn_inst!{z, nn, 10,  no,   Jpcc,    jpcc, ([POcc] [nn]) },
+        0xE2 => nn_inst!{z, nn,  0,  no,   Jpcc,    jpcc, ([POcc] [nn]) },
         0xE3 => regular!{z,     19, mem,     Ex,      ex, ([Address(SP)] [HL]) },
         0xE4 => nn_inst!{z, nn,  0, mem, Callcc,  callcc, ([POcc] [nn]) },
         0xE5 => regular!{z,     11, mem,   Push,    push, ([HL]) },
@@ -977,7 +1186,7 @@ where
         0xE7 => regular!{z,     11, mem,    Rst,     rst, ([0x20]) },
         0xE8 => regular!{z,      0, mem,  Retcc,   retcc, ([PEcc]) },
         0xE9 => regular!{z,      4, mem,     Jp,      jp, ([HL]) },
-        0xEA => nn_inst!{z, nn, 10,  no,   Jpcc,    jpcc, ([PEcc] [nn]) },
+        0xEA => nn_inst!{z, nn,  0,  no,   Jpcc,    jpcc, ([PEcc] [nn]) },
         0xEB => regular!{z,      4, mem,     Ex,      ex, ([DE] [HL]) },
         0xEC => nn_inst!{z, nn,  0, mem, Callcc,  callcc, ([PEcc] [nn]) },
         0xED => regular!{z,      0,  no,     Ed,      ed, () },
@@ -985,7 +1194,7 @@ where
         0xEF => regular!{z,     11, mem,    Rst,     rst, ([0x28]) },
         0xF0 => regular!{z,      0, mem,  Retcc,   retcc, ([Pcc]) },
         0xF1 => regular!{z,     10, mem,    Pop,     pop, ([AF]) },
-        0xF2 => nn_inst!{z, nn, 10,  no,   Jpcc,    jpcc, ([Pcc] [nn]) },
+        0xF2 => nn_inst!{z, nn,  0,  no,   Jpcc,    jpcc, ([Pcc] [nn]) },
         0xF3 => regular!{z,      4,  no,     Di,      di, () },
         0xF4 => nn_inst!{z, nn,  0, mem, Callcc,  callcc, ([Pcc] [nn]) },
         0xF5 => regular!{z,     11, mem,   Push,    push, ([AF]) },
@@ -993,28 +1202,23 @@ where
         0xF7 => regular!{z,     11, mem,    Rst,     rst, ([0x30]) },
         0xF8 => regular!{z,      0, mem,  Retcc,   retcc, ([Mcc]) },
         0xF9 => regular!{z,      6, mem,   Ld16,    ld16, ([SP] [HL]) },
-        0xFA => nn_inst!{z, nn, 10,  no,   Jpcc,    jpcc, ([Mcc] [nn]) },
+        0xFA => nn_inst!{z, nn,  0,  no,   Jpcc,    jpcc, ([Mcc] [nn]) },
         0xFB => regular!{z,      4,  no,     Ei,      ei, () },
         0xFC => nn_inst!{z, nn,  0, mem, Callcc,  callcc, ([Mcc] [nn]) },
         0xFD => regular!{z,      4,  no,     Fd,      fd, () },
         0xFE => n_inst! {z,  n,  7, mem,     Cp,      cp, ([n]) },
         0xFF => regular!{z,     11, mem,    Rst,     rst, ([0x38]) },
-        _ => unimplemented!(),
     }
 }
 
-pub fn ed<Z>(z: &mut Z)
-where
-    Z: Z80Emulator,
-{
-    use self::instruction_traits::*;
+instructions! {
+    pub fn ed, ed_table(z) {
+        use self::instruction_traits::*;
 
-    use self::Reg16::*;
-    use self::Reg8::*;
-
-    let pc = z.read_pc();
-    z.inc_pc();
-    match pc {
+        use self::Reg16::*;
+        use self::Reg8::*;
+    }
+    match {
         0x00 => regular!{z,  8, no, Nop, nop, () },
         0x01 => regular!{z,  8, no, Nop, nop, () },
         0x02 => regular!{z,  8, no, Nop, nop, () },
@@ -1283,22 +1487,17 @@ where
         0xFD => regular!{z,  8, no, Nop, nop, () },
         0xFE => regular!{z,  8, no, Nop, nop, () },
         0xFF => regular!{z,  8, no, Nop, nop, () },
-        _ => unimplemented!(),
     }
 }
 
-pub fn cb<Z>(z: &mut Z)
-where
-    Z: Z80Emulator,
-{
-    use self::instruction_traits::*;
+instructions! {
+    pub fn cb, cb_table(z) {
+        use self::instruction_traits::*;
 
-    use self::Reg16::*;
-    use self::Reg8::*;
-
-    let pc = z.read_pc();
-    z.inc_pc();
-    match pc {
+        use self::Reg16::*;
+        use self::Reg8::*;
+    }
+    match {
         0x00 => regular!{z,  8, mem, Rlc, rlc, ([B]) },
         0x01 => regular!{z,  8, mem, Rlc, rlc, ([C]) },
         0x02 => regular!{z,  8, mem, Rlc, rlc, ([D]) },
@@ -1555,23 +1754,18 @@ where
         0xFD => regular!{z,  8, mem, Set, set, ([7] [L]) },
         0xFE => regular!{z, 15, mem, Set, set, ([7] [Address(HL)]) },
         0xFF => regular!{z,  8, mem, Set, set, ([7] [A]) },
-        _ => unimplemented!(),
     }
 }
 
-pub fn dd<Z>(z: &mut Z)
-where
-    Z: Z80Emulator,
-{
-    use self::instruction_traits::*;
-
-    use self::ConditionCode::*;
-    use self::Reg16::*;
-    use self::Reg8::*;
+instructions! {
+    pub fn dd, dd_table(z) {
+        use self::instruction_traits::*;
 
-    let pc = z.read_pc();
-    z.inc_pc();
-    match pc {
+        use self::ConditionCode::*;
+        use self::Reg16::*;
+        use self::Reg8::*;
+    }
+    match {
         0x00 => regular!{z,        4,  no,    Nop,    nop, () },
         0x01 => nn_inst!{z,   nn, 10, mem,   Ld16,   ld16, ([BC] [nn]) },
         0x02 => regular!{z,        7, mem,     Ld,     ld, ([Address(BC)] [A]) },
@@ -1588,7 +1782,7 @@ where
         0x0D => regular!{z,        4, mem,    Dec,    dec, ([C]) },
         0x0E => n_inst! {z,    n,  7, mem,     Ld,     ld, ([C] [n]) },
         0x0F => regular!{z,        4, mem,   Rrca,   rrca, () },
-        0x10 => e_inst! {z,    e,  8,  no,   Djnz,   djnz, ([e]) },
+        0x10 => e_inst! {z,    e,  0,  no,   Djnz,   djnz, ([e]) },
         0x11 => nn_inst!{z,   nn, 10, mem,   Ld16,   ld16, ([DE] [nn]) },
         0x12 => regular!{z,        7, mem,     Ld,     ld, ([Address(DE)] [A]) },
         0x13 => regular!{z,        6,  no,  Inc16,  inc16, ([DE]) },
@@ -1764,87 +1958,82 @@ where
         0xBD => regular!{z,        4, mem,     Cp,     cp, ([IXL]) },
         0xBE => e_inst! {z,    d, 15, mem,     Cp,     cp, ([Shift(IX, d)]) },
         0xBF => regular!{z,        4, mem,     Cp,     cp, ([A]) },
-        0xC0 => regular!{z,        5, mem,  Retcc,  retcc, ([NZcc]) },
+        0xC0 => regular!{z,        0, mem,  Retcc,  retcc, ([NZcc]) },
         0xC1 => regular!{z,       10, mem,    Pop,    pop, ([BC]) },
-        0xC2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([NZcc] [nn]) },
+        0xC2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([NZcc] [nn]) },
         0xC3 => nn_inst!{z,   nn, 10, mem,     Jp,     jp, ([nn]) },
         0xC4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([NZcc] [nn]) },
         0xC5 => regular!{z,       11, mem,   Push,   push, ([BC]) },
         0xC6 => n_inst!{z,     n,  7, mem,    Add,    add, ([A] [n]) },
         0xC7 => regular!{z,       11, mem,    Rst,    rst, ([0x00]) },
-        0xC8 => regular!{z,        5, mem,  Retcc,  retcc, ([Zcc]) },
+        0xC8 => regular!{z,        0, mem,  Retcc,  retcc, ([Zcc]) },
         0xC9 => regular!{z,       10, mem,    Ret,    ret, () },
-        0xCA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Zcc] [nn]) },
+        0xCA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Zcc] [nn]) },
         0xCB => regular!{z,        0,  no,   Ddcb,   ddcb, () },
         0xCC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Zcc] [nn]) },
         0xCD => nn_inst!{z,   nn, 17, mem,   Call,   call, ([nn]) },
         0xCE => n_inst! {z,    n,  7, mem,    Adc,    adc, ([A] [n]) },
         0xCF => regular!{z,       11, mem,    Rst,    rst, ([0x08]) },
-        0xD0 => regular!{z,        5, mem,  Retcc,  retcc, ([NCcc]) },
+        0xD0 => regular!{z,        0, mem,  Retcc,  retcc, ([NCcc]) },
         0xD1 => regular!{z,       10, mem,    Pop,    pop, ([DE]) },
-        0xD2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([NCcc] [nn]) },
+        0xD2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([NCcc] [nn]) },
         0xD3 => n_inst! {z,    n, 11,  io,   OutN,  out_n, ([n ] [A]) },
         0xD4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([NCcc] [nn]) },
         0xD5 => regular!{z,       11, mem,   Push,   push, ([DE]) },
         0xD6 => n_inst! {z,    n,  7, mem,    Sub,    sub, ([A] [n]) },
         0xD7 => regular!{z,       11, mem,    Rst,    rst, ([0x10]) },
-        0xD8 => regular!{z,        5, mem,  Retcc,  retcc, ([Ccc]) },
+        0xD8 => regular!{z,        0, mem,  Retcc,  retcc, ([Ccc]) },
         0xD9 => regular!{z,        4,  no,    Exx,    exx, () },
-        0xDA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Ccc] [nn]) },
+        0xDA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Ccc] [nn]) },
         0xDB => n_inst! {z,    n, 11,  io,    InN,   in_n, ([A] [n]) },
         0xDC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Ccc] [nn]) },
         0xDD => regular!{z,        4,  no,     Dd,     dd, () },
         0xDE => n_inst! {z,    n,  7, mem,    Sbc,    sbc, ([A] [n]) },
         0xDF => regular!{z,       11, mem,    Rst,    rst, ([0x18]) },
-        0xE0 => regular!{z,        5, mem,  Retcc,  retcc, ([POcc]) },
+        0xE0 => regular!{z,        0, mem,  Retcc,  retcc, ([POcc]) },
         0xE1 => regular!{z,       10, mem,    Pop,    pop, ([IX]) },
-        0xE2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([POcc] [nn]) },
+        0xE2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([POcc] [nn]) },
         0xE3 => regular!{z,       19, mem,     Ex,     ex, ([Address(SP)] [IX]) },
         0xE4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([POcc] [nn]) },
         0xE5 => regular!{z,       11, mem,   Push,   push, ([IX]) },
         0xE6 => n_inst! {z,    n,  7, mem,    And,    and, ([n]) },
         0xE7 => regular!{z,       11, mem,    Rst,    rst, ([0x20]) },
-        0xE8 => regular!{z,        5, mem,  Retcc,  retcc, ([PEcc]) },
+        0xE8 => regular!{z,        0, mem,  Retcc,  retcc, ([PEcc]) },
         0xE9 => regular!{z,        4, mem,     Jp,     jp, ([IX]) },
-        0xEA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([PEcc] [nn]) },
+        0xEA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([PEcc] [nn]) },
         0xEB => regular!{z,        4, mem,     Ex,     ex, ([DE] [HL]) },
         0xEC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([PEcc] [nn]) },
         0xED => regular!{z,        4,  no,     Ed,     ed, () },
         0xEE => n_inst! {z,    n,  7, mem,    Xor,    xor, ([n]) },
         0xEF => regular!{z,       11, mem,    Rst,    rst, ([0x28]) },
-        0xF0 => regular!{z,        5, mem,  Retcc,  retcc, ([Pcc]) },
+        0xF0 => regular!{z,        0, mem,  Retcc,  retcc, ([Pcc]) },
         0xF1 => regular!{z,       10, mem,    Pop,    pop, ([AF]) },
-        0xF2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Pcc] [nn]) },
+        0xF2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Pcc] [nn]) },
         0xF3 => regular!{z,        4,  no,     Di,     di, () },
         0xF4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Pcc] [nn]) },
         0xF5 => regular!{z,       11, mem,   Push,   push, ([AF]) },
         0xF6 => n_inst! {z,    n,  7, mem,     Or,     or, ([n]) },
         0xF7 => regular!{z,       11, mem,    Rst,    rst, ([0x30]) },
-        0xF8 => regular!{z,        5, mem,  Retcc,  retcc, ([Mcc]) },
+        0xF8 => regular!{z,        0, mem,  Retcc,  retcc, ([Mcc]) },
         0xF9 => regular!{z,        6, mem,   Ld16,   ld16, ([SP] [IX]) },
-        0xFA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Mcc] [nn]) },
+        0xFA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Mcc] [nn]) },
         0xFB => regular!{z,        4,  no,     Ei,     ei, () },
         0xFC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Mcc] [nn]) },
         0xFD => regular!{z,        4,  no,     Fd,     fd, () },
         0xFE => n_inst! {z,    n,  7, mem,     Cp,     cp, ([n]) },
         0xFF => regular!{z,       11, mem,    Rst,    rst, ([0x38]) },
-        _ => unimplemented!(),
     }
 }
 
-pub fn fd<Z>(z: &mut Z)
-where
-    Z: Z80Emulator,
-{
-    use self::instruction_traits::*;
-
-    use self::ConditionCode::*;
-    use self::Reg16::*;
-    use self::Reg8::*;
+instructions! {
+    pub fn fd, fd_table(z) {
+        use self::instruction_traits::*;
 
-    let pc = z.read_pc();
-    z.inc_pc();
-    match pc {
+        use self::ConditionCode::*;
+        use self::Reg16::*;
+        use self::Reg8::*;
+    }
+    match {
         0x00 => regular!{z,        4,  no,    Nop,    nop, () },
         0x01 => nn_inst!{z,   nn, 10, mem,   Ld16,   ld16, ([BC] [nn]) },
         0x02 => regular!{z,        7, mem,     Ld,     ld, ([Address(BC)] [A]) },
@@ -1861,7 +2050,7 @@ where
         0x0D => regular!{z,        4, mem,    Dec,    dec, ([C]) },
         0x0E => n_inst! {z,    n,  7, mem,     Ld,     ld, ([C] [n]) },
         0x0F => regular!{z,        4, mem,   Rrca,   rrca, () },
-        0x10 => e_inst! {z,    e,  8,  no,   Djnz,   djnz, ([e]) },
+        0x10 => e_inst! {z,    e,  0,  no,   Djnz,   djnz, ([e]) },
         0x11 => nn_inst!{z,   nn, 10, mem,   Ld16,   ld16, ([DE] [nn]) },
         0x12 => regular!{z,        7, mem,     Ld,     ld, ([Address(DE)] [A]) },
         0x13 => regular!{z,        6,  no,  Inc16,  inc16, ([DE]) },
@@ -2037,88 +2226,81 @@ where
         0xBD => regular!{z,        4, mem,     Cp,     cp, ([IYL]) },
         0xBE => e_inst! {z,    d, 15, mem,     Cp,     cp, ([Shift(IY, d)]) },
         0xBF => regular!{z,        4, mem,     Cp,     cp, ([A]) },
-        0xC0 => regular!{z,        5, mem,  Retcc,  retcc, ([NZcc]) },
+        0xC0 => regular!{z,        0, mem,  Retcc,  retcc, ([NZcc]) },
         0xC1 => regular!{z,       10, mem,    Pop,    pop, ([BC]) },
-        0xC2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([NZcc] [nn]) },
+        0xC2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([NZcc] [nn]) },
         0xC3 => nn_inst!{z,   nn, 10, mem,     Jp,     jp, ([nn]) },
         0xC4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([NZcc] [nn]) },
         0xC5 => regular!{z,       11, mem,   Push,   push, ([BC]) },
         0xC6 => n_inst!{z,     n,  7, mem,    Add,    add, ([A] [n]) },
         0xC7 => regular!{z,       11, mem,    Rst,    rst, ([0x00]) },
-        0xC8 => regular!{z,        5, mem,  Retcc,  retcc, ([Zcc]) },
+        0xC8 => regular!{z,        0, mem,  Retcc,  retcc, ([Zcc]) },
         0xC9 => regular!{z,       10, mem,    Ret,    ret, () },
-        0xCA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Zcc] [nn]) },
+        0xCA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Zcc] [nn]) },
         0xCB => regular!{z,        0,  no,   Fdcb,   fdcb, () },
         0xCC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Zcc] [nn]) },
         0xCD => nn_inst!{z,   nn, 17, mem,   Call,   call, ([nn]) },
         0xCE => n_inst! {z,    n,  7, mem,    Adc,    adc, ([A] [n]) },
         0xCF => regular!{z,       11, mem,    Rst,    rst, ([0x08]) },
-        0xD0 => regular!{z,        5, mem,  Retcc,  retcc, ([NCcc]) },
+        0xD0 => regular!{z,        0, mem,  Retcc,  retcc, ([NCcc]) },
         0xD1 => regular!{z,       10, mem,    Pop,    pop, ([DE]) },
-        0xD2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([NCcc] [nn]) },
+        0xD2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([NCcc] [nn]) },
         0xD3 => n_inst! {z,    n, 11,  io,   OutN,  out_n, ([n ] [A]) },
         0xD4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([NCcc] [nn]) },
         0xD5 => regular!{z,       11, mem,   Push,   push, ([DE]) },
         0xD6 => n_inst! {z,    n,  7, mem,    Sub,    sub, ([A] [n]) },
         0xD7 => regular!{z,       11, mem,    Rst,    rst, ([0x10]) },
-        0xD8 => regular!{z,        5, mem,  Retcc,  retcc, ([Ccc]) },
+        0xD8 => regular!{z,        0, mem,  Retcc,  retcc, ([Ccc]) },
         0xD9 => regular!{z,        4,  no,    Exx,    exx, () },
-        0xDA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Ccc] [nn]) },
+        0xDA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Ccc] [nn]) },
         0xDB => n_inst! {z,    n, 11,  io,    InN,   in_n, ([A] [n]) },
         0xDC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Ccc] [nn]) },
         0xDD => regular!{z,        4,  no,     Dd,     dd, () },
         0xDE => n_inst! {z,    n,  7, mem,    Sbc,    sbc, ([A] [n]) },
         0xDF => regular!{z,       11, mem,    Rst,    rst, ([0x18]) },
-        0xE0 => regular!{z,        5, mem,  Retcc,  retcc, ([POcc]) },
+        0xE0 => regular!{z,        0, mem,  Retcc,  retcc, ([POcc]) },
         0xE1 => regular!{z,       10, mem,    Pop,    pop, ([IY]) },
-        0xE2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([POcc] [nn]) },
+        0xE2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([POcc] [nn]) },
         0xE3 => regular!{z,       19, mem,     Ex,     ex, ([Address(SP)] [IY]) },
         0xE4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([POcc] [nn]) },
         0xE5 => regular!{z,       11, mem,   Push,   push, ([IY]) },
         0xE6 => n_inst! {z,    n,  7, mem,    And,    and, ([n]) },
         0xE7 => regular!{z,       11, mem,    Rst,    rst, ([0x20]) },
-        0xE8 => regular!{z,        5, mem,  Retcc,  retcc, ([PEcc]) },
+        0xE8 => regular!{z,        0, mem,  Retcc,  retcc, ([PEcc]) },
         0xE9 => regular!{z,        4, mem,     Jp,     jp, ([IY]) },
-        0xEA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([PEcc] [nn]) },
+        0xEA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([PEcc] [nn]) },
         0xEB => regular!{z,        4, mem,     Ex,     ex, ([DE] [HL]) },
         0xEC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([PEcc] [nn]) },
         0xED => regular!{z,        4,  no,     Ed,     ed, () },
         0xEE => n_inst! {z,    n,  7, mem,    Xor,    xor, ([n]) },
         0xEF => regular!{z,       11, mem,    Rst,    rst, ([0x28]) },
-        0xF0 => regular!{z,        5, mem,  Retcc,  retcc, ([Pcc]) },
+        0xF0 => regular!{z,        0, mem,  Retcc,  retcc, ([Pcc]) },
         0xF1 => regular!{z,       10, mem,    Pop,    pop, ([AF]) },
-        0xF2 => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Pcc] [nn]) },
+        0xF2 => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Pcc] [nn]) },
         0xF3 => regular!{z,        4,  no,     Di,     di, () },
         0xF4 => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Pcc] [nn]) },
         0xF5 => regular!{z,       11, mem,   Push,   push, ([AF]) },
         0xF6 => n_inst! {z,    n,  7, mem,     Or,     or, ([n]) },
         0xF7 => regular!{z,       11, mem,    Rst,    rst, ([0x30]) },
-        0xF8 => regular!{z,        5, mem,  Retcc,  retcc, ([Mcc]) },
+        0xF8 => regular!{z,        0, mem,  Retcc,  retcc, ([Mcc]) },
         0xF9 => regular!{z,        6, mem,   Ld16,   ld16, ([SP] [IY]) },
-        0xFA => nn_inst!{z,   nn, 10,  no,   Jpcc,   jpcc, ([Mcc] [nn]) },
+        0xFA => nn_inst!{z,   nn,  0,  no,   Jpcc,   jpcc, ([Mcc] [nn]) },
         0xFB => regular!{z,        4,  no,     Ei,     ei, () },
         0xFC => nn_inst!{z,   nn,  0, mem, Callcc, callcc, ([Mcc] [nn]) },
         0xFD => regular!{z,        4,  no,     Fd,     fd, () },
         0xFE => n_inst! {z,    n,  7, mem,     Cp,     cp, ([n]) },
         0xFF => regular!{z,       11, mem,    Rst,    rst, ([0x38]) },
-        _ => unimplemented!(),
     }
 }
 
-pub fn ddcb<Z>(z: &mut Z)
-where
-    Z: Z80Emulator,
-{
-    use self::instruction_traits::*;
-
-    use self::Reg16::*;
-    use self::Reg8::*;
+instructions! {
+    pub fn ddcb, ddcb_table(z, d) {
+        use self::instruction_traits::*;
 
-    let d = z.read_pc() as i8;
-    z.inc_pc();
-    let pc = z.read_pc();
-    z.inc_pc();
-    match pc {
+        use self::Reg16::*;
+        use self::Reg8::*;
+    }
+    match {
         0x00 => regular!{z, 19, mem, RlcStore, rlc_store, ([Shift(IX, d)] [B]) },
         0x01 => regular!{z, 19, mem, RlcStore, rlc_store, ([Shift(IX, d)] [C]) },
         0x02 => regular!{z, 19, mem, RlcStore, rlc_store, ([Shift(IX, d)] [D]) },
@@ -2375,24 +2557,17 @@ where
         0xFD => regular!{z, 19, mem, SetStore, set_store, ([7] [Shift(IX, d)] [L]) },
         0xFE => regular!{z, 19, mem, Set, set, ([7] [Shift(IX, d)]) },
         0xFF => regular!{z, 19, mem, SetStore, set_store, ([7] [Shift(IX, d)] [A]) },
-        _ => unimplemented!(),
     }
 }
 
-pub fn fdcb<Z>(z: &mut Z)
-where
-    Z: Z80Emulator,
-{
-    use self::instruction_traits::*;
-
-    use self::Reg16::*;
-    use self::Reg8::*;
+instructions! {
+    pub fn fdcb, fdcb_table(z, d) {
+        use self::instruction_traits::*;
 
-    let d = z.read_pc() as i8;
-    z.inc_pc();
-    let pc = z.read_pc();
-    z.inc_pc();
-    match pc {
+        use self::Reg16::*;
+        use self::Reg8::*;
+    }
+    match {
         0x00 => regular!{z, 19, mem, RlcStore, rlc_store, ([Shift(IY, d)] [B]) },
         0x01 => regular!{z, 19, mem, RlcStore, rlc_store, ([Shift(IY, d)] [C]) },
         0x02 => regular!{z, 19, mem, RlcStore, rlc_store, ([Shift(IY, d)] [D]) },
@@ -2649,6 +2824,5 @@ where
         0xFD => regular!{z, 19, mem, SetStore, set_store, ([7] [Shift(IY, d)] [L]) },
         0xFE => regular!{z, 19, mem, Set, set, ([7] [Shift(IY, d)]) },
         0xFF => regular!{z, 19, mem, SetStore, set_store, ([7] [Shift(IY, d)] [A]) },
-        _ => unimplemented!(),
     }
 }
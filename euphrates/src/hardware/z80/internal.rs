@@ -1,4 +1,5 @@
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 use super::*;
 
@@ -116,6 +117,13 @@ pub trait Z80Internal {
     fn interrupt_status(&self) -> InterruptStatus;
     fn set_interrupt_status(&mut self, interrupt_status: InterruptStatus);
 
+    /// How many cycles the last instruction executed took, including any
+    /// prefix bytes and the extra cycles of a branch taken.
+    ///
+    /// Cycles spent accepting an interrupt aren't included.
+    fn last_instruction_cycles(&self) -> u32;
+    fn set_last_instruction_cycles(&mut self, cycles: u32);
+
     /// Increment the Z80's `cycles` by `x`.
     #[inline]
     fn inc_cycles(&mut self, x: u64) {
@@ -229,9 +237,10 @@ where
     dest.set_interrupt_mode(source.interrupt_mode());
     dest.set_prefix(source.prefix());
     dest.set_interrupt_status(source.interrupt_status());
+    dest.set_last_instruction_cycles(source.last_instruction_cycles());
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 #[repr(C)]
 pub struct Z80State {
     pub cycles: u64,
@@ -242,6 +251,41 @@ pub struct Z80State {
     pub prefix: Prefix,
     pub interrupt_mode: InterruptMode,
     pub interrupt_status: InterruptStatus,
+
+    /// See `Z80Internal::last_instruction_cycles`.
+    ///
+    /// This only describes what already happened, so it isn't saved, and
+    /// doesn't count in comparing or hashing states.
+    #[serde(skip)]
+    pub last_instruction_cycles: u32,
+}
+
+impl PartialEq for Z80State {
+    fn eq(&self, other: &Self) -> bool {
+        self.cycles == other.cycles
+            && self.registers == other.registers
+            && self.halted == other.halted
+            && self.iff1 == other.iff1
+            && self.iff2 == other.iff2
+            && self.prefix == other.prefix
+            && self.interrupt_mode == other.interrupt_mode
+            && self.interrupt_status == other.interrupt_status
+    }
+}
+
+impl Eq for Z80State {}
+
+impl Hash for Z80State {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.cycles.hash(state);
+        self.registers.hash(state);
+        self.halted.hash(state);
+        self.iff1.hash(state);
+        self.iff2.hash(state);
+        self.prefix.hash(state);
+        self.interrupt_mode.hash(state);
+        self.interrupt_status.hash(state);
+    }
 }
 
 /// This module contains offsets for the fields of the Z80State. It's probably
@@ -314,6 +358,7 @@ impl Default for Z80State {
             prefix: Prefix::NoPrefix,
             interrupt_status: Default::default(),
             interrupt_mode: Default::default(),
+            last_instruction_cycles: 0,
         };
         z80.set_reg16(Reg16::IX, 0xFFFF);
         z80.set_reg16(Reg16::IY, 0xFFFF);
@@ -424,6 +469,16 @@ impl Z80Internal for Z80State {
         self.interrupt_status = interrupt_status
     }
 
+    #[inline]
+    fn last_instruction_cycles(&self) -> u32 {
+        self.last_instruction_cycles
+    }
+
+    #[inline]
+    fn set_last_instruction_cycles(&mut self, cycles: u32) {
+        self.last_instruction_cycles = cycles
+    }
+
     #[inline]
     fn state(&self) -> Z80State {
         self.clone()
@@ -1,5 +1,6 @@
 //! Handling interrupts for the Z80
 
+use hardware::io16::Io16;
 use hardware::memory16::Memory16;
 use memo::Inbox;
 use utilities;
@@ -14,18 +15,91 @@ pub trait Z80Interrupt {
     fn nonmaskable_interrupt(&mut self);
 }
 
-pub struct Z80InterruptImpler<'a, Z: 'a + ?Sized, M: 'a + ?Sized, Irq: 'a + ?Sized, I: 'a + ?Sized>
-{
+pub struct Z80InterruptImpler<
+    'a,
+    Z: 'a + ?Sized,
+    M: 'a + ?Sized,
+    Io: 'a + ?Sized,
+    Irq: 'a + ?Sized,
+    I: 'a + ?Sized,
+> {
     pub z80: &'a mut Z,
     pub memory: &'a mut M,
+    pub io: &'a mut Io,
     pub irq: &'a mut Irq,
     pub inbox: &'a mut I,
 }
 
-impl<'a, Z: 'a, M: 'a, Irq: 'a, I: 'a> Z80Interrupt for Z80InterruptImpler<'a, Z, M, Irq, I>
+/// Executes the instruction placed on the data bus during an interrupt in
+/// mode 0.
+///
+/// Opcodes are fetched from `byte` rather than from memory, and PC is not
+/// incremented. Only single byte instructions (in practice, always an `rst`)
+/// make sense here; any further operand bytes the instruction fetches will
+/// also be `byte`.
+struct Im0Impler<'a, Z: 'a + ?Sized, M: 'a + ?Sized, Io: 'a + ?Sized> {
+    z80: &'a mut Z,
+    memory: &'a mut M,
+    io: &'a mut Io,
+    byte: u8,
+}
+
+impl<'a, Z, M, Io> instruction::Z80Emulator for Im0Impler<'a, Z, M, Io>
+where
+    Z: 'a + ?Sized + Z80Internal,
+    M: 'a + ?Sized + Memory16,
+    Io: 'a + ?Sized + Io16,
+{
+    type No = Z80NoImpler<Z>;
+
+    type Mem = Z80MemImpler<Z, M>;
+
+    type Io = Z80IoImpler<Z, M, Io>;
+
+    #[inline]
+    fn no<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Self::No),
+    {
+        f(unsafe { &mut Z80NoImpler::new(self.z80) });
+    }
+
+    #[inline]
+    fn mem<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Self::Mem),
+    {
+        f(unsafe { &mut Z80MemImpler::new(self.z80, self.memory) });
+    }
+
+    #[inline]
+    fn io<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Self::Io),
+    {
+        f(unsafe { &mut Z80IoImpler::new(self.z80, self.memory, self.io) });
+    }
+
+    #[inline]
+    fn read_pc(&mut self) -> u8 {
+        self.byte
+    }
+
+    #[inline]
+    fn inc_pc(&mut self) {}
+
+    #[inline]
+    fn inc_cycles(&mut self, c: u64) {
+        self.z80.inc_cycles(c);
+    }
+}
+
+impl<'a, Z: 'a, M: 'a, Io: 'a, Irq: 'a, I: 'a> Z80Interrupt
+    for Z80InterruptImpler<'a, Z, M, Io, Irq, I>
 where
     Z: Z80Internal + ?Sized,
     M: Memory16 + ?Sized,
+    Io: Io16 + ?Sized,
     Irq: Z80Irq + ?Sized,
     I: Inbox<Memo = Z80Memo> + ?Sized,
 {
@@ -56,21 +130,35 @@ where
             self.z80.set_iff1(false);
             self.z80.set_iff2(false);
             self.z80.set_prefix(Prefix::NoPrefix);
+            self.z80.set_halted(false);
 
             use self::instruction::instruction_traits::Rst;
 
             match self.z80.interrupt_mode() {
+                InterruptMode::Im0 => {
+                    // acknowledging the interrupt takes 2 cycles beyond the
+                    // instruction itself
+                    self.z80.inc_cycles(2);
+                    instruction::noprefix(&mut Im0Impler {
+                        z80: self.z80,
+                        memory: self.memory,
+                        io: self.io,
+                        byte: x,
+                    });
+                }
                 InterruptMode::Im1 => {
                     unsafe { Z80MemImpler::new(self.z80, self.memory).rst(0x38u8) };
                     self.z80.inc_cycles(13);
                 }
                 InterruptMode::Im2 => {
                     let i = self.z80.reg8(Reg8::I);
-                    let new_pc = utilities::to16(x, i);
+                    let vector_address = utilities::to16(x, i);
+                    let lo = self.memory.read(vector_address);
+                    let hi = self.memory.read(vector_address.wrapping_add(1));
+                    let new_pc = utilities::to16(lo, hi);
                     unsafe { Z80MemImpler::new(self.z80, self.memory).rst(new_pc) };
                     self.z80.inc_cycles(19);
                 }
-                _ => unimplemented!(),
             }
         }
     }
@@ -81,7 +169,99 @@ where
         self.z80.inc_r(1);
         self.z80.set_iff1(false);
         self.z80.set_prefix(Prefix::NoPrefix);
+        self.z80.set_halted(false);
         self.z80.inc_cycles(11);
         unsafe { Z80MemImpler::new(self.z80, self.memory).rst(0x66u8) };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use memo::NothingInbox;
+
+    struct TestIo;
+
+    impl Io16 for TestIo {
+        fn input(&mut self, _address: u16) -> u8 {
+            0xFF
+        }
+
+        fn output(&mut self, _address: u16, _value: u8) {}
+    }
+
+    struct TestIrq(u8);
+
+    impl Z80Irq for TestIrq {
+        fn requesting_mi(&mut self) -> Option<u8> {
+            Some(self.0)
+        }
+
+        fn requesting_nmi(&mut self) -> bool {
+            false
+        }
+
+        fn take_nmi(&mut self) {}
+    }
+
+    /// Take a maskable interrupt with `byte` on the data bus, returning the
+    /// Z80 and memory afterwards.
+    fn interrupt(mode: InterruptMode, byte: u8) -> (Z80State, Box<[u8; 0x10000]>) {
+        let mut z80 = Z80State::default();
+        z80.set_interrupt_mode(mode);
+        z80.set_iff1(true);
+        z80.set_iff2(true);
+        z80.set_reg16(Reg16::PC, 0x1234);
+        z80.set_reg16(Reg16::SP, 0x8000);
+        z80.set_reg8(Reg8::I, 0x40);
+        let mut memory = Box::new([0u8; 0x10000]);
+        memory[0x4020] = 0x78;
+        memory[0x4021] = 0x56;
+        Z80InterruptImpler {
+            z80: &mut z80,
+            memory: &mut *memory,
+            io: &mut TestIo,
+            irq: &mut TestIrq(byte),
+            inbox: &mut NothingInbox::default(),
+        }.check_interrupts();
+        (z80, memory)
+    }
+
+    fn assert_pushed_pc(z80: &Z80State, memory: &[u8; 0x10000]) {
+        assert_eq!(z80.reg16(Reg16::SP), 0x7FFE);
+        assert_eq!(memory[0x7FFE], 0x34);
+        assert_eq!(memory[0x7FFF], 0x12);
+        assert!(!z80.iff1());
+    }
+
+    #[test]
+    fn im0() {
+        // rst 0x38
+        let (z80, memory) = interrupt(InterruptMode::Im0, 0xFF);
+        assert_eq!(z80.reg16(Reg16::PC), 0x38);
+        assert_eq!(z80.cycles(), 13);
+        assert_pushed_pc(&z80, &memory);
+
+        // rst 0x10
+        let (z80, memory) = interrupt(InterruptMode::Im0, 0xD7);
+        assert_eq!(z80.reg16(Reg16::PC), 0x10);
+        assert_pushed_pc(&z80, &memory);
+    }
+
+    #[test]
+    fn im1() {
+        let (z80, memory) = interrupt(InterruptMode::Im1, 0x20);
+        assert_eq!(z80.reg16(Reg16::PC), 0x38);
+        assert_eq!(z80.cycles(), 13);
+        assert_pushed_pc(&z80, &memory);
+    }
+
+    #[test]
+    fn im2() {
+        let (z80, memory) = interrupt(InterruptMode::Im2, 0x20);
+        assert_eq!(z80.reg16(Reg16::PC), 0x5678);
+        assert_eq!(z80.cycles(), 19);
+        assert_pushed_pc(&z80, &memory);
+    }
+}
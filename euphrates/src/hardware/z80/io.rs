@@ -1,5 +1,6 @@
 use hardware::io16::Io16;
 use hardware::memory16::Memory16;
+use memo::Inbox;
 use utilities;
 
 use self::Reg16::*;
@@ -26,10 +27,80 @@ impl<Z: ?Sized, M: ?Sized, I: ?Sized> Z80IoImpler<Z, M, I> {
     }
 }
 
+/// An `Io16` that sends `inbox` a `Z80Memo::PortRead` or
+/// `Z80Memo::PortWrite` for each access to `io`, if the inbox is active.
+pub struct Z80MemoIo16Impler<I: ?Sized, Inb: ?Sized> {
+    io: *mut I,
+    inbox: *mut Inb,
+}
+
+impl<I: ?Sized, Inb: ?Sized> Z80MemoIo16Impler<I, Inb> {
+    /// Caller's responsibility to make sure the result doesn't live longer than
+    /// the references
+    #[inline(always)]
+    pub unsafe fn new(io: &mut I, inbox: &mut Inb) -> Self {
+        Z80MemoIo16Impler { io, inbox }
+    }
+}
+
+impl<I, Inb> Io16 for Z80MemoIo16Impler<I, Inb>
+where
+    I: Io16 + ?Sized,
+    Inb: Inbox<Memo = Z80Memo> + ?Sized,
+{
+    #[inline(always)]
+    fn input(&mut self, port: u16) -> u8 {
+        let (io, inbox) = unsafe { (&mut *self.io, &mut *self.inbox) };
+        let value = io.input(port);
+        if inbox.active() {
+            inbox.receive(Z80Memo::PortRead { port, value });
+        }
+        value
+    }
+
+    #[inline(always)]
+    fn output(&mut self, port: u16, value: u8) {
+        let (io, inbox) = unsafe { (&mut *self.io, &mut *self.inbox) };
+        io.output(port, value);
+        if inbox.active() {
+            inbox.receive(Z80Memo::PortWrite { port, value });
+        }
+    }
+
+    #[inline(always)]
+    fn wait_cycles(&mut self, port: u16, cycles: u64) -> u32 {
+        unsafe { (*self.io).wait_cycles(port, cycles) }
+    }
+}
+
 pub trait Z80IoT: Z80MemT {
     type Io: Io16 + ?Sized;
 
     fn io(&mut self) -> &mut Self::Io;
+
+    /// Input a byte from `io()`, including any wait cycles it imposes.
+    #[inline(always)]
+    fn io_input(&mut self, address: u16) -> u8 {
+        let val = self.io().input(address);
+        self.io_wait(address);
+        val
+    }
+
+    /// Output a byte to `io()`, including any wait cycles it imposes.
+    #[inline(always)]
+    fn io_output(&mut self, address: u16, value: u8) {
+        self.io().output(address, value);
+        self.io_wait(address);
+    }
+
+    #[inline(always)]
+    fn io_wait(&mut self, address: u16) {
+        let cycles = self.z80().cycles();
+        let wait = self.io().wait_cycles(address, cycles);
+        if wait != 0 {
+            self.z80().inc_cycles(wait as u64);
+        }
+    }
 }
 
 impl<Z: ?Sized, M: ?Sized, I: ?Sized> Z80MemT for Z80IoImpler<Z, M, I>
@@ -75,7 +146,7 @@ where
         let address_lo = y.view(self);
         let address_hi = x.view(self);
         let address = utilities::to16(address_lo, address_hi);
-        let val = self.io().input(address);
+        let val = self.io_input(address);
         x.change(self, val);
     }
 }
@@ -97,7 +168,7 @@ where
         let address_lo = y.view(self);
         let address_hi = x.view(self);
         let address = utilities::to16(address_lo, address_hi);
-        let val = self.io().input(address);
+        let val = self.io_input(address);
         x.change(self, val);
     }
 }
@@ -200,7 +271,7 @@ where
         let address_hi = B.view(self);
         let address = utilities::to16(address_lo, address_hi);
         let val = y.view(self);
-        self.io().output(address, val);
+        self.io_output(address, val);
 
         // our output may have triggered an interrupt
         self.z80().set_interrupt_status(InterruptStatus::Check);
@@ -216,7 +287,7 @@ where
         let address_hi = A.view(self);
         let address = utilities::to16(address_lo, address_hi);
         let val = y.view(self);
-        self.io().output(address, val);
+        self.io_output(address, val);
 
         // our output may have triggered an interrupt
         self.z80().set_interrupt_status(InterruptStatus::Check);
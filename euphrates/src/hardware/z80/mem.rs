@@ -1,6 +1,7 @@
 use utilities;
 
 use hardware::memory16::Memory16;
+use memo::Inbox;
 
 use super::instruction::instruction_traits::*;
 use super::*;
@@ -49,6 +50,47 @@ where
     }
 }
 
+/// A `Memory16` that sends `inbox` a `Z80Memo::MemoryRead` or
+/// `Z80Memo::MemoryWrite` for each access to `memory`, if the inbox is active.
+pub struct Z80MemoMemoryImpler<M: ?Sized, Inb: ?Sized> {
+    memory: *mut M,
+    inbox: *mut Inb,
+}
+
+impl<M: ?Sized, Inb: ?Sized> Z80MemoMemoryImpler<M, Inb> {
+    /// Caller's responsibility to make sure the result doesn't live longer than
+    /// the references
+    #[inline(always)]
+    pub unsafe fn new(memory: &mut M, inbox: &mut Inb) -> Self {
+        Z80MemoMemoryImpler { memory, inbox }
+    }
+}
+
+impl<M, Inb> Memory16 for Z80MemoMemoryImpler<M, Inb>
+where
+    M: Memory16 + ?Sized,
+    Inb: Inbox<Memo = Z80Memo> + ?Sized,
+{
+    #[inline(always)]
+    fn read(&mut self, address: u16) -> u8 {
+        let (memory, inbox) = unsafe { (&mut *self.memory, &mut *self.inbox) };
+        let value = memory.read(address);
+        if inbox.active() {
+            inbox.receive(Z80Memo::MemoryRead { address, value });
+        }
+        value
+    }
+
+    #[inline(always)]
+    fn write(&mut self, address: u16, value: u8) {
+        let (memory, inbox) = unsafe { (&mut *self.memory, &mut *self.inbox) };
+        memory.write(address, value);
+        if inbox.active() {
+            inbox.receive(Z80Memo::MemoryWrite { address, value });
+        }
+    }
+}
+
 /// An aspect of the Z80 that we can view, like a register or a memory address.
 ///
 /// This trait (and `Changeable`) exists so that we may implement an instruction
@@ -334,11 +376,10 @@ where
     Z: Z80MemT + ?Sized,
 {
     fn callcc(&mut self, x: ConditionCode, y: u16) {
-        if x.view(self) {
+        let taken = x.view(self);
+        self.z80().inc_cycles(CALLCC_CYCLES.cycles(taken));
+        if taken {
             <Self as Call<u16>>::call(self, y);
-            self.z80().inc_cycles(17);
-        } else {
-            self.z80().inc_cycles(10);
         }
     }
 }
@@ -623,11 +664,10 @@ where
     Z: Z80MemT + ?Sized,
 {
     fn retcc(&mut self, x: ConditionCode) {
-        if x.view(self) {
+        let taken = x.view(self);
+        self.z80().inc_cycles(RETCC_CYCLES.cycles(taken));
+        if taken {
             self.ret();
-            self.z80().inc_cycles(11);
-        } else {
-            self.z80().inc_cycles(5);
         }
     }
 }
@@ -875,7 +915,7 @@ where
     T: Changeable<u8>,
 {
     fn set_store(&mut self, x: u8, y: T, w: Reg8) {
-        self.set(x, w);
+        self.set(x, y);
 
         let y0 = y.view(self);
         w.change(self, y0);
@@ -1,5 +1,8 @@
 use std::fmt::{self, Display};
+#[cfg(not(feature = "std"))]
+use std::string::String;
 
+use hardware::memory16::Memory16;
 use utilities;
 
 use super::*;
@@ -8,13 +11,81 @@ use self::ConditionCode::*;
 use self::Reg16::*;
 use self::Reg8::*;
 
+/// Some of the Z80's registers, as they were when an instruction began.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TraceRegisters {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+}
+
+impl TraceRegisters {
+    #[inline]
+    pub fn from_z80<Z: Z80Internal + ?Sized>(z80: &Z) -> Self {
+        TraceRegisters {
+            af: z80.reg16(AF),
+            bc: z80.reg16(BC),
+            de: z80.reg16(DE),
+            hl: z80.reg16(HL),
+            sp: z80.reg16(SP),
+        }
+    }
+}
+
+impl Display for TraceRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.pad(&format!(
+            "AF={:0>4X} BC={:0>4X} DE={:0>4X} HL={:0>4X} SP={:0>4X}",
+            self.af, self.bc, self.de, self.hl, self.sp
+        ))
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Z80Memo {
-    Instruction { pc: u16, opcode: Opcode },
+    Instruction {
+        pc: u16,
+        opcode: Opcode,
+        registers: TraceRegisters,
+    },
 
-    MaskableInterrupt { mode: u8, byte: u8 },
+    MaskableInterrupt {
+        mode: u8,
+        byte: u8,
+    },
 
     NonmaskableInterrupt,
+
+    MemoryRead {
+        address: u16,
+        value: u8,
+    },
+
+    MemoryWrite {
+        address: u16,
+        value: u8,
+    },
+
+    /// An `in` instruction read `value` from `port`.
+    PortRead {
+        port: u16,
+        value: u8,
+    },
+
+    /// An `out` instruction wrote `value` to `port`.
+    PortWrite {
+        port: u16,
+        value: u8,
+    },
+
+    /// The ROM's `pages` 16 KiB pages weren't a power of two, so it was
+    /// mirrored up to `padded` of them. See `sms_roms::mirror_to_power_of_two`.
+    RomMirrored {
+        pages: u16,
+        padded: u16,
+    },
 }
 
 impl Display for Z80Memo {
@@ -26,9 +97,22 @@ impl Display for Z80Memo {
                 "Maskable interrupt: mode {}, byte: {:0>2X}",
                 mode, byte
             )),
-            Instruction { pc, opcode } => {
+            Instruction { pc, opcode, .. } => {
                 f.pad(&format!("Instruction {:0>4X}: {: <11}", pc, opcode))
             }
+            MemoryRead { address, value } => {
+                f.pad(&format!("Memory read {:0>4X}: {:0>2X}", address, value))
+            }
+            MemoryWrite { address, value } => {
+                f.pad(&format!("Memory write {:0>4X}: {:0>2X}", address, value))
+            }
+            PortRead { port, value } => f.pad(&format!("Port read {:0>4X}: {:0>2X}", port, value)),
+            PortWrite { port, value } => {
+                f.pad(&format!("Port write {:0>4X}: {:0>2X}", port, value))
+            }
+            RomMirrored { pages, padded } => {
+                f.pad(&format!("ROM mirrored from {} to {} pages", pages, padded))
+            }
         }
     }
 }
@@ -175,6 +259,16 @@ pub enum FullMnemonic {
 }
 
 impl FullMnemonic {
+    #[inline]
+    pub fn mnemonic(&self) -> Mnemonic {
+        match *self {
+            FullMnemonic::ZeroParameters(m) => m,
+            FullMnemonic::OneParameter(m, _) => m,
+            FullMnemonic::TwoParameters(m, _, _) => m,
+            FullMnemonic::ThreeParameters(m, _, _, _) => m,
+        }
+    }
+
     /// If this instruction is a jump with a fixed target, what is the target
     /// (that is, the PC it will jump to).
     pub fn jump_target(&self, pc: u16) -> Option<u16> {
@@ -189,9 +283,9 @@ impl FullMnemonic {
             OneParameter(Rst, U16(p)) => Some(p),
             // For the following instructions, e is added to the PC as it is
             // after the instruction is executed, so increase by 2
-            OneParameter(Jr, I8(e)) => Some(pc.wrapping_add(2 + e as i16 as u16)),
-            TwoParameters(Jr, _, I8(e)) => Some(pc.wrapping_add(2 + e as i16 as u16)),
-            OneParameter(Djnz, I8(e)) => Some(pc.wrapping_add(2 + e as i16 as u16)),
+            OneParameter(Jr, I8(e)) => Some(pc.wrapping_add(2).wrapping_add(e as i16 as u16)),
+            TwoParameters(Jr, _, I8(e)) => Some(pc.wrapping_add(2).wrapping_add(e as i16 as u16)),
+            OneParameter(Djnz, I8(e)) => Some(pc.wrapping_add(2).wrapping_add(e as i16 as u16)),
             _ => None,
             // There are also instructions JP (HL), JP (IX), and JP (IY), but we
             // can't statically compute their targets
@@ -525,6 +619,23 @@ macro_rules! function_to_mnemonic {
     };
 }
 
+/// Disassemble the instruction at `pc`.
+///
+/// Returns the instruction's text and its length in bytes. Prefixed
+/// instructions are decoded whole, as with `Opcode::from_memory`. A byte that
+/// doesn't begin a known instruction is rendered as `db` followed by the byte,
+/// with length 1.
+pub fn disassemble<M>(memory: &mut M, pc: u16) -> (String, u16)
+where
+    M: Memory16 + ?Sized,
+{
+    let opcode = Opcode::from_memory(memory, pc);
+    match opcode.mnemonic() {
+        Some(mnemonic) => (format!("{}", mnemonic), opcode.len() as u16),
+        None => (format!("db {:0>2X}", memory.read(pc)), 1),
+    }
+}
+
 impl Opcode {
     pub fn len(&self) -> usize {
         match *self {
@@ -546,6 +657,32 @@ impl Opcode {
         }
     }
 
+    /// Decode the opcode of the instruction at `pc`.
+    ///
+    /// If the bytes there don't form a known instruction, the result is just
+    /// the single byte at `pc`.
+    pub fn from_memory<M>(memory: &mut M, pc: u16) -> Opcode
+    where
+        M: Memory16 + ?Sized,
+    {
+        let b0 = memory.read(pc);
+        let b1 = memory.read(pc.wrapping_add(1));
+        let b2 = memory.read(pc.wrapping_add(2));
+        let b3 = memory.read(pc.wrapping_add(3));
+        let candidates = [
+            Opcode::OneByte([b0]),
+            Opcode::TwoBytes([b0, b1]),
+            Opcode::ThreeBytes([b0, b1, b2]),
+            Opcode::FourBytes([b0, b1, b2, b3]),
+        ];
+        for &opcode in candidates.iter() {
+            if opcode.mnemonic().is_some() {
+                return opcode;
+            }
+        }
+        Opcode::OneByte([b0])
+    }
+
     pub fn mnemonic(&self) -> Option<FullMnemonic> {
         // rustc insists these do not need to be mutable. Somehow it isn't
         // seeing the assignments behind the macros?
@@ -963,3 +1100,41 @@ impl Opcode {
         return None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_prefixes() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x00], "nop"),
+            (&[0x3E, 0x12], "ld a, 12"),
+            (&[0x21, 0x34, 0x12], "ld hl, 1234"),
+            (&[0xCB, 0x11], "rl c"),
+            (&[0xED, 0xB0], "ldir"),
+            (&[0xED, 0x43, 0x12, 0x00], "ld (0012), bc"),
+            (&[0xDD, 0x21, 0x00, 0xC0], "ld ix, C000"),
+            (&[0xFD, 0x7E, 0x05], "ld a, (iy+05)"),
+            (&[0xDD, 0xCB, 0xFE, 0x46], "bit 00, (ix-02)"),
+            (&[0xFD, 0xCB, 0x03, 0x16], "rl (iy+03)"),
+            (&[0xDD, 0x36, 0x80, 0x42], "ld (ix-80), 42"),
+            (&[0xED, 0xFF], "db ED"),
+        ];
+        for &(bytes, text) in cases.iter() {
+            let mut memory = [0u8; 0x10000];
+            // start just before the wraparound, to check it's handled
+            let pc = 0xFFFE;
+            for (i, &b) in bytes.iter().enumerate() {
+                memory[(pc as usize + i) & 0xFFFF] = b;
+            }
+            let expected_len = if text.starts_with("db") {
+                1
+            } else {
+                bytes.len() as u16
+            };
+            let memory: &mut dyn Memory16 = &mut memory;
+            assert_eq!(disassemble(memory, pc), (text.to_owned(), expected_len));
+        }
+    }
+}
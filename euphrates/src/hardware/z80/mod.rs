@@ -47,6 +47,55 @@ impl Default for InterruptMode {
     }
 }
 
+/// The cycles taken by a conditional control flow instruction, depending on
+/// whether its condition held and the branch was taken.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct BranchCycles {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCycles {
+    #[inline]
+    pub fn cycles(self, taken: bool) -> u64 {
+        if taken {
+            self.taken
+        } else {
+            self.not_taken
+        }
+    }
+}
+
+/// `jr cc, e`
+pub const JRCC_CYCLES: BranchCycles = BranchCycles {
+    taken: 12,
+    not_taken: 7,
+};
+
+/// `djnz e`; the branch is taken when B doesn't become 0.
+pub const DJNZ_CYCLES: BranchCycles = BranchCycles {
+    taken: 13,
+    not_taken: 8,
+};
+
+/// `jp cc, nn`
+pub const JPCC_CYCLES: BranchCycles = BranchCycles {
+    taken: 10,
+    not_taken: 10,
+};
+
+/// `call cc, nn`
+pub const CALLCC_CYCLES: BranchCycles = BranchCycles {
+    taken: 17,
+    not_taken: 10,
+};
+
+/// `ret cc`
+pub const RETCC_CYCLES: BranchCycles = BranchCycles {
+    taken: 11,
+    not_taken: 5,
+};
+
 #[cfg(target_endian = "little")]
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -253,7 +302,7 @@ impl fmt::Display for Address<Reg16> {
 
 impl fmt::Display for Address<u16> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let s = format!("({:0<4X})", self.0);
+        let s = format!("({:0>4X})", self.0);
         f.pad(&s)
     }
 }
@@ -263,7 +312,8 @@ pub struct Shift(pub Reg16, pub i8);
 
 impl fmt::Display for Shift {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let s = format!("({}{:<+07X})", self.0, self.1);
+        let sign = if self.1 < 0 { '-' } else { '+' };
+        let s = format!("({}{}{:0>2X})", self.0, sign, (self.1 as i16).abs());
         f.pad(&s)
     }
 }
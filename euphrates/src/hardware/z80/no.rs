@@ -146,11 +146,10 @@ where
         let b = self.z80().reg8(B);
         let new_b = b.wrapping_sub(1);
         self.z80().set_reg8(B, new_b);
-        if new_b != 0 {
-            self.z80().inc_cycles(13);
+        let taken = new_b != 0;
+        self.z80().inc_cycles(DJNZ_CYCLES.cycles(taken));
+        if taken {
             self.jr(e);
-        } else {
-            self.z80().inc_cycles(8);
         }
     }
 }
@@ -187,6 +186,7 @@ where
 {
     fn halt(&mut self) {
         self.z80().set_prefix(Prefix::Halt);
+        self.z80().set_halted(true);
     }
 }
 
@@ -220,7 +220,9 @@ where
 {
     fn jpcc(&mut self, cc: ConditionCode, nn: u16) {
         let flags = self.z80().reg8(F);
-        if cc.check(flags) {
+        let taken = cc.check(flags);
+        self.z80().inc_cycles(JPCC_CYCLES.cycles(taken));
+        if taken {
             self.z80().set_reg16(PC, nn);
         }
     }
@@ -242,11 +244,10 @@ where
 {
     fn jrcc(&mut self, cc: ConditionCode, e: i8) {
         let flags = self.z80().reg8(F);
-        if cc.check(flags) {
-            self.z80().inc_cycles(12);
+        let taken = cc.check(flags);
+        self.z80().inc_cycles(JRCC_CYCLES.cycles(taken));
+        if taken {
             self.jr(e);
-        } else {
-            self.z80().inc_cycles(7);
         }
     }
 }
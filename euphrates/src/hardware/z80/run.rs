@@ -1,3 +1,4 @@
+use super::instruction::{Dispatch, DispatchTable, MatchDispatch};
 use super::*;
 use hardware::io16::Io16;
 use hardware::memory16::Memory16;
@@ -11,6 +12,17 @@ pub trait Z80Run {
     /// Doesn't handle interrupts. Will return early if an `ei` is executed and
     /// interrupts were not already enabled so that interrupts can be checked.
     fn run(&mut self, target_cycles: u64);
+
+    /// Execute exactly one instruction, returning the number of cycles it
+    /// took.
+    ///
+    /// Any prefix bytes are treated as part of the instruction they modify, so
+    /// that, for instance, `DD DD DD 21 34 12` is stepped over all at once.
+    /// Interrupts are checked before the instruction, just as they would be in
+    /// `run`; if one is taken, the cycles to take it are included in the
+    /// result, and the instruction executed is the first of the interrupt
+    /// handler. If the Z80 is halted, this executes a single `nop`.
+    fn step(&mut self) -> u64;
 }
 
 pub struct Z80RunImpler<
@@ -38,9 +50,9 @@ where
 {
     type No = Z80NoImpler<Z>;
 
-    type Mem = Z80MemImpler<Z, M>;
+    type Mem = Z80MemImpler<Z, Z80MemoMemoryImpler<M, Inb>>;
 
-    type Io = Z80IoImpler<Z, M, I>;
+    type Io = Z80IoImpler<Z, Z80MemoMemoryImpler<M, Inb>, Z80MemoIo16Impler<I, Inb>>;
 
     #[inline]
     fn no<F>(&mut self, f: F)
@@ -55,7 +67,8 @@ where
     where
         F: FnOnce(&mut Self::Mem),
     {
-        f(unsafe { &mut Z80MemImpler::new(self.z80, self.memory) });
+        let mut memory = unsafe { Z80MemoMemoryImpler::new(self.memory, self.inbox) };
+        f(unsafe { &mut Z80MemImpler::new(self.z80, &mut memory) });
     }
 
     #[inline]
@@ -63,7 +76,9 @@ where
     where
         F: FnOnce(&mut Self::Io),
     {
-        f(unsafe { &mut Z80IoImpler::new(self.z80, self.memory, self.io) });
+        let mut memory = unsafe { Z80MemoMemoryImpler::new(self.memory, self.inbox) };
+        let mut io = unsafe { Z80MemoIo16Impler::new(self.io, self.inbox) };
+        f(unsafe { &mut Z80IoImpler::new(self.z80, &mut memory, &mut io) });
     }
 
     #[inline]
@@ -89,6 +104,7 @@ macro_rules! interrupt {
         let mut $var = Z80InterruptImpler {
             z80: $x.z80,
             memory: $x.memory,
+            io: $x.io,
             irq: $x.irq,
             inbox: $x.inbox,
         };
@@ -98,15 +114,17 @@ macro_rules! interrupt {
     }};
 }
 
-fn run<'a, Z: 'a, M: 'a, Irq: 'a, I: 'a, Inb: 'a>(
+fn run<'a, Z, M, Irq, I, Inb, D>(
     z: &mut Z80RunImpler<'a, Z, M, Irq, I, Inb>,
+    dispatch: &D,
     cycles: u64,
 ) where
-    Z: Z80Internal + ?Sized,
-    M: Memory16 + ?Sized,
-    Irq: Z80Irq + ?Sized,
-    I: Io16 + ?Sized,
-    Inb: Inbox<Memo = Z80Memo> + ?Sized,
+    Z: 'a + Z80Internal + ?Sized,
+    M: 'a + Memory16 + ?Sized,
+    Irq: 'a + Z80Irq + ?Sized,
+    I: 'a + Io16 + ?Sized,
+    Inb: 'a + Inbox<Memo = Z80Memo> + ?Sized,
+    D: Dispatch<Z80RunImpler<'a, Z, M, Irq, I, Inb>> + ?Sized,
 {
     use self::InterruptStatus::*;
     use self::Prefix::*;
@@ -124,9 +142,11 @@ fn run<'a, Z: 'a, M: 'a, Irq: 'a, I: 'a, Inb: 'a>(
         let z80_cycles = z.z80.cycles();
         match (prefix, interrupt_status) {
             (Halt, NoCheck) => {
-                use std::cmp::max;
-                let current_cycles = z.z80.cycles();
-                z.z80.set_cycles(max(current_cycles, cycles));
+                // skip ahead, but R still counts each `nop` the halted Z80
+                // would have executed
+                let nops = (cycles - z80_cycles).div_ceil(4);
+                z.z80.inc_r(nops as u8);
+                z.z80.set_cycles(cycles);
             }
             (Halt, _) => {
                 interrupt!{z; i; i.check_interrupts()};
@@ -137,35 +157,130 @@ fn run<'a, Z: 'a, M: 'a, Irq: 'a, I: 'a, Inb: 'a>(
             (NoPrefix, Ei(ei_cycles)) if z80_cycles > ei_cycles => {
                 interrupt!{z; i; i.check_interrupts()};
             }
-            (NoPrefix, _) => instruction::noprefix(z),
-            (Cb, _) => {
-                z.z80.set_prefix(NoPrefix);
-                instruction::cb(z);
-            }
-            (Ed, _) => {
-                z.z80.set_prefix(NoPrefix);
-                instruction::ed(z);
-            }
-            (Dd, _) => {
-                z.z80.inc_r(1);
-                z.z80.set_prefix(NoPrefix);
-                instruction::dd(z);
-            }
-            (Fd, _) => {
-                z.z80.inc_r(1);
-                z.z80.set_prefix(NoPrefix);
-                instruction::fd(z);
-            }
-            (DdCb, _) => {
-                z.z80.set_prefix(NoPrefix);
-                instruction::ddcb(z);
-            }
-            (FdCb, _) => {
-                z.z80.set_prefix(NoPrefix);
-                instruction::fdcb(z);
+            _ => execute(z, dispatch),
+        }
+    }
+}
+
+/// Execute the opcode at PC, given the current prefix.
+///
+/// If the opcode is itself a prefix, this just sets the new prefix. Either way,
+/// the Z80's `last_instruction_cycles` is updated.
+#[inline(always)]
+fn execute<'a, Z, M, Irq, I, Inb, D>(z: &mut Z80RunImpler<'a, Z, M, Irq, I, Inb>, dispatch: &D)
+where
+    Z: 'a + Z80Internal + ?Sized,
+    M: 'a + Memory16 + ?Sized,
+    Irq: 'a + Z80Irq + ?Sized,
+    I: 'a + Io16 + ?Sized,
+    Inb: 'a + Inbox<Memo = Z80Memo> + ?Sized,
+    D: Dispatch<Z80RunImpler<'a, Z, M, Irq, I, Inb>> + ?Sized,
+{
+    use self::Prefix::*;
+
+    let start_cycles = z.z80.cycles();
+    let prefix = z.z80.prefix();
+
+    match prefix {
+        NoPrefix => {
+            if z.inbox.active() {
+                let pc = z.z80.reg16(PC);
+                let opcode = Opcode::from_memory(z.memory, pc);
+                let registers = TraceRegisters::from_z80(z.z80);
+                z.inbox.receive(Z80Memo::Instruction {
+                    pc,
+                    opcode,
+                    registers,
+                });
+                if z.inbox.holding() {
+                    // The inbox wants to hold before this instruction, as at
+                    // a breakpoint.
+                    return;
+                }
             }
+            z.z80.inc_r(1);
+            dispatch.noprefix(z);
+        }
+        Cb => {
+            z.z80.inc_r(1);
+            z.z80.set_prefix(NoPrefix);
+            dispatch.cb(z);
+        }
+        Ed => {
+            z.z80.inc_r(1);
+            z.z80.set_prefix(NoPrefix);
+            dispatch.ed(z);
         }
+        Dd => {
+            z.z80.inc_r(1);
+            z.z80.set_prefix(NoPrefix);
+            dispatch.dd(z);
+        }
+        Fd => {
+            z.z80.inc_r(1);
+            z.z80.set_prefix(NoPrefix);
+            dispatch.fd(z);
+        }
+        DdCb => {
+            z.z80.set_prefix(NoPrefix);
+            dispatch.ddcb(z);
+        }
+        FdCb => {
+            z.z80.set_prefix(NoPrefix);
+            dispatch.fdcb(z);
+        }
+        Halt => {
+            // a halted Z80 executes `nop`s
+            z.z80.inc_r(1);
+            z.z80.inc_cycles(4);
+        }
+    }
+
+    // An instruction begins with no prefix (or halted); the cycles of any
+    // prefix bytes are added to those of the instruction they modify.
+    let cycles = (z.z80.cycles() - start_cycles) as u32;
+    let last_cycles = match prefix {
+        NoPrefix | Halt => cycles,
+        _ => z.z80.last_instruction_cycles() + cycles,
+    };
+    z.z80.set_last_instruction_cycles(last_cycles);
+}
+
+fn step<'a, Z, M, Irq, I, Inb, D>(z: &mut Z80RunImpler<'a, Z, M, Irq, I, Inb>, dispatch: &D) -> u64
+where
+    Z: 'a + Z80Internal + ?Sized,
+    M: 'a + Memory16 + ?Sized,
+    Irq: 'a + Z80Irq + ?Sized,
+    I: 'a + Io16 + ?Sized,
+    Inb: 'a + Inbox<Memo = Z80Memo> + ?Sized,
+    D: Dispatch<Z80RunImpler<'a, Z, M, Irq, I, Inb>> + ?Sized,
+{
+    use self::InterruptStatus::*;
+    use self::Prefix::*;
+
+    let start_cycles = z.z80.cycles();
+
+    if z.z80.interrupt_status() == NoCheck {
+        z.z80.set_interrupt_status(Check);
     }
+
+    match (z.z80.prefix(), z.z80.interrupt_status()) {
+        (NoPrefix, Ei(ei_cycles)) if start_cycles <= ei_cycles => {}
+        (NoPrefix, _) | (Halt, _) => {
+            interrupt!{z; i; i.check_interrupts()};
+        }
+        _ => {}
+    }
+
+    loop {
+        execute(z, dispatch);
+        match z.z80.prefix() {
+            NoPrefix | Halt => break,
+            _ => {}
+        }
+    }
+
+    z.z80.cycles() - start_cycles
 }
 
 impl<'a, Z: 'a, M: 'a, Irq: 'a, I: 'a, Inb: 'a> Z80Run for Z80RunImpler<'a, Z, M, Irq, I, Inb>
@@ -178,6 +293,665 @@ where
 {
     #[inline]
     fn run(&mut self, target_cycles: u64) {
-        run(self, target_cycles)
+        run(self, &MatchDispatch, target_cycles)
+    }
+
+    #[inline]
+    fn step(&mut self) -> u64 {
+        step(self, &MatchDispatch)
+    }
+}
+
+/// Like `Z80RunImpler`, but executes instructions through a `DispatchTable`
+/// rather than `match`ing on opcodes.
+///
+/// The behavior is identical; only the performance may differ.
+pub struct Z80TableRunImpler<
+    'a,
+    Z: 'a + ?Sized,
+    M: 'a + ?Sized,
+    Irq: 'a + ?Sized,
+    I: 'a + ?Sized,
+    Inb: 'a + ?Sized,
+> {
+    pub run: Z80RunImpler<'a, Z, M, Irq, I, Inb>,
+    pub table: &'a DispatchTable<Z80RunImpler<'a, Z, M, Irq, I, Inb>>,
+}
+
+impl<'a, Z: 'a, M: 'a, Irq: 'a, I: 'a, Inb: 'a> Z80Run for Z80TableRunImpler<'a, Z, M, Irq, I, Inb>
+where
+    Z: Z80Internal + ?Sized,
+    M: Memory16 + ?Sized,
+    Irq: Z80Irq + ?Sized,
+    I: Io16 + ?Sized,
+    Inb: Inbox<Memo = Z80Memo> + ?Sized,
+{
+    #[inline]
+    fn run(&mut self, target_cycles: u64) {
+        run(&mut self.run, self.table, target_cycles)
+    }
+
+    #[inline]
+    fn step(&mut self) -> u64 {
+        step(&mut self.run, self.table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use memo::NothingInbox;
+    #[cfg(feature = "std")]
+    use save;
+    use utilities;
+
+    struct TestIo;
+
+    impl Io16 for TestIo {
+        fn input(&mut self, _address: u16) -> u8 {
+            0xFF
+        }
+
+        fn output(&mut self, _address: u16, _value: u8) {}
+    }
+
+    struct TestIrq;
+
+    impl Z80Irq for TestIrq {
+        fn requesting_mi(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn requesting_nmi(&mut self) -> bool {
+            false
+        }
+
+        fn take_nmi(&mut self) {}
+    }
+
+    /// Step through the instructions at the beginning of `program`, returning
+    /// the PC and the cycles taken after each step.
+    fn steps(program: &[u8], count: usize) -> Vec<(u16, u64)> {
+        let mut z80 = Z80State::default();
+        let mut memory = Box::new([0u8; 0x10000]);
+        memory[..program.len()].copy_from_slice(program);
+        let mut inbox = NothingInbox::default();
+        let mut z = Z80RunImpler {
+            z80: &mut z80,
+            memory: &mut *memory,
+            io: &mut TestIo,
+            irq: &mut TestIrq,
+            inbox: &mut inbox,
+        };
+        (0..count)
+            .map(|_| {
+                let cycles = z.step();
+                (z.z80.reg16(PC), cycles)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn step_noprefix() {
+        // nop; ld a, 0x12; halt
+        assert_eq!(
+            steps(&[0x00, 0x3E, 0x12, 0x76], 5),
+            vec![(1, 4), (3, 7), (4, 4), (4, 4), (4, 4)]
+        );
+    }
+
+    #[test]
+    fn step_prefixes() {
+        // ld ix, 0x1234, with redundant prefixes; ld a, (ix+1)
+        assert_eq!(
+            steps(&[0xDD, 0xDD, 0xDD, 0x21, 0x34, 0x12, 0xDD, 0x7E, 0x01], 2),
+            vec![(6, 22), (9, 19)]
+        );
+        // rlc b; neg
+        assert_eq!(
+            steps(&[0xCB, 0x00, 0xED, 0x44], 2),
+            vec![(2, 8), (4, 8)]
+        );
+        // set 0, (ix+2)
+        assert_eq!(steps(&[0xDD, 0xCB, 0x02, 0xC6], 1), vec![(4, 23)]);
+    }
+
+    /// A loop over 256 bytes using instructions from every prefix group.
+    ///
+    /// The last instruction is at `0x2A`; it's a `halt` if `forever` is false,
+    /// and otherwise a jump back to the start of the loop.
+    fn prefix_loop(forever: bool) -> Box<[u8; 0x10000]> {
+        let mut memory = Box::new([0u8; 0x10000]);
+        let program: &[u8] = &[
+            0x31, 0x00, 0xF0, // ld sp, 0xF000
+            0xDD, 0x21, 0x00, 0x80, // ld ix, 0x8000
+            0xFD, 0x21, 0x00, 0x90, // ld iy, 0x9000
+            0x21, 0x00, 0xA0, // ld hl, 0xA000
+            0x06, 0x00, // ld b, 0
+            0x78, // ld a, b
+            0x86, // add a, (hl)
+            0x27, // daa
+            0x77, // ld (hl), a
+            0xDD, 0x77, 0x01, // ld (ix+1), a
+            0xDD, 0xCB, 0x01, 0x06, // rlc (ix+1)
+            0xFD, 0xCB, 0x02, 0xC6, // set 0, (iy+2)
+            0xCB, 0x3F, // srl a
+            0xED, 0x44, // neg
+            0xC5, // push bc
+            0xC1, // pop bc
+            0x23, // inc hl
+            0xDD, 0x23, // inc ix
+            0x10, 0xE6, // djnz -26
+        ];
+        memory[..program.len()].copy_from_slice(program);
+        if forever {
+            memory[0x2A..0x2D].copy_from_slice(&[0xC3, 0x0E, 0x00]); // jp 0x000E
+        } else {
+            memory[0x2A] = 0x76; // halt
+        }
+        for i in 0..0x100 {
+            memory[0xA000 + i] = (i * 7) as u8;
+        }
+        memory
+    }
+
+    #[test]
+    fn table_matches() {
+        let mut z80_match = Z80State::default();
+        let mut memory_match = prefix_loop(false);
+        let mut z80_table = Z80State::default();
+        let mut memory_table = prefix_loop(false);
+        let mut inbox = NothingInbox::default();
+        Z80RunImpler {
+            z80: &mut z80_match,
+            memory: &mut *memory_match,
+            io: &mut TestIo,
+            irq: &mut TestIrq,
+            inbox: &mut inbox,
+        }.run(100_000);
+        let table = DispatchTable::new();
+        Z80TableRunImpler {
+            run: Z80RunImpler {
+                z80: &mut z80_table,
+                memory: &mut *memory_table,
+                io: &mut TestIo,
+                irq: &mut TestIrq,
+                inbox: &mut inbox,
+            },
+            table: &table,
+        }.run(100_000);
+        assert_eq!(z80_match.prefix(), Prefix::Halt);
+        assert_eq!(z80_match.reg16(HL), 0xA100);
+        assert_eq!(z80_match, z80_table);
+        assert!(memory_match[..] == memory_table[..]);
+    }
+
+    /// The tables in a `DispatchTable` are built in the order the `match`
+    /// arms are written, so check that each entry does what its opcode's arm
+    /// does, by running every opcode in every prefix group both ways.
+    #[test]
+    fn table_order() {
+        let prefixes: &[&[u8]] = &[
+            &[],
+            &[0xED],
+            &[0xCB],
+            &[0xDD],
+            &[0xFD],
+            &[0xDD, 0xCB, 0x05],
+            &[0xFD, 0xCB, 0x05],
+        ];
+        for prefix in prefixes.iter() {
+            for opcode in 0..0x100 {
+                // so that each instruction reads something different
+                let mut memory = Box::new([0u8; 0x10000]);
+                for (i, x) in memory.iter_mut().enumerate() {
+                    *x = (i * 13 + i / 0x100) as u8;
+                }
+                memory[0x100..0x100 + prefix.len()].copy_from_slice(prefix);
+                memory[0x100 + prefix.len()] = opcode as u8;
+                let mut z80 = Z80State::default();
+                z80.set_reg16(PC, 0x100);
+                z80.set_reg16(SP, 0x8000);
+                z80.set_reg16(AF, 0x5AC3);
+                z80.set_reg16(BC, 0x1234);
+                z80.set_reg16(DE, 0x5678);
+                z80.set_reg16(HL, 0x9ABC);
+                z80.set_reg16(IX, 0x3000);
+                z80.set_reg16(IY, 0x4000);
+
+                let mut inbox = NothingInbox::default();
+                let mut z80_match = z80;
+                let mut memory_match = memory.clone();
+                Z80RunImpler {
+                    z80: &mut z80_match,
+                    memory: &mut *memory_match,
+                    io: &mut TestIo,
+                    irq: &mut TestIrq,
+                    inbox: &mut inbox,
+                }.step();
+
+                let mut z80_table = z80;
+                let mut memory_table = memory.clone();
+                let table = DispatchTable::new();
+                Z80TableRunImpler {
+                    run: Z80RunImpler {
+                        z80: &mut z80_table,
+                        memory: &mut *memory_table,
+                        io: &mut TestIo,
+                        irq: &mut TestIrq,
+                        inbox: &mut inbox,
+                    },
+                    table: &table,
+                }.step();
+
+                assert_eq!(z80_match, z80_table, "{:02X?} {:02X}", prefix, opcode);
+                assert!(
+                    memory_match[..] == memory_table[..],
+                    "{:02X?} {:02X}",
+                    prefix,
+                    opcode
+                );
+            }
+        }
+    }
+
+    /// Compare instructions per second of `Z80RunImpler` and
+    /// `Z80TableRunImpler`. See `utilities::print_throughput`.
+    #[test]
+    #[ignore]
+    fn dispatch_throughput() {
+        const STEPS: usize = 50_000_000;
+
+        fn time<R: Z80Run>(name: &str, z: &mut R) {
+            utilities::print_throughput(name, "instructions", STEPS as u64, || {
+                let mut cycles = 0;
+                for _ in 0..STEPS {
+                    cycles += z.step();
+                }
+                cycles
+            });
+        }
+
+        let mut z80 = Z80State::default();
+        let mut memory = prefix_loop(true);
+        let mut inbox = NothingInbox::default();
+        time(
+            "Z80RunImpler",
+            &mut Z80RunImpler {
+                z80: &mut z80,
+                memory: &mut *memory,
+                io: &mut TestIo,
+                irq: &mut TestIrq,
+                inbox: &mut inbox,
+            },
+        );
+
+        let mut z80 = Z80State::default();
+        let mut memory = prefix_loop(true);
+        let table = DispatchTable::new();
+        time(
+            "Z80TableRunImpler",
+            &mut Z80TableRunImpler {
+                run: Z80RunImpler {
+                    z80: &mut z80,
+                    memory: &mut *memory,
+                    io: &mut TestIo,
+                    irq: &mut TestIrq,
+                    inbox: &mut inbox,
+                },
+                table: &table,
+            },
+        );
+    }
+
+    /// Step `count` instructions starting from the given state.
+    fn step_state(z80: &mut Z80State, memory: &mut [u8; 0x10000], count: usize) {
+        let mut inbox = NothingInbox::default();
+        let mut z = Z80RunImpler {
+            z80,
+            memory,
+            io: &mut TestIo,
+            irq: &mut TestIrq,
+            inbox: &mut inbox,
+        };
+        for _ in 0..count {
+            z.step();
+        }
+    }
+
+    /// Execute `ld ix, 0x8000; rlc (ix+5), b` or one of its relatives, with
+    /// `0x8005` initially holding `value` and F holding `f`.
+    ///
+    /// `prefix` is `0xDD` or `0xFD` and `opcode` is the last byte of the
+    /// instruction. Returns the final value at `0x8005` and the Z80 state.
+    fn index_cb(prefix: u8, opcode: u8, value: u8, f: u8) -> (u8, Z80State) {
+        let mut z80 = Z80State::default();
+        z80.set_reg8(Reg8::F, f);
+        let mut memory = Box::new([0u8; 0x10000]);
+        memory[..8].copy_from_slice(&[prefix, 0x21, 0x00, 0x80, prefix, 0xCB, 0x05, opcode]);
+        memory[0x8005] = value;
+        step_state(&mut z80, &mut memory, 2);
+        assert_eq!(z80.reg16(PC), 8);
+        (memory[0x8005], z80)
+    }
+
+    const INDEX_CB_REGISTERS: [Option<Reg8>; 8] = [
+        Some(Reg8::B),
+        Some(Reg8::C),
+        Some(Reg8::D),
+        Some(Reg8::E),
+        Some(Reg8::H),
+        Some(Reg8::L),
+        None,
+        Some(Reg8::A),
+    ];
+
+    #[test]
+    fn index_cb_rotate_store() {
+        fn expected(operation: u8, value: u8, carry: bool) -> (u8, u8) {
+            let c = carry as u8;
+            let (result, carry) = match operation {
+                0 => (value.rotate_left(1), value >> 7),
+                1 => (value.rotate_right(1), value & 1),
+                2 => (value << 1 | c, value >> 7),
+                3 => (value >> 1 | c << 7, value & 1),
+                4 => (value << 1, value >> 7),
+                5 => (value >> 1 | value & 0x80, value & 1),
+                6 => (value << 1 | 1, value >> 7),
+                _ => (value >> 1, value & 1),
+            };
+            let mut f = result & SF | carry;
+            if result == 0 {
+                f |= ZF;
+            }
+            if result.count_ones() % 2 == 0 {
+                f |= PF;
+            }
+            (result, f)
+        }
+
+        for &prefix in [0xDD, 0xFD].iter() {
+            for opcode in 0..0x40u8 {
+                for &value in [0x00, 0x01, 0x80, 0xA5].iter() {
+                    for &carry in [false, true].iter() {
+                        let (result, f) = expected(opcode >> 3, value, carry);
+                        let (memory, z80) = index_cb(prefix, opcode, value, carry as u8);
+                        let context = (prefix, opcode, value, carry);
+                        assert_eq!(memory, result, "{:X?}", context);
+                        let mask = SF | ZF | HF | PF | NF | CF;
+                        assert_eq!(z80.reg8(Reg8::F) & mask, f, "{:X?}", context);
+                        if let Some(reg) = INDEX_CB_REGISTERS[opcode as usize & 7] {
+                            assert_eq!(z80.reg8(reg), result, "{:X?}", context);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn index_cb_res_set_store() {
+        for &prefix in [0xDD, 0xFD].iter() {
+            for opcode in 0x80..=0xFFu8 {
+                let bit = 1 << (opcode >> 3 & 7);
+                for &value in [0x00, 0xFF, 0x5A].iter() {
+                    let result = if opcode < 0xC0 {
+                        value & !bit
+                    } else {
+                        value | bit
+                    };
+                    let (memory, z80) = index_cb(prefix, opcode, value, 0xD7);
+                    let context = (prefix, opcode, value);
+                    assert_eq!(memory, result, "{:X?}", context);
+                    assert_eq!(z80.reg8(Reg8::F), 0xD7, "{:X?}", context);
+                    if let Some(reg) = INDEX_CB_REGISTERS[opcode as usize & 7] {
+                        assert_eq!(z80.reg8(reg), result, "{:X?}", context);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_store() {
+        // set 3, (ix+5), b
+        let (memory, z80) = index_cb(0xDD, 0xD8, 0x41, 0);
+        assert_eq!(memory, 0x49);
+        assert_eq!(z80.reg8(Reg8::B), 0x49);
+
+        // res 6, (iy+5), a
+        let (memory, z80) = index_cb(0xFD, 0xB7, 0x41, 0);
+        assert_eq!(memory, 0x01);
+        assert_eq!(z80.reg8(Reg8::A), 0x01);
+    }
+
+    #[test]
+    fn daa() {
+        // A reference implementation, following the description of the
+        // correction in terms of nibbles rather than the table in Young.
+        fn expected(a: u8, f: u8) -> (u8, u8) {
+            let low = a & 0xF;
+            let mut correction = 0;
+            let mut carry = f & CF != 0;
+            if f & HF != 0 || low > 9 {
+                correction |= 0x06;
+            }
+            if carry || a > 0x99 {
+                correction |= 0x60;
+                carry = true;
+            }
+            let (result, half) = if f & NF != 0 {
+                (a.wrapping_sub(correction), f & HF != 0 && low < 6)
+            } else {
+                (a.wrapping_add(correction), low > 9)
+            };
+            let mut new_f = result & SF | f & NF;
+            if result == 0 {
+                new_f |= ZF;
+            }
+            if result.count_ones() % 2 == 0 {
+                new_f |= PF;
+            }
+            if half {
+                new_f |= HF;
+            }
+            if carry {
+                new_f |= CF;
+            }
+            (result, new_f)
+        }
+
+        let mut memory = Box::new([0u8; 0x10000]);
+        memory[0] = 0x27; // daa
+        for a in 0..=0xFFu8 {
+            // every combination of C, N, and H
+            for f in (0..0x20u8).filter(|f| f & !(CF | NF | HF) == 0) {
+                let mut z80 = Z80State::default();
+                z80.set_reg8(Reg8::A, a);
+                z80.set_reg8(Reg8::F, f);
+                step_state(&mut z80, &mut memory, 1);
+                let mask = SF | ZF | HF | PF | NF | CF;
+                let result = (z80.reg8(Reg8::A), z80.reg8(Reg8::F) & mask);
+                assert_eq!(result, expected(a, f), "{:0>2X} {:0>2X}", a, f);
+            }
+        }
+    }
+
+    #[test]
+    fn branch_cycles() {
+        // jr nz, 0; xor a; jr nz, 0
+        let jr = steps(&[0x20, 0x00, 0xAF, 0x20, 0x00], 3);
+        assert_eq!(jr, vec![(2, 12), (3, 4), (5, 7)]);
+        assert_eq!(jr[0].1 - jr[2].1, JRCC_CYCLES.taken - JRCC_CYCLES.not_taken);
+
+        // ld b, 2; djnz -2; nop
+        assert_eq!(
+            steps(&[0x06, 0x02, 0x10, 0xFE, 0x00], 4),
+            vec![(2, 7), (2, 13), (4, 8), (5, 4)]
+        );
+
+        // xor a; jp nz, 0x0000; call nz, 0x0000; ret nz; ret nz with a
+        // redundant prefix
+        assert_eq!(
+            steps(
+                &[0xAF, 0xC2, 0x00, 0x00, 0xC4, 0x00, 0x00, 0xC0, 0xDD, 0xC0],
+                5
+            ),
+            vec![(1, 4), (4, 10), (7, 10), (8, 5), (10, 9)]
+        );
+    }
+
+    #[test]
+    fn last_instruction_cycles() {
+        let mut z80 = Z80State::default();
+        let mut memory = Box::new([0u8; 0x10000]);
+        let program: &[u8] = &[
+            0x00, // nop
+            0x01, 0x34, 0x12, // ld bc, 0x1234
+            0xDD, 0xDD, 0x21, 0x00, 0x80, // ld ix, 0x8000, with a redundant prefix
+            0x18, 0x00, // jr 0
+            0xCB, 0x00, // rlc b
+        ];
+        memory[..program.len()].copy_from_slice(program);
+        for &cycles in &[4, 10, 18, 12, 8] {
+            step_state(&mut z80, &mut memory, 1);
+            assert_eq!(z80.last_instruction_cycles(), cycles);
+        }
+
+        // it isn't part of a saved state
+        #[cfg(feature = "std")]
+        {
+            let saved: Z80State = save::deserialize(&save::serialize(&z80).unwrap()).unwrap();
+            assert_eq!(saved.last_instruction_cycles(), 0);
+            assert_eq!(saved, z80);
+        }
+    }
+
+    #[test]
+    fn refresh_register() {
+        let mut memory = Box::new([0u8; 0x10000]);
+        let program: &[u8] = &[
+            0x3E, 0xFD, // ld a, 0xFD
+            0xED, 0x4F, // ld r, a
+            0x00, // nop
+            0x41, // ld b, c
+            0xCB, 0x00, // rlc b
+            0xED, 0x44, // neg
+            0xDD, 0x21, 0x00, 0x80, // ld ix, 0x8000
+            0xDD, 0xCB, 0x00, 0x06, // rlc (ix+0)
+            0xDD, 0xDD, 0x23, // inc ix, with a redundant prefix
+            0xED, 0x5F, // ld a, r
+            0x76, // halt
+        ];
+        memory[..program.len()].copy_from_slice(program);
+        let mut z80 = Z80State::default();
+        step_state(&mut z80, &mut memory, 10);
+        // 1 for each unprefixed instruction and prefix, and bit 7 stays put
+        // as the low 7 bits wrap
+        assert_eq!(z80.reg8(Reg8::A), 0x8C);
+        assert_eq!(z80.reg8(Reg8::R), 0x8C);
+
+        // and the halted Z80 keeps refreshing
+        step_state(&mut z80, &mut memory, 3);
+        assert_eq!(z80.reg8(Reg8::R), 0x8F);
+        let mut inbox = NothingInbox::default();
+        let cycles = z80.cycles();
+        Z80RunImpler {
+            z80: &mut z80,
+            memory: &mut *memory,
+            io: &mut TestIo,
+            irq: &mut TestIrq,
+            inbox: &mut inbox,
+        }.run(cycles + 40);
+        assert_eq!(z80.reg8(Reg8::R), 0x99);
+    }
+
+    /// Always requesting a maskable interrupt.
+    struct PendingIrq;
+
+    impl Z80Irq for PendingIrq {
+        fn requesting_mi(&mut self) -> Option<u8> {
+            Some(0xFF)
+        }
+
+        fn requesting_nmi(&mut self) -> bool {
+            false
+        }
+
+        fn take_nmi(&mut self) {}
+    }
+
+    /// Run `program` for 200 cycles in interrupt mode 1 with an interrupt
+    /// always pending, and with a handler at `0x38` doing `ld b, a; halt`.
+    fn run_interrupted(program: &[u8]) -> (Z80State, Box<[u8; 0x10000]>) {
+        let mut memory = Box::new([0u8; 0x10000]);
+        memory[..program.len()].copy_from_slice(program);
+        memory[0x38..0x3A].copy_from_slice(&[0x47, 0x76]);
+        let mut z80 = Z80State::default();
+        let mut inbox = NothingInbox::default();
+        Z80RunImpler {
+            z80: &mut z80,
+            memory: &mut *memory,
+            io: &mut TestIo,
+            irq: &mut PendingIrq,
+            inbox: &mut inbox,
+        }.run(200);
+        (z80, memory)
+    }
+
+    #[test]
+    fn ei_delay() {
+        // ld sp, 0xF000; ei; ld a, 0x42; halt
+        let (z80, memory) = run_interrupted(&[0x31, 0x00, 0xF0, 0xFB, 0x3E, 0x42, 0x76]);
+        // the interrupt came after `ld a, 0x42`, but before `halt`
+        assert_eq!(z80.reg8(Reg8::B), 0x42);
+        assert_eq!(z80.reg16(SP), 0xEFFE);
+        assert_eq!(&memory[0xEFFE..0xF000], &[0x06, 0x00]);
+
+        // ld sp, 0xF000; ei; di; halt
+        let (z80, _) = run_interrupted(&[0x31, 0x00, 0xF0, 0xFB, 0xF3, 0x76]);
+        // `di` took effect before the interrupt could be taken
+        assert_eq!(z80.reg16(SP), 0xF000);
+        assert_eq!(z80.reg16(PC), 0x0006);
+        assert_eq!(z80.prefix(), Prefix::Halt);
+    }
+
+    #[test]
+    fn halt_wakes_on_interrupt() {
+        let mut memory = Box::new([0u8; 0x10000]);
+        // ld sp, 0xF000; ei; halt
+        memory[..5].copy_from_slice(&[0x31, 0x00, 0xF0, 0xFB, 0x76]);
+        let mut z80 = Z80State::default();
+        let mut inbox = NothingInbox::default();
+
+        // with no interrupt, the halted Z80 just burns cycles
+        Z80RunImpler {
+            z80: &mut z80,
+            memory: &mut *memory,
+            io: &mut TestIo,
+            irq: &mut TestIrq,
+            inbox: &mut inbox,
+        }.run(100);
+        assert!(z80.halted());
+        assert_eq!(z80.prefix(), Prefix::Halt);
+        assert_eq!(z80.reg16(PC), 5);
+        assert_eq!(z80.cycles(), 100);
+
+        // taking the interrupt (13 cycles) wakes it, returning to after the
+        // `halt`, and then the `nop` at 0x38 executes (4 cycles)
+        let cycles = Z80RunImpler {
+            z80: &mut z80,
+            memory: &mut *memory,
+            io: &mut TestIo,
+            irq: &mut PendingIrq,
+            inbox: &mut inbox,
+        }.step();
+        assert_eq!(cycles, 17);
+        assert!(!z80.halted());
+        assert_eq!(z80.prefix(), Prefix::NoPrefix);
+        assert_eq!(z80.reg16(PC), 0x39);
+        assert_eq!(z80.reg16(SP), 0xEFFE);
+        assert_eq!(&memory[0xEFFE..0xF000], &[0x05, 0x00]);
     }
 }
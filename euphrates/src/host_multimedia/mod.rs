@@ -0,0 +1,415 @@
+use std;
+
+use failure::Error;
+
+mod png;
+mod resample;
+mod ring_buffer;
+
+pub use self::png::*;
+pub use self::resample::*;
+pub use self::ring_buffer::*;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SimpleColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+pub trait SimpleGraphics {
+    fn set_resolution(&mut self, width: u32, height: u32) -> Result<()>;
+
+    fn resolution(&self) -> (u32, u32);
+
+    /// Will panic if x and y are outside the bounds determined by the
+    /// resolution, but is memory safe. Any errors in the implementation that occur
+    /// will be returned in the next call to `render`.
+    fn paint(&mut self, x: u32, y: u32, color: SimpleColor);
+
+    /// Will panic if x and y are outside the bounds determined by the
+    /// resolution, but is memory safe. Any errors in the implementation that occur
+    /// will be returned in the next call to `render`.
+    fn get(&self, x: u32, y: u32) -> SimpleColor;
+
+    /// Display the pixels that have been `paint`ed. Any pixel position that has
+    /// not been `paint`ed since the last call to `render` may show arbitrary
+    /// results.
+    fn render(&mut self) -> Result<()>;
+
+    /// The whole frame as red, green, and blue bytes for each pixel, row by
+    /// row.
+    ///
+    /// The default implementation calls `get` for every pixel; implementations
+    /// that keep their pixels in memory may do better.
+    fn read_rgb(&self) -> Vec<u8> {
+        let (width, height) = self.resolution();
+        let mut rgb = Vec::with_capacity(3 * width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let color = self.get(x, y);
+                rgb.extend_from_slice(&[color.red, color.green, color.blue]);
+            }
+        }
+        rgb
+    }
+}
+
+/// A fingerprint of the pixels in `graphics`, for comparing frames.
+///
+/// This is a 64 bit FNV-1a hash of the resolution and the colors of every
+/// pixel, row by row. Unlike `std::hash::Hash` with `DefaultHasher`, its
+/// values are guaranteed not to change, so they can be saved in tests.
+pub fn frame_hash<G>(graphics: &G) -> u64
+where
+    G: SimpleGraphics + ?Sized,
+{
+    const OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+    const PRIME: u64 = 0x100000001B3;
+
+    fn add(hash: &mut u64, bytes: &[u8]) {
+        for &byte in bytes {
+            *hash ^= byte as u64;
+            *hash = hash.wrapping_mul(PRIME);
+        }
+    }
+
+    let mut hash = OFFSET_BASIS;
+    let (width, height) = graphics.resolution();
+    for &x in [width, height].iter() {
+        add(
+            &mut hash,
+            &[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8],
+        );
+    }
+    add(&mut hash, &graphics.read_rgb());
+    hash
+}
+
+/// The alpha of black to blend over a color to darken it by `intensity`, from
+/// 0 (unchanged) to 1 (black).
+pub fn scanline_alpha(intensity: f32) -> u8 {
+    (intensity.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// The rows darkened for scanlines in an image `height` rows tall: every other
+/// one, starting with the second.
+pub fn scanline_rows(height: u32) -> impl Iterator<Item = u32> {
+    (1..height).step_by(2)
+}
+
+/// `color` darkened by `intensity`, from 0 (unchanged) to 1 (black).
+///
+/// This is alpha blending black over `color` with an alpha of
+/// `scanline_alpha(intensity)`.
+pub fn darken(color: SimpleColor, intensity: f32) -> SimpleColor {
+    let keep = 255 - scanline_alpha(intensity) as u32;
+    let f = |x: u8| ((x as u32 * keep + 127) / 255) as u8;
+    SimpleColor {
+        red: f(color.red),
+        green: f(color.green),
+        blue: f(color.blue),
+    }
+}
+
+/// Simulate a CRT's scanlines by darkening the `scanline_rows` of `pixels`,
+/// which has rows `width` pixels long.
+///
+/// For the scanlines to look right, `pixels` should already be scaled up to
+/// the output resolution.
+pub fn apply_scanlines(pixels: &mut [SimpleColor], width: usize, intensity: f32) {
+    let height = (pixels.len() / width) as u32;
+    for y in scanline_rows(height) {
+        let start = y as usize * width;
+        for pixel in pixels[start..start + width].iter_mut() {
+            *pixel = darken(*pixel, intensity);
+        }
+    }
+}
+
+pub struct SimpleGraphicsImpl;
+
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FakeGraphics(u32, u32);
+
+impl SimpleGraphics for FakeGraphics {
+    #[inline]
+    fn set_resolution(&mut self, width: u32, height: u32) -> Result<()> {
+        self.0 = width;
+        self.1 = height;
+        Ok(())
+    }
+
+    #[inline]
+    fn resolution(&self) -> (u32, u32) {
+        (self.0, self.1)
+    }
+
+    #[inline]
+    fn paint(&mut self, _x: u32, _y: u32, _color: SimpleColor) {}
+
+    #[inline]
+    fn get(&self, _x: u32, _y: u32) -> SimpleColor {
+        SimpleColor {
+            red: 0,
+            green: 0,
+            blue: 0,
+        }
+    }
+
+    #[inline]
+    fn render(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Graphics that just keep their pixels in memory, for running without a
+/// window.
+///
+/// Setting the resolution clears the buffer to black.
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct BufferGraphics {
+    width: u32,
+    height: u32,
+    pixels: Vec<SimpleColor>,
+}
+
+impl BufferGraphics {
+    pub fn new(width: u32, height: u32) -> Self {
+        BufferGraphics {
+            width,
+            height,
+            pixels: vec![Default::default(); (width * height) as usize],
+        }
+    }
+
+    /// The pixels, row by row.
+    #[inline]
+    pub fn pixels(&self) -> &[SimpleColor] {
+        &self.pixels
+    }
+}
+
+impl SimpleGraphics for BufferGraphics {
+    #[inline]
+    fn set_resolution(&mut self, width: u32, height: u32) -> Result<()> {
+        *self = BufferGraphics::new(width, height);
+        Ok(())
+    }
+
+    #[inline]
+    fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    #[inline]
+    fn paint(&mut self, x: u32, y: u32, color: SimpleColor) {
+        assert!(x < self.width && y < self.height);
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    #[inline]
+    fn get(&self, x: u32, y: u32) -> SimpleColor {
+        assert!(x < self.width && y < self.height);
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    #[inline]
+    fn render(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(3 * self.pixels.len());
+        for color in self.pixels.iter() {
+            rgb.extend_from_slice(&[color.red, color.green, color.blue]);
+        }
+        rgb
+    }
+}
+
+pub trait SimpleAudio {
+    fn configure(&mut self, frequency: u32, buffer_size: u16) -> Result<()>;
+
+    fn play(&mut self) -> Result<()>;
+
+    fn pause(&mut self) -> Result<()>;
+
+    /// How many samples fit in the buffer?
+    fn buffer_len(&self) -> usize;
+
+    /// Set the `i`th sample in the buffer, for the left and right speakers.
+    ///
+    /// Mono implementations can just use one of `left` and `right`; unless a
+    /// Game Gear program has set its stereo control register, they'll be the
+    /// same.
+    fn buffer_set(&mut self, i: usize, left: i16, right: i16);
+
+    fn queue_buffer(&mut self) -> Result<()>;
+
+    fn clear(&mut self) -> Result<()>;
+}
+
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FakeAudio;
+
+impl SimpleAudio for FakeAudio {
+    #[inline]
+    fn configure(&mut self, _frequency: u32, _buffer_size: u16) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn play(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn pause(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn buffer_set(&mut self, _i: usize, _left: i16, _right: i16) {}
+
+    #[inline]
+    fn buffer_len(&self) -> usize {
+        1024 // lie
+    }
+
+    #[inline]
+    fn queue_buffer(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn clear(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_graphics() {
+        let red = SimpleColor {
+            red: 0xFF,
+            green: 0,
+            blue: 0,
+        };
+        let blue = SimpleColor {
+            red: 0,
+            green: 0,
+            blue: 0xFF,
+        };
+        let black = SimpleColor::default();
+
+        let mut graphics = BufferGraphics::default();
+        assert_eq!(graphics.resolution(), (0, 0));
+        assert!(graphics.pixels().is_empty());
+
+        graphics.set_resolution(256, 192).unwrap();
+        assert_eq!(graphics.pixels().len(), 256 * 192);
+        graphics.paint(0, 0, red);
+        graphics.paint(255, 191, blue);
+        graphics.paint(10, 1, blue);
+        graphics.render().unwrap();
+        assert_eq!(graphics.get(0, 0), red);
+        assert_eq!(graphics.get(255, 191), blue);
+        assert_eq!(graphics.get(10, 1), blue);
+        assert_eq!(graphics.pixels()[256 + 10], blue);
+        assert_eq!(graphics.get(1, 0), black);
+
+        // a new resolution resizes the buffer and clears it
+        graphics.set_resolution(160, 144).unwrap();
+        assert_eq!(graphics.resolution(), (160, 144));
+        assert_eq!(graphics.pixels().len(), 160 * 144);
+        assert!(graphics.pixels().iter().all(|&c| c == black));
+        graphics.paint(159, 143, red);
+        assert_eq!(graphics.get(159, 143), red);
+    }
+
+    #[test]
+    fn read_rgb() {
+        /// Like `BufferGraphics`, but using the default `read_rgb`.
+        struct DefaultRgb(BufferGraphics);
+
+        impl SimpleGraphics for DefaultRgb {
+            fn set_resolution(&mut self, width: u32, height: u32) -> Result<()> {
+                self.0.set_resolution(width, height)
+            }
+
+            fn resolution(&self) -> (u32, u32) {
+                self.0.resolution()
+            }
+
+            fn paint(&mut self, x: u32, y: u32, color: SimpleColor) {
+                self.0.paint(x, y, color)
+            }
+
+            fn get(&self, x: u32, y: u32) -> SimpleColor {
+                self.0.get(x, y)
+            }
+
+            fn render(&mut self) -> Result<()> {
+                self.0.render()
+            }
+        }
+
+        // red increases to the right and green downwards
+        let mut graphics = DefaultRgb(BufferGraphics::new(64, 32));
+        for y in 0..32 {
+            for x in 0..64 {
+                let color = SimpleColor {
+                    red: 4 * x as u8,
+                    green: 8 * y as u8,
+                    blue: 0x55,
+                };
+                graphics.paint(x, y, color);
+            }
+        }
+
+        let rgb = graphics.read_rgb();
+        assert_eq!(rgb.len(), 3 * 64 * 32);
+        assert_eq!(rgb[..6], [0, 0, 0x55, 4, 0, 0x55]);
+        let i = 3 * (64 * 10 + 20);
+        assert_eq!(rgb[i..i + 3], [80, 80, 0x55]);
+        assert_eq!(rgb[rgb.len() - 3..], [252, 248, 0x55]);
+        assert_eq!(graphics.0.read_rgb(), rgb);
+    }
+
+    #[test]
+    fn scanlines() {
+        let color = SimpleColor {
+            red: 200,
+            green: 100,
+            blue: 1,
+        };
+        let mut pixels = vec![color; 4 * 3];
+        apply_scanlines(&mut pixels, 4, 0.25);
+        let dark = SimpleColor {
+            red: 150,
+            green: 75,
+            blue: 1,
+        };
+        assert_eq!(pixels[..4], [color; 4]);
+        assert_eq!(pixels[4..8], [dark; 4]);
+        assert_eq!(pixels[8..], [color; 4]);
+
+        assert_eq!(darken(color, 0.0), color);
+        assert_eq!(darken(color, 1.0), SimpleColor::default());
+        assert_eq!(darken(color, 2.0), SimpleColor::default());
+
+        assert_eq!(scanline_rows(5).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(scanline_rows(6).collect::<Vec<_>>(), vec![1, 3, 5]);
+        assert_eq!(scanline_rows(1).count(), 0);
+
+        assert_eq!(scanline_alpha(0.25), 64);
+        assert_eq!(scanline_alpha(-1.0), 0);
+        assert_eq!(scanline_alpha(2.0), 255);
+    }
+}
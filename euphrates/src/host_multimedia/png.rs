@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+use super::*;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn write_chunk<W>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<()>
+where
+    W: Write,
+{
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc.sum().to_be_bytes())?;
+    Ok(())
+}
+
+/// Write the pixels in `graphics` to `writer` as a PNG image.
+///
+/// The image has the resolution of `graphics`, and is 8 bit RGB with no
+/// alpha channel.
+pub fn write_png<G, W>(graphics: &G, mut writer: W) -> Result<()>
+where
+    G: SimpleGraphics + ?Sized,
+    W: Write,
+{
+    let (width, height) = graphics.resolution();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[
+        8, // bit depth
+        2, // color type: RGB
+        0, // compression method: deflate
+        0, // filter method: adaptive
+        0, // interlace method: none
+    ]);
+
+    // Each row is a filter type byte (0, none) followed by its pixels.
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let rgb = graphics.read_rgb();
+    if width > 0 {
+        for row in rgb.chunks(3 * width as usize) {
+            encoder.write_all(&[0])?;
+            encoder.write_all(row)?;
+        }
+    }
+    let data = encoder.finish()?;
+
+    writer.write_all(&SIGNATURE)?;
+    write_chunk(&mut writer, b"IHDR", &header)?;
+    write_chunk(&mut writer, b"IDAT", &data)?;
+    write_chunk(&mut writer, b"IEND", &[])?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Save the pixels in `graphics` as a PNG image at `path`.
+///
+/// See `write_png`.
+pub fn save_png<G, P>(graphics: &G, path: P) -> Result<()>
+where
+    G: SimpleGraphics + ?Sized,
+    P: AsRef<Path>,
+{
+    let file = File::create(path)?;
+    write_png(graphics, BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    /// Graphics that are entirely one color.
+    struct SolidGraphics(u32, u32, SimpleColor);
+
+    impl SimpleGraphics for SolidGraphics {
+        fn set_resolution(&mut self, width: u32, height: u32) -> Result<()> {
+            self.0 = width;
+            self.1 = height;
+            Ok(())
+        }
+
+        fn resolution(&self) -> (u32, u32) {
+            (self.0, self.1)
+        }
+
+        fn paint(&mut self, _x: u32, _y: u32, color: SimpleColor) {
+            self.2 = color;
+        }
+
+        fn get(&self, _x: u32, _y: u32) -> SimpleColor {
+            self.2
+        }
+
+        fn render(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn be_u32(bytes: &[u8]) -> u32 {
+        (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+    }
+
+    /// Decode a PNG written by `write_png`, returning its width, height, and
+    /// pixels.
+    fn decode(png: &[u8]) -> (u32, u32, Vec<SimpleColor>) {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut rest = &png[8..];
+        let mut header = Vec::new();
+        let mut data = Vec::new();
+        while !rest.is_empty() {
+            let len = be_u32(rest) as usize;
+            let kind = &rest[4..8];
+            let chunk = &rest[8..8 + len];
+            let mut crc = Crc::new();
+            crc.update(kind);
+            crc.update(chunk);
+            assert_eq!(be_u32(&rest[8 + len..]), crc.sum());
+            match kind {
+                b"IHDR" => header.extend_from_slice(chunk),
+                b"IDAT" => data.extend_from_slice(chunk),
+                b"IEND" => assert_eq!(len, 0),
+                _ => panic!("unexpected chunk"),
+            }
+            rest = &rest[12 + len..];
+        }
+
+        let width = be_u32(&header);
+        let height = be_u32(&header[4..]);
+        assert_eq!(header[8..], [8, 2, 0, 0, 0]);
+
+        let mut raw = Vec::new();
+        ZlibDecoder::new(&data[..]).read_to_end(&mut raw).unwrap();
+        let row_len = 1 + 3 * width as usize;
+        assert_eq!(raw.len(), row_len * height as usize);
+        let mut pixels = Vec::new();
+        for row in raw.chunks(row_len) {
+            assert_eq!(row[0], 0);
+            for rgb in row[1..].chunks(3) {
+                pixels.push(SimpleColor {
+                    red: rgb[0],
+                    green: rgb[1],
+                    blue: rgb[2],
+                });
+            }
+        }
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn png() {
+        let color = SimpleColor {
+            red: 0x55,
+            green: 0xAA,
+            blue: 0xFF,
+        };
+        // the Master System's and Game Gear's resolutions
+        for &(width, height) in &[(256, 192), (160, 144)] {
+            let graphics = SolidGraphics(width, height, color);
+            let mut png = Vec::new();
+            write_png(&graphics, &mut png).unwrap();
+            let (w, h, pixels) = decode(&png);
+            assert_eq!((w, h), (width, height));
+            assert_eq!(pixels.len(), (width * height) as usize);
+            assert!(pixels.iter().all(|&c| c == color));
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use super::{Result, SimpleAudio};
+
+/// How many zero crossings of the sinc function are used on each side of a
+/// sample by `Interpolation::Sinc`.
+const SINC_ZERO_CROSSINGS: f64 = 8.0;
+
+/// How a `Resampler` computes samples that fall between its input samples.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// Draw a straight line between the two nearest input samples. Cheap, but
+    /// lets high frequencies alias.
+    Linear,
+
+    /// A Blackman-windowed sinc filter, low passed below the lower of the two
+    /// Nyquist frequencies.
+    Sinc,
+}
+
+impl Default for Interpolation {
+    #[inline]
+    fn default() -> Self {
+        Interpolation::Linear
+    }
+}
+
+/// Converts a stream of stereo samples from one sample rate to another.
+///
+/// The position of the next output sample is tracked exactly, as a fraction
+/// with denominator `output_rate`, so there is no drift however long it runs.
+#[derive(Clone, Debug)]
+pub struct Resampler {
+    interpolation: Interpolation,
+    input_rate: u32,
+    output_rate: u32,
+    cutoff: f64,
+
+    /// How far past `history[history.len() / 2 - 1]` the next output sample
+    /// is, in units of `1 / output_rate` input samples.
+    phase: u64,
+    history: VecDeque<(f64, f64)>,
+    output: VecDeque<(i16, i16)>,
+}
+
+impl Resampler {
+    /// A resampler producing samples at `output_rate`. Its input rate is
+    /// initially the same; set it with `set_input_rate`.
+    pub fn new(output_rate: u32, interpolation: Interpolation) -> Self {
+        assert!(output_rate > 0);
+        let mut resampler = Resampler {
+            interpolation,
+            input_rate: output_rate,
+            output_rate,
+            cutoff: 1.0,
+            phase: 0,
+            history: VecDeque::new(),
+            output: VecDeque::new(),
+        };
+        resampler.reset();
+        resampler
+    }
+
+    #[inline]
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    #[inline]
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    #[inline]
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /// Set the rate of incoming samples. If it's changed, samples already
+    /// given but not yet resampled are discarded.
+    pub fn set_input_rate(&mut self, input_rate: u32) {
+        assert!(input_rate > 0);
+        if input_rate != self.input_rate {
+            self.input_rate = input_rate;
+            self.reset();
+        }
+    }
+
+    /// How many resampled samples are waiting to be `pop`ped?
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.output.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.output.is_empty()
+    }
+
+    /// Take the oldest resampled sample.
+    #[inline]
+    pub fn pop(&mut self) -> Option<(i16, i16)> {
+        self.output.pop_front()
+    }
+
+    /// Discard all samples, both those given and those resampled.
+    pub fn reset(&mut self) {
+        let taps = match self.interpolation {
+            Interpolation::Linear => 2,
+            Interpolation::Sinc => {
+                self.cutoff = (self.output_rate as f64 / self.input_rate as f64).min(1.0);
+                2 * (SINC_ZERO_CROSSINGS / self.cutoff).ceil() as usize
+            }
+        };
+        self.phase = 0;
+        self.history.clear();
+        self.history.resize(taps, (0.0, 0.0));
+        self.output.clear();
+    }
+
+    /// Give the next input sample.
+    pub fn push(&mut self, left: i16, right: i16) {
+        self.history.pop_front();
+        self.history.push_back((left as f64, right as f64));
+
+        let output_rate = self.output_rate as u64;
+        while self.phase < output_rate {
+            let fraction = self.phase as f64 / output_rate as f64;
+            let (left, right) = match self.interpolation {
+                Interpolation::Linear => self.linear(fraction),
+                Interpolation::Sinc => self.sinc(fraction),
+            };
+            self.output.push_back((to_i16(left), to_i16(right)));
+            self.phase += self.input_rate as u64;
+        }
+        self.phase -= output_rate;
+    }
+
+    fn linear(&self, fraction: f64) -> (f64, f64) {
+        let (l0, r0) = self.history[0];
+        let (l1, r1) = self.history[1];
+        (l0 + (l1 - l0) * fraction, r0 + (r1 - r0) * fraction)
+    }
+
+    fn sinc(&self, fraction: f64) -> (f64, f64) {
+        let half = (self.history.len() / 2) as f64;
+        let mut left = 0.0;
+        let mut right = 0.0;
+        let mut total = 0.0;
+        for (j, &(l, r)) in self.history.iter().enumerate() {
+            // distance in input samples from the output sample
+            let d = j as f64 - (half - 1.0) - fraction;
+            let x = self.cutoff * d * PI;
+            let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+            let w = d / half * PI;
+            let blackman = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+            let weight = sinc * blackman;
+            left += l * weight;
+            right += r * weight;
+            total += weight;
+        }
+        (left / total, right / total)
+    }
+}
+
+fn to_i16(x: f64) -> i16 {
+    x.round().max(i16::MIN as f64).min(i16::MAX as f64) as i16
+}
+
+/// A `SimpleAudio` that resamples what it's given before passing it on to
+/// `audio`.
+///
+/// When `configure`d, the frequency it's given becomes the resampler's input
+/// rate, and `audio` is configured at its output rate instead. Samples must
+/// be set in order. If `resampler` is `None`, everything is passed straight
+/// through.
+pub struct ResamplerImpler<'a, Audio: 'a + ?Sized> {
+    pub resampler: Option<&'a mut Resampler>,
+    pub audio: &'a mut Audio,
+}
+
+impl<'a, Audio: 'a + ?Sized> SimpleAudio for ResamplerImpler<'a, Audio>
+where
+    Audio: SimpleAudio,
+{
+    fn configure(&mut self, frequency: u32, buffer_size: u16) -> Result<()> {
+        match self.resampler {
+            Some(ref mut resampler) => {
+                resampler.set_input_rate(frequency);
+                self.audio.configure(resampler.output_rate(), buffer_size)
+            }
+            None => self.audio.configure(frequency, buffer_size),
+        }
+    }
+
+    #[inline]
+    fn play(&mut self) -> Result<()> {
+        self.audio.play()
+    }
+
+    #[inline]
+    fn pause(&mut self) -> Result<()> {
+        self.audio.pause()
+    }
+
+    #[inline]
+    fn buffer_len(&self) -> usize {
+        self.audio.buffer_len()
+    }
+
+    #[inline]
+    fn buffer_set(&mut self, i: usize, left: i16, right: i16) {
+        match self.resampler {
+            Some(ref mut resampler) => resampler.push(left, right),
+            None => self.audio.buffer_set(i, left, right),
+        }
+    }
+
+    fn queue_buffer(&mut self) -> Result<()> {
+        let resampler = match self.resampler {
+            Some(ref mut resampler) => resampler,
+            None => return self.audio.queue_buffer(),
+        };
+        let len = self.audio.buffer_len();
+        if len == 0 {
+            return Ok(());
+        }
+        while resampler.len() >= len {
+            for i in 0..len {
+                let (left, right) = resampler.pop().unwrap();
+                self.audio.buffer_set(i, left, right);
+            }
+            self.audio.queue_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.reset();
+        }
+        self.audio.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_wave() {
+        // the SN76489's rate on an NTSC console, and a square wave with a
+        // period of 128 samples
+        const INPUT_RATE: u32 = 223721;
+        const PERIOD: u32 = 128;
+
+        for &interpolation in [Interpolation::Linear, Interpolation::Sinc].iter() {
+            for &output_rate in [44100u32, 48000].iter() {
+                let mut resampler = Resampler::new(output_rate, interpolation);
+                resampler.set_input_rate(INPUT_RATE);
+                let mut samples = Vec::new();
+                for i in 0..INPUT_RATE {
+                    let x = if i % PERIOD < PERIOD / 2 { 2000 } else { -2000 };
+                    resampler.push(x, -x);
+                    while let Some(sample) = resampler.pop() {
+                        samples.push(sample);
+                    }
+                }
+
+                // one second in, one second out
+                assert!((samples.len() as i64 - output_rate as i64).abs() <= 1);
+
+                // Find where the wave crosses zero, skipping the filter's
+                // warm up.
+                let mut crossings = Vec::new();
+                let mut last = 0;
+                for (i, &(left, right)) in samples.iter().enumerate().skip(100) {
+                    assert_eq!(left, -right);
+                    if left != 0 {
+                        if left.signum() != last && last != 0 {
+                            crossings.push(i);
+                        }
+                        last = left.signum();
+                    }
+                }
+                let half_periods = (crossings.len() - 1) as f64;
+                let seconds =
+                    (crossings[crossings.len() - 1] - crossings[0]) as f64 / output_rate as f64;
+                let frequency = half_periods / 2.0 / seconds;
+                let expected = INPUT_RATE as f64 / PERIOD as f64;
+                assert!(
+                    (frequency - expected).abs() < 1.0,
+                    "{:?} at {}: {} Hz",
+                    interpolation,
+                    output_rate,
+                    frequency
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{Result, SimpleAudio};
+
+#[derive(Debug)]
+struct Ring {
+    frames: VecDeque<(i16, i16)>,
+    capacity: usize,
+    last: (i16, i16),
+    playing: bool,
+}
+
+fn lock<'a>(ring: &'a Mutex<Ring>) -> MutexGuard<'a, Ring> {
+    // Nothing done while holding the lock can leave the ring inconsistent, so
+    // carry on even if some other thread panicked with it.
+    ring.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Ring {
+    fn push(&mut self, frame: (i16, i16)) {
+        if self.frames.len() >= self.capacity {
+            // overflow: drop the oldest frame to keep latency bounded
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    fn pull(&mut self, out: &mut [i16]) {
+        assert_eq!(out.len() % 2, 0, "odd length buffer for stereo samples");
+        for chunk in out.chunks_exact_mut(2) {
+            let frame = if !self.playing {
+                (0, 0)
+            } else if let Some(frame) = self.frames.pop_front() {
+                self.last = frame;
+                frame
+            } else {
+                // underrun: hold the last frame rather than clicking to zero
+                self.last
+            };
+            chunk[0] = frame.0;
+            chunk[1] = frame.1;
+        }
+    }
+
+    fn fill_level(&self) -> f32 {
+        self.frames.len() as f32 / self.capacity as f32
+    }
+}
+
+/// A `SimpleAudio` that, rather than playing samples itself, collects them in
+/// a ring buffer for a host audio callback to `pull`.
+///
+/// The emulator fills the ring a frame at a time while the host drains it at
+/// its own pace, so neither has to match the other's timing exactly. If the
+/// ring fills up, the oldest samples are dropped; if the host pulls more than
+/// there is, the last sample is repeated.
+///
+/// Samples are stereo pairs, which `push` and `pull` interleave, left first.
+/// The host callback will usually run on another thread; give it a
+/// `RingBufferReader`.
+#[derive(Debug)]
+pub struct RingBufferAudio {
+    ring: Arc<Mutex<Ring>>,
+    buffer: Vec<(i16, i16)>,
+    frequency: u32,
+}
+
+impl RingBufferAudio {
+    /// A `RingBufferAudio` holding up to `capacity` stereo samples.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        RingBufferAudio {
+            ring: Arc::new(Mutex::new(Ring {
+                frames: VecDeque::with_capacity(capacity),
+                capacity,
+                last: (0, 0),
+                playing: false,
+            })),
+            buffer: Vec::new(),
+            frequency: 0,
+        }
+    }
+
+    /// A handle for the host to `pull` samples from another thread.
+    #[inline]
+    pub fn reader(&self) -> RingBufferReader {
+        RingBufferReader {
+            ring: self.ring.clone(),
+        }
+    }
+
+    /// The frequency last given to `configure`, or 0 if it hasn't been
+    /// called. The host should play samples at this rate.
+    #[inline]
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        lock(&self.ring).capacity
+    }
+
+    /// Add interleaved stereo samples to the ring. If `samples` has odd
+    /// length, the last one is ignored.
+    pub fn push(&mut self, samples: &[i16]) {
+        let mut ring = lock(&self.ring);
+        for pair in samples.chunks(2) {
+            if pair.len() == 2 {
+                ring.push((pair[0], pair[1]));
+            }
+        }
+    }
+
+    /// See `RingBufferReader::pull`.
+    #[inline]
+    pub fn pull(&self, out: &mut [i16]) {
+        lock(&self.ring).pull(out)
+    }
+
+    /// See `RingBufferReader::len`.
+    #[inline]
+    pub fn len(&self) -> usize {
+        lock(&self.ring).frames.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// See `RingBufferReader::fill_level`.
+    #[inline]
+    pub fn fill_level(&self) -> f32 {
+        lock(&self.ring).fill_level()
+    }
+}
+
+/// A handle to a `RingBufferAudio`'s ring, for the host's audio callback.
+#[derive(Clone, Debug)]
+pub struct RingBufferReader {
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl RingBufferReader {
+    /// Fill `out` with interleaved stereo samples from the ring.
+    ///
+    /// Panics if `out` has odd length, since it couldn't hold the right
+    /// channel of its last sample.
+    ///
+    /// If the ring runs out, the last sample pulled is repeated. While the
+    /// `RingBufferAudio` is paused, `out` is filled with silence and nothing
+    /// is taken from the ring.
+    #[inline]
+    pub fn pull(&self, out: &mut [i16]) {
+        lock(&self.ring).pull(out)
+    }
+
+    /// How many stereo samples are waiting in the ring?
+    #[inline]
+    pub fn len(&self) -> usize {
+        lock(&self.ring).frames.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How full is the ring, from 0.0 for empty to 1.0 for full?
+    #[inline]
+    pub fn fill_level(&self) -> f32 {
+        lock(&self.ring).fill_level()
+    }
+}
+
+impl SimpleAudio for RingBufferAudio {
+    fn configure(&mut self, frequency: u32, buffer_size: u16) -> Result<()> {
+        self.frequency = frequency;
+        self.buffer = vec![(0, 0); buffer_size as usize];
+        let mut ring = lock(&self.ring);
+        ring.frames.clear();
+        ring.last = (0, 0);
+        Ok(())
+    }
+
+    #[inline]
+    fn play(&mut self) -> Result<()> {
+        lock(&self.ring).playing = true;
+        Ok(())
+    }
+
+    #[inline]
+    fn pause(&mut self) -> Result<()> {
+        lock(&self.ring).playing = false;
+        Ok(())
+    }
+
+    #[inline]
+    fn buffer_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    #[inline]
+    fn buffer_set(&mut self, i: usize, left: i16, right: i16) {
+        self.buffer[i] = (left, right);
+    }
+
+    fn queue_buffer(&mut self) -> Result<()> {
+        let mut ring = lock(&self.ring);
+        for &frame in self.buffer.iter() {
+            ring.push(frame);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        let mut ring = lock(&self.ring);
+        ring.frames.clear();
+        ring.last = (0, 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatched_sizes() {
+        let mut audio = RingBufferAudio::new(16);
+        audio.configure(44100, 5).unwrap();
+        audio.play().unwrap();
+        let reader = audio.reader();
+
+        // three buffers of 5 samples each, numbered 0 through 14
+        for chunk in 0..3 {
+            for i in 0..5 {
+                let x = 5 * chunk + i;
+                audio.buffer_set(i as usize, x, -x);
+            }
+            audio.queue_buffer().unwrap();
+        }
+        assert_eq!(reader.len(), 15);
+
+        // pull 7, then 4, then 1
+        let mut pulled = Vec::new();
+        for &n in [14, 8, 2].iter() {
+            let mut out = vec![0i16; n];
+            reader.pull(&mut out);
+            pulled.extend_from_slice(&out);
+        }
+        let mut expected: Vec<i16> = (0..11).flat_map(|x| vec![x, -x]).collect();
+        expected.extend_from_slice(&[11, -11]);
+        assert_eq!(pulled, expected);
+        assert_eq!(reader.len(), 3);
+
+        // underrun: what's left, then the last sample repeated
+        let mut out = [0i16; 10];
+        reader.pull(&mut out);
+        assert_eq!(out, [12, -12, 13, -13, 14, -14, 14, -14, 14, -14]);
+        assert!(reader.is_empty());
+
+        // overflow: the oldest samples are dropped
+        let samples: Vec<i16> = (100..120).flat_map(|x| vec![x, x]).collect();
+        audio.push(&samples);
+        assert_eq!(audio.len(), 16);
+        assert_eq!(audio.fill_level(), 1.0);
+        let mut out = [0i16; 2];
+        audio.pull(&mut out);
+        assert_eq!(out, [104, 104]);
+        assert_eq!(audio.fill_level(), 15.0 / 16.0);
+
+        // paused: silence, and the ring is untouched
+        audio.pause().unwrap();
+        let mut out = [1i16; 4];
+        reader.pull(&mut out);
+        assert_eq!(out, [0; 4]);
+        assert_eq!(reader.len(), 15);
+    }
+
+    #[test]
+    #[should_panic]
+    fn odd_pull() {
+        let mut audio = RingBufferAudio::new(16);
+        audio.push(&[1, 2, 3, 4]);
+        let mut out = [0i16; 3];
+        audio.pull(&mut out);
+    }
+}
@@ -1,20 +1,43 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![deny(bare_trait_objects, anonymous_parameters)]
 
+#[cfg(not(any(feature = "std", test)))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate failure;
+#[cfg(feature = "std")]
 extern crate chrono;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "std")]
 extern crate bincode;
+#[cfg(feature = "std")]
+extern crate flate2;
+#[cfg(feature = "std")]
+extern crate sha1_smol;
+
+/// Without `std`, the parts of it the Z80 and memory use, so that they can
+/// keep naming them `std::`.
+#[cfg(not(any(feature = "std", test)))]
+mod std {
+    pub use alloc::{boxed, string, sync, vec};
+    pub use core::*;
+}
 
 pub mod memo;
 #[macro_use]
 mod utilities;
 #[macro_use]
 pub mod hardware;
+#[cfg(feature = "std")]
 pub mod host_multimedia;
+#[cfg(feature = "std")]
 pub mod save;
+#[cfg(feature = "std")]
 pub mod systems;
 
+#[cfg(feature = "host_clock")]
 pub use utilities::{time_govern, TimeInfo};
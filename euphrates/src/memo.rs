@@ -2,6 +2,7 @@
 //!
 //! Memos are useful for debugging.
 
+#[cfg(feature = "std")]
 use std::fmt::Display;
 use std::marker::PhantomData;
 
@@ -49,9 +50,12 @@ impl<M> Inbox for NothingInbox<M> {
     }
 }
 
+/// An Inbox that prints its memos. Only with the `std` feature.
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct PrintingInbox<M: ?Sized>(PhantomData<M>);
 
+#[cfg(feature = "std")]
 impl<M> Default for PrintingInbox<M> {
     #[inline]
     fn default() -> Self {
@@ -59,6 +63,7 @@ impl<M> Default for PrintingInbox<M> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<M> Inbox for PrintingInbox<M>
 where
     M: Display,
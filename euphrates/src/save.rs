@@ -5,13 +5,16 @@ const MAX_BYTES: u64 = 0x10000000; // 256 MiB
 use std;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use failure::Error;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use bincode;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -76,3 +79,341 @@ where
     let file = File::open(path)?;
     deserialize_from(&file)
 }
+
+// This superfluous module with the `allow` attribute is necessary until the
+// `fail` crate begins using `dyn trait` syntax
+#[allow(bare_trait_objects)]
+mod save_load_error {
+    use super::*;
+
+    /// Error generated when loading a state with `read_state`.
+    #[derive(Debug, Fail)]
+    pub enum SaveLoadError {
+        #[fail(display = "IO error {}", _0)]
+        Io(#[cause] std::io::Error),
+        #[fail(display = "Not a saved state (it begins with {:?})", _0)]
+        BadMagic([u8; 4]),
+        #[fail(display = "Unsupported saved state version {}", _0)]
+        UnsupportedVersion(u32),
+        #[fail(display = "{}", _0)]
+        Deserialization(Error),
+    }
+}
+
+pub use self::save_load_error::SaveLoadError;
+
+impl From<std::io::Error> for SaveLoadError {
+    fn from(x: std::io::Error) -> Self {
+        SaveLoadError::Io(x)
+    }
+}
+
+/// Saved states begin with these bytes, followed by a little endian `u32`
+/// version number.
+pub const STATE_MAGIC: [u8; 4] = *b"EUPH";
+
+/// Like `STATE_MAGIC`, but for states compressed with deflate.
+pub const COMPRESSED_MAGIC: [u8; 4] = *b"EUPZ";
+
+/// The version of the layout of the states we write.
+///
+/// Version 1 is just `serialize_into`'s output. Version 2 added the SC-3000
+/// keyboard to `SmsPlayerInput`, version 3 `SmsState::frame_count`, version
+/// 4 `SmsState::io`, version 5 `SmsState::ym2413`, version 6
+/// `SmsState::memory_control`, and version 7 `Sn76489State::noise_kind`.
+pub const STATE_VERSION: u32 = 7;
+
+/// A type `read_state` can read, including from payloads saved with an older
+/// layout.
+///
+/// When a type's layout changes, `STATE_VERSION` should be incremented and
+/// the old version handled in `migrate`, by deserializing the old layout and
+/// converting it.
+pub trait Versioned: DeserializeOwned {
+    /// Read a payload saved with an older layout `version`.
+    ///
+    /// By default there are no older versions.
+    fn migrate<R>(version: u32, _reader: R) -> std::result::Result<Self, SaveLoadError>
+    where
+        R: Read,
+    {
+        Err(SaveLoadError::UnsupportedVersion(version))
+    }
+}
+
+/// Like `serialize_into`, but beginning with `STATE_MAGIC` and
+/// `STATE_VERSION`, so `read_state` can tell what it's reading.
+pub fn write_state<W, T>(t: &T, mut w: W) -> Result<()>
+where
+    W: Write,
+    T: ?Sized + Serialize,
+{
+    w.write_all(&STATE_MAGIC)?;
+    w.write_all(&STATE_VERSION.to_le_bytes())?;
+    serialize_into(w, t)
+}
+
+/// Like `write_state`, but compressed.
+///
+/// A state like `SmsState` is mostly RAM, which is mostly zeros, so this makes
+/// it much smaller. The output begins with `COMPRESSED_MAGIC` and
+/// `STATE_VERSION`, followed by a deflate stream.
+pub fn write_state_compressed<W, T>(t: &T, mut w: W) -> Result<()>
+where
+    W: Write,
+    T: ?Sized + Serialize,
+{
+    w.write_all(&COMPRESSED_MAGIC)?;
+    w.write_all(&STATE_VERSION.to_le_bytes())?;
+    let mut encoder = DeflateEncoder::new(w, Compression::default());
+    serialize_into(&mut encoder, t)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+pub fn write_state_compressed_at<P, T>(path: P, t: &T) -> Result<()>
+where
+    P: AsRef<Path>,
+    T: ?Sized + Serialize,
+{
+    let file = File::create(path)?;
+    write_state_compressed(t, &file)
+}
+
+/// Read a state written by either `write_state` or `write_state_compressed`.
+pub fn read_state<R, T>(mut reader: R) -> std::result::Result<T, SaveLoadError>
+where
+    R: Read,
+    T: Versioned,
+{
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    match magic {
+        STATE_MAGIC => read_payload(version, reader),
+        COMPRESSED_MAGIC => read_payload(version, DeflateDecoder::new(reader)),
+        _ => Err(SaveLoadError::BadMagic(magic)),
+    }
+}
+
+pub fn read_state_at<P, T>(path: P) -> std::result::Result<T, SaveLoadError>
+where
+    P: AsRef<Path>,
+    T: Versioned,
+{
+    let file = File::open(path)?;
+    read_state(&file)
+}
+
+fn read_payload<R, T>(version: u32, reader: R) -> std::result::Result<T, SaveLoadError>
+where
+    R: Read,
+    T: Versioned,
+{
+    match version {
+        STATE_VERSION => deserialize_from(reader).map_err(SaveLoadError::Deserialization),
+        _ => T::migrate(version, reader),
+    }
+}
+
+/// A file we might save, for use with `PathPolicy`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Artifact<'a> {
+    /// Battery-backed cartridge RAM.
+    CartridgeRam,
+
+    /// A saved state, with the given name or slot.
+    State(&'a str),
+
+    /// Recorded gameplay, with the given name.
+    Recording(&'a str),
+
+    /// A numbered screenshot.
+    Screenshot(u32),
+
+    /// A numbered audio capture.
+    Audio(u32),
+}
+
+/// Where to put the files associated with a game.
+///
+/// Everything goes in one directory, with filenames beginning with the game's
+/// name:
+///
+/// ```
+/// use std::path::PathBuf;
+/// use euphrates::save::{Artifact, PathPolicy};
+/// let policy = PathPolicy::new("saves", "Game");
+/// assert_eq!(
+///     policy.path(Artifact::State("slot0")),
+///     PathBuf::from("saves/Game.slot0.sms_state")
+/// );
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PathPolicy {
+    directory: PathBuf,
+    name: String,
+}
+
+impl PathPolicy {
+    pub fn new<P, S>(directory: P, name: S) -> Self
+    where
+        P: Into<PathBuf>,
+        S: Into<String>,
+    {
+        PathPolicy {
+            directory: directory.into(),
+            name: name.into(),
+        }
+    }
+
+    /// Files go next to the file at `path`, named after it without its
+    /// extension.
+    ///
+    /// So the cartridge RAM for `roms/Game.sms` is at `roms/Game.sav`.
+    pub fn beside<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let directory = match path.parent() {
+            Some(p) => p.to_path_buf(),
+            None => PathBuf::new(),
+        };
+        let name = match path.file_stem() {
+            Some(s) => s.to_string_lossy().into_owned(),
+            None => String::new(),
+        };
+        PathPolicy { directory, name }
+    }
+
+    /// The same policy, but putting files in `directory`.
+    pub fn in_directory<P>(&self, directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        PathPolicy {
+            directory: directory.into(),
+            name: self.name.clone(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self, artifact: Artifact) -> PathBuf {
+        let filename = match artifact {
+            Artifact::CartridgeRam => format!("{}.sav", self.name),
+            Artifact::State(slot) => format!("{}.{}.sms_state", self.name, slot),
+            Artifact::Recording(name) => format!("{}.{}.sms_record", self.name, name),
+            Artifact::Screenshot(n) => format!("{}-{:0>4}.png", self.name, n),
+            Artifact::Audio(n) => format!("{}-{:0>4}.wav", self.name, n),
+        };
+        self.directory.join(filename)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use systems::sms::{Kind, SmsMemoryMapper, SmsState, TvSystem};
+
+    impl Versioned for (u32, String) {}
+
+    #[test]
+    fn compressed_state() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0] = 0xC3; // jp 0x1234
+        rom[1] = 0x34;
+        rom[2] = 0x12;
+        let mut state = SmsState::from_rom(
+            Arc::new(rom.into_boxed_slice()),
+            Some(SmsMemoryMapper::Sega),
+            TvSystem::Ntsc,
+            Kind::Sms2,
+        );
+        state.memory.system_ram[0x100] = 0x56;
+
+        let raw = serialize(&state).unwrap();
+        let mut compressed = Vec::new();
+        write_state_compressed(&state, &mut compressed).unwrap();
+        assert_eq!(compressed[..4], COMPRESSED_MAGIC);
+        assert!(compressed.len() < raw.len() / 10);
+
+        let state2: SmsState = read_state(&compressed[..]).unwrap();
+        assert!(state2 == state);
+    }
+
+    #[test]
+    fn versioned_state() {
+        let state: (u32, String) = (17, "seventeen".to_owned());
+        let mut bytes = Vec::new();
+        write_state(&state, &mut bytes).unwrap();
+        assert_eq!(bytes[..4], STATE_MAGIC);
+        assert_eq!(bytes[4..8], STATE_VERSION.to_le_bytes());
+        let state2: (u32, String) = read_state(&bytes[..]).unwrap();
+        assert_eq!(state2, state);
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        match read_state::<_, (u32, String)>(&bad_magic[..]) {
+            Err(SaveLoadError::BadMagic(magic)) => assert_eq!(magic, *b"XUPH"),
+            x => panic!("Wrong result {:?}", x),
+        }
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 0x22;
+        match read_state::<_, (u32, String)>(&bad_version[..]) {
+            Err(SaveLoadError::UnsupportedVersion(0x22)) => {}
+            x => panic!("Wrong result {:?}", x),
+        }
+
+        // without a header at all
+        match read_state::<_, (u32, String)>(&serialize(&state).unwrap()[..]) {
+            Err(SaveLoadError::BadMagic(_)) => {}
+            x => panic!("Wrong result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn path_policy() {
+        let policy = PathPolicy::beside("roms/Game.sms");
+        assert_eq!(policy.name(), "Game");
+        assert_eq!(
+            policy.path(Artifact::CartridgeRam),
+            PathBuf::from("roms/Game.sav")
+        );
+
+        let policy = policy.in_directory("saves");
+        assert_eq!(
+            policy.path(Artifact::CartridgeRam),
+            PathBuf::from("saves/Game.sav")
+        );
+        assert_eq!(
+            policy.path(Artifact::State("slot0")),
+            PathBuf::from("saves/Game.slot0.sms_state")
+        );
+        assert_eq!(
+            policy.path(Artifact::Recording("run")),
+            PathBuf::from("saves/Game.run.sms_record")
+        );
+        assert_eq!(
+            policy.path(Artifact::Screenshot(1)),
+            PathBuf::from("saves/Game-0001.png")
+        );
+        assert_eq!(
+            policy.path(Artifact::Audio(12)),
+            PathBuf::from("saves/Game-0012.wav")
+        );
+    }
+}
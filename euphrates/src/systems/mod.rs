@@ -1 +1,2 @@
+pub mod sg1000;
 pub mod sms;
@@ -0,0 +1,131 @@
+//! The SG-1000 and SC-3000, Sega's systems before the Master System.
+//!
+//! They have the same Z80, IO ports, and SN76489 (but with a shorter noise
+//! shift register) as the Master System, and their TMS9918 VDP's modes are
+//! the legacy modes of the Master System's VDP. So rather than a separate
+//! emulator, an SG-1000 is an `Sms` with the `SmsMemoryMapper::Sg1000`
+//! mapper: up to 48 KiB of ROM at `0x0000`, with no paging, and RAM mirrored
+//! through `0xC000` to `0xFFFF`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use host_multimedia::FakeAudio;
+use memo::NothingInbox;
+use systems::sms::*;
+
+/// How much RAM an SG-1000 has, in KiB. The SC-3000 has 2 KiB, and some
+/// cartridges bring more; for those use `SmsMemoryMapper::Sg1000` directly.
+pub const SG1000_RAM_KIB: usize = 1;
+
+pub struct Sg1000;
+
+impl Sg1000 {
+    /// A freshly powered on SG-1000 state with this ROM.
+    ///
+    /// As with `SmsState::from_rom`, `rom` should already be formatted with
+    /// `sms_roms::format`.
+    pub fn state_from_rom(rom: Arc<Box<[u8]>>, tv_system: TvSystem) -> SmsState {
+        let mut state = SmsState::from_rom(
+            rom,
+            Some(SmsMemoryMapper::Sg1000(SG1000_RAM_KIB)),
+            tv_system,
+            Kind::Sms,
+        );
+        state.sn76489.noise_kind = NoiseKind::Sg1000;
+        state
+    }
+
+    /// An `SmsBuilder` with the SG-1000's mapper, VDP, and noise generator.
+    ///
+    /// Graphics, audio, and the rest can be set as with any `SmsBuilder`.
+    pub fn builder(
+    ) -> SmsBuilder<FakeSmsGraphics, FakeAudio, FakeSn76489, PointerSmsMemory, NothingInbox<Z80Memo>>
+    {
+        SmsBuilder::new()
+            .mapper(Some(SmsMemoryMapper::Sg1000(SG1000_RAM_KIB)))
+            .kind(Kind::Sms)
+            .noise_kind(NoiseKind::Sg1000)
+    }
+
+    /// A freshly powered on SG-1000 with this ROM, with no graphics or audio.
+    pub fn from_rom(rom: Arc<Box<[u8]>>) -> Result<Box<dyn Sms>, SmsCreationError> {
+        Sg1000::builder().build_from_rom(rom)
+    }
+
+    /// A freshly powered on SG-1000 with the ROM in this file, with no
+    /// graphics or audio.
+    pub fn from_file<P>(path: P) -> Result<Box<dyn Sms>, SmsCreationError>
+    where
+        P: AsRef<Path>,
+    {
+        let rom = sms_roms::from_file(path)?;
+        Sg1000::from_rom(Arc::new(rom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use save;
+
+    #[test]
+    fn smoke() {
+        let mut rom = vec![0u8; 0x4000];
+        let program = [
+            0xF3, // di
+            0x31, 0x00, 0xC4, // ld sp, 0xC400
+            0xED, 0x56, // im 1
+            0x3E, 0x00, // ld a, 0x00
+            0xD3, 0xBF, // out (0xBF), a
+            0x3E, 0x80, // ld a, 0x80
+            0xD3, 0xBF, // out (0xBF), a: Graphics I mode
+            0x3E, 0xE0, // ld a, 0xE0
+            0xD3, 0xBF, // out (0xBF), a
+            0x3E, 0x81, // ld a, 0x81
+            0xD3, 0xBF, // out (0xBF), a: display and frame interrupts on
+            0xFB, // ei
+            0x18, 0xFE, // jr -2
+        ];
+        rom[..program.len()].copy_from_slice(&program);
+        let handler = [
+            0xF5, // push af
+            0xDB, 0xBF, // in a, (0xBF)
+            0x3A, 0x00, 0xC4, // ld a, (0xC400)
+            0x3C, // inc a
+            0x32, 0x00, 0xC4, // ld (0xC400), a
+            0xF1, // pop af
+            0xFB, // ei
+            0xC9, // ret
+        ];
+        rom[0x38..0x38 + handler.len()].copy_from_slice(&handler);
+
+        // a real SN76489, so its noise kind is kept
+        let mut sms = Sg1000::builder()
+            .sn76489(TypeWrap::<Sn76489State>::default())
+            .build_from_rom(Arc::new(rom.into_boxed_slice()))
+            .unwrap();
+        for _ in 0..5 {
+            sms.run_frame_headless(SmsPlayerInput::default()).unwrap();
+        }
+
+        let state = sms.state();
+        assert_eq!(state.vdp.reg[1], 0xE0);
+        assert_eq!(state.sn76489.noise_kind, NoiseKind::Sg1000);
+        assert_eq!(state.memory.system_ram.len(), 0x400);
+        // 0xC400 is mirrored down to the beginning of RAM, and it's counted
+        // one interrupt per frame
+        let count = state.memory.system_ram[0];
+        assert!((4..=5).contains(&count), "count was {}", count);
+
+        // a saved state keeps its noise kind, whatever the builder's
+        let mut bytes = Vec::new();
+        save::write_state(&state, &mut bytes).unwrap();
+        let sms = SmsBuilder::new()
+            .sn76489(TypeWrap::<Sn76489State>::default())
+            .build(save::read_state(&bytes[..]).unwrap())
+            .unwrap();
+        assert_eq!(sms.state().sn76489.noise_kind, NoiseKind::Sg1000);
+    }
+}
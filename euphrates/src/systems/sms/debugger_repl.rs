@@ -0,0 +1,301 @@
+use std::io::{BufRead, Write};
+
+use failure::Error;
+
+use super::*;
+
+const ALL_REG8: [Reg8; 26] = [
+    Reg8::B,
+    Reg8::C,
+    Reg8::D,
+    Reg8::E,
+    Reg8::A,
+    Reg8::F,
+    Reg8::H,
+    Reg8::L,
+    Reg8::B0,
+    Reg8::C0,
+    Reg8::D0,
+    Reg8::E0,
+    Reg8::A0,
+    Reg8::F0,
+    Reg8::H0,
+    Reg8::L0,
+    Reg8::IXL,
+    Reg8::IXH,
+    Reg8::IYL,
+    Reg8::IYH,
+    Reg8::SPL,
+    Reg8::SPH,
+    Reg8::PCL,
+    Reg8::PCH,
+    Reg8::I,
+    Reg8::R,
+];
+
+const ALL_REG16: [Reg16; 12] = [
+    Reg16::BC,
+    Reg16::DE,
+    Reg16::AF,
+    Reg16::HL,
+    Reg16::BC0,
+    Reg16::DE0,
+    Reg16::AF0,
+    Reg16::HL0,
+    Reg16::IX,
+    Reg16::IY,
+    Reg16::SP,
+    Reg16::PC,
+];
+
+/// How many instructions `continue` will run if not told otherwise.
+const DEFAULT_CONTINUE_LIMIT: u32 = 0x100000;
+
+/// Run a line-oriented debugger on `sms`, reading commands from `input` and
+/// writing results to `output`.
+///
+/// All numbers, both in commands and in output, are hexadecimal. The commands
+/// are:
+///
+/// - `step [n]`: execute `n` instructions (default 1) and show the next one.
+/// - `continue [n]`: execute instructions until a breakpoint is reached, or
+///   until `n` of them have run.
+/// - `break addr`: set a breakpoint at `addr`.
+/// - `delete`: remove all breakpoints.
+/// - `disassemble [addr]`: disassemble the instructions executed so far around
+///   `addr` (default PC). This requires `sms` to have a debugger, as with a
+///   `DebuggingInbox`.
+/// - `memos`: show recent memos. Also requires a debugger.
+/// - `dump addr [len]`: show `len` bytes of memory (default 0x10) starting at
+///   `addr`.
+/// - `regs`: show the Z80's registers.
+/// - `set reg value`: set a Z80 register, named as in `regs`, like `a` or `hl`.
+/// - `quit`: stop. Reaching the end of `input` also stops.
+///
+/// Mistakes in commands are reported to `output`; only failures in emulation
+/// or reading or writing are returned as errors.
+pub fn debugger_repl<R, W>(sms: &mut dyn Sms, input: R, mut output: W) -> Result<(), Error>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut breakpoints: Vec<u16> = Vec::new();
+
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        macro_rules! arg {
+            ($i:expr, $default:expr) => {
+                match words.get($i).map(|s| parse_hex(s)) {
+                    None => $default,
+                    Some(Some(x)) => x,
+                    Some(None) => {
+                        writeln!(output, "error: bad number {}", words[$i])?;
+                        continue;
+                    }
+                }
+            };
+        }
+
+        match words[0] {
+            "step" | "s" => {
+                let n = arg!(1, 1);
+                for _ in 0..n {
+                    sms.step()?;
+                }
+                write_next_instruction(sms, &mut output)?;
+            }
+            "continue" | "c" => {
+                let n = arg!(1, DEFAULT_CONTINUE_LIMIT);
+                let mut hit = false;
+                for _ in 0..n {
+                    sms.step()?;
+                    if breakpoints.contains(&sms.z80().reg16(Reg16::PC)) {
+                        hit = true;
+                        break;
+                    }
+                }
+                if hit {
+                    writeln!(output, "breakpoint")?;
+                }
+                write_next_instruction(sms, &mut output)?;
+            }
+            "break" | "b" => {
+                let pc = arg!(1, sms.z80().reg16(Reg16::PC) as u32) as u16;
+                if !breakpoints.contains(&pc) {
+                    breakpoints.push(pc);
+                }
+                if let Some(debugger) = sms.debugger() {
+                    debugger.command(Command::BreakAtPc(pc));
+                }
+                writeln!(output, "breakpoint at {:0>4X}", pc)?;
+            }
+            "delete" => {
+                breakpoints.clear();
+                if let Some(debugger) = sms.debugger() {
+                    debugger.command(Command::RemovePcBreakpoints);
+                }
+                writeln!(output, "breakpoints removed")?;
+            }
+            "disassemble" | "d" => {
+                let pc = arg!(1, sms.z80().reg16(Reg16::PC) as u32) as u16;
+                match sms.query(Query::DisassemblyAt(pc)) {
+                    Some(s) => write!(output, "{}", s)?,
+                    None => writeln!(output, "error: no debugger")?,
+                }
+            }
+            "memos" => match sms.query(Query::RecentMemos) {
+                Some(s) => write!(output, "{}", s)?,
+                None => writeln!(output, "error: no debugger")?,
+            },
+            "dump" => {
+                if words.len() < 2 {
+                    writeln!(output, "error: dump needs an address")?;
+                    continue;
+                }
+                let address = arg!(1, 0) as u16;
+                let len = arg!(2, 0x10);
+                write_dump(sms, address, len, &mut output)?;
+            }
+            "regs" | "r" => writeln!(output, "{}", Z80Display(sms.z80()))?,
+            "set" => {
+                if words.len() < 3 {
+                    writeln!(output, "error: set needs a register and a value")?;
+                    continue;
+                }
+                let value = arg!(2, 0);
+                if let Some(&reg) = ALL_REG8.iter().find(|r| format!("{}", r) == words[1]) {
+                    sms.z80_mut().set_reg8(reg, value as u8);
+                    writeln!(output, "{} = {:0>2X}", reg, value as u8)?;
+                } else if let Some(&reg) = ALL_REG16.iter().find(|r| format!("{}", r) == words[1])
+                {
+                    sms.z80_mut().set_reg16(reg, value as u16);
+                    writeln!(output, "{} = {:0>4X}", reg, value as u16)?;
+                } else {
+                    writeln!(output, "error: unknown register {}", words[1])?;
+                }
+            }
+            "quit" | "q" => break,
+            command => writeln!(output, "error: unknown command {}", command)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    let digits = s.trim_start_matches("0x").trim_start_matches('$');
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn write_next_instruction<W: Write>(sms: &mut dyn Sms, output: &mut W) -> Result<(), Error> {
+    let pc = sms.z80().reg16(Reg16::PC);
+    let opcode = Opcode::from_memory(sms.memory(), pc);
+    match opcode.mnemonic() {
+        Some(mnemonic) => writeln!(output, "{:0>4X} {}", pc, mnemonic)?,
+        None => writeln!(output, "{:0>4X} {} <Unknown instruction>", pc, opcode)?,
+    }
+    Ok(())
+}
+
+fn write_dump<W: Write>(
+    sms: &mut dyn Sms,
+    address: u16,
+    len: u32,
+    output: &mut W,
+) -> Result<(), Error> {
+    let memory = sms.memory();
+    for line_start in (0..len).step_by(16) {
+        let line_address = address.wrapping_add(line_start as u16);
+        write!(output, "{:0>4X}:", line_address)?;
+        for i in line_start..len.min(line_start + 16) {
+            write!(output, " {:0>2X}", memory.read(address.wrapping_add(i as u16)))?;
+        }
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use host_multimedia::FakeAudio;
+
+    #[test]
+    fn script() {
+        let mut rom = vec![0u8; 0x8000];
+        let program = [
+            0x3E, 0x12, // ld a, 0x12
+            0x21, 0x00, 0xC0, // ld hl, 0xC000
+            0x77, // ld (hl), a
+            0x23, // inc hl
+            0x18, 0xFC, // jr -4
+        ];
+        rom[..program.len()].copy_from_slice(&program);
+        let state = SmsState::from_rom(
+            Arc::new(rom.into_boxed_slice()),
+            Some(SmsMemoryMapper::Sega),
+            TvSystem::Ntsc,
+            Kind::Sms2,
+        );
+        let mut sms = new_sms(
+            None,
+            state,
+            FakeSmsGraphics,
+            FakeAudio,
+            DebuggingInbox::default(),
+            TypeWrap::<PointerSmsMemory>::default(),
+            TypeWrap::<FakeSn76489>::default(),
+        ).unwrap();
+
+        let script = "\
+step
+break 6
+continue
+set a 34
+continue 10
+delete
+step 3
+disassemble
+
+dump C000 3
+frobnicate
+set q 1
+quit
+step
+";
+        let mut output = Vec::new();
+        debugger_repl(&mut *sms, script.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().map(|l| l.trim_end()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0002 ld hl, C000",
+                "breakpoint at 0006",
+                "breakpoint",
+                "0006 inc hl",
+                "a = 34",
+                "breakpoint",
+                "0006 inc hl",
+                "breakpoints removed",
+                "0006 inc hl",
+                "        0000 ld a, 12",
+                "        0002 ld hl, C000",
+                "L_0000: 0005 ld (hl), a",
+                "        0006 inc hl                         <<<<<",
+                "        0007 jr L_0000 [+FC]",
+                "C000: 12 34 34",
+                "error: unknown command frobnicate",
+                "error: unknown register q",
+            ]
+        );
+    }
+}
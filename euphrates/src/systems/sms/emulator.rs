@@ -1,13 +1,16 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::marker::PhantomData;
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "host_clock")]
 use std::time::Instant;
 
 use failure::Error;
 
-use host_multimedia::SimpleAudio;
-use memo::Inbox;
+use host_multimedia::{FakeAudio, Interpolation, Resampler, ResamplerImpler, SimpleAudio};
+use memo::{Inbox, NothingInbox};
 use utilities;
 
 use super::*;
@@ -16,6 +19,18 @@ pub const NTSC_Z80_FREQUENCY: u64 = 10738580 / 3;
 
 pub const PAL_Z80_FREQUENCY: u64 = 10640685 / 3;
 
+/// The Z80's clock frequency on a console for this TV system.
+///
+/// The VDP's frame has 262 lines on NTSC and 313 on PAL, so at these
+/// frequencies they run at about 60 and 50 frames per second.
+#[inline]
+pub fn z80_frequency(tv_system: TvSystem) -> u64 {
+    match tv_system {
+        TvSystem::Ntsc => NTSC_Z80_FREQUENCY,
+        TvSystem::Pal => PAL_Z80_FREQUENCY,
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct SmsState {
     pub z80: Z80State,
@@ -24,15 +39,52 @@ pub struct SmsState {
     pub player_input: SmsPlayerInput,
     pub pause_irq: SmsPauseInterruptState,
     pub sn76489: Sn76489State,
+
+    /// How many frames have been finished since power on. See
+    /// `Sms::frame_count`.
+    pub frame_count: u64,
+
+    /// The controller ports' TH lines and the SC-3000 PPI.
+    pub io: SmsIoState,
+
+    /// The FM sound chip, used if `Sms::set_fm` is on.
+    pub ym2413: Ym2413State,
+
+    /// The memory control register, IO port 0x3E. See
+    /// `ControlledMemory16Impler`.
+    pub memory_control: u8,
 }
 
 impl SmsState {
+    /// A freshly powered on `SmsState` with this ROM.
+    ///
+    /// If `mapper` is `None`, it's guessed with `sms_roms::detect_mapper`.
+    /// RAM starts out zeroed, as does cartridge RAM once a game maps it in;
+    /// for anything else, use `SmsMemoryState::fill_ram` on `memory`, or
+    /// build with `SmsBuilder::ram_fill`.
+    ///
+    /// As by `sms_roms::format`, a copier header (see
+    /// `sms_roms::has_copier_header`) is stripped off, and a ROM whose
+    /// number of pages isn't a power of two is mirrored up to one (see
+    /// `sms_roms::mirror_to_power_of_two`).
     pub fn from_rom(
         rom: Arc<Box<[u8]>>,
-        mapper: SmsMemoryMapper,
+        mapper: Option<SmsMemoryMapper>,
         tv_system: TvSystem,
         vdp_kind: Kind,
     ) -> SmsState {
+        let rom = if sms_roms::has_copier_header(&rom) {
+            Arc::new(rom[0x200..].to_vec().into_boxed_slice())
+        } else {
+            rom
+        };
+        let mirrored = sms_roms::mirror_to_power_of_two(rom[..].into());
+        let rom = if mirrored.len() == rom.len() {
+            rom
+        } else {
+            Arc::new(mirrored)
+        };
+        let mapper = mapper.unwrap_or_else(|| sms_roms::detect_mapper(&rom));
         let mut state = SmsState {
             z80: Default::default(),
             vdp: Default::default(),
@@ -45,8 +97,14 @@ impl SmsState {
                 half_cartridge_ram: Default::default(),
                 pages: Default::default(),
                 mapper,
+                ram_fill: Default::default(),
+                first_slot: Default::default(),
             },
             sn76489: Default::default(),
+            frame_count: 0,
+            io: Default::default(),
+            ym2413: Default::default(),
+            memory_control: MEMORY_CONTROL_CARTRIDGE,
         };
         state.vdp.set_tv_system(tv_system);
         state.vdp.set_kind(vdp_kind);
@@ -60,9 +118,10 @@ impl SmsState {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TimeStatus {
     /// Any time before now
+    #[cfg(feature = "host_clock")]
     pub start_time: Instant,
 
     /// How many cycles on the clock at `start_time`?
@@ -71,6 +130,10 @@ pub struct TimeStatus {
     /// Clock frequency in Hz
     pub frequency: Option<u64>,
 
+    /// How many times faster than `frequency` to run; 1.0 is normal speed.
+    pub speed: f64,
+
+    /// Set by `Sms::hold`, so that frames don't run until `Sms::resume`.
     pub holding: bool,
 }
 
@@ -78,25 +141,71 @@ impl TimeStatus {
     pub fn new(start_cycles: u64, frequency: Option<u64>) -> Self {
         TimeStatus {
             start_cycles,
+            #[cfg(feature = "host_clock")]
             start_time: Instant::now(),
             frequency,
+            speed: 1.0,
             holding: false,
         }
     }
+
+    /// The frequency we're actually keeping time by: `frequency` scaled by
+    /// `speed`.
+    pub fn effective_frequency(&self) -> Option<u64> {
+        self.frequency
+            .map(|f| ((f as f64 * self.speed) as u64).max(1))
+    }
+
+    /// How long after `start_time` should there be `cycles` on the clock?
+    ///
+    /// `None` if there's no frequency, so we're running as fast as possible.
+    pub fn target_duration(&self, cycles: u64) -> Option<Duration> {
+        self.effective_frequency()
+            .map(|f| utilities::cycles_duration(cycles.wrapping_sub(self.start_cycles), f))
+    }
 }
 
-#[derive(Clone)]
-struct SmsS<Graphics, Audio, Sn76489, Mem, Inx> {
+/// The concrete type implementing `Sms`.
+///
+/// Usually it's used as a `Box<dyn Sms>`, but holding one directly, as from
+/// `new_sms_concrete` or `SmsBuilder::build_concrete`, lets calls like
+/// `run_frame` be monomorphized and inlined.
+pub struct SmsS<Graphics, Audio, Sn76489, Mem, Inx> {
     z80: Z80State,
     vdp: SmsVdpState,
     memory: Mem,
     player_input: SmsPlayerInput,
     pause_irq: SmsPauseInterruptState,
+    frame_count: u64,
     graphics: Graphics,
     audio: Audio,
     sn76489: Sn76489,
     time_status: TimeStatus,
     inbox: Inx,
+    accurate_vdp_access: bool,
+    overscan: bool,
+    ram_fill: RamFill,
+    bios: Option<Arc<Box<[u8]>>>,
+    memory_control: u8,
+    port_devices: [PortDevice; 2],
+    sc3000_keyboard: bool,
+    io: SmsIoState,
+    fm: bool,
+    ym2413: Ym2413State,
+    resampler: Option<Resampler>,
+    memory_watch: Option<Box<dyn MemoryWatch>>,
+    cheats: Vec<Cheat>,
+    fault_checks: bool,
+    illegal_opcodes: Vec<Opcode>,
+    rewind: RewindBuffer,
+    turbo: bool,
+    /// Was the last frame run with `run_frame_headless`?
+    headless: bool,
+    /// How many times a frame has waited out a hold. See `wait_while_holding`.
+    #[cfg(test)]
+    hold_waits: u32,
+    last_frame_load: f32,
+    cartridge_info: Option<&'static GameInfo>,
 }
 
 pub trait Sms {
@@ -110,25 +219,287 @@ pub trait Sms {
 
     fn memory(&mut self) -> &mut dyn Memory16;
 
+    /// Where does the logical address `logical` currently point?
+    fn describe_address(&self, logical: u16) -> MemoryLocation;
+
+    /// The contents of battery-backed cartridge RAM, for saving to a file.
+    ///
+    /// See `SmsMemory::save_cartridge_ram`.
+    fn save_cartridge_ram(&self) -> Option<Vec<u8>>;
+
+    /// Load cartridge RAM saved by `save_cartridge_ram`.
+    fn load_cartridge_ram(&mut self, data: &[u8]) -> Result<(), SmsMemoryLoadError>;
+
     fn debugger(&mut self) -> Option<&mut dyn Debugger>;
 
+    /// Ask the debugger `query`, or get `None` if there is no debugger.
+    fn query(&mut self, query: Query) -> Option<String>;
+
+    /// Run a frame, queueing audio and sleeping to keep time at the `Sms`'s
+    /// frequency.
+    ///
+    /// Without the `host_clock` feature there's no clock to keep time by, so this
+    /// is the same as `run_frame_headless`.
     fn run_frame(&mut self, player_input: SmsPlayerInput) -> Result<(), SmsEmulationError>;
 
+    /// Like `run_frame`, but as fast as possible: never queue audio or sleep
+    /// to keep time, whatever the frequency.
+    ///
+    /// Unlike in turbo mode, the frame is still drawn, so this suits test
+    /// harnesses and other batch processing that want every frame.
+    fn run_frame_headless(&mut self, player_input: SmsPlayerInput)
+        -> Result<(), SmsEmulationError>;
+
+    /// Execute a single Z80 instruction, returning the number of cycles it
+    /// took.
+    ///
+    /// The VDP is first run until it's caught up with the Z80, so that its
+    /// interrupts are current. See `Z80Run::step`.
+    fn step(&mut self) -> Result<u64, SmsEmulationError>;
+
+    /// Run the Z80 for at least `cycles` cycles, stopping at the first
+    /// instruction boundary after that, and return how many cycles it actually
+    /// ran.
+    ///
+    /// The VDP is run a line at a time to keep up, as in `run_frame`, but
+    /// there's no audio or keeping time. If the inbox starts holding, as at a
+    /// breakpoint, this returns early.
+    fn run_cycles(&mut self, cycles: u64) -> Result<u64, SmsEmulationError>;
+
     fn state(&self) -> SmsState;
 
+    /// Soft reset, as by the reset line rather than the power switch.
+    ///
+    /// The Z80 goes back to its power on state (but with SP at 0xDFEE, as
+    /// in `SmsState::from_rom`), the VDP's registers to their
+    /// `reset_defaults`, and the mapper to its default pages. RAM, both
+    /// system and cartridge, and VRAM are left alone, and time keeps going.
+    ///
+    /// This isn't the Master System's reset button, which is just an input
+    /// for software to read; see `joypad_b_bits::RESET`.
+    fn reset(&mut self);
+
+    /// Power off and on again.
+    ///
+    /// Beyond `reset`, system and cartridge RAM are filled as set by
+    /// `set_ram_fill` (so a battery save is lost), the VDP, SN76489, and
+    /// YM2413 go back to their power on states, and `frame_count` starts
+    /// over. Settings like the TV system and `set_noise_kind` are kept.
+    fn power_cycle(&mut self);
+
+    /// What should `power_cycle` fill RAM with?
+    ///
+    /// Cartridge RAM a game maps in for the first time is filled this way
+    /// too. `RamFill::Zero` by default.
+    fn set_ram_fill(&mut self, fill: RamFill);
+
+    /// Boot from this BIOS at the next `reset` or `power_cycle`, rather than
+    /// straight into the cartridge.
+    ///
+    /// The BIOS is mapped over the cartridge until it disables itself
+    /// through the memory control register at IO port 0x3E; see
+    /// `ControlledMemory16Impler`. An empty BIOS is the same as `None`, the
+    /// default. Removing the BIOS while it's mapped in enables the cartridge
+    /// instead.
+    fn set_bios(&mut self, bios: Option<Arc<Box<[u8]>>>);
+
+    /// Freeze emulation: until `resume`, `run_frame` and
+    /// `run_frame_headless` return without running anything, and audio is
+    /// paused.
     fn hold(&mut self) -> Result<(), SmsEmulationError>;
 
+    /// Undo `hold`, keeping time and starting audio from here.
+    ///
+    /// This is also what starts audio in the first place.
     fn resume(&mut self) -> Result<(), SmsEmulationError>;
+
+    /// Should VDP accesses during active display cost the Z80 extra cycles?
+    ///
+    /// Off by default. See `SmsIo16Impler::accurate_vdp_access`.
+    fn set_accurate_vdp_access(&mut self, x: bool);
+
+    /// Should a border in the backdrop color be drawn around the active
+    /// display, as a TV shows it?
+    ///
+    /// Off by default. See `SmsVdpGraphicsImpler::overscan`.
+    fn set_overscan(&mut self, x: bool);
+
+    /// Plug `device` into controller port `port`.
+    ///
+    /// Both ports have a `PortDevice::Joypad` by default.
+    fn set_port_device(&mut self, port: ControllerPort, device: PortDevice);
+
+    /// What's plugged into controller port `port`. See `set_port_device`.
+    fn port_device(&self, port: ControllerPort) -> PortDevice;
+
+    /// Replace the controller ports with an SC-3000's keyboard, read through
+    /// a PPI at IO ports 0xDC through 0xDF, with keys given by
+    /// `SmsPlayerInput::set_key_pressed`.
+    ///
+    /// Off by default. See `SmsIo16Impler::sc3000_keyboard`.
+    fn set_sc3000_keyboard(&mut self, x: bool);
+
+    fn sc3000_keyboard(&self) -> bool;
+
+    /// A light phaser aimed at `(x, y)` in the displayed part of the screen.
+    ///
+    /// With `set_overscan`, `(0, 0)` is the top left of the border, as in the
+    /// graphics. See `LightPhaserInput::in_window`.
+    fn light_phaser_at(&self, x: i32, y: i32, trigger: bool) -> LightPhaserInput;
+
+    /// Tell `watch` about every memory read and write the Z80 makes, or with
+    /// `None`, stop.
+    ///
+    /// Accesses through `memory` aren't included. With no watch installed,
+    /// memory accesses cost no more than they would otherwise.
+    fn set_memory_watch(&mut self, watch: Option<Box<dyn MemoryWatch>>);
+
+    /// Put `cheat` into effect for the Z80's memory reads of ROM. Reads of
+    /// addresses mapped to RAM aren't affected.
+    ///
+    /// Like a memory watch, this doesn't affect `memory`, and the ROM itself
+    /// isn't changed.
+    fn add_cheat(&mut self, cheat: Cheat);
+
+    /// Remove all cheats added with `add_cheat`.
+    fn clear_cheats(&mut self);
+
+    /// Watch for faults as the Z80 runs, and return them as errors from
+    /// `run_frame`, `step`, and `run_cycles`.
+    ///
+    /// The faults are the Z80 halting with interrupts disabled
+    /// (`SmsEmulationError::Halted`), reaching one of the opcodes given to
+    /// `set_illegal_opcodes` (`SmsEmulationError::IllegalOpcode`), and writes
+    /// to the Sega mapper's bank shifting and ROM write bits, which aren't
+    /// emulated (`SmsEmulationError::MapperFault`). An illegal opcode isn't
+    /// executed, so running again will report it again.
+    ///
+    /// Off by default, since it costs some speed.
+    fn set_fault_checks(&mut self, x: bool);
+
+    /// Treat each of `opcodes` as a fault when fault checks are on. See
+    /// `set_fault_checks`.
+    ///
+    /// None by default; the Z80 has no illegal opcodes of its own.
+    fn set_illegal_opcodes(&mut self, opcodes: Vec<Opcode>);
+
+    /// Keep the state at the start of each of the last `frames` frames run
+    /// with `run_frame`, so `rewind_step` can go back to them.
+    ///
+    /// This forgets any states already kept. With 0, the default, no states
+    /// are kept.
+    fn set_rewind_capacity(&mut self, frames: usize);
+
+    /// Go back to the start of the last frame, as kept by
+    /// `set_rewind_capacity`.
+    ///
+    /// Returns `false`, doing nothing, if no frames are left to rewind.
+    fn rewind_step(&mut self) -> bool;
+
+    /// Run frames as fast as possible.
+    ///
+    /// In turbo mode, `run_frame` still executes the Z80 and all the VDP's
+    /// line and interrupt logic, but doesn't draw anything, queue any audio,
+    /// or sleep to keep time. This is meant for fast forwarding, as when
+    /// seeking in a recording.
+    ///
+    /// Sprites are still evaluated, so the sprite overflow and collision
+    /// flags are set just as they would be otherwise.
+    ///
+    /// Off by default.
+    fn set_turbo(&mut self, x: bool);
+
+    /// Run at `speed` times the `Sms`'s frequency: 2.0 for double speed, 0.5
+    /// for slow motion, and so on.
+    ///
+    /// Audio is only queued at normal speed, 1.0, since at any other speed
+    /// the host would play it too slowly or too quickly to keep up. Has no
+    /// effect without a frequency.
+    ///
+    /// 1.0 by default. A speed that isn't finite and positive is rejected,
+    /// leaving the current one in place.
+    fn set_speed(&mut self, speed: f64) -> Result<(), SmsSettingError>;
+
+    /// Attach a YM2413 FM sound chip to IO ports 0xF0 through 0xF2, as in a
+    /// Japanese Master System, and mix its output with the SN76489's.
+    ///
+    /// Off by default, in which case those ports behave as on an export
+    /// console. Turning FM on or off resets the YM2413.
+    fn set_fm(&mut self, x: bool);
+
+    /// Resample audio to `rate` samples per second before passing it to the
+    /// `SimpleAudio`, or, if `rate` is `None`, pass along the SN76489's
+    /// samples at its own rate, the `Sms`'s frequency divided by its clock
+    /// divider.
+    ///
+    /// `None` by default. Like the clock divider, this should be set before
+    /// `resume`.
+    fn set_audio_rate(&mut self, rate: Option<u32>, interpolation: Interpolation);
+
+    /// Set the SN76489's clock divider. See `Sn76489State::clock_divider`.
+    ///
+    /// Since the divider determines the audio sample rate, this should be
+    /// called before `resume`. A divider of 0 is rejected, leaving the
+    /// current one in place.
+    fn set_sn76489_clock_divider(&mut self, divider: u16) -> Result<(), SmsSettingError>;
+
+    /// Silence one of the SN76489's channels. See
+    /// `Sn76489Interface::set_channel_muted`.
+    fn set_sn76489_channel_muted(&mut self, channel: u8, muted: bool);
+
+    /// Choose the SN76489's noise generator, `NoiseKind::Sms` by default.
+    ///
+    /// This is part of `SmsState`, in `Sn76489State::noise_kind`.
+    fn set_noise_kind(&mut self, kind: NoiseKind);
+
+    /// How many frames the VDP has finished since power on.
+    ///
+    /// Every frame counts, whether it was run by `run_frame`, `run_cycles`,
+    /// or `step`, and in turbo mode or not. It's part of `SmsState`, so it's
+    /// kept by saved states and rewinding.
+    fn frame_count(&self) -> u64;
+
+    /// How much of its real time budget did the last frame take to emulate?
+    ///
+    /// This is the host time `run_frame` spent emulating the frame, not
+    /// counting time spent sleeping to keep time, divided by how long the
+    /// frame should take at the `Sms`'s frequency. A value over 1.0 means the
+    /// host can't keep up.
+    ///
+    /// This is only measured when there is a frequency, turbo mode is off,
+    /// and the `host_clock` feature is on; otherwise it's 0.0.
+    fn last_frame_load(&self) -> f32;
+
+    /// A fingerprint of the most recently drawn frame, for regression tests.
+    ///
+    /// See `host_multimedia::frame_hash`. With graphics that don't keep
+    /// their pixels, like `FakeSmsGraphics`, this is always 0.
+    fn frame_hash(&self) -> u64;
+
+    /// Save the most recently drawn frame as a PNG image at `path`, at
+    /// whatever resolution it was drawn: 256 pixels wide for the Master
+    /// System, or 160x144 for the Game Gear.
+    ///
+    /// Fails with graphics that don't keep their pixels, like
+    /// `FakeSmsGraphics`.
+    fn save_screenshot(&self, path: &Path) -> Result<(), SmsVdpGraphicsError>;
+
+    /// What `sms_roms::GAME_DATABASE` knows about the cartridge, if it's
+    /// there.
+    fn cartridge_info(&self) -> Option<&'static GameInfo>;
 }
 
 impl<Graphics, Audio, Sn76489, Mem, Inx> Sms for SmsS<Graphics, Audio, Sn76489, Mem, Inx>
 where
     for<'a> SmsVdpGraphicsImpler<'a, SmsVdpState, Graphics>: SmsVdpLineImpler,
+    Graphics: SmsVdpFrame,
     Audio: SimpleAudio,
     Sn76489: Sn76489Interface + HasSn76489State,
-    for<'a> Sn76489Impler<'a, Sn76489, Audio>: Sn76489Audio,
+    for<'a> Sn76489Impler<'a, Sn76489, ResamplerImpler<'a, Audio>>: Sn76489Audio,
+    for<'a> Sn76489Impler<'a, Sn76489, Ym2413Impler<'a, Ym2413State, ResamplerImpler<'a, Audio>>>:
+        Sn76489Audio,
     Inx: Inbox<Memo = Z80Memo> + GetDebugger,
-    Mem: Memory16 + SmsMemory,
+    Mem: Memory16 + SmsMemory + SmsMemoryLoad,
 {
     fn z80(&self) -> &dyn Z80Internal {
         &self.z80
@@ -150,13 +521,73 @@ where
         &mut self.memory
     }
 
+    fn describe_address(&self, logical: u16) -> MemoryLocation {
+        self.memory.describe_address(logical)
+    }
+
+    fn save_cartridge_ram(&self) -> Option<Vec<u8>> {
+        self.memory.save_cartridge_ram()
+    }
+
+    fn load_cartridge_ram(&mut self, data: &[u8]) -> Result<(), SmsMemoryLoadError> {
+        self.memory.load_cartridge_ram(data)
+    }
+
     fn debugger(&mut self) -> Option<&mut dyn Debugger> {
         self.inbox.debugger()
     }
 
+    fn query(&mut self, query: Query) -> Option<String> {
+        let z80 = &self.z80;
+        let vdp = &self.vdp;
+        self.inbox.debugger().map(|d| d.query(query, z80, vdp))
+    }
+
     fn run_frame(&mut self, player_input: SmsPlayerInput) -> Result<(), SmsEmulationError> {
+        if self.rewind.capacity() > 0 {
+            let state = Sms::state(self);
+            self.rewind.push(state);
+        }
+        self.player_input = player_input;
+        if self.headless {
+            // as when leaving turbo mode, don't try to make up for the
+            // headless frames
+            self.headless = false;
+            self.resync();
+        }
+        run_frame(self, true)
+    }
+
+    fn run_frame_headless(
+        &mut self,
+        player_input: SmsPlayerInput,
+    ) -> Result<(), SmsEmulationError> {
+        if self.rewind.capacity() > 0 {
+            let state = Sms::state(self);
+            self.rewind.push(state);
+        }
         self.player_input = player_input;
-        run_frame(self)
+        self.headless = true;
+        run_frame(self, false)
+    }
+
+    fn step(&mut self) -> Result<u64, SmsEmulationError> {
+        vdp_catch_up(self)?;
+        with_z80_run(self, |z| z.step())
+    }
+
+    fn run_cycles(&mut self, cycles: u64) -> Result<u64, SmsEmulationError> {
+        let start_cycles = self.z80.cycles();
+        let target_cycles = start_cycles + cycles;
+        while self.z80.cycles() < target_cycles {
+            if self.inbox.holding() {
+                break;
+            }
+            vdp_catch_up(self)?;
+            let line_end_cycles = (self.vdp.cycles() * 2) / 3;
+            with_z80_run(self, |z| z.run(target_cycles.min(line_end_cycles)))?;
+        }
+        Ok(self.z80.cycles() - start_cycles)
     }
 
     fn state(&self) -> SmsState {
@@ -167,6 +598,65 @@ where
             player_input: self.player_input.clone(),
             pause_irq: self.pause_irq.clone(),
             sn76489: self.sn76489.state(),
+            frame_count: self.frame_count,
+            io: self.io,
+            ym2413: self.ym2413.clone(),
+            memory_control: self.memory_control,
+        }
+    }
+
+    fn reset(&mut self) {
+        let cycles = self.z80.cycles();
+        self.z80 = Default::default();
+        self.z80.set_cycles(cycles);
+        self.z80.set_reg16(Reg16::SP, 0xDFEE);
+        self.vdp.reset_defaults();
+        sms_memory::reset_mappings(&mut self.memory);
+        self.pause_irq = Default::default();
+        self.memory_control = if self.bios.is_some() {
+            MEMORY_CONTROL_BIOS
+        } else {
+            MEMORY_CONTROL_CARTRIDGE
+        };
+    }
+
+    fn power_cycle(&mut self) {
+        self.reset();
+        sms_memory::fill_ram(&mut self.memory, self.ram_fill);
+
+        // the VDP starts a new frame, but keeps time with the Z80
+        let mut vdp = SmsVdpState::default();
+        vdp.set_tv_system(self.vdp.tv_system());
+        vdp.set_kind(self.vdp.kind());
+        vdp.set_cycles(self.vdp.cycles());
+        self.vdp = vdp;
+
+        let settings = self.sn76489.state();
+        self.sn76489 = Sn76489::load(Sn76489State {
+            linear_feedback: settings.noise_kind.reset_value(),
+            cycles: settings.cycles,
+            clock_divider: settings.clock_divider,
+            muted: settings.muted,
+            noise_kind: settings.noise_kind,
+            ..Default::default()
+        });
+
+        self.ym2413 = Default::default();
+        self.io = Default::default();
+        self.frame_count = 0;
+    }
+
+    fn set_ram_fill(&mut self, fill: RamFill) {
+        self.ram_fill = fill;
+        self.memory.set_cartridge_ram_fill(fill);
+    }
+
+    fn set_bios(&mut self, bios: Option<Arc<Box<[u8]>>>) {
+        self.bios = bios.filter(|b| !b.is_empty());
+        // with the BIOS gone, a BIOS still mapped in would leave nothing
+        // enabled to run
+        if self.bios.is_none() && self.memory_control == MEMORY_CONTROL_BIOS {
+            self.memory_control = MEMORY_CONTROL_CARTRIDGE;
         }
     }
 
@@ -174,22 +664,34 @@ where
         if let Some(d) = self.inbox.debugger() {
             d.command(Command::Hold);
         }
-        Ok(())
+        self.time_status.holding = true;
+        self.audio.pause().map_err(SmsEmulationError::AudioError)
     }
 
     fn resume(&mut self) -> Result<(), SmsEmulationError> {
         if let Some(d) = self.inbox.debugger() {
             d.command(Command::Resume);
         }
+        self.time_status.holding = false;
 
-        self.time_status.start_time = Instant::now();
+        #[cfg(feature = "host_clock")]
+        {
+            self.time_status.start_time = Instant::now();
+        }
         self.time_status.start_cycles = self.z80.cycles();
 
         // audio
         const AUDIO_BUFFER_SIZE: u16 = 0x800;
         if let Some(frequency) = self.time_status.frequency {
-            self.audio
-                .configure(frequency as u32 / 16, AUDIO_BUFFER_SIZE)
+            let mut audio = ResamplerImpler {
+                resampler: self.resampler.as_mut(),
+                audio: &mut self.audio,
+            };
+            audio
+                .configure(
+                    frequency as u32 / self.sn76489.state().clock_divider as u32,
+                    AUDIO_BUFFER_SIZE,
+                )
                 .map_err(|s| SmsEmulationError::AudioError(s))?;
             self.audio
                 .play()
@@ -202,6 +704,170 @@ where
 
         Ok(())
     }
+
+    fn set_accurate_vdp_access(&mut self, x: bool) {
+        self.accurate_vdp_access = x;
+    }
+
+    fn set_overscan(&mut self, x: bool) {
+        self.overscan = x;
+    }
+
+    fn set_port_device(&mut self, port: ControllerPort, device: PortDevice) {
+        self.port_devices[port as usize] = device;
+    }
+
+    fn port_device(&self, port: ControllerPort) -> PortDevice {
+        self.port_devices[port as usize]
+    }
+
+    fn set_sc3000_keyboard(&mut self, x: bool) {
+        self.sc3000_keyboard = x;
+    }
+
+    fn sc3000_keyboard(&self) -> bool {
+        self.sc3000_keyboard
+    }
+
+    fn light_phaser_at(&self, x: i32, y: i32, trigger: bool) -> LightPhaserInput {
+        if self.overscan && self.vdp.kind() != Kind::Gg {
+            let x = x - OVERSCAN_WIDTH as i32;
+            let y = y - OVERSCAN_HEIGHT as i32;
+            LightPhaserInput::in_window(&self.vdp, x, y, trigger)
+        } else {
+            LightPhaserInput::in_window(&self.vdp, x, y, trigger)
+        }
+    }
+
+    fn set_memory_watch(&mut self, watch: Option<Box<dyn MemoryWatch>>) {
+        self.memory_watch = watch;
+    }
+
+    fn add_cheat(&mut self, cheat: Cheat) {
+        let i = self.cheats.partition_point(|c| c < &cheat);
+        self.cheats.insert(i, cheat);
+    }
+
+    fn clear_cheats(&mut self) {
+        self.cheats.clear();
+    }
+
+    fn set_fault_checks(&mut self, x: bool) {
+        self.fault_checks = x;
+    }
+
+    fn set_illegal_opcodes(&mut self, opcodes: Vec<Opcode>) {
+        self.illegal_opcodes = opcodes;
+    }
+
+    fn set_rewind_capacity(&mut self, frames: usize) {
+        self.rewind = RewindBuffer::new(frames);
+    }
+
+    fn rewind_step(&mut self) -> bool {
+        let state = match self.rewind.pop() {
+            Some(state) => state,
+            None => return false,
+        };
+        let rom = state.memory.rom.clone();
+        let rom_changed = !Arc::ptr_eq(&rom, &self.memory.state().rom);
+        // every state in the buffer came from `state`, so it loads
+        self.memory = Mem::load(state.memory).expect("loading a rewind state");
+        self.memory.set_cartridge_ram_fill(self.ram_fill);
+        if rom_changed {
+            self.cartridge_info = sms_roms::lookup_game(&rom);
+        }
+        self.z80 = state.z80;
+        self.vdp = state.vdp;
+        self.player_input = state.player_input;
+        self.pause_irq = state.pause_irq;
+        self.frame_count = state.frame_count;
+        self.io = state.io;
+        self.ym2413 = state.ym2413;
+        self.memory_control = state.memory_control;
+        // the muted channels aren't saved, so keep the current ones
+        let mut sn76489_state = state.sn76489;
+        sn76489_state.muted = self.sn76489.state().muted;
+        self.sn76489 = Sn76489::load(sn76489_state);
+
+        // keep time from here, rather than trying to make up for the frames
+        // we've gone back
+        #[cfg(feature = "host_clock")]
+        {
+            self.time_status.start_time = Instant::now();
+        }
+        self.time_status.start_cycles = self.z80.cycles();
+
+        true
+    }
+
+    fn set_fm(&mut self, x: bool) {
+        self.fm = x;
+        self.ym2413 = Default::default();
+    }
+
+    fn set_turbo(&mut self, x: bool) {
+        if self.turbo && !x {
+            self.resync();
+        }
+        self.turbo = x;
+    }
+
+    fn set_speed(&mut self, speed: f64) -> Result<(), SmsSettingError> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(SmsSettingError::Speed(speed));
+        }
+        if speed != self.time_status.speed {
+            self.resync();
+        }
+        self.time_status.speed = speed;
+        Ok(())
+    }
+
+    fn set_audio_rate(&mut self, rate: Option<u32>, interpolation: Interpolation) {
+        self.resampler = rate.map(|r| Resampler::new(r, interpolation));
+    }
+
+    fn set_sn76489_clock_divider(&mut self, divider: u16) -> Result<(), SmsSettingError> {
+        if divider == 0 {
+            return Err(SmsSettingError::ClockDivider(divider));
+        }
+        let mut sn76489_state = self.sn76489.state();
+        sn76489_state.clock_divider = divider;
+        sn76489_state.cycles = self.z80.cycles() / divider as u64;
+        self.sn76489 = Sn76489::load(sn76489_state);
+        Ok(())
+    }
+
+    fn set_sn76489_channel_muted(&mut self, channel: u8, muted: bool) {
+        self.sn76489.set_channel_muted(channel, muted);
+    }
+
+    fn set_noise_kind(&mut self, kind: NoiseKind) {
+        let mut sn76489_state = self.sn76489.state();
+        sn76489_state.noise_kind = kind;
+        self.sn76489 = Sn76489::load(sn76489_state);
+    }
+
+    fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    fn last_frame_load(&self) -> f32 {
+        self.last_frame_load
+    }
+
+    fn frame_hash(&self) -> u64 {
+        self.graphics.frame_hash()
+    }
+
+    fn save_screenshot(&self, path: &Path) -> Result<(), SmsVdpGraphicsError> {
+        self.graphics.save_png(path)
+    }
+
+    fn cartridge_info(&self) -> Option<&'static GameInfo> {
+        self.cartridge_info
+    }
 }
 
 #[derive(Debug)]
@@ -213,37 +879,370 @@ impl<M> Default for TypeWrap<M> {
     }
 }
 
-pub fn new_sms<Graphics: 'static, Audio: 'static, Sn76489: 'static, Memory: 'static, Inx: 'static>(
+pub fn new_sms<Graphics, Audio, Sn76489, Memory, Inx>(
     frequency: Option<u64>,
     state: SmsState,
     graphics: Graphics,
     audio: Audio,
     inbox: Inx,
-    _mem: TypeWrap<Memory>,
-    _sn76489: TypeWrap<Sn76489>,
+    mem: TypeWrap<Memory>,
+    sn76489: TypeWrap<Sn76489>,
 ) -> Result<Box<dyn Sms>, SmsCreationError>
 where
+    Graphics: 'static + SmsVdpFrame,
     for<'a> SmsVdpGraphicsImpler<'a, SmsVdpState, Graphics>: SmsVdpLineImpler,
-    Audio: SimpleAudio,
-    Sn76489: Sn76489Interface + HasSn76489State,
-    for<'a> Sn76489Impler<'a, Sn76489, Audio>: Sn76489Audio,
-    Inx: Inbox<Memo = Z80Memo> + GetDebugger,
-    Memory: SmsMemory + SmsMemoryLoad,
+    Audio: 'static + SimpleAudio,
+    Sn76489: 'static + Sn76489Interface + HasSn76489State,
+    for<'a> Sn76489Impler<'a, Sn76489, ResamplerImpler<'a, Audio>>: Sn76489Audio,
+    for<'a> Sn76489Impler<'a, Sn76489, Ym2413Impler<'a, Ym2413State, ResamplerImpler<'a, Audio>>>:
+        Sn76489Audio,
+    Inx: 'static + Inbox<Memo = Z80Memo> + GetDebugger,
+    Memory: 'static + SmsMemory + SmsMemoryLoad,
+{
+    let sms = new_sms_concrete(frequency, state, graphics, audio, inbox, mem, sn76489)?;
+    Ok(Box::new(sms))
+}
+
+/// Like `new_sms`, but the `Sms` isn't boxed. See `SmsS`.
+pub fn new_sms_concrete<Graphics, Audio, Sn76489, Memory, Inx>(
+    frequency: Option<u64>,
+    state: SmsState,
+    graphics: Graphics,
+    audio: Audio,
+    inbox: Inx,
+    _mem: TypeWrap<Memory>,
+    _sn76489: TypeWrap<Sn76489>,
+) -> Result<SmsS<Graphics, Audio, Sn76489, Memory, Inx>, SmsCreationError>
+where
+    Sn76489: HasSn76489State,
+    Memory: SmsMemoryLoad,
 {
+    if state.sn76489.clock_divider == 0 {
+        return Err(SmsSettingError::ClockDivider(0).into());
+    }
+
     let time_status = TimeStatus::new(state.z80.cycles(), frequency);
+    let cartridge_info = sms_roms::lookup_game(&state.memory.rom);
 
-    Ok(Box::new(SmsS {
+    Ok(SmsS {
         graphics,
         audio,
         inbox,
         time_status,
         player_input: state.player_input,
         pause_irq: state.pause_irq,
+        frame_count: state.frame_count,
         vdp: state.vdp,
         memory: <Memory as SmsMemoryLoad>::load(state.memory)?,
         z80: state.z80,
         sn76489: Sn76489::load(state.sn76489),
-    }))
+        accurate_vdp_access: false,
+        overscan: false,
+        ram_fill: Default::default(),
+        bios: None,
+        memory_control: state.memory_control,
+        port_devices: Default::default(),
+        sc3000_keyboard: false,
+        io: state.io,
+        fm: false,
+        ym2413: state.ym2413,
+        resampler: None,
+        memory_watch: None,
+        cheats: Vec::new(),
+        fault_checks: false,
+        illegal_opcodes: Vec::new(),
+        rewind: Default::default(),
+        turbo: false,
+        headless: false,
+        #[cfg(test)]
+        hold_waits: 0,
+        last_frame_load: 0.0,
+        cartridge_info,
+    })
+}
+
+/// Builds an `Sms`, as an alternative to `SmsState::from_rom` and `new_sms`.
+///
+/// Every setting has a default, so only the ones that matter need to be
+/// given. By default there are no graphics, audio, or debugging, the memory
+/// mapper is detected from the ROM, the TV system is NTSC, the VDP is a
+/// Master System 2's, and the frequency is the TV system's (see
+/// `z80_frequency`).
+///
+/// ```no_run
+/// # use euphrates::systems::sms::*;
+/// let sms = SmsBuilder::new()
+///     .tv_system(TvSystem::Pal)
+///     .inbox(DebuggingInbox::default())
+///     .build_from_file("game.sms")
+///     .unwrap();
+/// ```
+pub struct SmsBuilder<Graphics, Audio, Sn76489, Memory, Inx> {
+    graphics: Graphics,
+    audio: Audio,
+    inbox: Inx,
+    mapper: Option<SmsMemoryMapper>,
+    tv_system: TvSystem,
+    kind: Kind,
+    frequency: Option<Option<u64>>,
+    noise_kind: NoiseKind,
+    bios: Option<Arc<Box<[u8]>>>,
+    ram_fill: RamFill,
+    memory: TypeWrap<Memory>,
+    sn76489: TypeWrap<Sn76489>,
+}
+
+impl SmsBuilder<FakeSmsGraphics, FakeAudio, FakeSn76489, PointerSmsMemory, NothingInbox<Z80Memo>> {
+    pub fn new() -> Self {
+        SmsBuilder {
+            graphics: FakeSmsGraphics,
+            audio: FakeAudio,
+            inbox: NothingInbox::default(),
+            mapper: None,
+            tv_system: TvSystem::Ntsc,
+            kind: Kind::Sms2,
+            frequency: None,
+            noise_kind: NoiseKind::Sms,
+            bios: None,
+            ram_fill: RamFill::Zero,
+            memory: TypeWrap::default(),
+            sn76489: TypeWrap::default(),
+        }
+    }
+}
+
+impl Default
+    for SmsBuilder<FakeSmsGraphics, FakeAudio, FakeSn76489, PointerSmsMemory, NothingInbox<Z80Memo>>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Graphics, Audio, Sn76489, Memory, Inx> SmsBuilder<Graphics, Audio, Sn76489, Memory, Inx> {
+    pub fn graphics<G>(self, graphics: G) -> SmsBuilder<G, Audio, Sn76489, Memory, Inx> {
+        SmsBuilder {
+            graphics,
+            audio: self.audio,
+            inbox: self.inbox,
+            mapper: self.mapper,
+            tv_system: self.tv_system,
+            kind: self.kind,
+            frequency: self.frequency,
+            noise_kind: self.noise_kind,
+            bios: self.bios,
+            ram_fill: self.ram_fill,
+            memory: self.memory,
+            sn76489: self.sn76489,
+        }
+    }
+
+    /// Note that audio is only produced if the `Sn76489` is something
+    /// other than `FakeSn76489`; see `sn76489`.
+    pub fn audio<A>(self, audio: A) -> SmsBuilder<Graphics, A, Sn76489, Memory, Inx> {
+        SmsBuilder {
+            graphics: self.graphics,
+            audio,
+            inbox: self.inbox,
+            mapper: self.mapper,
+            tv_system: self.tv_system,
+            kind: self.kind,
+            frequency: self.frequency,
+            noise_kind: self.noise_kind,
+            bios: self.bios,
+            ram_fill: self.ram_fill,
+            memory: self.memory,
+            sn76489: self.sn76489,
+        }
+    }
+
+    /// For debugging, use a `DebuggingInbox`.
+    pub fn inbox<I>(self, inbox: I) -> SmsBuilder<Graphics, Audio, Sn76489, Memory, I> {
+        SmsBuilder {
+            graphics: self.graphics,
+            audio: self.audio,
+            inbox,
+            mapper: self.mapper,
+            tv_system: self.tv_system,
+            kind: self.kind,
+            frequency: self.frequency,
+            noise_kind: self.noise_kind,
+            bios: self.bios,
+            ram_fill: self.ram_fill,
+            memory: self.memory,
+            sn76489: self.sn76489,
+        }
+    }
+
+    pub fn memory<M>(self, memory: TypeWrap<M>) -> SmsBuilder<Graphics, Audio, Sn76489, M, Inx> {
+        SmsBuilder {
+            graphics: self.graphics,
+            audio: self.audio,
+            inbox: self.inbox,
+            mapper: self.mapper,
+            tv_system: self.tv_system,
+            kind: self.kind,
+            frequency: self.frequency,
+            noise_kind: self.noise_kind,
+            bios: self.bios,
+            ram_fill: self.ram_fill,
+            memory,
+            sn76489: self.sn76489,
+        }
+    }
+
+    pub fn sn76489<S>(self, sn76489: TypeWrap<S>) -> SmsBuilder<Graphics, Audio, S, Memory, Inx> {
+        SmsBuilder {
+            graphics: self.graphics,
+            audio: self.audio,
+            inbox: self.inbox,
+            mapper: self.mapper,
+            tv_system: self.tv_system,
+            kind: self.kind,
+            frequency: self.frequency,
+            noise_kind: self.noise_kind,
+            bios: self.bios,
+            ram_fill: self.ram_fill,
+            memory: self.memory,
+            sn76489,
+        }
+    }
+
+    /// If `None`, detect the mapper from the ROM.
+    ///
+    /// Only used by `build_from_rom` and `build_from_file`.
+    pub fn mapper(mut self, mapper: Option<SmsMemoryMapper>) -> Self {
+        self.mapper = mapper;
+        self
+    }
+
+    /// Only used by `build_from_rom` and `build_from_file`.
+    pub fn tv_system(mut self, tv_system: TvSystem) -> Self {
+        self.tv_system = tv_system;
+        self
+    }
+
+    /// Only used by `build_from_rom` and `build_from_file`.
+    pub fn kind(mut self, kind: Kind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// The Z80 frequency to emulate, or `None` to run as fast as possible.
+    pub fn frequency(mut self, frequency: Option<u64>) -> Self {
+        self.frequency = Some(frequency);
+        self
+    }
+
+    /// See `Sms::set_noise_kind`.
+    ///
+    /// Only used by `build_from_rom` and `build_from_file`.
+    pub fn noise_kind(mut self, noise_kind: NoiseKind) -> Self {
+        self.noise_kind = noise_kind;
+        self
+    }
+
+    /// See `Sms::set_bios`.
+    ///
+    /// Only used by `build_from_rom` and `build_from_file`.
+    pub fn bios(mut self, bios: Option<Arc<Box<[u8]>>>) -> Self {
+        self.bios = bios;
+        self
+    }
+
+    /// See `Sms::set_ram_fill`.
+    ///
+    /// `build_from_rom` and `build_from_file` also fill RAM this way at power
+    /// on; `build` leaves the RAM in its state alone.
+    pub fn ram_fill(mut self, fill: RamFill) -> Self {
+        self.ram_fill = fill;
+        self
+    }
+}
+
+impl<Graphics, Audio, Sn76489, Memory, Inx> SmsBuilder<Graphics, Audio, Sn76489, Memory, Inx>
+where
+    Graphics: 'static + SmsVdpFrame,
+    for<'a> SmsVdpGraphicsImpler<'a, SmsVdpState, Graphics>: SmsVdpLineImpler,
+    Audio: 'static + SimpleAudio,
+    Sn76489: 'static + Sn76489Interface + HasSn76489State,
+    for<'a> Sn76489Impler<'a, Sn76489, ResamplerImpler<'a, Audio>>: Sn76489Audio,
+    for<'a> Sn76489Impler<'a, Sn76489, Ym2413Impler<'a, Ym2413State, ResamplerImpler<'a, Audio>>>:
+        Sn76489Audio,
+    Inx: 'static + Inbox<Memo = Z80Memo> + GetDebugger,
+    Memory: 'static + SmsMemory + SmsMemoryLoad,
+{
+    /// Build an `Sms` starting from `state`.
+    ///
+    /// The mapper, TV system, kind, and noise kind settings are ignored in
+    /// favor of `state`'s, but the default frequency is that of `state`'s TV
+    /// system.
+    pub fn build(self, state: SmsState) -> Result<Box<dyn Sms>, SmsCreationError> {
+        Ok(Box::new(self.build_concrete(state)?))
+    }
+
+    /// Like `build`, but the `Sms` isn't boxed. See `SmsS`.
+    pub fn build_concrete(
+        self,
+        state: SmsState,
+    ) -> Result<SmsS<Graphics, Audio, Sn76489, Memory, Inx>, SmsCreationError> {
+        let frequency = self
+            .frequency
+            .unwrap_or_else(|| Some(z80_frequency(state.vdp.tv_system())));
+        let mut sms = new_sms_concrete(
+            frequency,
+            state,
+            self.graphics,
+            self.audio,
+            self.inbox,
+            self.memory,
+            self.sn76489,
+        )?;
+        sms.set_ram_fill(self.ram_fill);
+        Ok(sms)
+    }
+
+    /// Build a freshly powered on `Sms` with this ROM.
+    ///
+    /// If the ROM is mirrored up to a power of two number of pages (see
+    /// `SmsState::from_rom`), the inbox gets a `Z80Memo::RomMirrored`.
+    pub fn build_from_rom(self, rom: Arc<Box<[u8]>>) -> Result<Box<dyn Sms>, SmsCreationError> {
+        Ok(Box::new(self.build_concrete_from_rom(rom)?))
+    }
+
+    /// Like `build_from_rom`, but the `Sms` isn't boxed. See `SmsS`.
+    pub fn build_concrete_from_rom(
+        self,
+        rom: Arc<Box<[u8]>>,
+    ) -> Result<SmsS<Graphics, Audio, Sn76489, Memory, Inx>, SmsCreationError> {
+        let pages = rom.len() / 0x4000;
+        let mut state = SmsState::from_rom(rom, self.mapper, self.tv_system, self.kind);
+        state.memory.fill_ram(self.ram_fill);
+        state.sn76489.noise_kind = self.noise_kind;
+        let padded = state.memory.rom.len() / 0x4000;
+        let bios = self.bios.clone();
+        let mut sms = self.build_concrete(state)?;
+        if padded > pages {
+            sms.inbox.receive(Z80Memo::RomMirrored {
+                pages: pages as u16,
+                padded: padded as u16,
+            });
+        }
+        if bios.is_some() {
+            sms.set_bios(bios);
+            sms.power_cycle();
+        }
+        Ok(sms)
+    }
+
+    /// Build a freshly powered on `Sms` with the ROM in this file.
+    pub fn build_from_file<P>(self, path: P) -> Result<Box<dyn Sms>, SmsCreationError>
+    where
+        P: AsRef<Path>,
+    {
+        let rom = sms_roms::from_file(path)?;
+        self.build_from_rom(Arc::new(rom))
+    }
 }
 
 // This superfluous module with the `allow` attribute is necessary until the
@@ -254,9 +1253,21 @@ mod sms_emulation_error {
 
     #[derive(Debug, Fail)]
     pub enum SmsEmulationError {
-        // XXX
-        #[fail(display = "SomeError")]
-        SomeError,
+        /// The Z80 halted with interrupts disabled, so only an NMI can wake
+        /// it. Only reported with `Sms::set_fault_checks`.
+        #[fail(display = "Z80 halted with interrupts disabled at PC 0x{:0>4X}", pc)]
+        Halted { pc: u16 },
+
+        /// The Z80 reached an opcode given to `Sms::set_illegal_opcodes`,
+        /// without executing it. Only reported with `Sms::set_fault_checks`.
+        #[fail(display = "Illegal opcode {} at PC 0x{:0>4X}", opcode, pc)]
+        IllegalOpcode { pc: u16, opcode: Opcode },
+
+        /// The memory mapper was put in a state we can't emulate. Only
+        /// reported with `Sms::set_fault_checks`.
+        #[fail(display = "Mapper fault: {}", description)]
+        MapperFault { description: String },
+
         #[fail(display = "Audio Error {}", _0)]
         AudioError(Error),
         #[fail(display = "Graphics Error {}", _0)]
@@ -272,6 +1283,24 @@ impl From<SmsVdpGraphicsError> for SmsEmulationError {
     }
 }
 
+// This superfluous module with the `allow` attribute is necessary until the
+// `fail` crate begins using `dyn trait` syntax
+#[allow(bare_trait_objects)]
+mod sms_setting_error {
+    #[derive(Debug, Fail)]
+    pub enum SmsSettingError {
+        /// The SN76489 can't run with a clock divider of 0.
+        #[fail(display = "invalid SN76489 clock divider {}", _0)]
+        ClockDivider(u16),
+
+        /// Speeds must be finite and positive.
+        #[fail(display = "invalid speed {}", _0)]
+        Speed(f64),
+    }
+}
+
+pub use self::sms_setting_error::SmsSettingError;
+
 // This superfluous module with the `allow` attribute is necessary until the
 // `fail` crate begins using `dyn trait` syntax
 #[allow(bare_trait_objects)]
@@ -285,6 +1314,10 @@ mod sms_creation_error {
 
         #[fail(display = "memory load error: {}", _0)]
         MemoryLoadError(#[cause] SmsMemoryLoadError),
+
+        /// The state has a setting the emulator can't run with.
+        #[fail(display = "invalid state: {}", _0)]
+        SettingError(#[cause] SmsSettingError),
     }
 }
 
@@ -302,79 +1335,1592 @@ impl From<SmsMemoryLoadError> for SmsCreationError {
     }
 }
 
-fn run_frame<Graphics, Audio, Sn76489, Mem, Inx>(
+impl From<SmsSettingError> for SmsCreationError {
+    fn from(x: SmsSettingError) -> Self {
+        SmsCreationError::SettingError(x)
+    }
+}
+
+/// Run VDP lines until the VDP is ahead of the Z80.
+///
+/// The VDP is never more than a line ahead, and `run_frame` and `run_cycles`
+/// run the Z80 only as far as the end of the line just drawn before calling
+/// this again, so anything the Z80 writes to the VDP during line `k`, like a
+/// scroll register for a split screen, shows up in line `k + 1`.
+fn vdp_catch_up<Graphics, Audio, Sn76489, Mem, Inx>(
     sms: &mut SmsS<Graphics, Audio, Sn76489, Mem, Inx>,
 ) -> Result<(), SmsEmulationError>
 where
     for<'a> SmsVdpGraphicsImpler<'a, SmsVdpState, Graphics>: SmsVdpLineImpler,
-    Audio: SimpleAudio,
-    Sn76489: Sn76489Interface,
-    for<'a> Sn76489Impler<'a, Sn76489, Audio>: Sn76489Audio,
-    Inx: Inbox<Memo = Z80Memo>,
-    Mem: Memory16 + SmsMemory,
 {
-    sms.pause_irq.pause_pressed(sms.player_input.pause());
-
-    loop {
-        while 3 * sms.z80.cycles() >= 2 * sms.vdp.cycles() {
+    while 3 * sms.z80.cycles() >= 2 * sms.vdp.cycles() {
+        if sms.turbo {
+            sms_vdp::line(&mut SmsVdpGraphicsImpler {
+                graphics: &mut FakeSmsGraphics,
+                vdp: &mut sms.vdp,
+                overscan: false,
+            })?;
+        } else {
             sms_vdp::line(&mut SmsVdpGraphicsImpler {
                 graphics: &mut sms.graphics,
                 vdp: &mut sms.vdp,
+                overscan: sms.overscan,
             })?;
         }
+        latch_light_phasers(&mut sms.vdp, &sms.player_input, sms.port_devices);
+        if sms.vdp.v() == 0 {
+            sms.frame_count += 1;
+        }
+    }
+    Ok(())
+}
+
+/// An `Inbox` passing memos along to `inbox`, which, if `checks` is on,
+/// watches them for the faults described in `Sms::set_fault_checks`.
+///
+/// After a fault it holds, so the Z80 stops before the next instruction.
+struct FaultInbox<'a> {
+    inbox: &'a mut dyn Inbox<Memo = Z80Memo>,
+    checks: bool,
+    illegal_opcodes: &'a [Opcode],
+    mapper: SmsMemoryMapper,
+    fault: Option<SmsEmulationError>,
+}
+
+impl<'a> Inbox for FaultInbox<'a> {
+    type Memo = Z80Memo;
+
+    fn receive_impl(&mut self, memo: Z80Memo) {
+        if self.checks && self.fault.is_none() {
+            self.fault = match memo {
+                Z80Memo::Instruction { pc, opcode, .. }
+                    if self.illegal_opcodes.contains(&opcode) =>
+                {
+                    Some(SmsEmulationError::IllegalOpcode { pc, opcode })
+                }
+                Z80Memo::MemoryWrite {
+                    address: 0xFFFC,
+                    value,
+                } if self.mapper == SmsMemoryMapper::Sega && value & 0x83 != 0 => {
+                    Some(SmsEmulationError::MapperFault {
+                        description: format!(
+                            "wrote 0x{:0>2X} to 0xFFFC, but bank shifting and ROM writes \
+                             aren't emulated",
+                            value
+                        ),
+                    })
+                }
+                _ => None,
+            };
+        }
+        self.inbox.receive(memo);
+    }
+
+    fn active(&self) -> bool {
+        self.checks || self.inbox.active()
+    }
+
+    fn holding(&self) -> bool {
+        self.fault.is_some() || self.inbox.holding()
+    }
+}
+
+/// Hand `f` a `Z80Run` hooked up to the rest of the `Sms`.
+///
+/// With fault checks on, a fault found while `f` runs is returned instead of
+/// its result.
+fn with_z80_run<Graphics, Audio, Sn76489, Mem, Inx, F, R>(
+    sms: &mut SmsS<Graphics, Audio, Sn76489, Mem, Inx>,
+    f: F,
+) -> Result<R, SmsEmulationError>
+where
+    Sn76489: Sn76489Interface,
+    Inx: Inbox<Memo = Z80Memo>,
+    Mem: Memory16 + SmsMemory,
+    F: FnOnce(&mut dyn Z80Run) -> R,
+{
+    let mapper = sms.memory.mapper();
+    // use a trait object for this to cut down on code bloat
+    let sn76489: &mut dyn Sn76489Interface = &mut sms.sn76489;
+    let rc_vdp = Rc::new(RefCell::new(&mut sms.vdp));
+    let memory_control = Cell::new(sms.memory_control);
+    let irq = &mut SmsZ80IrqImpler {
+        pause_interrupt: &mut sms.pause_irq,
+        vdp: rc_vdp.clone(),
+    };
+    let io = &mut SmsIo16Impler {
+        vdp: rc_vdp,
+        player_input: sms.player_input,
+        sn76489,
+        io_state: &mut sms.io,
+        ym2413: if sms.fm {
+            Some(&mut sms.ym2413)
+        } else {
+            None
+        },
+        port_devices: sms.port_devices,
+        accurate_vdp_access: sms.accurate_vdp_access,
+        sc3000_keyboard: sms.sc3000_keyboard,
+        memory_control: &memory_control,
+    };
+    let controlled_memory = &mut ControlledMemory16Impler {
+        memory: &mut sms.memory,
+        bios: sms.bios.as_ref().map(|b| &b[..]),
+        memory_control: &memory_control,
+    };
+    if sms.memory_watch.is_none() && sms.cheats.is_empty() && !sms.fault_checks {
+        let result = f(&mut Z80RunImpler {
+            z80: &mut sms.z80,
+            memory: controlled_memory,
+            inbox: &mut sms.inbox,
+            irq,
+            io,
+        });
+        sms.memory_control = memory_control.get();
+        return Ok(result);
+    }
+
+    // with a watch, cheats, or fault checks, use a trait object again
+    let cheat_memory = &mut CheatMemory16Impler {
+        memory: controlled_memory,
+        cheats: &sms.cheats,
+    };
+    let mut watched_memory;
+    let memory: &mut dyn Memory16 = match sms.memory_watch {
+        Some(ref mut watch) => {
+            watched_memory = WatchedMemory16Impler {
+                memory: cheat_memory,
+                watch: &mut **watch,
+            };
+            &mut watched_memory
+        }
+        None => cheat_memory,
+    };
+    let mut inbox = FaultInbox {
+        inbox: &mut sms.inbox,
+        checks: sms.fault_checks,
+        illegal_opcodes: &sms.illegal_opcodes,
+        mapper,
+        fault: None,
+    };
+    let result = f(&mut Z80RunImpler {
+        z80: &mut sms.z80,
+        memory,
+        inbox: &mut inbox,
+        irq,
+        io,
+    });
+    sms.memory_control = memory_control.get();
+    if let Some(fault) = inbox.fault {
+        return Err(fault);
+    }
+    if sms.fault_checks && sms.z80.halted() && !sms.z80.iff1() {
+        return Err(SmsEmulationError::Halted {
+            pc: sms.z80.reg16(Reg16::PC),
+        });
+    }
+    Ok(result)
+}
+
+impl<Graphics, Audio, Sn76489, Mem, Inx> SmsS<Graphics, Audio, Sn76489, Mem, Inx>
+where
+    Sn76489: HasSn76489State,
+{
+    /// Pick up time and sound from where we are now, rather than trying to
+    /// catch up on everything we skipped or ran ahead.
+    fn resync(&mut self) {
+        #[cfg(feature = "host_clock")]
+        {
+            self.time_status.start_time = Instant::now();
+        }
+        self.time_status.start_cycles = self.z80.cycles();
+        let mut sn76489_state = self.sn76489.state();
+        sn76489_state.cycles = self.z80.cycles() / sn76489_state.clock_divider as u64;
+        self.sn76489 = Sn76489::load(sn76489_state);
+    }
+}
+
+#[cfg(feature = "host_clock")]
+fn queue_audio<Graphics, Audio, Sn76489, Mem, Inx>(
+    sms: &mut SmsS<Graphics, Audio, Sn76489, Mem, Inx>,
+) -> Result<(), SmsEmulationError>
+where
+    Audio: SimpleAudio,
+    Sn76489: HasSn76489State,
+    for<'a> Sn76489Impler<'a, Sn76489, ResamplerImpler<'a, Audio>>: Sn76489Audio,
+    for<'a> Sn76489Impler<'a, Sn76489, Ym2413Impler<'a, Ym2413State, ResamplerImpler<'a, Audio>>>:
+        Sn76489Audio,
+{
+    let mut audio = ResamplerImpler {
+        resampler: sms.resampler.as_mut(),
+        audio: &mut sms.audio,
+    };
+    if sms.fm {
+        let clock_divider = sms.sn76489.state().clock_divider;
+        Sn76489Impler {
+            sn76489: &mut sms.sn76489,
+            audio: &mut Ym2413Impler {
+                ym2413: &mut sms.ym2413,
+                audio: &mut audio,
+                clock_divider,
+            },
+        }.queue(sms.z80.cycles())
+    } else {
+        Sn76489Impler {
+            sn76489: &mut sms.sn76489,
+            audio: &mut audio,
+        }.queue(sms.z80.cycles())
+    }.map_err(SmsEmulationError::AudioError)
+}
+
+/// Sleep a little, so that a caller running synced frames in a loop doesn't
+/// spin while `sms` or its inbox is holding.
+#[cfg_attr(not(test), allow(unused_variables))]
+fn wait_while_holding<Graphics, Audio, Sn76489, Mem, Inx>(
+    sms: &mut SmsS<Graphics, Audio, Sn76489, Mem, Inx>,
+) {
+    #[cfg(test)]
+    {
+        sms.hold_waits += 1;
+    }
+    #[cfg(feature = "host_clock")]
+    std::thread::sleep(Duration::from_millis(10));
+}
+
+/// Run a frame, queueing audio and sleeping to keep time only if `synced`.
+fn run_frame<Graphics, Audio, Sn76489, Mem, Inx>(
+    sms: &mut SmsS<Graphics, Audio, Sn76489, Mem, Inx>,
+    synced: bool,
+) -> Result<(), SmsEmulationError>
+where
+    for<'a> SmsVdpGraphicsImpler<'a, SmsVdpState, Graphics>: SmsVdpLineImpler,
+    Audio: SimpleAudio,
+    Sn76489: Sn76489Interface + HasSn76489State,
+    for<'a> Sn76489Impler<'a, Sn76489, ResamplerImpler<'a, Audio>>: Sn76489Audio,
+    for<'a> Sn76489Impler<'a, Sn76489, Ym2413Impler<'a, Ym2413State, ResamplerImpler<'a, Audio>>>:
+        Sn76489Audio,
+    Inx: Inbox<Memo = Z80Memo>,
+    Mem: Memory16 + SmsMemory,
+{
+    // without the host's clock, there's no keeping time
+    let synced = synced && cfg!(feature = "host_clock");
+
+    if sms.time_status.holding {
+        if synced {
+            wait_while_holding(sms);
+        }
+        return Ok(());
+    }
+
+    // The pause button is wired to the Z80's NMI line, except on the Game
+    // Gear, where it's the Start button and is read from IO port 0x00.
+    if sms.vdp.kind() != Kind::Gg {
+        sms.pause_irq.pause_pressed(sms.player_input.pause());
+    }
+
+    #[cfg(feature = "host_clock")]
+    let (frame_start_time, frame_start_cycles) = (Instant::now(), sms.z80.cycles());
+
+    loop {
+        vdp_catch_up(sms)?;
         let z80_target_cycles = (sms.vdp.cycles() * 2) / 3;
         while sms.z80.cycles() < z80_target_cycles {
             if sms.inbox.holding() {
-                use std::thread;
-                use std::time::Duration;
-                thread::sleep(Duration::from_millis(10));
+                if synced {
+                    wait_while_holding(sms);
+                }
                 return Ok(());
             }
 
-            // use a trait object for this to cut down on code bloat
-            let sn76489: &mut dyn Sn76489Interface = &mut sms.sn76489;
-            let rc_vdp = Rc::new(RefCell::new(&mut sms.vdp));
-            let irq = &mut SmsZ80IrqImpler {
-                pause_interrupt: &mut sms.pause_irq,
-                vdp: rc_vdp.clone(),
-            };
-            let io = &mut SmsIo16Impler {
-                vdp: rc_vdp,
-                player_input: sms.player_input,
-                sn76489,
-            };
-            Z80RunImpler {
-                z80: &mut sms.z80,
-                memory: &mut sms.memory,
-                inbox: &mut sms.inbox,
-                irq,
-                io,
-            }.run(z80_target_cycles);
+            with_z80_run(sms, |z| z.run(z80_target_cycles))?;
         }
         if sms.vdp.v() == 0 {
             // we've just finished a frame
 
-            let time_status = sms.time_status;
-
-            if let Some(f) = time_status.frequency {
-                // Sound
-                let sound_target_cycles = sms.z80.cycles() / 16;
-                Sn76489Impler {
-                    sn76489: &mut sms.sn76489,
-                    audio: &mut sms.audio,
-                }.queue(sound_target_cycles)
-                    .map_err(|s| SmsEmulationError::AudioError(s))?;
-
-                // sleep to sync time
-                utilities::time_govern2(
-                    time_status.start_time,
-                    time_status.start_cycles,
-                    z80_target_cycles,
-                    f,
-                );
+            if sms.turbo || !synced {
+                sms.last_frame_load = 0.0;
+                return Ok(());
+            }
+
+            #[cfg(feature = "host_clock")]
+            {
+                keep_time(sms, frame_start_time, frame_start_cycles, z80_target_cycles)?;
             }
 
             return Ok(());
         }
     }
 }
+
+/// At the end of a synced frame, queue its audio and sleep until it's time
+/// for the next one.
+#[cfg(feature = "host_clock")]
+fn keep_time<Graphics, Audio, Sn76489, Mem, Inx>(
+    sms: &mut SmsS<Graphics, Audio, Sn76489, Mem, Inx>,
+    frame_start_time: Instant,
+    frame_start_cycles: u64,
+    z80_target_cycles: u64,
+) -> Result<(), SmsEmulationError>
+where
+    Audio: SimpleAudio,
+    Sn76489: HasSn76489State,
+    for<'a> Sn76489Impler<'a, Sn76489, ResamplerImpler<'a, Audio>>: Sn76489Audio,
+    for<'a> Sn76489Impler<'a, Sn76489, Ym2413Impler<'a, Ym2413State, ResamplerImpler<'a, Audio>>>:
+        Sn76489Audio,
+{
+    let time_status = sms.time_status;
+
+    if let Some(f) = time_status.effective_frequency() {
+        // Sound
+        if time_status.speed == 1.0 {
+            queue_audio(sms)?;
+        }
+
+        sms.last_frame_load = frame_load(
+            frame_start_time.elapsed(),
+            sms.z80.cycles() - frame_start_cycles,
+            f,
+        );
+
+        // sleep to sync time
+        utilities::time_govern2(
+            time_status.start_time,
+            time_status.start_cycles,
+            z80_target_cycles,
+            f,
+        );
+    }
+
+    Ok(())
+}
+
+/// How much of the real time budget for `cycles` Z80 cycles at `frequency` did
+/// taking `elapsed` to emulate them use? See `Sms::last_frame_load`.
+#[cfg(feature = "host_clock")]
+fn frame_load(elapsed: Duration, cycles: u64, frequency: u64) -> f32 {
+    let elapsed_seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+    let budget_seconds = cycles as f64 / frequency as f64;
+    (elapsed_seconds / budget_seconds) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use host_multimedia::{self, SimpleColor, SimpleGraphics};
+    use save;
+
+    /// Graphics that just count how often they're used.
+    struct CountingGraphics(Rc<Cell<usize>>);
+
+    impl SimpleGraphics for CountingGraphics {
+        fn set_resolution(&mut self, _width: u32, _height: u32) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn resolution(&self) -> (u32, u32) {
+            (256, 192)
+        }
+
+        fn paint(&mut self, _x: u32, _y: u32, _color: SimpleColor) {
+            self.0.set(self.0.get() + 1);
+        }
+
+        fn get(&self, _x: u32, _y: u32) -> SimpleColor {
+            Default::default()
+        }
+
+        fn render(&mut self) -> Result<(), Error> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+    }
+
+    /// An inbox that's always holding, as at a breakpoint.
+    #[derive(Default)]
+    struct HoldingInbox;
+
+    impl Inbox for HoldingInbox {
+        type Memo = Z80Memo;
+
+        fn receive_impl(&mut self, _memo: Z80Memo) {}
+
+        fn holding(&self) -> bool {
+            true
+        }
+    }
+
+    impl GetDebugger for HoldingInbox {
+        fn debugger(&mut self) -> Option<&mut dyn Debugger> {
+            None
+        }
+    }
+
+    /// An inbox that keeps every memo it gets.
+    #[derive(Default)]
+    struct RecordingInbox(Vec<Z80Memo>);
+
+    impl Inbox for RecordingInbox {
+        type Memo = Z80Memo;
+
+        fn receive_impl(&mut self, memo: Z80Memo) {
+            self.0.push(memo);
+        }
+    }
+
+    impl GetDebugger for RecordingInbox {
+        fn debugger(&mut self) -> Option<&mut dyn Debugger> {
+            None
+        }
+    }
+
+    /// A program that turns on the display and frame interrupts, then keeps
+    /// storing the V counter at 0xC000 while counting interrupts at 0xC001.
+    fn program_state() -> SmsState {
+        let main = [
+            0xF3, // di
+            0xED, 0x56, // im 1
+            0x3E, 0x04, 0xD3, 0xBF, 0x3E, 0x80, 0xD3, 0xBF, // vdp register 0 = 0x04
+            0x3E, 0x60, 0xD3, 0xBF, 0x3E, 0x81, 0xD3, 0xBF, // vdp register 1 = 0x60
+            0xFB, // ei
+            0xDB, 0x7E, // in a, (0x7E)
+            0x32, 0x00, 0xC0, // ld (0xC000), a
+            0x18, 0xF9, // jr -7
+        ];
+        let handler = [
+            0xF5, // push af
+            0xDB, 0xBF, // in a, (0xBF)
+            0x3A, 0x01, 0xC0, // ld a, (0xC001)
+            0x3C, // inc a
+            0x32, 0x01, 0xC0, // ld (0xC001), a
+            0xF1, // pop af
+            0xFB, // ei
+            0xED, 0x4D, // reti
+        ];
+        let mut program = vec![0u8; 0x38];
+        program[..main.len()].copy_from_slice(&main);
+        program.extend_from_slice(&handler);
+        state_with_program(&program)
+    }
+
+    /// A Sega mapped NTSC SMS 2 state with `program` at the start of an
+    /// otherwise zeroed 32 KiB ROM.
+    fn state_with_program(program: &[u8]) -> SmsState {
+        let mut rom = vec![0u8; 0x8000];
+        rom[..program.len()].copy_from_slice(program);
+        SmsState::from_rom(
+            Arc::new(rom.into_boxed_slice()),
+            Some(SmsMemoryMapper::Sega),
+            TvSystem::Ntsc,
+            Kind::Sms2,
+        )
+    }
+
+    fn run(turbo: bool, frames: usize) -> (SmsState, usize) {
+        let count = Rc::new(Cell::new(0));
+        let mut sms = SmsBuilder::new()
+            .frequency(None)
+            .graphics(CountingGraphics(count.clone()))
+            .build(program_state())
+            .unwrap();
+        sms.set_turbo(turbo);
+        for _ in 0..frames {
+            sms.run_frame(Default::default()).unwrap();
+        }
+        (sms.state(), count.get())
+    }
+
+    #[test]
+    fn reset() {
+        let mut sms = SmsBuilder::new().build(program_state()).unwrap();
+        for _ in 0..3 {
+            sms.run_frame_headless(Default::default()).unwrap();
+        }
+        // cartridge RAM in slot 2
+        sms.memory().write(0xFFFC, 0x08);
+        sms.memory().write(0x8000, 0x42);
+        assert_eq!(
+            sms.describe_address(0x8000),
+            MemoryLocation::MainCartridgeRam(0)
+        );
+        let cycles = sms.z80().cycles();
+        let interrupts = sms.memory().read(0xC001);
+        assert!(interrupts >= 2);
+        assert!(sms.z80().iff1());
+        assert_eq!(sms.state().vdp.reg[1], 0x60);
+
+        sms.reset();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0);
+        assert_eq!(sms.z80().reg16(Reg16::SP), 0xDFEE);
+        assert!(!sms.z80().iff1());
+        assert!(!sms.z80().iff2());
+        assert_eq!(sms.z80().cycles(), cycles);
+        assert_eq!(sms.state().vdp.reg[1], 0xA0);
+        assert_eq!(
+            sms.describe_address(0x8000),
+            MemoryLocation::Rom { page: 0, offset: 0 }
+        );
+        assert_eq!(sms.memory().read(0xC001), interrupts);
+        assert_eq!(sms.save_cartridge_ram().unwrap()[0], 0x42);
+
+        // and the program starts over
+        sms.run_frame_headless(Default::default()).unwrap();
+        assert_eq!(sms.state().vdp.reg[1], 0x60);
+        assert!(sms.memory().read(0xC001) > interrupts);
+    }
+
+    #[test]
+    fn power_cycle() {
+        let mut sms = SmsBuilder::new().build(program_state()).unwrap();
+        for _ in 0..3 {
+            sms.run_frame_headless(Default::default()).unwrap();
+        }
+        sms.memory().write(0xFFFC, 0x08);
+        sms.memory().write(0x8000, 0x42);
+        let interrupts = sms.memory().read(0xC001);
+        assert!(interrupts >= 2);
+
+        sms.reset();
+        assert_eq!(sms.memory().read(0xC001), interrupts);
+        assert_eq!(sms.frame_count(), 3);
+
+        sms.power_cycle();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0);
+        assert_eq!(sms.z80().reg16(Reg16::SP), 0xDFEE);
+        assert_eq!(sms.memory().read(0xC001), 0);
+        assert!(sms.state().memory.system_ram.iter().all(|&x| x == 0));
+        assert!(sms.save_cartridge_ram().unwrap().iter().all(|&x| x == 0));
+        assert_eq!(sms.frame_count(), 0);
+
+        // and it boots again
+        sms.run_frame_headless(Default::default()).unwrap();
+        assert_eq!(sms.state().vdp.reg[1], 0x60);
+        assert_eq!(sms.frame_count(), 1);
+
+        sms.set_ram_fill(RamFill::Ones);
+        sms.power_cycle();
+        assert!(sms.state().memory.system_ram.iter().all(|&x| x == 0xFF));
+        assert!(sms.save_cartridge_ram().unwrap().iter().all(|&x| x == 0xFF));
+    }
+
+    #[test]
+    fn builder_ram_fill() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[..2].copy_from_slice(&[0x18, 0xFE]); // jr -2
+        let rom = Arc::new(rom.into_boxed_slice());
+
+        let mut ones = SmsBuilder::new()
+            .ram_fill(RamFill::Ones)
+            .build_concrete_from_rom(rom.clone())
+            .unwrap();
+        let mut pattern = SmsBuilder::new()
+            .ram_fill(RamFill::Pattern(0xA5))
+            .build_concrete_from_rom(rom)
+            .unwrap();
+
+        assert!(ones.state().memory.system_ram.iter().all(|&x| x == 0xFF));
+        assert!(pattern.state().memory.system_ram.iter().all(|&x| x == 0xA5));
+
+        // cartridge RAM isn't allocated until it's mapped in
+        ones.run_frame_headless(Default::default()).unwrap();
+        pattern.run_frame_headless(Default::default()).unwrap();
+        ones.memory().write(0xFFFC, 0x08);
+        pattern.memory().write(0xFFFC, 0x08);
+        assert_eq!(ones.memory().read(0x8000), 0xFF);
+        assert_eq!(pattern.memory().read(0xBFFF), 0xA5);
+        let ones_ram = ones.save_cartridge_ram().unwrap();
+        let pattern_ram = pattern.save_cartridge_ram().unwrap();
+        assert!(ones_ram.iter().all(|&x| x == 0xFF));
+        assert!(pattern_ram.iter().all(|&x| x == 0xA5));
+    }
+
+    #[test]
+    fn bios() {
+        let mut bios = vec![0u8; 0x2000];
+        let bios_program = [
+            0x3E, 0x42, // ld a, 0x42
+            0x32, 0x00, 0xC0, // ld (0xC000), a
+            0x3E, 0xA8, // ld a, 0xA8
+            0xD3, 0x3E, // out (0x3E), a: disable the BIOS
+        ];
+        bios[..bios_program.len()].copy_from_slice(&bios_program);
+        let mut rom = vec![0u8; 0x8000];
+        let program = [
+            0x3E, 0x99, // ld a, 0x99
+            0x32, 0x02, 0xC0, // ld (0xC002), a
+            0x18, 0xFE, // jr -2
+        ];
+        rom[..program.len()].copy_from_slice(&program);
+        // where the BIOS hands off
+        let handoff = [
+            0x3E, 0x17, // ld a, 0x17
+            0x32, 0x01, 0xC0, // ld (0xC001), a
+            0x18, 0xFE, // jr -2
+        ];
+        rom[9..9 + handoff.len()].copy_from_slice(&handoff);
+        let rom = Arc::new(rom.into_boxed_slice());
+
+        let mut sms = SmsBuilder::new()
+            .bios(Some(Arc::new(bios.clone().into_boxed_slice())))
+            .build_concrete_from_rom(rom.clone())
+            .unwrap();
+        assert_eq!(sms.memory_control, MEMORY_CONTROL_BIOS);
+        for _ in 0..3 {
+            sms.step().unwrap();
+        }
+        assert_eq!(sms.memory().read(0xC000), 0x42);
+        assert_eq!(sms.z80().reg16(Reg16::PC), 7);
+
+        // a state saved in the BIOS is still in the BIOS
+        let state = sms.state();
+        assert_eq!(state.memory_control, MEMORY_CONTROL_BIOS);
+        let mut sms2 = SmsBuilder::new().build_concrete(state).unwrap();
+        assert_eq!(sms2.memory_control, MEMORY_CONTROL_BIOS);
+        sms2.set_bios(Some(Arc::new(bios.clone().into_boxed_slice())));
+        sms2.step().unwrap();
+        assert_eq!(sms2.memory_control, 0xA8);
+
+        sms.step().unwrap();
+        assert_eq!(sms.memory_control, 0xA8);
+        for _ in 0..3 {
+            sms.step().unwrap();
+        }
+        assert_eq!(sms.memory().read(0xC001), 0x17);
+        assert_eq!(sms.memory().read(0xC002), 0);
+
+        // a reset goes back through the BIOS
+        sms.memory().write(0xC000, 0);
+        sms.reset();
+        assert_eq!(sms.memory_control, MEMORY_CONTROL_BIOS);
+        for _ in 0..2 {
+            sms.step().unwrap();
+        }
+        assert_eq!(sms.memory().read(0xC000), 0x42);
+
+        // removing the BIOS while it's mapped in maps the cartridge
+        sms.set_bios(None);
+        assert_eq!(sms.memory_control, MEMORY_CONTROL_CARTRIDGE);
+
+        // without a BIOS, the cartridge starts at 0
+        let mut sms = SmsBuilder::new().build_concrete_from_rom(rom).unwrap();
+        assert_eq!(sms.memory_control, MEMORY_CONTROL_CARTRIDGE);
+        for _ in 0..2 {
+            sms.step().unwrap();
+        }
+        assert_eq!(sms.memory().read(0xC000), 0);
+        assert_eq!(sms.memory().read(0xC002), 0x99);
+    }
+
+    #[test]
+    fn frame_count() {
+        let mut sms = SmsBuilder::new().build(program_state()).unwrap();
+        assert_eq!(sms.frame_count(), 0);
+        for _ in 0..5 {
+            sms.run_frame_headless(Default::default()).unwrap();
+        }
+        assert_eq!(sms.frame_count(), 5);
+
+        // frames finished by `run_cycles` count too: 262 NTSC lines of 228
+        // Z80 cycles each
+        let cycles = 3 * 228 * 262;
+        sms.run_cycles(cycles).unwrap();
+        assert_eq!(sms.frame_count(), 8);
+
+        let mut bytes = Vec::new();
+        save::write_state(&sms.state(), &mut bytes).unwrap();
+        let state: SmsState = save::read_state(&bytes[..]).unwrap();
+        assert_eq!(state.frame_count, 8);
+        let mut sms = SmsBuilder::new().build(state).unwrap();
+        assert_eq!(sms.frame_count(), 8);
+        sms.run_frame_headless(Default::default()).unwrap();
+        assert_eq!(sms.frame_count(), 9);
+    }
+
+    #[test]
+    fn io_state_saved() {
+        let mut state = program_state();
+        state.io.th_output = [Some(false), None];
+        state.io.ppi_port_c = 0x02;
+
+        let mut bytes = Vec::new();
+        save::write_state(&state, &mut bytes).unwrap();
+        let state: SmsState = save::read_state(&bytes[..]).unwrap();
+        let sms = SmsBuilder::new().build(state).unwrap();
+        assert_eq!(sms.state().io.th_output, [Some(false), None]);
+        assert_eq!(sms.state().io.ppi_port_c, 0x02);
+    }
+
+    #[test]
+    fn memory_state_saved() {
+        let mut state = program_state();
+        Arc::make_mut(&mut state.memory.rom)[0x4800] = 0x42;
+        state.memory.fix_first_slot();
+        state.memory.write(0xFFFD, 1);
+
+        let mut bytes = Vec::new();
+        save::write_state(&state, &mut bytes).unwrap();
+        let mut state2: SmsState = save::read_state(&bytes[..]).unwrap();
+        // read straight from the state, without `SmsMemoryLoad::load`
+        assert_eq!(state2.memory.read(0x0800), 0x42);
+        for address in 0..0x4000 {
+            assert_eq!(state2.memory.read(address), state.memory.read(address));
+        }
+    }
+
+    #[test]
+    fn ym2413_state_saved() {
+        let mut state = program_state();
+        state.ym2413.write_address(0x30);
+        state.ym2413.write_data(0x15);
+        // key on channel 0, and run long enough for its envelope to move
+        state.ym2413.write_address(0x20);
+        state.ym2413.write_data(0x1B);
+        for _ in 0..10 {
+            state.ym2413.advance(Z80_CYCLES_PER_SAMPLE);
+        }
+        let ym2413 = state.ym2413.clone();
+
+        let mut bytes = Vec::new();
+        save::write_state(&state, &mut bytes).unwrap();
+        let state: SmsState = save::read_state(&bytes[..]).unwrap();
+        let sms = SmsBuilder::new().build(state).unwrap();
+        assert!(sms.state().ym2413 == ym2413);
+    }
+
+    #[test]
+    #[cfg(feature = "host_clock")]
+    fn last_frame_load() {
+        // di; halt
+        // at this frequency each frame has a budget of about a quarter second
+        let mut sms = SmsBuilder::new()
+            .frequency(Some(240000))
+            .build(state_with_program(&[0xF3, 0x76]))
+            .unwrap();
+        assert_eq!(sms.last_frame_load(), 0.0);
+        sms.run_frame(Default::default()).unwrap();
+        // how long the frame took depends on the host, so only check that
+        // something sensible was measured
+        let load = sms.last_frame_load();
+        assert!(load >= 0.0 && load.is_finite());
+
+        // headless frames aren't measured
+        sms.run_frame_headless(Default::default()).unwrap();
+        assert_eq!(sms.last_frame_load(), 0.0);
+
+        // a quarter second spent on a half second budget
+        let load = frame_load(Duration::from_millis(250), 120000, 240000);
+        assert_eq!(load, 0.5);
+        let load = frame_load(Duration::from_millis(750), 120000, 240000);
+        assert_eq!(load, 1.5);
+    }
+
+    #[test]
+    fn headless() {
+        let mut states = Vec::new();
+        let mut counts = Vec::new();
+        for &headless in &[false, true] {
+            let count = Rc::new(Cell::new(0));
+            let mut sms = SmsBuilder::new()
+                .graphics(CountingGraphics(count.clone()))
+                .build(program_state())
+                .unwrap();
+            for _ in 0..5 {
+                if headless {
+                    sms.run_frame_headless(Default::default()).unwrap();
+                } else {
+                    sms.run_frame(Default::default()).unwrap();
+                }
+            }
+            states.push(sms.state());
+            counts.push(count.get());
+        }
+        assert!(states[0] == states[1]);
+        assert!(counts[0] > 0);
+        assert_eq!(counts[0], counts[1]);
+
+        // back to synced frames, time is kept from there
+        let mut sms = SmsBuilder::new()
+            .frequency(Some(NTSC_Z80_FREQUENCY))
+            .build_concrete(program_state())
+            .unwrap();
+        for _ in 0..5 {
+            sms.run_frame_headless(Default::default()).unwrap();
+        }
+        let cycles = sms.z80.cycles();
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.time_status.start_cycles, cycles);
+
+        // a headless frame doesn't wait out a holding inbox
+        let mut sms = SmsBuilder::new()
+            .inbox(HoldingInbox)
+            .build_concrete(program_state())
+            .unwrap();
+        for _ in 0..20 {
+            sms.run_frame_headless(Default::default()).unwrap();
+        }
+        assert_eq!(sms.z80.cycles(), 0);
+        assert_eq!(sms.hold_waits, 0);
+        // but a synced one does, if there's a clock to keep time by
+        sms.run_frame(Default::default()).unwrap();
+        let expected = if cfg!(feature = "host_clock") { 1 } else { 0 };
+        assert_eq!(sms.hold_waits, expected);
+    }
+
+    #[test]
+    fn run_cycles() {
+        let new = || SmsBuilder::new().build(program_state()).unwrap();
+        // enough cycles for several lines and a frame interrupt
+        for &cycles in &[1000, 100_000] {
+            let mut sms = new();
+            let ran = sms.run_cycles(cycles).unwrap();
+            assert_eq!(sms.z80().cycles(), ran);
+            // no instruction here takes more than 23 cycles
+            assert!(cycles <= ran && ran < cycles + 23);
+
+            // stepping instruction by instruction stops in the same place
+            let mut stepped = new();
+            while stepped.z80().cycles() < cycles {
+                stepped.step().unwrap();
+            }
+            assert!(stepped.state() == sms.state());
+        }
+
+        // a holding inbox stops it, rather than leaving it spinning
+        let mut sms = SmsBuilder::new()
+            .inbox(HoldingInbox)
+            .build(program_state())
+            .unwrap();
+        assert_eq!(sms.run_cycles(1000).unwrap(), 0);
+    }
+
+    #[test]
+    fn hold() {
+        let mut sms = SmsBuilder::new().build(program_state()).unwrap();
+        sms.run_frame_headless(Default::default()).unwrap();
+        let cycles = sms.z80().cycles();
+        let vdp_cycles = sms.state().vdp.cycles;
+
+        sms.hold().unwrap();
+        for _ in 0..3 {
+            sms.run_frame(Default::default()).unwrap();
+            sms.run_frame_headless(Default::default()).unwrap();
+        }
+        assert_eq!(sms.z80().cycles(), cycles);
+        assert_eq!(sms.state().vdp.cycles, vdp_cycles);
+
+        sms.resume().unwrap();
+        sms.run_frame_headless(Default::default()).unwrap();
+        assert!(sms.z80().cycles() > cycles);
+    }
+
+    #[test]
+    fn speed() {
+        let mut time_status = TimeStatus::new(1000, Some(NTSC_Z80_FREQUENCY));
+        let cycles = 1000 + NTSC_Z80_FREQUENCY;
+        let normal = time_status.target_duration(cycles).unwrap();
+        assert_eq!(normal, Duration::from_secs(1));
+        time_status.speed = 2.0;
+        let double = time_status.target_duration(cycles).unwrap();
+        assert_eq!(double, Duration::from_millis(500));
+
+        assert_eq!(TimeStatus::new(0, None).target_duration(100), None);
+
+        let mut sms = SmsBuilder::new().build_concrete(program_state()).unwrap();
+        sms.set_speed(2.0).unwrap();
+        for &speed in [0.0, -1.0, f64::NAN, f64::INFINITY].iter() {
+            assert!(sms.set_speed(speed).is_err());
+        }
+        assert_eq!(sms.time_status.speed, 2.0);
+    }
+
+    #[test]
+    fn clock_divider() {
+        let mut sms = SmsBuilder::new()
+            .sn76489(TypeWrap::<Sn76489State>::default())
+            .build(program_state())
+            .unwrap();
+        sms.set_sn76489_clock_divider(32).unwrap();
+        assert_eq!(sms.state().sn76489.clock_divider, 32);
+        assert!(sms.set_sn76489_clock_divider(0).is_err());
+        assert_eq!(sms.state().sn76489.clock_divider, 32);
+
+        let mut state = program_state();
+        state.sn76489.clock_divider = 0;
+        match SmsBuilder::new().build(state) {
+            Err(SmsCreationError::SettingError(SmsSettingError::ClockDivider(0))) => {}
+            Err(e) => panic!("expected a clock divider error, got {}", e),
+            Ok(_) => panic!("built an Sms with a clock divider of 0"),
+        }
+    }
+
+    #[test]
+    fn turbo() {
+        let (normal_state, normal_count) = run(false, 10);
+        let (turbo_state, turbo_count) = run(true, 10);
+        assert!(normal_count > 0);
+        assert_eq!(turbo_count, 0);
+        assert!(normal_state == turbo_state);
+
+        // make sure the program actually did something
+        let mut memory = PointerSmsMemory::load(turbo_state.memory).unwrap();
+        assert!(memory.read(0xC001) >= 9);
+    }
+
+    #[test]
+    fn turbo_sprites() {
+        // nine opaque sprites on top of each other, which both collide and
+        // overflow
+        let mut state = state_with_program(&[
+            0xF3, // di
+            0x18, 0xFE, // jr -2
+        ]);
+        {
+            let vdp = &mut state.vdp;
+            vdp.set_register(0, 0x04); // mode 4
+            vdp.set_register(1, 0x40); // display on
+            vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+            vdp.set_register(6, 0xFB); // sprite patterns at 0
+            for row in 0..8 {
+                vdp.set_vram(4 * row, 0xFF);
+            }
+            for i in 0..9 {
+                vdp.set_vram(0x3F00 + i, 50);
+                vdp.set_vram(0x3F80 + 2 * i, 10);
+            }
+            vdp.set_vram(0x3F09, 0xD0);
+        }
+
+        let run = |turbo: bool| {
+            let mut sms = SmsBuilder::new()
+                .frequency(None)
+                .graphics(PixelGraphics::default())
+                .build(state.clone())
+                .unwrap();
+            sms.set_turbo(turbo);
+            for _ in 0..2 {
+                sms.run_frame(Default::default()).unwrap();
+            }
+            sms.state()
+        };
+
+        let normal = run(false);
+        let turbo = run(true);
+        let flags = SPRITE_OVERFLOW_FLAG | SPRITE_COLLISION_FLAG;
+        assert_eq!(normal.vdp.status_flags() & flags, flags);
+        assert!(normal == turbo);
+    }
+
+    /// Graphics that keep the pixels they're given.
+    #[derive(Default)]
+    struct PixelGraphics {
+        width: u32,
+        height: u32,
+        pixels: Vec<SimpleColor>,
+    }
+
+    impl SimpleGraphics for PixelGraphics {
+        fn set_resolution(&mut self, width: u32, height: u32) -> Result<(), Error> {
+            self.width = width;
+            self.height = height;
+            self.pixels.resize((width * height) as usize, Default::default());
+            Ok(())
+        }
+
+        fn resolution(&self) -> (u32, u32) {
+            (self.width, self.height)
+        }
+
+        fn paint(&mut self, x: u32, y: u32, color: SimpleColor) {
+            self.pixels[(self.width * y + x) as usize] = color;
+        }
+
+        fn get(&self, x: u32, y: u32) -> SimpleColor {
+            self.pixels[(self.width * y + x) as usize]
+        }
+
+        fn render(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    /// Run a program that draws blue and white stripes, returning the hash
+    /// of each frame.
+    fn stripes_hashes(frames: usize) -> Vec<u64> {
+        let program = [
+            0xF3, // di
+            0x3E, 0x04, 0xD3, 0xBF, 0x3E, 0x80, 0xD3, 0xBF, // vdp register 0 = 0x04
+            0x3E, 0xFF, 0xD3, 0xBF, 0x3E, 0x82, 0xD3, 0xBF, // vdp register 2 = 0xFF
+            0x3E, 0xFF, 0xD3, 0xBF, 0x3E, 0x85, 0xD3, 0xBF, // vdp register 5 = 0xFF
+            // no sprites: 0xD0 at VRAM address 0x3F00
+            0x3E, 0x00, 0xD3, 0xBF, 0x3E, 0x7F, 0xD3, 0xBF, 0x3E, 0xD0, 0xD3, 0xBE,
+            // the first line of pattern 0 is 0xF0 in its first bitplane
+            0x3E, 0x00, 0xD3, 0xBF, 0x3E, 0x40, 0xD3, 0xBF, 0x3E, 0xF0, 0xD3, 0xBE,
+            // CRAM colors 0 and 1 are white and blue
+            0x3E, 0x00, 0xD3, 0xBF, 0x3E, 0xC0, 0xD3, 0xBF, 0x3E, 0x3F, 0xD3, 0xBE, 0x3E,
+            0x30, 0xD3, 0xBE,
+            0x3E, 0x40, 0xD3, 0xBF, 0x3E, 0x81, 0xD3, 0xBF, // vdp register 1 = 0x40
+            0x18, 0xFE, // jr -2
+        ];
+        let mut sms = SmsBuilder::new()
+            .frequency(None)
+            .graphics(PixelGraphics::default())
+            .build(state_with_program(&program))
+            .unwrap();
+        (0..frames)
+            .map(|_| {
+                sms.run_frame(Default::default()).unwrap();
+                sms.frame_hash()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frame_hash() {
+        let hashes = stripes_hashes(3);
+        // the first frame is partly drawn before the program finishes
+        assert_eq!(hashes[1], hashes[2]);
+        assert_eq!(hashes, stripes_hashes(3));
+
+        let white = vdp_color_to_simple_color(0x3F);
+        let blue = vdp_color_to_simple_color(0x30);
+        let mut expected = PixelGraphics::default();
+        expected.set_resolution(256, 192).unwrap();
+        for y in 0..192 {
+            for x in 0..256 {
+                let color = if y % 8 == 0 && x % 8 < 4 { blue } else { white };
+                expected.paint(x, y, color);
+            }
+        }
+        assert_eq!(hashes[2], host_multimedia::frame_hash(&expected));
+        assert_eq!(hashes[2], 10914640406252063530);
+    }
+
+    #[test]
+    fn raster_split() {
+        let main = [
+            0xF3, // di
+            0xED, 0x56, // im 1
+            0x31, 0xF0, 0xDF, // ld sp, 0xDFF0
+            0xFB, // ei
+            0x76, // halt
+            0x18, 0xFD, // jr -3
+        ];
+        // scroll 12 pixels after a line interrupt, and back to 0 after a
+        // frame interrupt
+        let handler = [
+            0xF5, // push af
+            0xDB, 0xBF, // in a, (0xBF)
+            0xE6, 0x80, // and 0x80
+            0x20, 0x04, // jr nz, +4
+            0x3E, 0x0C, // ld a, 12
+            0x18, 0x01, // jr +1
+            0xAF, // xor a
+            0xD3, 0xBF, // out (0xBF), a
+            0x3E, 0x88, // ld a, 0x88
+            0xD3, 0xBF, // out (0xBF), a: register 8, horizontal scroll
+            0xF1, // pop af
+            0xFB, // ei
+            0xED, 0x4D, // reti
+        ];
+        let mut program = vec![0u8; 0x38];
+        program[..main.len()].copy_from_slice(&main);
+        program.extend_from_slice(&handler);
+        let mut state = state_with_program(&program);
+        {
+            let vdp = &mut state.vdp;
+            vdp.set_register(0, 0x14); // mode 4, line interrupts
+            vdp.set_register(1, 0x60); // display and frame interrupts on
+            vdp.set_register(2, 0xFF); // name table at 0x3800
+            vdp.set_register(5, 0xFF); // sprite attribute table at 0x3F00
+            vdp.set_register(10, 99);
+            vdp.set_vram(0x3F00, 0xD0); // no sprites
+
+            // tile 1 is solid color 1, and the columns alternate between
+            // tiles 0 and 1
+            for line in 0..8 {
+                vdp.set_vram(32 + 4 * line, 0xFF);
+            }
+            for i in 0..32 * 28 {
+                vdp.set_vram(0x3800 + 2 * i, i as u8 & 1);
+            }
+            vdp.set_cram(0, 0x30);
+            vdp.set_cram(1, 0x0C);
+        }
+        let blue = vdp_color_to_simple_color(0x30);
+        let green = vdp_color_to_simple_color(0x0C);
+
+        let mut sms = SmsBuilder::new()
+            .frequency(None)
+            .graphics(PixelGraphics::default())
+            .build_concrete(state)
+            .unwrap();
+        // after the first frame, the line counter runs out at the end of line
+        // 98
+        for _ in 0..3 {
+            sms.run_frame(Default::default()).unwrap();
+        }
+
+        for y in 0..192 {
+            let scroll = if y <= 98 { 0u8 } else { 12 };
+            for x in 16..256 {
+                let column = (x as u8).wrapping_sub(scroll) / 8;
+                let expected = if column & 1 != 0 { green } else { blue };
+                assert_eq!(sms.graphics.get(x, y), expected, "line {}, x {}", y, x);
+            }
+        }
+    }
+
+    /// Keeps the accesses it's told about.
+    struct RecordingWatch(Rc<RefCell<Vec<(bool, u16, u8)>>>);
+
+    impl MemoryWatch for RecordingWatch {
+        fn on_read(&mut self, logical_address: u16, value: u8) {
+            self.0.borrow_mut().push((false, logical_address, value));
+        }
+
+        fn on_write(&mut self, logical_address: u16, value: u8) {
+            self.0.borrow_mut().push((true, logical_address, value));
+        }
+    }
+
+    #[test]
+    fn memory_watch() {
+        let program = [
+            0x3E, 0x12, // ld a, 0x12
+            0x32, 0x00, 0xC0, // ld (0xC000), a
+            0x3A, 0x00, 0xC0, // ld a, (0xC000)
+        ];
+        let mut sms = SmsBuilder::new()
+            .frequency(None)
+            .build(state_with_program(&program))
+            .unwrap();
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        sms.set_memory_watch(Some(Box::new(RecordingWatch(accesses.clone()))));
+        sms.step().unwrap();
+        sms.step().unwrap();
+        sms.set_memory_watch(None);
+        sms.step().unwrap();
+
+        assert_eq!(
+            *accesses.borrow(),
+            vec![
+                (false, 0x0000, 0x3E),
+                (false, 0x0001, 0x12),
+                (false, 0x0002, 0x32),
+                (false, 0x0003, 0x00),
+                (false, 0x0004, 0xC0),
+                (true, 0xC000, 0x12),
+            ]
+        );
+        assert_eq!(sms.z80().reg8(Reg8::A), 0x12);
+    }
+
+    #[test]
+    fn cheats() {
+        let code = [
+            0x3A, 0x00, 0x10, // ld a, (0x1000)
+            0x47, // ld b, a
+            0x3A, 0x01, 0x10, // ld a, (0x1001)
+            0x4F, // ld c, a
+            0x3A, 0x02, 0x10, // ld a, (0x1002)
+            0x3A, 0x00, 0xC0, // ld a, (0xC000)
+        ];
+        let mut program = vec![0u8; 0x1000];
+        program[..code.len()].copy_from_slice(&code);
+        program.extend_from_slice(&[0x11, 0x22, 0x33]);
+        let mut sms = SmsBuilder::new()
+            .frequency(None)
+            .build(state_with_program(&program))
+            .unwrap();
+        sms.add_cheat(Cheat {
+            address: 0x1000,
+            value: 0x99,
+            compare: None,
+        });
+        // the compare byte matches
+        sms.add_cheat(Cheat {
+            address: 0x1001,
+            value: 0x88,
+            compare: Some(0x22),
+        });
+        // the compare byte doesn't match
+        sms.add_cheat(Cheat {
+            address: 0x1002,
+            value: 0x77,
+            compare: Some(0x44),
+        });
+        // not ROM
+        sms.add_cheat(Cheat {
+            address: 0xC000,
+            value: 0x66,
+            compare: None,
+        });
+        for _ in 0..5 {
+            sms.step().unwrap();
+        }
+
+        assert_eq!(sms.z80().reg8(Reg8::B), 0x99);
+        assert_eq!(sms.z80().reg8(Reg8::C), 0x88);
+        assert_eq!(sms.z80().reg8(Reg8::A), 0x33);
+
+        sms.step().unwrap();
+        assert_eq!(sms.z80().reg8(Reg8::A), 0x00);
+
+        // the ROM itself is unchanged
+        assert_eq!(sms.memory().read(0x1000), 0x11);
+        assert_eq!(sms.state().memory.rom[0x1000], 0x11);
+
+        sms.clear_cheats();
+        sms.z80_mut().set_reg16(Reg16::PC, 0);
+        sms.step().unwrap();
+        assert_eq!(sms.z80().reg8(Reg8::A), 0x11);
+    }
+
+    #[test]
+    fn rewind() {
+        let mut sms = SmsBuilder::new()
+            .frequency(None)
+            .build_concrete(program_state())
+            .unwrap();
+        sms.set_rewind_capacity(4);
+
+        let mut snapshots = Vec::new();
+        for i in 0..6 {
+            snapshots.push(sms.state());
+            let mut player_input = SmsPlayerInput::default();
+            player_input.set_joypad_a(i);
+            sms.run_frame(player_input).unwrap();
+        }
+
+        // only the last 4 frames were kept
+        for snapshot in snapshots[2..].iter().rev() {
+            assert!(sms.rewind_step());
+            assert!(sms.state() == *snapshot);
+        }
+        assert!(!sms.rewind_step());
+        assert!(sms.state() == snapshots[2]);
+
+        // we can run forward again from here
+        sms.run_frame(Default::default()).unwrap();
+        assert!(sms.rewind_step());
+        assert!(sms.state() == snapshots[2]);
+
+        // the cartridge info goes with the ROM
+        static INFO: GameInfo = GameInfo {
+            title: "Test Game",
+            fingerprint: sms_roms::RomFingerprint {
+                crc32: 0,
+                sha1: [0; 20],
+            },
+            mapper: SmsMemoryMapper::Sega,
+            tv_system: TvSystem::Ntsc,
+        };
+        sms.cartridge_info = Some(&INFO);
+        sms.run_frame(Default::default()).unwrap();
+        assert!(sms.rewind_step());
+        assert_eq!(sms.cartridge_info(), Some(&INFO));
+        let mut other = snapshots[2].clone();
+        other.memory.rom = Arc::new(other.memory.rom.to_vec().into_boxed_slice());
+        sms.rewind.push(other);
+        assert!(sms.rewind_step());
+        assert_eq!(sms.cartridge_info(), None);
+
+        // the IO, YM2413 and memory control states are restored too
+        sms.run_frame(Default::default()).unwrap();
+        sms.io.ppi_port_c = 0x02;
+        sms.ym2413.write_control(1);
+        sms.memory_control = 0xA8;
+        assert!(sms.rewind_step());
+        assert!(sms.state() == snapshots[2]);
+    }
+
+    #[test]
+    fn pause_nmi() {
+        for &kind in [Kind::Sms2, Kind::Gg].iter() {
+            let mut program = vec![0u8; 0x66];
+            program[..2].copy_from_slice(&[0x18, 0xFE]); // jr -2
+            program.extend_from_slice(&[
+                0x21, 0x02, 0xC0, // ld hl, 0xC002
+                0x34, // inc (hl)
+                0xED, 0x45, // retn
+            ]);
+            let mut state = state_with_program(&program);
+            state.vdp.set_kind(kind);
+            let mut sms = SmsBuilder::new().frequency(None).build(state).unwrap();
+
+            let mut nmis = Vec::new();
+            for &pause in [true, true, true, false, true, true, false].iter() {
+                let mut player_input = SmsPlayerInput::default();
+                player_input.set_pause(pause);
+                sms.run_frame(player_input).unwrap();
+                nmis.push(sms.memory().read(0xC002));
+            }
+            if kind == Kind::Gg {
+                assert_eq!(nmis, vec![0; 7]);
+            } else {
+                // holding pause doesn't repeat the NMI
+                assert_eq!(nmis, vec![1, 1, 1, 1, 2, 2, 2]);
+            }
+        }
+    }
+
+    #[test]
+    fn copier_header() {
+        let mut rom = vec![0xFFu8; 0x200];
+        rom.extend((0..0x8000).map(|i| (i >> 8) as u8));
+        let state = SmsState::from_rom(
+            Arc::new(rom.into_boxed_slice()),
+            None,
+            TvSystem::Ntsc,
+            Kind::Sms2,
+        );
+        assert_eq!(state.memory.rom.len(), 0x8000);
+        let mut memory = PointerSmsMemory::load(state.memory).unwrap();
+        assert_eq!(memory.read(0x0000), 0x00);
+        assert_eq!(memory.read(0x1234), 0x12);
+        assert_eq!(memory.read(0x7FFF), 0x7F);
+    }
+
+    #[test]
+    fn non_power_of_two_rom() {
+        let rom: Vec<u8> = (0..0xC000).map(|i| (i / 0x4000) as u8).collect();
+        let state = SmsState::from_rom(
+            Arc::new(rom.into_boxed_slice()),
+            Some(SmsMemoryMapper::Sega),
+            TvSystem::Ntsc,
+            Kind::Sms2,
+        );
+        let mut memory = PointerSmsMemory::load(state.memory).unwrap();
+        for &(bank, expected) in &[(1, 1), (2, 2), (3, 1), (7, 1), (6, 2)] {
+            memory.write(0xFFFF, bank);
+            assert_eq!(memory.read(0x8000), expected);
+        }
+    }
+
+    #[test]
+    fn non_power_of_two_rom_memo() {
+        let build = |len: usize| {
+            SmsBuilder::new()
+                .inbox(RecordingInbox::default())
+                .build_concrete_from_rom(Arc::new(vec![0u8; len].into_boxed_slice()))
+                .unwrap()
+        };
+        assert_eq!(
+            build(0xC000).inbox.0,
+            vec![Z80Memo::RomMirrored {
+                pages: 3,
+                padded: 4,
+            }]
+        );
+        // a copier header doesn't count
+        assert_eq!(
+            build(0x14200).inbox.0,
+            vec![Z80Memo::RomMirrored {
+                pages: 5,
+                padded: 8,
+            }]
+        );
+        assert!(build(0x8000).inbox.0.is_empty());
+    }
+
+    #[test]
+    fn light_phaser_overscan() {
+        let rom = Arc::new(vec![0u8; 0x8000].into_boxed_slice());
+        for &kind in [Kind::Sms2, Kind::Gg].iter() {
+            let mut sms = SmsBuilder::new()
+                .kind(kind)
+                .build_from_rom(rom.clone())
+                .unwrap();
+            let phaser = sms.light_phaser_at(40, 30, true);
+            sms.set_overscan(true);
+            if kind == Kind::Gg {
+                // the Game Gear has no border
+                assert_eq!(sms.light_phaser_at(40, 30, true), phaser);
+            } else {
+                let x = 40 + OVERSCAN_WIDTH as i32;
+                let y = 30 + OVERSCAN_HEIGHT as i32;
+                assert_eq!(sms.light_phaser_at(x, y, true), phaser);
+                // aiming at the border clamps to the edge of the screen
+                assert_eq!(sms.light_phaser_at(0, 0, false).x, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x7FF0..0x7FF8].copy_from_slice(b"TMR SEGA");
+        let rom = Arc::new(rom.into_boxed_slice());
+
+        let sms = SmsBuilder::new()
+            .tv_system(TvSystem::Pal)
+            .kind(Kind::Gg)
+            .mapper(Some(SmsMemoryMapper::Codemasters))
+            .frequency(None)
+            .memory(TypeWrap::<SmsMemoryState>::default())
+            .build_from_rom(rom.clone())
+            .unwrap();
+        let state = SmsState::from_rom(
+            rom.clone(),
+            Some(SmsMemoryMapper::Codemasters),
+            TvSystem::Pal,
+            Kind::Gg,
+        );
+        assert!(sms.state() == state);
+
+        // and the defaults
+        let sms = SmsBuilder::new().build_from_rom(rom.clone()).unwrap();
+        let state = SmsState::from_rom(rom, None, TvSystem::Ntsc, Kind::Sms2);
+        assert!(sms.state() == state);
+    }
+
+    #[test]
+    fn faults() {
+        let new = |program: &[u8]| {
+            let mut rom = vec![0u8; 0x8000];
+            rom[..program.len()].copy_from_slice(program);
+            let mut sms = SmsBuilder::new()
+                .build_from_rom(Arc::new(rom.into_boxed_slice()))
+                .unwrap();
+            sms.set_fault_checks(true);
+            sms
+        };
+
+        // nop; ld a, 0x12; ld b, b
+        let program = [0x00, 0x3E, 0x12, 0x40];
+        let illegal = Opcode::OneByte([0x40]);
+        let mut sms = new(&program);
+        sms.set_illegal_opcodes(vec![illegal]);
+        match sms.run_frame_headless(Default::default()) {
+            Err(SmsEmulationError::IllegalOpcode { pc, opcode }) => {
+                assert_eq!(pc, 3);
+                assert_eq!(opcode, illegal);
+            }
+            x => panic!("expected an illegal opcode, got {:?}", x),
+        }
+        // it wasn't executed
+        assert_eq!(sms.z80().reg16(Reg16::PC), 3);
+        assert_eq!(sms.z80().reg8(Reg8::A), 0x12);
+
+        // without fault checks, it's just an instruction
+        sms.set_fault_checks(false);
+        sms.run_frame_headless(Default::default()).unwrap();
+
+        // di; halt
+        let mut sms = new(&[0xF3, 0x76]);
+        match sms.run_frame_headless(Default::default()) {
+            Err(SmsEmulationError::Halted { .. }) => {}
+            x => panic!("expected a halt, got {:?}", x),
+        }
+
+        // ld a, 0x80; ld (0xFFFC), a
+        let mut sms = new(&[0x3E, 0x80, 0x32, 0xFC, 0xFF]);
+        match sms.run_frame_headless(Default::default()) {
+            Err(SmsEmulationError::MapperFault { .. }) => {}
+            x => panic!("expected a mapper fault, got {:?}", x),
+        }
+    }
+
+    /// Graphics stuck at the Game Gear's resolution.
+    struct SmallGraphics;
+
+    impl SimpleGraphics for SmallGraphics {
+        fn set_resolution(&mut self, _width: u32, _height: u32) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn resolution(&self) -> (u32, u32) {
+            (160, 144)
+        }
+
+        fn paint(&mut self, x: u32, y: u32, _color: SimpleColor) {
+            assert!(x < 160 && y < 144);
+        }
+
+        fn get(&self, _x: u32, _y: u32) -> SimpleColor {
+            Default::default()
+        }
+
+        fn render(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn paint_out_of_bounds() {
+        let mut sms = SmsBuilder::new()
+            .graphics(SmallGraphics)
+            .build(program_state())
+            .unwrap();
+        match sms.run_frame_headless(Default::default()) {
+            Err(SmsEmulationError::GraphicsError(SmsVdpGraphicsError::PaintOutOfBounds {
+                x,
+                y,
+                width,
+                height,
+            })) => assert_eq!((x, y, width, height), (160, 0, 160, 144)),
+            x => panic!("expected painting out of bounds, got {:?}", x),
+        }
+    }
+
+    /// Everything here works without the `host_clock` feature.
+    #[test]
+    fn step_from_bytes() {
+        // ld a, 0x12; ld b, 0x34; add a, b
+        let mut rom = vec![0u8; 0x8000];
+        rom[..5].copy_from_slice(&[0x3E, 0x12, 0x06, 0x34, 0x80]);
+        let mut sms = SmsBuilder::new()
+            .frequency(None)
+            .build_from_rom(Arc::new(rom.into_boxed_slice()))
+            .unwrap();
+        let cycles: Vec<u64> = (0..3).map(|_| sms.step().unwrap()).collect();
+        assert_eq!(cycles, vec![7, 7, 4]);
+        assert_eq!(sms.z80().reg8(Reg8::A), 0x46);
+        assert_eq!(sms.z80().reg16(Reg16::PC), 5);
+    }
+
+    #[test]
+    fn build_concrete() {
+        let mut boxed = SmsBuilder::new().build(program_state()).unwrap();
+        let mut concrete = SmsBuilder::new().build_concrete(program_state()).unwrap();
+        for _ in 0..3 {
+            boxed.run_frame_headless(Default::default()).unwrap();
+            concrete.run_frame_headless(Default::default()).unwrap();
+        }
+        assert!(boxed.state() == concrete.state());
+    }
+
+    /// Compare how fast frames run through a `Box<dyn Sms>` and through the
+    /// concrete `SmsS`. See `utilities::print_throughput`.
+    #[test]
+    #[ignore]
+    fn frame_throughput() {
+        fn time<S: Sms + ?Sized>(name: &str, sms: &mut S) {
+            const FRAMES: usize = 5000;
+            utilities::print_throughput(name, "frames", FRAMES as u64, || {
+                for _ in 0..FRAMES {
+                    sms.run_frame_headless(Default::default()).unwrap();
+                }
+                sms.z80().cycles()
+            });
+        }
+
+        let mut boxed = SmsBuilder::new().build(program_state()).unwrap();
+        time("Box<dyn Sms>", &mut *boxed);
+        let mut concrete = SmsBuilder::new().build_concrete(program_state()).unwrap();
+        time("SmsS", &mut concrete);
+    }
+
+    /// Compare how fast frames run with and without turbo mode. See
+    /// `utilities::print_throughput`.
+    #[test]
+    #[ignore]
+    fn turbo_throughput() {
+        const FRAMES: usize = 2000;
+        for &(name, turbo) in [("normal", false), ("turbo", true)].iter() {
+            utilities::print_throughput(name, "frames", FRAMES as u64, || run(turbo, FRAMES).1);
+        }
+    }
+}
@@ -1,3 +1,10 @@
+use std::io::Read;
+use std::path::Path;
+
+use failure::Error;
+
+use save::{self, SaveLoadError, Versioned};
+
 use super::*;
 
 /// Contains a saved recording of gameplay, together with the initial state of
@@ -5,9 +12,101 @@ use super::*;
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Recording<H> {
     pub state: H,
+
+    /// The player input for each frame, run-length encoded: each entry is a
+    /// number of frames and the input held for all of them.
+    pub player_statuses: Vec<(u32, SmsPlayerInput)>,
+}
+
+impl<H> Versioned for Recording<H>
+where
+    H: Versioned,
+{
+    /// The state is migrated by `H`. Before version 2, `SmsPlayerInput` had
+    /// no keyboard.
+    fn migrate<R>(version: u32, mut reader: R) -> Result<Self, SaveLoadError>
+    where
+        R: Read,
+    {
+        let state = H::migrate(version, &mut reader)?;
+        let player_statuses = if version == 1 {
+            let old: Vec<(u32, SmsPlayerInputV1)> =
+                save::deserialize_from(reader).map_err(SaveLoadError::Deserialization)?;
+            old.into_iter()
+                .map(|(count, input)| (count, input.into()))
+                .collect()
+        } else {
+            save::deserialize_from(reader).map_err(SaveLoadError::Deserialization)?
+        };
+        Ok(Recording {
+            state,
+            player_statuses,
+        })
+    }
+}
+
+/// The way recordings used to be saved, with one `SmsPlayerInput` per frame.
+///
+/// These were written with `save::serialize_into`, without a header.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FrameRecording<H> {
+    pub state: H,
     pub player_statuses: Vec<SmsPlayerInput>,
 }
 
+impl<H> From<FrameRecording<H>> for Recording<H> {
+    fn from(x: FrameRecording<H>) -> Self {
+        Recording {
+            state: x.state,
+            player_statuses: run_length_encode(&x.player_statuses),
+        }
+    }
+}
+
+/// Run-length encode `player_statuses`, as in `Recording`.
+pub fn run_length_encode(player_statuses: &[SmsPlayerInput]) -> Vec<(u32, SmsPlayerInput)> {
+    let mut runs: Vec<(u32, SmsPlayerInput)> = Vec::new();
+    for &player_status in player_statuses.iter() {
+        push_run(&mut runs, player_status);
+    }
+    runs
+}
+
+/// Add one frame of `player_status` to the run-length encoded `runs`.
+fn push_run(runs: &mut Vec<(u32, SmsPlayerInput)>, player_status: SmsPlayerInput) {
+    match runs.last_mut() {
+        Some(&mut (ref mut count, status)) if status == player_status => *count += 1,
+        _ => runs.push((1, player_status)),
+    }
+}
+
+/// Read a recording written with `save::write_state` or
+/// `save::write_state_compressed`, or in the old `FrameRecording` format.
+pub fn read_recording<R, H>(mut reader: R) -> Result<Recording<H>, Error>
+where
+    R: Read,
+    H: Versioned,
+{
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    match save::read_state(&bytes[..]) {
+        Err(SaveLoadError::BadMagic(_)) => {
+            let old: FrameRecording<H> = save::deserialize(&bytes)?;
+            Ok(old.into())
+        }
+        x => Ok(x?),
+    }
+}
+
+pub fn read_recording_at<P, H>(path: P) -> Result<Recording<H>, Error>
+where
+    P: AsRef<Path>,
+    H: Versioned,
+{
+    let file = std::fs::File::open(path)?;
+    read_recording(&file)
+}
+
 /// Internal type for UserInterface to record gameplay
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct RecordingStatus<S>(Option<Box<Recording<S>>>);
@@ -23,7 +122,7 @@ impl<S> RecordingStatus<S> {
     /// emulating the frame
     pub fn update(&mut self, player_status: SmsPlayerInput) {
         if let Some(ref mut recording) = self.0 {
-            recording.player_statuses.push(player_status)
+            push_run(&mut recording.player_statuses, player_status);
         }
     }
 
@@ -46,21 +145,112 @@ impl<S> RecordingStatus<S> {
     }
 }
 
+/// Player input to play back, a frame at a time.
 #[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub struct PlaybackStatus(Vec<SmsPlayerInput>);
+pub struct PlaybackStatus(Vec<(u32, SmsPlayerInput)>);
 
 impl PlaybackStatus {
     pub fn from_recorded(player_statuses: &[SmsPlayerInput]) -> PlaybackStatus {
-        let mut v = player_statuses.to_vec();
+        PlaybackStatus::from_delta(&run_length_encode(player_statuses))
+    }
+
+    /// Play back run-length encoded input, as in `Recording`.
+    pub fn from_delta(player_statuses: &[(u32, SmsPlayerInput)]) -> PlaybackStatus {
+        let mut v: Vec<(u32, SmsPlayerInput)> = player_statuses
+            .iter()
+            .cloned()
+            .filter(|&(count, _)| count > 0)
+            .collect();
         v.reverse();
         PlaybackStatus(v)
     }
 
     pub fn pop(&mut self) -> Option<SmsPlayerInput> {
-        self.0.pop()
+        let (count, player_status) = *self.0.last()?;
+        if count == 1 {
+            self.0.pop();
+        } else {
+            self.0.last_mut().unwrap().0 -= 1;
+        }
+        Some(player_status)
     }
 
     pub fn end_playback(&mut self) {
         self.0 = Vec::new();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a `u32` has had the same layout in every version
+    impl Versioned for u32 {
+        fn migrate<R>(_version: u32, reader: R) -> Result<Self, SaveLoadError>
+        where
+            R: Read,
+        {
+            save::deserialize_from(reader).map_err(SaveLoadError::Deserialization)
+        }
+    }
+
+    #[test]
+    fn delta_recording() {
+        let mut frames = Vec::new();
+        for i in 0..8u8 {
+            // alternating
+            let mut player_status = SmsPlayerInput::default();
+            player_status.set_joypad_a(i & 1);
+            frames.push(player_status);
+        }
+        let mut held = SmsPlayerInput::default();
+        held.set_joypad_b(!joypad_b_bits::JOYPAD2_A);
+        for _ in 0..300 {
+            frames.push(held);
+        }
+        frames.push(SmsPlayerInput::default());
+
+        let mut recording_status = RecordingStatus::default();
+        recording_status.begin_recording(17u32);
+        for &frame in frames.iter() {
+            recording_status.update(frame);
+        }
+        let recording = recording_status.recording().unwrap().clone();
+        assert_eq!(recording.player_statuses.len(), 10);
+        assert_eq!(recording.player_statuses[8], (300, held));
+
+        let mut bytes = Vec::new();
+        save::write_state_compressed(&recording, &mut bytes).unwrap();
+        let recording2: Recording<u32> = read_recording(&bytes[..]).unwrap();
+        assert_eq!(recording2, recording);
+
+        let mut playback = PlaybackStatus::from_delta(&recording2.player_statuses);
+        let played: Vec<SmsPlayerInput> = (0..frames.len())
+            .map(|_| playback.pop().unwrap())
+            .collect();
+        assert_eq!(played, frames);
+        assert_eq!(playback.pop(), None);
+
+        // the old format is still readable
+        let old = FrameRecording {
+            state: 17u32,
+            player_statuses: frames.clone(),
+        };
+        let old_bytes = save::serialize(&old).unwrap();
+        let recording3: Recording<u32> = read_recording(&old_bytes[..]).unwrap();
+        assert_eq!(recording3, recording);
+
+        // and so is version 1, before the SC-3000 keyboard
+        let v1_statuses: Vec<(u32, SmsPlayerInputV1)> = recording
+            .player_statuses
+            .iter()
+            .map(|&(count, input)| (count, input.into()))
+            .collect();
+        let mut v1_bytes = Vec::new();
+        v1_bytes.extend_from_slice(&save::STATE_MAGIC);
+        v1_bytes.extend_from_slice(&1u32.to_le_bytes());
+        save::serialize_into(&mut v1_bytes, &(17u32, v1_statuses)).unwrap();
+        let recording4: Recording<u32> = read_recording(&v1_bytes[..]).unwrap();
+        assert_eq!(recording4, recording);
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::VecDeque;
 use std::fmt::Write;
 
-use hardware::z80::{Opcode, TargetMnemonic};
+use hardware::z80::{Mnemonic, Opcode, TargetMnemonic, TraceRegisters};
 use memo::{Inbox, NothingInbox};
 
 use super::*;
@@ -14,22 +14,123 @@ pub enum Query {
     Disassembly,
     /// Show the last few memos received
     RecentMemos,
+    /// Show the last `n` instructions executed, with the registers as each
+    /// began
+    Trace(usize),
+    /// Show all the Z80's registers as they are now
+    Registers,
+    /// Hex dump of VDP video RAM, starting at the first address and going on
+    /// for the given number of bytes, but not past the end of VRAM
+    Vram(u16, u16),
+    /// Show each color RAM entry, with the color it's displayed as
+    Cram,
+    /// Show the last few port reads and writes, with the PC of the
+    /// instruction that made each
+    IoLog,
+}
+
+/// A description of some `Z80Memo`s, for the debugger to hold on.
+///
+/// Address ranges are inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MemoPattern {
+    /// An instruction at a PC in the range.
+    Instruction {
+        first: u16,
+        last: u16,
+    },
+    /// A memory read in the range, of `value` if it's given.
+    MemoryRead {
+        first: u16,
+        last: u16,
+        value: Option<u8>,
+    },
+    /// A memory write in the range, of `value` if it's given.
+    MemoryWrite {
+        first: u16,
+        last: u16,
+        value: Option<u8>,
+    },
+    MaskableInterrupt,
+    NonmaskableInterrupt,
+}
+
+impl MemoPattern {
+    /// Any write to the Sega memory mapper's registers, at 0xFFFC through
+    /// 0xFFFF.
+    pub const SEGA_MAPPER_WRITE: MemoPattern = MemoPattern::MemoryWrite {
+        first: 0xFFFC,
+        last: 0xFFFF,
+        value: None,
+    };
+
+    /// A write of `value` to `address`.
+    #[inline]
+    pub fn write_of(value: u8, address: u16) -> Self {
+        MemoPattern::MemoryWrite {
+            first: address,
+            last: address,
+            value: Some(value),
+        }
+    }
+
+    pub fn matches(&self, memo: &Z80Memo) -> bool {
+        use self::MemoPattern::*;
+
+        let in_range = |first: u16, last: u16, x: u16| first <= x && x <= last;
+        let value_matches = |value: Option<u8>, x: u8| value.is_none() || value == Some(x);
+
+        match (*self, *memo) {
+            (Instruction { first, last }, Z80Memo::Instruction { pc, .. }) => {
+                in_range(first, last, pc)
+            }
+            (MemoryRead { first, last, value }, Z80Memo::MemoryRead { address, value: x }) => {
+                in_range(first, last, address) && value_matches(value, x)
+            }
+            (MemoryWrite { first, last, value }, Z80Memo::MemoryWrite { address, value: x }) => {
+                in_range(first, last, address) && value_matches(value, x)
+            }
+            (MaskableInterrupt, Z80Memo::MaskableInterrupt { .. }) => true,
+            (NonmaskableInterrupt, Z80Memo::NonmaskableInterrupt) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Command {
     Hold,
     Resume,
+    /// Run one instruction and hold again.
     Step,
+    /// Like `Step`, except that if the instruction is a `call` or `rst`, run
+    /// until it returns.
+    StepOver,
     BreakAtPc(u16),
     RemovePcBreakpoints,
-    // BreakAtMemo(MemoPattern),
-    // RemoveBreakMemos,
+    /// Hold after an instruction reads memory at this address.
+    BreakOnRead(u16),
+    RemoveReadBreakpoints,
+    /// Hold after an instruction writes memory at this address.
+    BreakOnWrite(u16),
+    RemoveWriteBreakpoints,
+    /// Hold after a memo matching the pattern.
+    BreakAtMemo(MemoPattern),
+    RemoveBreakMemos,
 }
 
 pub trait Debugger {
     fn command(&mut self, command: Command);
-    fn query(&self, query: Query) -> String;
+
+    /// `z80` and `vdp` are the machine's, for queries about their current
+    /// state.
+    ///
+    /// With an `Sms`, `Sms::query` is more convenient.
+    fn query(&self, query: Query, z80: &dyn Z80Internal, vdp: &dyn SmsVdpInternal) -> String;
+
+    /// Take the messages the debugger has for the user since this was last
+    /// called, like which breakpoint made it hold.
+    fn messages(&mut self) -> Vec<UserMessage>;
 }
 
 pub struct DebuggerImpl;
@@ -38,7 +139,12 @@ pub struct DebuggerImpl;
 enum DebugStatus {
     None,
     Hold,
+    /// Let the next instruction run...
     Step,
+    /// ...and then hold before the one after.
+    Stepped,
+    /// Like `Step`, but for a `call` or `rst`, set `step_over_return`.
+    StepOver,
 }
 
 impl Default for DebugStatus {
@@ -49,7 +155,7 @@ impl Default for DebugStatus {
 
 /// Debugging information about the instruction at each memory location.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
-struct MemoryLocation {
+struct InstructionInfo {
     opcode: Option<Opcode>,
     /// If this PC is the target of some jump, here's a label to use.
     label: Option<u16>,
@@ -57,26 +163,48 @@ struct MemoryLocation {
 
 const MAX_MEMOS: usize = 400;
 
+const MAX_TRACE: usize = 1000;
+
+const MAX_IO_LOG: usize = 400;
+
 #[derive(Clone)]
 pub struct DebuggingInbox {
     last_pc: u16,
-    instructions: [MemoryLocation; 0x10000],
+    /// Indexed by address; boxed since it's much too big for the stack.
+    instructions: Box<[InstructionInfo]>,
     next_label: u16,
     status: DebugStatus,
+    /// Hold when an instruction at this PC begins with this SP.
+    step_over_return: Option<(u16, u16)>,
+    /// The PC of an instruction that we held before it could execute.
+    held_at: Option<u16>,
     pc_breakpoints: Vec<u16>,
-    // memo_patterns: Vec<MemoPattern>,
+    read_breakpoints: Vec<u16>,
+    write_breakpoints: Vec<u16>,
+    messages: Vec<UserMessage>,
+    memo_patterns: Vec<MemoPattern>,
     recent_memos: VecDeque<Z80Memo>,
+    trace: VecDeque<(u16, Opcode, TraceRegisters)>,
+    io_log: VecDeque<(u16, Z80Memo)>,
 }
 
 impl DebuggingInbox {
     fn new() -> Self {
         DebuggingInbox {
             last_pc: 0,
-            instructions: [Default::default(); 0x10000],
+            instructions: vec![Default::default(); 0x10000].into_boxed_slice(),
             next_label: 0,
             status: DebugStatus::None,
+            step_over_return: None,
+            held_at: None,
             pc_breakpoints: Vec::new(),
+            read_breakpoints: Vec::new(),
+            write_breakpoints: Vec::new(),
+            memo_patterns: Vec::new(),
+            messages: Vec::new(),
             recent_memos: VecDeque::new(),
+            trace: VecDeque::new(),
+            io_log: VecDeque::new(),
         }
     }
 
@@ -177,6 +305,33 @@ impl DebuggingInbox {
         result
     }
 
+    /// Update `status` for an instruction that's about to execute.
+    fn before_instruction(&mut self, pc: u16, opcode: Opcode, sp: u16) {
+        match self.status {
+            DebugStatus::None => {
+                if self.step_over_return == Some((pc, sp)) {
+                    self.step_over_return = None;
+                    self.status = DebugStatus::Hold;
+                }
+            }
+            DebugStatus::Hold => {}
+            DebugStatus::Step => self.status = DebugStatus::Stepped,
+            DebugStatus::Stepped => self.status = DebugStatus::Hold,
+            DebugStatus::StepOver => match opcode.mnemonic().map(|m| m.mnemonic()) {
+                Some(Mnemonic::Call) | Some(Mnemonic::Rst) => {
+                    // The return address, with the stack as it is now. Checking
+                    // the stack too means a recursive call won't fool us.
+                    self.step_over_return = Some((pc.wrapping_add(opcode.len() as u16), sp));
+                    self.status = DebugStatus::None;
+                }
+                _ => self.status = DebugStatus::Stepped,
+            },
+        }
+        if self.status == DebugStatus::Hold {
+            self.held_at = Some(pc);
+        }
+    }
+
     fn disassembly_around(&self, pc: u16) -> String {
         let start = self.back_n(8, pc);
         self.disassembly(Some(pc), start, pc + 40)
@@ -193,17 +348,38 @@ impl Inbox for DebuggingInbox {
     type Memo = Z80Memo;
 
     fn receive_impl(&mut self, memo: Z80Memo) {
+        if let Z80Memo::Instruction {
+            pc,
+            opcode,
+            registers,
+        } = memo
+        {
+            if self.held_at.take() == Some(pc) {
+                // We held before this instruction could execute, and it's
+                // being tried again. It's already been recorded and checked
+                // against breakpoints.
+                self.before_instruction(pc, opcode, registers.sp);
+                return;
+            }
+        }
+
         if self.recent_memos.len() >= MAX_MEMOS {
             self.recent_memos.pop_front();
         }
 
-        if self.status == DebugStatus::Step {
-            self.status = DebugStatus::Hold
-        }
+        if let Z80Memo::Instruction {
+            pc,
+            opcode,
+            registers,
+        } = memo
+        {
+            if self.trace.len() >= MAX_TRACE {
+                self.trace.pop_front();
+            }
+            self.trace.push_back((pc, opcode, registers));
 
-        if let Z80Memo::Instruction { pc, opcode } = memo {
             let current_info = self.instructions[pc as usize];
-            self.instructions[pc as usize] = MemoryLocation {
+            self.instructions[pc as usize] = InstructionInfo {
                 opcode: Some(opcode),
                 label: current_info.label,
             };
@@ -221,7 +397,47 @@ impl Inbox for DebuggingInbox {
             self.last_pc = pc;
         }
 
+        if let Z80Memo::PortRead { .. } | Z80Memo::PortWrite { .. } = memo {
+            if self.io_log.len() >= MAX_IO_LOG {
+                self.io_log.pop_front();
+            }
+            self.io_log.push_back((self.last_pc, memo));
+        }
+
         // if the new memo matches a pattern, hold
+        match memo {
+            Z80Memo::MemoryRead { address, value } if self.read_breakpoints.contains(&address) => {
+                self.status = DebugStatus::Hold;
+                self.messages.push(UserMessage::Ok(format!(
+                    "Read breakpoint: instruction at {:0>4X} read {:0>2X} from {:0>4X}",
+                    self.last_pc, value, address
+                )));
+            }
+            Z80Memo::MemoryWrite { address, value }
+                if self.write_breakpoints.contains(&address) =>
+            {
+                self.status = DebugStatus::Hold;
+                self.messages.push(UserMessage::Ok(format!(
+                    "Write breakpoint: instruction at {:0>4X} wrote {:0>2X} to {:0>4X}",
+                    self.last_pc, value, address
+                )));
+            }
+            _ => {}
+        }
+        if self.memo_patterns.iter().any(|p| p.matches(&memo)) {
+            self.status = DebugStatus::Hold;
+            self.messages
+                .push(UserMessage::Ok(format!("Memo breakpoint: {}", memo)));
+        }
+
+        if let Z80Memo::Instruction {
+            pc,
+            opcode,
+            registers,
+        } = memo
+        {
+            self.before_instruction(pc, opcode, registers.sp);
+        }
 
         self.recent_memos.push_back(memo);
     }
@@ -232,7 +448,7 @@ impl Inbox for DebuggingInbox {
 }
 
 impl Debugger for DebuggingInbox {
-    fn query(&self, query: Query) -> String {
+    fn query(&self, query: Query, z80: &dyn Z80Internal, vdp: &dyn SmsVdpInternal) -> String {
         use self::Query::*;
 
         let result = match query {
@@ -245,10 +461,36 @@ impl Debugger for DebuggingInbox {
             }
             DisassemblyAt(pc) => self.disassembly_around(pc),
             Disassembly => self.disassembly(None, 0, 0xFFFF),
+            Trace(n) => {
+                let mut result = String::new();
+                let start = self.trace.len().saturating_sub(n);
+                for &(pc, opcode, registers) in self.trace.iter().skip(start) {
+                    let text = match opcode.mnemonic() {
+                        Some(mnemonic) => format!("{}", mnemonic),
+                        None => format!("{} <Unknown instruction>", opcode),
+                    };
+                    writeln!(result, "{:0>4X} {:<20} {}", pc, text, registers).unwrap();
+                }
+                result
+            }
+            Registers => registers(z80),
+            Vram(start, len) => vram(vdp, start, len),
+            Cram => cram(vdp),
+            IoLog => {
+                let mut result = String::new();
+                for &(pc, memo) in self.io_log.iter() {
+                    writeln!(result, "{:0>4X} {}", pc, memo).unwrap();
+                }
+                result
+            }
         };
         result
     }
 
+    fn messages(&mut self) -> Vec<UserMessage> {
+        std::mem::take(&mut self.messages)
+    }
+
     fn command(&mut self, command: Command) {
         use self::Command::*;
 
@@ -256,16 +498,106 @@ impl Debugger for DebuggingInbox {
             Step => if self.status == DebugStatus::Hold {
                 self.status = DebugStatus::Step;
             },
+            StepOver => if self.status == DebugStatus::Hold {
+                self.status = DebugStatus::StepOver;
+            },
             Hold => self.status = DebugStatus::Hold,
-            Resume => self.status = DebugStatus::None,
+            Resume => {
+                self.status = DebugStatus::None;
+                self.step_over_return = None;
+            }
             BreakAtPc(pc) => self.pc_breakpoints.push(pc),
             RemovePcBreakpoints => self.pc_breakpoints = Vec::new(),
-            // BreakAtMemo(pattern) => self.memo_patterns.push(pattern),
-            // RemoveBreakMemos => self.memo_patterns = Vec::new(),
+            BreakOnRead(address) => self.read_breakpoints.push(address),
+            RemoveReadBreakpoints => self.read_breakpoints = Vec::new(),
+            BreakOnWrite(address) => self.write_breakpoints.push(address),
+            RemoveWriteBreakpoints => self.write_breakpoints = Vec::new(),
+            BreakAtMemo(pattern) => self.memo_patterns.push(pattern),
+            RemoveBreakMemos => self.memo_patterns = Vec::new(),
         }
     }
 }
 
+fn registers(z80: &dyn Z80Internal) -> String {
+    use self::Reg16::*;
+    use self::Reg8::*;
+
+    let mut result = String::new();
+    writeln!(
+        result,
+        "AF={:0>4X}  BC={:0>4X}  DE={:0>4X}  HL={:0>4X}  IX={:0>4X}  IY={:0>4X}",
+        z80.reg16(AF),
+        z80.reg16(BC),
+        z80.reg16(DE),
+        z80.reg16(HL),
+        z80.reg16(IX),
+        z80.reg16(IY),
+    ).unwrap();
+    writeln!(
+        result,
+        "AF'={:0>4X} BC'={:0>4X} DE'={:0>4X} HL'={:0>4X} SP={:0>4X}  PC={:0>4X}",
+        z80.reg16(AF0),
+        z80.reg16(BC0),
+        z80.reg16(DE0),
+        z80.reg16(HL0),
+        z80.reg16(SP),
+        z80.reg16(PC),
+    ).unwrap();
+    let flags: String = "SZYHXPNC"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if z80.reg8(F) & (0x80 >> i) != 0 {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    writeln!(
+        result,
+        "I={:0>2X} R={:0>2X} IFF1={} IFF2={} IM={} Flags={}",
+        z80.reg8(I),
+        z80.reg8(R),
+        z80.iff1() as u8,
+        z80.iff2() as u8,
+        z80.interrupt_mode() as u8,
+        flags,
+    ).unwrap();
+    result
+}
+
+fn vram(vdp: &dyn SmsVdpInternal, start: u16, len: u16) -> String {
+    const VRAM_LEN: u32 = 0x4000;
+
+    let start = start as u32;
+    let end = (start + len as u32).min(VRAM_LEN);
+    let mut result = String::new();
+    for line_start in (start..end).step_by(16) {
+        write!(result, "{:0>4X}:", line_start).unwrap();
+        for i in line_start..end.min(line_start + 16) {
+            write!(result, " {:0>2X}", vdp.vram(i as u16)).unwrap();
+        }
+        writeln!(result).unwrap();
+    }
+    result
+}
+
+fn cram(vdp: &dyn SmsVdpInternal) -> String {
+    let kind = vdp.kind();
+    let mut result = String::new();
+    for i in 0..32 {
+        let entry = vdp.cram(i);
+        let color = cram_color_to_simple_color(kind, entry);
+        writeln!(
+            result,
+            "{:>2}: {:0>3X} #{:0>2X}{:0>2X}{:0>2X}",
+            i, entry, color.red, color.green, color.blue
+        ).unwrap();
+    }
+    result
+}
+
 pub trait GetDebugger {
     fn debugger(&mut self) -> Option<&mut dyn Debugger>;
 }
@@ -336,3 +668,293 @@ impl Inbox for BoxedInbox {
         self.0.active()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use host_multimedia::FakeAudio;
+
+    fn sms_with_program(program: &[u8]) -> Box<dyn Sms> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[..program.len()].copy_from_slice(program);
+        let state = SmsState::from_rom(
+            Arc::new(rom.into_boxed_slice()),
+            Some(SmsMemoryMapper::Sega),
+            TvSystem::Ntsc,
+            Kind::Sms2,
+        );
+        new_sms(
+            None,
+            state,
+            FakeSmsGraphics,
+            FakeAudio,
+            DebuggingInbox::default(),
+            TypeWrap::<PointerSmsMemory>::default(),
+            TypeWrap::<FakeSn76489>::default(),
+        ).unwrap()
+    }
+
+    #[test]
+    fn write_breakpoint() {
+        let mut sms = sms_with_program(&[
+            0x3E, 0x12, // ld a, 0x12
+            0x21, 0x00, 0xC0, // ld hl, 0xC000
+            0x77, // ld (hl), a
+            0x23, // inc hl
+            0x18, 0xFC, // jr -4
+        ]);
+
+        sms.debugger()
+            .unwrap()
+            .command(Command::BreakOnWrite(0xC005));
+        sms.run_frame(Default::default()).unwrap();
+
+        // held just after the store
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0x0006);
+        assert_eq!(sms.z80().reg16(Reg16::HL), 0xC005);
+        assert_eq!(sms.memory().read(0xC005), 0x12);
+        assert_eq!(sms.memory().read(0xC006), 0x00);
+        assert_eq!(
+            sms.debugger().unwrap().messages(),
+            vec![UserMessage::Ok(
+                "Write breakpoint: instruction at 0005 wrote 12 to C005".to_owned()
+            )]
+        );
+
+        // and it stays held
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0x0006);
+
+        sms.debugger().unwrap().command(Command::Resume);
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.memory().read(0xC006), 0x12);
+        assert!(sms.debugger().unwrap().messages().is_empty());
+    }
+
+    #[test]
+    fn memo_pattern() {
+        let mut sms = sms_with_program(&[
+            0x3E, 0x02, // ld a, 2
+            0x32, 0xFE, 0xFF, // ld (0xFFFE), a
+            0x3C, // inc a
+            0x32, 0xFF, 0xFF, // ld (0xFFFF), a
+            0x18, 0xFE, // jr -2
+        ]);
+
+        sms.debugger()
+            .unwrap()
+            .command(Command::BreakAtMemo(MemoPattern::SEGA_MAPPER_WRITE));
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0x0005);
+        assert_eq!(
+            sms.debugger().unwrap().messages(),
+            vec![UserMessage::Ok(
+                "Memo breakpoint: Memory write FFFE: 02".to_owned()
+            )]
+        );
+
+        // this one only matches the second write
+        let debugger = sms.debugger().unwrap();
+        debugger.command(Command::RemoveBreakMemos);
+        debugger.command(Command::BreakAtMemo(MemoPattern::write_of(2, 0xFFFF)));
+        debugger.command(Command::BreakAtMemo(MemoPattern::write_of(3, 0xFFFF)));
+        debugger.command(Command::Resume);
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0x0009);
+        assert_eq!(
+            sms.debugger().unwrap().messages(),
+            vec![UserMessage::Ok(
+                "Memo breakpoint: Memory write FFFF: 03".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn io_log() {
+        let mut sms = sms_with_program(&[
+            0x3E, 0x9F, // ld a, 0x9F
+            0xD3, 0x7F, // out (0x7F), a
+            0xDB, 0xDC, // in a, (0xDC)
+        ]);
+        for _ in 0..3 {
+            sms.step().unwrap();
+        }
+        let log = sms.query(Query::IoLog).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines,
+            vec!["0002 Port write 9F7F: 9F", "0004 Port read 9FDC: FF"]
+        );
+        let memos = sms.query(Query::RecentMemos).unwrap();
+        assert!(memos.contains("Port write 9F7F: 9F"));
+    }
+
+    #[test]
+    fn trace() {
+        let mut sms = sms_with_program(&[
+            0x31, 0xF0, 0xDF, // ld sp, 0xDFF0
+            0x21, 0x34, 0x12, // ld hl, 0x1234
+            0xE5, // push hl
+            0xD1, // pop de
+            0x3E, 0x56, // ld a, 0x56
+            0x47, // ld b, a
+        ]);
+        for _ in 0..6 {
+            sms.step().unwrap();
+        }
+        let trace = sms.query(Query::Trace(4)).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0006 push hl              AF=0000 BC=0000 DE=0000 HL=1234 SP=DFF0",
+                "0007 pop de               AF=0000 BC=0000 DE=0000 HL=1234 SP=DFEE",
+                "0008 ld a, 56             AF=0000 BC=0000 DE=1234 HL=1234 SP=DFF0",
+                "000A ld b, a              AF=5600 BC=0000 DE=1234 HL=1234 SP=DFF0",
+            ]
+        );
+
+        // asking for more than there is gives everything
+        let trace = sms.query(Query::Trace(100)).unwrap();
+        assert_eq!(trace.lines().count(), 6);
+        assert!(trace.starts_with("0000 ld sp, DFF0"));
+    }
+
+    #[test]
+    fn registers() {
+        let mut sms = sms_with_program(&[]);
+        {
+            let z80 = sms.z80_mut();
+            let values = [
+                (Reg16::AF, 0x12A5),
+                (Reg16::BC, 0x3456),
+                (Reg16::DE, 0x789A),
+                (Reg16::HL, 0xBCDE),
+                (Reg16::IX, 0xF012),
+                (Reg16::IY, 0x3456),
+                (Reg16::AF0, 0x0140),
+                (Reg16::BC0, 0x2345),
+                (Reg16::DE0, 0x6789),
+                (Reg16::HL0, 0xABCD),
+                (Reg16::SP, 0xDFF0),
+                (Reg16::PC, 0x0038),
+            ];
+            for &(reg, value) in values.iter() {
+                z80.set_reg16(reg, value);
+            }
+            z80.set_reg8(Reg8::I, 0x3F);
+            z80.set_reg8(Reg8::R, 0x7E);
+            z80.set_iff1(true);
+            z80.set_iff2(false);
+            z80.set_interrupt_mode(InterruptMode::Im2);
+        }
+        assert_eq!(
+            sms.query(Query::Registers).unwrap(),
+            "AF=12A5  BC=3456  DE=789A  HL=BCDE  IX=F012  IY=3456\n\
+             AF'=0140 BC'=2345 DE'=6789 HL'=ABCD SP=DFF0  PC=0038\n\
+             I=3F R=7E IFF1=1 IFF2=0 IM=2 Flags=S-Y--P-C\n"
+        );
+    }
+
+    #[test]
+    fn step_over() {
+        let mut program = vec![0u8; 0x15];
+        let parts: [(usize, &[u8]); 2] = [
+            (
+                0x0000,
+                &[
+                    0x31, 0xF0, 0xDF, // ld sp, 0xDFF0
+                    0xCD, 0x10, 0x00, // call 0x0010
+                    0x3E, 0x01, // ld a, 1
+                    0x18, 0xFE, // jr -2
+                ],
+            ),
+            (
+                0x0010,
+                &[
+                    0x06, 0x20, // ld b, 0x20
+                    0x10, 0xFE, // djnz -2
+                    0xC9, // ret
+                ],
+            ),
+        ];
+        for &(start, bytes) in parts.iter() {
+            program[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+
+        // Step goes into the subroutine
+        let mut sms = sms_with_program(&program);
+        sms.step().unwrap();
+        sms.debugger().unwrap().command(Command::Hold);
+        sms.debugger().unwrap().command(Command::Step);
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0x0010);
+
+        // StepOver runs it
+        let mut sms = sms_with_program(&program);
+        sms.step().unwrap();
+        sms.debugger().unwrap().command(Command::Hold);
+        sms.debugger().unwrap().command(Command::StepOver);
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0x0006);
+        assert_eq!(sms.z80().reg8(Reg8::B), 0);
+        assert_eq!(sms.z80().reg8(Reg8::A), 0);
+
+        // and then it's like Step
+        sms.debugger().unwrap().command(Command::StepOver);
+        sms.run_frame(Default::default()).unwrap();
+        assert_eq!(sms.z80().reg16(Reg16::PC), 0x0008);
+        assert_eq!(sms.z80().reg8(Reg8::A), 1);
+
+        // Held instructions aren't traced twice
+        let trace = sms.query(Query::Trace(3)).unwrap();
+        let pcs: Vec<&str> = trace.lines().map(|l| &l[..4]).collect();
+        assert_eq!(pcs, vec!["0014", "0006", "0008"]);
+    }
+
+    #[test]
+    fn vram_and_cram() {
+        let mut sms = sms_with_program(&[]);
+        {
+            let vdp = sms.vdp_mut();
+            // VRAM from 3FF8, running off the end
+            vdp.write_control(0xF8);
+            vdp.write_control(0x7F);
+            for i in 0..8 {
+                vdp.write_data(0xA0 | i);
+            }
+            // VRAM from 0012
+            vdp.write_control(0x12);
+            vdp.write_control(0x40);
+            for i in 0..20 {
+                vdp.write_data(i);
+            }
+            // the first two CRAM entries
+            vdp.write_control(0x00);
+            vdp.write_control(0xC0);
+            vdp.write_data(0x3F);
+            vdp.write_data(0x06);
+        }
+
+        assert_eq!(
+            sms.query(Query::Vram(0x0012, 20)).unwrap(),
+            "0012: 00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F\n\
+             0022: 10 11 12 13\n"
+        );
+        assert_eq!(
+            sms.query(Query::Vram(0x3FF8, 0x100)).unwrap(),
+            "3FF8: A0 A1 A2 A3 A4 A5 A6 A7\n"
+        );
+
+        let cram = sms.query(Query::Cram).unwrap();
+        let lines: Vec<&str> = cram.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(
+            &lines[..3],
+            &[" 0: 03F #C0C0C0", " 1: 006 #804000", " 2: 000 #000000"]
+        );
+    }
+}
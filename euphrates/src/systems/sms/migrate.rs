@@ -0,0 +1,430 @@
+//! Reading `SmsState`s saved with older layouts. See `save::Versioned`.
+//!
+//! Each older layout has a struct here, deserialized as it was saved and then
+//! converted to an `SmsState`, filling whatever was added since with what a
+//! freshly powered on system would have.
+
+use std::io::Read;
+
+use serde::de::DeserializeOwned;
+
+use save::{self, SaveLoadError, Versioned};
+
+use super::*;
+
+/// `Sn76489State` through version 6, before `noise_kind`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Sn76489StateV6 {
+    registers: [u16; 8],
+    latch: u8,
+    linear_feedback: u16,
+    counters: [u16; 4],
+    polarity: [i8; 4],
+    cycles: u64,
+    clock_divider: u16,
+    stereo: u8,
+}
+
+/// There's no telling which noise generator an older state had, so it gets
+/// the Master System's.
+impl From<Sn76489StateV6> for Sn76489State {
+    fn from(x: Sn76489StateV6) -> Self {
+        Sn76489State {
+            registers: x.registers,
+            latch: x.latch,
+            linear_feedback: x.linear_feedback,
+            counters: x.counters,
+            polarity: x.polarity,
+            cycles: x.cycles,
+            clock_divider: x.clock_divider,
+            stereo: x.stereo,
+            muted: 0,
+            noise_kind: NoiseKind::Sms,
+        }
+    }
+}
+
+/// `SmsState` in version 1, before the SC-3000 keyboard.
+#[derive(Clone, Serialize, Deserialize)]
+struct SmsStateV1 {
+    z80: Z80State,
+    vdp: SmsVdpState,
+    memory: SmsMemoryState,
+    player_input: SmsPlayerInputV1,
+    pause_irq: SmsPauseInterruptState,
+    sn76489: Sn76489StateV6,
+}
+
+/// `SmsState` in version 2, before `frame_count`.
+#[derive(Clone, Serialize, Deserialize)]
+struct SmsStateV2 {
+    z80: Z80State,
+    vdp: SmsVdpState,
+    memory: SmsMemoryState,
+    player_input: SmsPlayerInput,
+    pause_irq: SmsPauseInterruptState,
+    sn76489: Sn76489StateV6,
+}
+
+impl From<SmsStateV1> for SmsStateV2 {
+    fn from(x: SmsStateV1) -> Self {
+        SmsStateV2 {
+            z80: x.z80,
+            vdp: x.vdp,
+            memory: x.memory,
+            player_input: x.player_input.into(),
+            pause_irq: x.pause_irq,
+            sn76489: x.sn76489,
+        }
+    }
+}
+
+/// `SmsState` in version 3, before `io`.
+#[derive(Clone, Serialize, Deserialize)]
+struct SmsStateV3 {
+    z80: Z80State,
+    vdp: SmsVdpState,
+    memory: SmsMemoryState,
+    player_input: SmsPlayerInput,
+    pause_irq: SmsPauseInterruptState,
+    sn76489: Sn76489StateV6,
+    frame_count: u64,
+}
+
+impl From<SmsStateV2> for SmsStateV3 {
+    fn from(x: SmsStateV2) -> Self {
+        SmsStateV3 {
+            z80: x.z80,
+            vdp: x.vdp,
+            memory: x.memory,
+            player_input: x.player_input,
+            pause_irq: x.pause_irq,
+            sn76489: x.sn76489,
+            frame_count: 0,
+        }
+    }
+}
+
+/// `SmsState` in version 4, before `ym2413`.
+#[derive(Clone, Serialize, Deserialize)]
+struct SmsStateV4 {
+    z80: Z80State,
+    vdp: SmsVdpState,
+    memory: SmsMemoryState,
+    player_input: SmsPlayerInput,
+    pause_irq: SmsPauseInterruptState,
+    sn76489: Sn76489StateV6,
+    frame_count: u64,
+    io: SmsIoState,
+}
+
+impl From<SmsStateV3> for SmsStateV4 {
+    fn from(x: SmsStateV3) -> Self {
+        SmsStateV4 {
+            z80: x.z80,
+            vdp: x.vdp,
+            memory: x.memory,
+            player_input: x.player_input,
+            pause_irq: x.pause_irq,
+            sn76489: x.sn76489,
+            frame_count: x.frame_count,
+            io: Default::default(),
+        }
+    }
+}
+
+/// `SmsState` in version 5, before `memory_control`.
+#[derive(Clone, Serialize, Deserialize)]
+struct SmsStateV5 {
+    z80: Z80State,
+    vdp: SmsVdpState,
+    memory: SmsMemoryState,
+    player_input: SmsPlayerInput,
+    pause_irq: SmsPauseInterruptState,
+    sn76489: Sn76489StateV6,
+    frame_count: u64,
+    io: SmsIoState,
+    ym2413: Ym2413State,
+}
+
+impl From<SmsStateV4> for SmsStateV5 {
+    fn from(x: SmsStateV4) -> Self {
+        SmsStateV5 {
+            z80: x.z80,
+            vdp: x.vdp,
+            memory: x.memory,
+            player_input: x.player_input,
+            pause_irq: x.pause_irq,
+            sn76489: x.sn76489,
+            frame_count: x.frame_count,
+            io: x.io,
+            ym2413: Default::default(),
+        }
+    }
+}
+
+/// `SmsState` in version 6, before `Sn76489State::noise_kind`.
+#[derive(Clone, Serialize, Deserialize)]
+struct SmsStateV6 {
+    z80: Z80State,
+    vdp: SmsVdpState,
+    memory: SmsMemoryState,
+    player_input: SmsPlayerInput,
+    pause_irq: SmsPauseInterruptState,
+    sn76489: Sn76489StateV6,
+    frame_count: u64,
+    io: SmsIoState,
+    ym2413: Ym2413State,
+    memory_control: u8,
+}
+
+/// Older states were all saved with the cartridge enabled.
+impl From<SmsStateV5> for SmsStateV6 {
+    fn from(x: SmsStateV5) -> Self {
+        SmsStateV6 {
+            z80: x.z80,
+            vdp: x.vdp,
+            memory: x.memory,
+            player_input: x.player_input,
+            pause_irq: x.pause_irq,
+            sn76489: x.sn76489,
+            frame_count: x.frame_count,
+            io: x.io,
+            ym2413: x.ym2413,
+            memory_control: MEMORY_CONTROL_CARTRIDGE,
+        }
+    }
+}
+
+impl From<SmsStateV6> for SmsState {
+    fn from(x: SmsStateV6) -> Self {
+        SmsState {
+            z80: x.z80,
+            vdp: x.vdp,
+            memory: x.memory,
+            player_input: x.player_input,
+            pause_irq: x.pause_irq,
+            sn76489: x.sn76489.into(),
+            frame_count: x.frame_count,
+            io: x.io,
+            ym2413: x.ym2413,
+            memory_control: x.memory_control,
+        }
+    }
+}
+
+// Convert a state in each older version to the current one, a version at a
+// time.
+
+fn from_v1(x: SmsStateV1) -> SmsState {
+    from_v2(x.into())
+}
+
+fn from_v2(x: SmsStateV2) -> SmsState {
+    from_v3(x.into())
+}
+
+fn from_v3(x: SmsStateV3) -> SmsState {
+    from_v4(x.into())
+}
+
+fn from_v4(x: SmsStateV4) -> SmsState {
+    from_v5(x.into())
+}
+
+fn from_v5(x: SmsStateV5) -> SmsState {
+    from_v6(x.into())
+}
+
+fn from_v6(x: SmsStateV6) -> SmsState {
+    x.into()
+}
+
+fn read<R, T>(reader: R) -> Result<T, SaveLoadError>
+where
+    R: Read,
+    T: DeserializeOwned,
+{
+    save::deserialize_from(reader).map_err(SaveLoadError::Deserialization)
+}
+
+impl Versioned for SmsState {
+    fn migrate<R>(version: u32, reader: R) -> Result<Self, SaveLoadError>
+    where
+        R: Read,
+    {
+        match version {
+            1 => Ok(from_v1(read(reader)?)),
+            2 => Ok(from_v2(read(reader)?)),
+            3 => Ok(from_v3(read(reader)?)),
+            4 => Ok(from_v4(read(reader)?)),
+            5 => Ok(from_v5(read(reader)?)),
+            6 => Ok(from_v6(read(reader)?)),
+            _ => Err(SaveLoadError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use serde::Serialize;
+
+    fn state() -> SmsState {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0] = 0xC3; // jp 0x1234
+        rom[1] = 0x34;
+        rom[2] = 0x12;
+        let mut state = SmsState::from_rom(
+            Arc::new(rom.into_boxed_slice()),
+            Some(SmsMemoryMapper::Sega),
+            TvSystem::Ntsc,
+            Kind::Sms2,
+        );
+        state.memory.system_ram[0x100] = 0x56;
+        state.player_input.set_joypad_a(!joypad_a_bits::JOYPAD1_A);
+        state
+    }
+
+    fn old_sn76489(state: &SmsState) -> Sn76489StateV6 {
+        let x = state.sn76489;
+        Sn76489StateV6 {
+            registers: x.registers,
+            latch: x.latch,
+            linear_feedback: x.linear_feedback,
+            counters: x.counters,
+            polarity: x.polarity,
+            cycles: x.cycles,
+            clock_divider: x.clock_divider,
+            stereo: x.stereo,
+        }
+    }
+
+    fn with_header<T>(version: u32, t: &T) -> Vec<u8>
+    where
+        T: Serialize,
+    {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&save::STATE_MAGIC);
+        bytes.extend_from_slice(&version.to_le_bytes());
+        save::serialize_into(&mut bytes, t).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn version1() {
+        let state = state();
+        let old = SmsStateV1 {
+            z80: state.z80,
+            vdp: state.vdp,
+            memory: state.memory.clone(),
+            player_input: state.player_input.into(),
+            pause_irq: state.pause_irq,
+            sn76489: old_sn76489(&state),
+        };
+        let state2: SmsState = save::read_state(&with_header(1, &old)[..]).unwrap();
+        assert!(state2 == state);
+    }
+
+    #[test]
+    fn version2() {
+        let mut state = state();
+        state.player_input.set_key_pressed(sc3000_keys::Q, true);
+        let old = SmsStateV2 {
+            z80: state.z80,
+            vdp: state.vdp,
+            memory: state.memory.clone(),
+            player_input: state.player_input,
+            pause_irq: state.pause_irq,
+            sn76489: old_sn76489(&state),
+        };
+        let state2: SmsState = save::read_state(&with_header(2, &old)[..]).unwrap();
+        assert_eq!(state2.frame_count, 0);
+        assert!(state2 == state);
+    }
+    #[test]
+    fn version3() {
+        let mut state = state();
+        state.frame_count = 17;
+        let old = SmsStateV3 {
+            z80: state.z80,
+            vdp: state.vdp,
+            memory: state.memory.clone(),
+            player_input: state.player_input,
+            pause_irq: state.pause_irq,
+            sn76489: old_sn76489(&state),
+            frame_count: state.frame_count,
+        };
+        let state2: SmsState = save::read_state(&with_header(3, &old)[..]).unwrap();
+        assert_eq!(state2.io, SmsIoState::default());
+        assert!(state2 == state);
+    }
+    #[test]
+    fn version4() {
+        let mut state = state();
+        state.io.ppi_port_c = 0x02;
+        let old = SmsStateV4 {
+            z80: state.z80,
+            vdp: state.vdp,
+            memory: state.memory.clone(),
+            player_input: state.player_input,
+            pause_irq: state.pause_irq,
+            sn76489: old_sn76489(&state),
+            frame_count: state.frame_count,
+            io: state.io,
+        };
+        let state2: SmsState = save::read_state(&with_header(4, &old)[..]).unwrap();
+        assert_eq!(state2.ym2413, Ym2413State::default());
+        assert!(state2 == state);
+    }
+    #[test]
+    fn version5() {
+        let mut state = state();
+        state.ym2413.write_control(1);
+        let old = SmsStateV5 {
+            z80: state.z80,
+            vdp: state.vdp,
+            memory: state.memory.clone(),
+            player_input: state.player_input,
+            pause_irq: state.pause_irq,
+            sn76489: old_sn76489(&state),
+            frame_count: state.frame_count,
+            io: state.io,
+            ym2413: state.ym2413.clone(),
+        };
+        let state2: SmsState = save::read_state(&with_header(5, &old)[..]).unwrap();
+        assert_eq!(state2.memory_control, MEMORY_CONTROL_CARTRIDGE);
+        assert!(state2 == state);
+    }
+    #[test]
+    fn version6() {
+        let mut state = state();
+        state.memory_control = MEMORY_CONTROL_BIOS;
+        state.sn76489.registers[6] = 0x4;
+        let old = SmsStateV6 {
+            z80: state.z80,
+            vdp: state.vdp,
+            memory: state.memory.clone(),
+            player_input: state.player_input,
+            pause_irq: state.pause_irq,
+            sn76489: old_sn76489(&state),
+            frame_count: state.frame_count,
+            io: state.io,
+            ym2413: state.ym2413.clone(),
+            memory_control: state.memory_control,
+        };
+        let state2: SmsState = save::read_state(&with_header(6, &old)[..]).unwrap();
+        assert_eq!(state2.sn76489.noise_kind, NoiseKind::Sms);
+        assert!(state2 == state);
+
+        // and the noise kind is saved from here on
+        state.sn76489.noise_kind = NoiseKind::Sg1000;
+        let mut bytes = Vec::new();
+        save::write_state(&state, &mut bytes).unwrap();
+        let state2: SmsState = save::read_state(&bytes[..]).unwrap();
+        assert_eq!(state2.sn76489.noise_kind, NoiseKind::Sg1000);
+    }
+}
@@ -11,14 +11,22 @@ pub use hardware::sms_player_input::*;
 pub use hardware::sms_roms::{self, *};
 pub use hardware::sms_vdp::{self, *};
 pub use hardware::sn76489::*;
+pub use hardware::ym2413::*;
 pub use hardware::z80::*;
 
+mod debugger_repl;
 mod emulator;
 mod help;
 mod inbox;
+mod migrate;
+mod movie;
+mod rewind;
 mod user_interface;
 
+pub use self::debugger_repl::*;
 pub use self::emulator::*;
 pub use self::help::*;
 pub use self::inbox::*;
+pub use self::movie::*;
+pub use self::rewind::*;
 pub use self::user_interface::*;
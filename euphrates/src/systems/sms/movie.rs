@@ -0,0 +1,270 @@
+//! Movies: gameplay recorded from power on, as player input alone.
+//!
+//! Unlike a `Recording`, a movie doesn't contain a state to start from, so it
+//! doesn't contain the ROM either. Instead its header has the ROM's CRC32, so
+//! that playing it against the wrong ROM, which would quickly desync, can be
+//! refused.
+
+use std;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use failure::Error;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use save;
+
+use super::*;
+
+/// Movies begin with these bytes, followed by a little endian `u32` version
+/// number.
+pub const MOVIE_MAGIC: [u8; 4] = *b"EUPV";
+
+/// The version of the layout of the movies we write.
+///
+/// Version 1 is the magic and version, then a `MovieHeader` written with
+/// `save::serialize_into`, then the run-length encoded player input, also
+/// written with `save::serialize_into` but compressed with deflate.
+///
+/// Version 2 is the same, but with the SC-3000 keyboard in `SmsPlayerInput`.
+/// Version 1 movies are still read, with no keys pressed.
+pub const MOVIE_VERSION: u32 = 2;
+
+/// Everything about a movie but its input.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct MovieHeader {
+    /// The `sms_roms::crc32` of the ROM the movie was recorded with.
+    pub rom_crc32: u32,
+
+    pub tv_system: TvSystem,
+
+    /// How many frames of input there are.
+    pub frame_count: u64,
+
+    /// Who made the movie.
+    pub author: String,
+}
+
+/// A movie: player input for each frame since power on.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Movie {
+    pub rom_crc32: u32,
+
+    pub tv_system: TvSystem,
+
+    pub author: String,
+
+    /// Run-length encoded, as in `Recording`.
+    pub player_statuses: Vec<(u32, SmsPlayerInput)>,
+}
+
+impl Movie {
+    /// An empty movie of `rom`.
+    ///
+    /// `rom` should be the ROM as it's loaded, after `sms_roms::format`.
+    pub fn new(rom: &[u8], tv_system: TvSystem, author: String) -> Self {
+        Movie {
+            rom_crc32: sms_roms::crc32(rom),
+            tv_system,
+            author,
+            player_statuses: Vec::new(),
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.player_statuses
+            .iter()
+            .map(|&(count, _)| count as u64)
+            .sum()
+    }
+
+    pub fn header(&self) -> MovieHeader {
+        MovieHeader {
+            rom_crc32: self.rom_crc32,
+            tv_system: self.tv_system,
+            frame_count: self.frame_count(),
+            author: self.author.clone(),
+        }
+    }
+}
+
+// This superfluous module with the `allow` attribute is necessary until the
+// `fail` crate begins using `dyn trait` syntax
+#[allow(bare_trait_objects)]
+mod movie_load_error {
+    use super::*;
+
+    /// Error generated when loading a movie with `read_movie`.
+    #[derive(Debug, Fail)]
+    pub enum MovieLoadError {
+        #[fail(display = "IO error {}", _0)]
+        Io(#[cause] std::io::Error),
+        #[fail(display = "Not a movie (it begins with {:?})", _0)]
+        BadMagic([u8; 4]),
+        #[fail(display = "Unsupported movie version {}", _0)]
+        UnsupportedVersion(u32),
+        #[fail(display = "{}", _0)]
+        Deserialization(Error),
+        #[fail(
+            display = "Movie was recorded with a ROM with CRC32 0x{:0>8X}, but this ROM's is 0x{:0>8X}",
+            expected, found
+        )]
+        RomMismatch { expected: u32, found: u32 },
+        #[fail(
+            display = "Movie header claims {} frames, but it has {}",
+            header, found
+        )]
+        FrameCountMismatch { header: u64, found: u64 },
+    }
+}
+
+pub use self::movie_load_error::MovieLoadError;
+
+impl From<std::io::Error> for MovieLoadError {
+    fn from(x: std::io::Error) -> Self {
+        MovieLoadError::Io(x)
+    }
+}
+
+pub fn write_movie<W>(movie: &Movie, mut w: W) -> Result<(), Error>
+where
+    W: Write,
+{
+    w.write_all(&MOVIE_MAGIC)?;
+    w.write_all(&MOVIE_VERSION.to_le_bytes())?;
+    save::serialize_into(&mut w, &movie.header())?;
+    let mut encoder = DeflateEncoder::new(w, Compression::default());
+    save::serialize_into(&mut encoder, &movie.player_statuses)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+pub fn save_movie<P>(path: P, movie: &Movie) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let file = File::create(path)?;
+    write_movie(movie, &file)
+}
+
+/// Read a movie written by `write_movie`, making sure it was recorded with
+/// `rom`.
+///
+/// As in `Movie::new`, `rom` should be the ROM as it's loaded.
+pub fn read_movie<R>(mut reader: R, rom: &[u8]) -> Result<Movie, MovieLoadError>
+where
+    R: Read,
+{
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MOVIE_MAGIC {
+        return Err(MovieLoadError::BadMagic(magic));
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != MOVIE_VERSION && version != 1 {
+        return Err(MovieLoadError::UnsupportedVersion(version));
+    }
+
+    let header: MovieHeader =
+        save::deserialize_from(&mut reader).map_err(MovieLoadError::Deserialization)?;
+    let found = sms_roms::crc32(rom);
+    if header.rom_crc32 != found {
+        return Err(MovieLoadError::RomMismatch {
+            expected: header.rom_crc32,
+            found,
+        });
+    }
+
+    let decoder = DeflateDecoder::new(reader);
+    let player_statuses = if version == 1 {
+        let old: Vec<(u32, SmsPlayerInputV1)> =
+            save::deserialize_from(decoder).map_err(MovieLoadError::Deserialization)?;
+        old.into_iter()
+            .map(|(count, input)| (count, input.into()))
+            .collect()
+    } else {
+        save::deserialize_from(decoder).map_err(MovieLoadError::Deserialization)?
+    };
+    let movie = Movie {
+        rom_crc32: header.rom_crc32,
+        tv_system: header.tv_system,
+        author: header.author,
+        player_statuses,
+    };
+    if movie.frame_count() != header.frame_count {
+        return Err(MovieLoadError::FrameCountMismatch {
+            header: header.frame_count,
+            found: movie.frame_count(),
+        });
+    }
+    Ok(movie)
+}
+
+pub fn load_movie<P>(path: P, rom: &[u8]) -> Result<Movie, MovieLoadError>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(path)?;
+    read_movie(&file, rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0] = 0xC3;
+        let mut movie = Movie::new(&rom, TvSystem::Pal, "someone".to_owned());
+        let mut held = SmsPlayerInput::default();
+        held.set_joypad_a(!joypad_a_bits::JOYPAD1_UP);
+        movie.player_statuses = vec![(60, SmsPlayerInput::default()), (30, held)];
+        assert_eq!(movie.frame_count(), 90);
+
+        let mut bytes = Vec::new();
+        write_movie(&movie, &mut bytes).unwrap();
+        assert_eq!(bytes[..4], MOVIE_MAGIC);
+        let movie2 = read_movie(&bytes[..], &rom).unwrap();
+        assert_eq!(movie2, movie);
+
+        // version 1, before the SC-3000 keyboard
+        let mut v1_bytes = Vec::new();
+        v1_bytes.extend_from_slice(&MOVIE_MAGIC);
+        v1_bytes.extend_from_slice(&1u32.to_le_bytes());
+        save::serialize_into(&mut v1_bytes, &movie.header()).unwrap();
+        let v1_statuses: Vec<(u32, SmsPlayerInputV1)> = movie
+            .player_statuses
+            .iter()
+            .map(|&(count, input)| (count, input.into()))
+            .collect();
+        let mut encoder = DeflateEncoder::new(&mut v1_bytes, Compression::default());
+        save::serialize_into(&mut encoder, &v1_statuses).unwrap();
+        encoder.finish().unwrap();
+        let movie3 = read_movie(&v1_bytes[..], &rom).unwrap();
+        assert_eq!(movie3, movie);
+
+        // a different ROM
+        rom[1] = 0x01;
+        match read_movie(&bytes[..], &rom) {
+            Err(MovieLoadError::RomMismatch { expected, found }) => {
+                assert_eq!(expected, movie.rom_crc32);
+                assert_eq!(found, sms_roms::crc32(&rom));
+            }
+            x => panic!("expected a ROM mismatch, got {:?}", x),
+        }
+
+        // not a movie at all
+        let mut state_bytes = Vec::new();
+        save::write_state(&17u32, &mut state_bytes).unwrap();
+        match read_movie(&state_bytes[..], &rom) {
+            Err(MovieLoadError::BadMagic(magic)) => assert_eq!(magic, save::STATE_MAGIC),
+            x => panic!("expected bad magic, got {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Keeping recent states around to rewind gameplay.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+
+use save;
+
+use super::*;
+
+/// Runs of differing bytes closer together than this are stored as one run.
+const MAX_GAP: usize = 8;
+
+/// How to turn one serialized state into the one before it.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+struct Delta {
+    len: usize,
+
+    /// Where each run of changed bytes starts, and what they are.
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// The changes that turn `current` into `previous`.
+    fn new(current: &[u8], previous: &[u8]) -> Self {
+        let differs = |i: usize| current.get(i) != Some(&previous[i]);
+        let mut runs = Vec::new();
+        let mut i = 0;
+        while i < previous.len() {
+            if !differs(i) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            let mut end = i + 1;
+            while i < previous.len() && i < end + MAX_GAP {
+                if differs(i) {
+                    end = i + 1;
+                }
+                i += 1;
+            }
+            runs.push((start, previous[start..end].to_vec()));
+        }
+        Delta {
+            len: previous.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, bytes: &mut Vec<u8>) {
+        bytes.resize(self.len, 0);
+        for &(start, ref run) in self.runs.iter() {
+            bytes[start..start + run.len()].copy_from_slice(run);
+        }
+    }
+}
+
+/// The last few `SmsState`s, so that gameplay can be rewound.
+///
+/// Only the newest state is kept in full; each older one is kept as the bytes
+/// that differ between it and the state after it. Since most of memory is the
+/// same from one frame to the next, this is much smaller than a full copy.
+/// The ROM isn't kept at all, except for the one in the most recent state
+/// `push`ed, which is given to every state `pop`ped.
+///
+/// See also `Sms::set_rewind_capacity`.
+#[derive(Clone, Default)]
+pub struct RewindBuffer {
+    capacity: usize,
+    rom: Arc<Box<[u8]>>,
+
+    /// The newest state, serialized without its ROM. Empty if there are no
+    /// states.
+    newest: Vec<u8>,
+
+    /// The changes that lead from `newest` back to each older state, oldest
+    /// first.
+    deltas: VecDeque<Delta>,
+}
+
+impl RewindBuffer {
+    /// A `RewindBuffer` that will keep the last `capacity` states.
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many states are there?
+    #[inline]
+    pub fn len(&self) -> usize {
+        if self.newest.is_empty() {
+            0
+        } else {
+            self.deltas.len() + 1
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest.clear();
+        self.deltas.clear();
+    }
+
+    /// Add `state` as the newest state, forgetting the oldest if we're at
+    /// capacity.
+    pub fn push(&mut self, mut state: SmsState) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.rom = mem::take(&mut state.memory.rom);
+        let bytes = save::serialize(&state).expect("Serializing an SmsState");
+        if !self.newest.is_empty() {
+            self.deltas.push_back(Delta::new(&bytes, &self.newest));
+        }
+        self.newest = bytes;
+        if self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Remove and return the newest state.
+    pub fn pop(&mut self) -> Option<SmsState> {
+        if self.newest.is_empty() {
+            return None;
+        }
+        let mut state: SmsState =
+            save::deserialize(&self.newest).expect("Deserializing an SmsState");
+        state.memory.rom = self.rom.clone();
+        match self.deltas.pop_back() {
+            Some(delta) => delta.apply(&mut self.newest),
+            None => self.newest.clear(),
+        }
+        Some(state)
+    }
+}
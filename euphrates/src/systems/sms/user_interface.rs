@@ -1,10 +1,9 @@
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::vec::IntoIter;
 
-use save;
+use save::{self, Artifact, PathPolicy};
 
 use super::*;
 
@@ -17,7 +16,7 @@ pub enum UserMessage {
 
 pub struct UiStatus {
     master_system: Box<dyn Sms>,
-    save_directory: Option<PathBuf>,
+    paths: Option<PathPolicy>,
     recording_status: RecordingStatus<SmsState>,
     messages: Arc<RwLock<Vec<UserMessage>>>,
 }
@@ -57,10 +56,16 @@ impl UiStatus {
     pub fn messages(&mut self) -> IntoIter<UserMessage> {
         use std::mem::swap;
 
+        let debugger_messages = match self.master_system.debugger() {
+            Some(debugger) => debugger.messages(),
+            None => Vec::new(),
+        };
+
         match self.messages.write() {
             Ok(ref mut vec) => {
                 let mut vec2 = Vec::new();
                 swap(vec.deref_mut(), &mut vec2);
+                vec2.extend(debugger_messages);
                 vec2.into_iter()
             }
             Err(e) => panic!("Poisoned RwLock {}", e),
@@ -68,12 +73,11 @@ impl UiStatus {
     }
 
     pub fn save_state(&mut self, name: Option<&str>) {
-        if let Some(mut path) = self.save_directory.clone() {
-            let filename = generate_filename(name);
+        if let Some(ref paths) = self.paths {
+            let path = paths.path(Artifact::State(&generate_filename(name)));
             let state = Sms::state(self.master_system.deref());
             do_in_thread(self.messages.clone(), move || {
-                path.push(format!("{}.sms_state", filename));
-                if let Err(e) = save::serialize_at(&path, &state) {
+                if let Err(e) = save::write_state_compressed_at(&path, &state) {
                     Some(UserMessage::Error(format!(
                         "Cannot save state to '{}': {}",
                         path.to_string_lossy(),
@@ -104,15 +108,13 @@ impl UiStatus {
     }
 
     pub fn save_recording(&mut self, name: Option<&str>) {
-        if let (Some(mut path), Some(recording)) = (
-            self.save_directory.clone(),
-            self.recording_status.recording(),
-        ) {
-            let filename = generate_filename(name);
+        if let (Some(ref paths), Some(recording)) =
+            (&self.paths, self.recording_status.recording())
+        {
+            let path = paths.path(Artifact::Recording(&generate_filename(name)));
             let recording2 = recording.clone();
             do_in_thread(self.messages.clone(), move || {
-                path.push(format!("{}.sms_record", filename));
-                if let Err(e) = save::serialize_at(&path, &recording2) {
+                if let Err(e) = save::write_state_compressed_at(&path, &recording2) {
                     Some(UserMessage::Error(format!(
                         "Cannot save recording to '{}': {}",
                         path.to_string_lossy(),
@@ -158,12 +160,12 @@ impl Ui {
     pub fn new(
         master_system: Box<dyn Sms>,
         helper: Box<dyn UiHelper>,
-        save_directory: Option<PathBuf>,
+        paths: Option<PathPolicy>,
     ) -> Self {
         Ui {
             status: UiStatus {
                 master_system,
-                save_directory,
+                paths,
                 recording_status: Default::default(),
                 messages: Default::default(),
             },
@@ -183,6 +185,21 @@ impl Ui {
         self.status.master_system
     }
 
+    /// Check the ROM's header checksum with `sms_roms::verify_checksum`,
+    /// leaving a `UserMessage::Error` if it's wrong.
+    ///
+    /// The game is still run either way: a bad checksum may just mean a hacked
+    /// or translated ROM.
+    pub fn verify_checksum(&mut self) {
+        let rom = self.status.master_system.state().memory.rom;
+        if let Some(mismatch) = sms_roms::verify_checksum(&rom) {
+            push_or_panic(
+                &mut self.status.messages,
+                UserMessage::Error(format!("{}", mismatch)),
+            );
+        }
+    }
+
     pub fn run(&mut self) -> Result<(), SmsEmulationError> {
         self.status.master_system.resume()?;
         loop {
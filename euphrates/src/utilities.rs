@@ -1,7 +1,13 @@
+#[cfg(feature = "std")]
 use std::fmt;
+#[cfg(feature = "std")]
 use std::marker::PhantomData;
+#[cfg(feature = "host_clock")]
 use std::thread;
-use std::time::{Duration, Instant};
+#[cfg(feature = "std")]
+use std::time::Duration;
+#[cfg(feature = "host_clock")]
+use std::time::Instant;
 
 pub fn to16(lo: u8, hi: u8) -> u16 {
     ((hi as u16) << 8) | (lo as u16)
@@ -19,6 +25,7 @@ pub fn clear_bit(dest: &mut u8, bit: u8) {
     *dest &= !(1 << bit);
 }
 
+#[cfg(feature = "std")]
 use serde::de::{Deserialize, Deserializer, Error as DeError, SeqAccess, Visitor};
 
 //// Deriving help
@@ -93,10 +100,13 @@ macro_rules! impl_hash_via {
 /// `Copy`, and `Default`, and if `array_deserialize!{len}` has been called.
 /// Ditto `ArrayWrap<Vec<[T; len]>>` if `vec_array_deserialize!{len}` has been
 /// called (which requires `array_deserialize`).
+#[cfg(feature = "std")]
 pub struct ArrayWrap<T>(pub T);
 
+#[cfg(feature = "std")]
 struct ArrayVisitor<T>(PhantomData<T>);
 
+#[cfg(feature = "std")]
 macro_rules! array_deserialize {
     ($array_len:expr) => {
         impl<'de, T> Visitor<'de> for ArrayVisitor<[T; $array_len]>
@@ -147,12 +157,16 @@ macro_rules! array_deserialize {
     };
 }
 
+#[cfg(feature = "std")]
 array_deserialize!{0x2000}
+#[cfg(feature = "std")]
 array_deserialize!{0x4000}
+#[cfg(feature = "std")]
 array_deserialize!{0x10000}
 
 //// Things that are immediately helpful for an emulator
 
+#[cfg(feature = "host_clock")]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct TimeInfo {
     pub total_cycles: u64,
@@ -169,6 +183,7 @@ pub struct TimeInfo {
 /// * `start_cycles` - How many cycles were on the clock at `start_time`?
 /// * `target_cycles` - How many cycles do we want on the clock when we get done sleeping?
 /// * `frequency` - What's the frequency (in Hz) the clock is running at?
+#[cfg(feature = "host_clock")]
 pub fn time_govern2(start_time: Instant, start_cycles: u64, target_cycles: u64, frequency: u64) {
     // We need target_cycles - start_cycles = (sleep_time + now - start_time) * frequency
 
@@ -176,16 +191,23 @@ pub fn time_govern2(start_time: Instant, start_cycles: u64, target_cycles: u64,
     let duration_since_start = now.duration_since(start_time);
 
     let cycles_diff = target_cycles.wrapping_sub(start_cycles);
-    let total_time_seconds = cycles_diff / frequency;
-    let remainder_cycles = cycles_diff % frequency;
-    let total_time_nanos = (1000000000 * remainder_cycles) / frequency;
-    let total_duration = Duration::new(total_time_seconds, total_time_nanos as u32);
+    let total_duration = cycles_duration(cycles_diff, frequency);
 
     if let Some(sleep_duration) = total_duration.checked_sub(duration_since_start) {
         thread::sleep(sleep_duration);
     }
 }
 
+/// How long does a clock running at `frequency` (in Hz) take to run `cycles`?
+#[cfg(feature = "std")]
+pub fn cycles_duration(cycles: u64, frequency: u64) -> Duration {
+    let seconds = cycles / frequency;
+    let remainder_cycles = cycles % frequency;
+    let nanos = (1000000000 * remainder_cycles) / frequency;
+    Duration::new(seconds, nanos as u32)
+}
+
+#[cfg(feature = "host_clock")]
 pub fn time_govern(time_info: TimeInfo) {
     debug_assert!(time_info.cycles_start <= time_info.total_cycles);
 
@@ -208,3 +230,28 @@ pub fn time_govern(time_info: TimeInfo) {
         }
     };
 }
+
+/// Run `f`, which does `count` of something (reads, instructions, frames),
+/// and print how many per second it managed, along with what `f` returned.
+///
+/// This is for the ignored throughput tests. Since they measure time, run them
+/// in release mode, like `cargo test --release --lib -- --ignored throughput
+/// --nocapture`.
+#[cfg(test)]
+pub fn print_throughput<F, T>(name: &str, what: &str, count: u64, f: F)
+where
+    F: FnOnce() -> T,
+    T: std::fmt::Display,
+{
+    let start = std::time::Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+    println!(
+        "{}: {:.0} {} per second ({})",
+        name,
+        count as f64 / seconds,
+        what,
+        result
+    );
+}
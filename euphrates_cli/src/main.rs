@@ -10,7 +10,6 @@ extern crate euphrates_x64;
 extern crate failure;
 extern crate sdl2;
 
-use std::path::PathBuf;
 use std::sync::Arc;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
@@ -19,16 +18,17 @@ use sdl2::Sdl;
 
 use euphrates::hardware::sms_roms;
 use euphrates::hardware::sn76489::{FakeSn76489, Sn76489State};
-use euphrates::host_multimedia::FakeAudio;
+use euphrates::host_multimedia::{FakeAudio, Interpolation};
 use euphrates::memo::NothingInbox;
-use euphrates::save;
+use euphrates::save::{self, Artifact, PathPolicy};
 use euphrates::systems::sms::{
-    self, DebuggingInbox, FakeSmsGraphics, Kind, Recording, Sms, SmsMemoryMapper, SmsState,
-    TvSystem, TypeWrap,
+    self, ControllerPort, DebuggingInbox, FakeSmsGraphics, Kind, PortDevice, Recording, Sms,
+    SmsBuilder, SmsMemoryMapper, SmsState, TvSystem, TypeWrap,
 };
 
+use euphrates_sdl2::simple_audio::Audio;
+use euphrates_sdl2::simple_graphics::{Scanlines, Window, WindowHandle};
 use euphrates_sdl2::sms_user_interface;
-use euphrates_sdl2::{simple_audio::Audio, simple_graphics::Window};
 
 #[cfg(all(feature = "state_memory", not(feature = "euphrates_virtual_memory")))]
 type MemoryType = sms::SmsMemoryState;
@@ -41,14 +41,22 @@ type MemoryType = euphrates_virtual_memory::SmsVirtualMemory;
 
 type Result<T> = std::result::Result<T, Error>;
 
-fn new_sms(sdl: &Sdl, state: SmsState, matches: &ArgMatches) -> Result<Box<dyn Sms>> {
-    let frequency = match matches.value_of("frequency").expect("unwrapping frequency") {
-        "ntsc" => Some(sms::NTSC_Z80_FREQUENCY),
-        "pal" => Some(sms::PAL_Z80_FREQUENCY),
-        "unlimited" => None,
-        x => Some(x.parse::<u64>().unwrap()),
+/// The `Sms`, and, if it has a window, a handle to the window.
+fn new_sms(
+    sdl: &Sdl,
+    state: SmsState,
+    matches: &ArgMatches,
+) -> Result<(Box<dyn Sms>, Option<WindowHandle>)> {
+    let frequency = match matches.value_of("frequency") {
+        Some("ntsc") => Some(sms::NTSC_Z80_FREQUENCY),
+        Some("pal") => Some(sms::PAL_Z80_FREQUENCY),
+        Some("unlimited") => None,
+        Some(x) => Some(x.parse::<u64>().unwrap()),
+        None => Some(sms::z80_frequency(state.vdp.tv_system)),
     };
 
+    let mut window = None;
+
     macro_rules! eval_args {
         ($sn76489:expr, $audio:expr, $inbox:expr, $graphics:expr) => {
             Ok(sms::new_sms(
@@ -68,6 +76,20 @@ fn new_sms(sdl: &Sdl, state: SmsState, matches: &ArgMatches) -> Result<Box<dyn S
                     graphics.set_size(768, 576);
                     graphics.set_texture_size(256, 192);
                     graphics.set_title("Euphrates");
+                    graphics.set_aspect_correction(
+                        matches.value_of("aspect_correction") == Some("true"),
+                    );
+                    match matches.value_of("scale") {
+                        Some("auto") | None => {}
+                        Some(x) => graphics.set_scale(Some(x.parse::<u32>().unwrap())),
+                    }
+                    if let Some(x) = matches.value_of("scanlines") {
+                        graphics.set_scanlines(Scanlines {
+                            on: true,
+                            intensity: x.parse::<f32>().unwrap(),
+                        });
+                    }
+                    window = Some(graphics.handle());
                     eval_args!($sn76489, $audio, $inbox, graphics)
                 }
                 _ => eval_args!($sn76489, $audio, $inbox, FakeSmsGraphics::default()),
@@ -87,14 +109,45 @@ fn new_sms(sdl: &Sdl, state: SmsState, matches: &ArgMatches) -> Result<Box<dyn S
         };
     }
 
-    eval_args!()
-}
+    let result: Result<Box<dyn Sms>> = eval_args!();
+    let mut sms = result?;
 
-fn run_rom(matches: &ArgMatches) -> Result<()> {
-    let rom = {
-        let filename = matches.value_of("rom").unwrap();
-        sms_roms::from_file(&filename)?
+    let port_a = match matches.value_of("port_a").expect("unwrapping port_a") {
+        "light_phaser" => PortDevice::LightPhaser,
+        "paddle" => PortDevice::Paddle,
+        "none" => PortDevice::None,
+        _ => PortDevice::Joypad,
+    };
+    sms.set_port_device(ControllerPort::A, port_a);
+    sms.set_fm(matches.value_of("fm") == Some("true"));
+    sms.set_sc3000_keyboard(matches.value_of("keyboard") == Some("true"));
+    sms.set_overscan(matches.value_of("overscan") == Some("true"));
+
+    let interpolation = match matches.value_of("interpolation") {
+        Some("sinc") => Interpolation::Sinc,
+        _ => Interpolation::Linear,
     };
+    let audio_rate = match matches.value_of("audio_rate") {
+        Some("native") | None => None,
+        Some(x) => Some(x.parse::<u32>().unwrap()),
+    };
+    sms.set_audio_rate(audio_rate, interpolation);
+
+    if let Some(speed) = matches.value_of("speed") {
+        sms.set_speed(speed.parse::<f64>()?)?;
+    }
+
+    Ok((sms, window))
+}
+
+fn rom_state(matches: &ArgMatches) -> Result<SmsState> {
+    let rom_filename = matches.value_of("rom").unwrap();
+    let rom = std::fs::read(rom_filename)?;
+    if sms_roms::has_copier_header(&rom) {
+        eprintln!("Warning: stripping a 512 byte copier header from the ROM");
+    }
+    let rom = sms_roms::format(rom.into_boxed_slice())?;
+
     let tv_system = match matches.value_of("tv").unwrap() {
         "ntsc" => TvSystem::Ntsc,
         _ => TvSystem::Pal,
@@ -105,30 +158,84 @@ fn run_rom(matches: &ArgMatches) -> Result<()> {
         _ => Kind::Gg,
     };
     let memory_mapper = match matches.value_of("memory_map").unwrap() {
-        "sg1000_1" => SmsMemoryMapper::Sg1000(1),
-        "sg1000_2" => SmsMemoryMapper::Sg1000(2),
-        "sg1000_4" => SmsMemoryMapper::Sg1000(4),
-        "codemasters" => SmsMemoryMapper::Codemasters,
-        _ => SmsMemoryMapper::Sega,
+        "sg1000_1" => Some(SmsMemoryMapper::Sg1000(1)),
+        "sg1000_2" => Some(SmsMemoryMapper::Sg1000(2)),
+        "sg1000_4" => Some(SmsMemoryMapper::Sg1000(4)),
+        "codemasters" => Some(SmsMemoryMapper::Codemasters),
+        "korean" => Some(SmsMemoryMapper::Korean),
+        "multicart" => Some(SmsMemoryMapper::Multicart),
+        "sega" => Some(SmsMemoryMapper::Sega),
+        _ => None,
     };
 
-    let state = SmsState::from_rom(Arc::new(rom), memory_mapper, tv_system, kind);
+    Ok(SmsState::from_rom(Arc::new(rom), memory_mapper, tv_system, kind))
+}
 
-    let sdl = sdl2::init().unwrap();
+/// Files saved while playing `filename` go in the save directory, if one was
+/// given, named after `filename`.
+fn save_paths(matches: &ArgMatches, filename: &str) -> Option<PathPolicy> {
+    matches
+        .value_of("save_directory")
+        .map(|s| PathPolicy::beside(filename).in_directory(s))
+}
+
+fn run_rom(matches: &ArgMatches) -> Result<()> {
+    use std::fs;
 
-    let sms = new_sms(&sdl, state, matches)?;
+    let rom_filename = matches.value_of("rom").unwrap();
+    let paths = save_paths(matches, rom_filename);
 
-    let save_directory = match matches.value_of("save_directory") {
-        None => None,
-        Some(s) => Some(PathBuf::from(s)),
+    // without a save directory, cartridge RAM is kept next to the ROM
+    let sav_path = match paths {
+        Some(ref p) => p.path(Artifact::CartridgeRam),
+        None => PathPolicy::beside(rom_filename).path(Artifact::CartridgeRam),
     };
 
-    let mut user_interface = sms_user_interface::ui(sms, &sdl, save_directory, &[])?;
+    let state = rom_state(matches)?;
+
+    let sdl = sdl2::init().unwrap();
+
+    let (mut sms, window) = new_sms(&sdl, state, matches)?;
+
+    if let Some(bios_filename) = matches.value_of("bios") {
+        let bios = std::fs::read(bios_filename)?;
+        sms.set_bios(Some(Arc::new(bios.into_boxed_slice())));
+        sms.power_cycle();
+    }
+
+    if sav_path.exists() {
+        sms.load_cartridge_ram(&fs::read(&sav_path)?)?;
+    }
+
+    let mut user_interface = sms_user_interface::ui(sms, &sdl, paths, &[], window)?;
+    if matches.value_of("verify_checksum") == Some("true") {
+        user_interface.verify_checksum();
+    }
     user_interface.run()?;
 
+    if let Some(data) = user_interface.master_system().save_cartridge_ram() {
+        fs::write(&sav_path, data)?;
+    }
+
     Ok(())
 }
 
+fn run_repl(matches: &ArgMatches) -> Result<()> {
+    use std::io;
+
+    let state = rom_state(matches)?;
+
+    let mut sms = SmsBuilder::new()
+        .inbox(DebuggingInbox::default())
+        .memory(TypeWrap::<MemoryType>::default())
+        .frequency(None)
+        .build(state)?;
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    sms::debugger_repl(&mut *sms, stdin.lock(), stdout.lock())
+}
+
 fn run_playback(matches: &ArgMatches) -> Result<()> {
     use std::time::Instant;
 
@@ -136,9 +243,9 @@ fn run_playback(matches: &ArgMatches) -> Result<()> {
 
     let sdl = sdl2::init().unwrap();
 
-    let recording: Recording<SmsState> = save::deserialize_at(&load_filename)?;
+    let recording: Recording<SmsState> = sms::read_recording_at(&load_filename)?;
 
-    let sms = new_sms(&sdl, recording.state, matches)?;
+    let (sms, _) = new_sms(&sdl, recording.state, matches)?;
 
     let mut user_interface =
         euphrates_sdl2::sms_user_interface::playback_ui(sms, &recording.player_statuses);
@@ -166,18 +273,15 @@ fn run_playback(matches: &ArgMatches) -> Result<()> {
 
 fn run_load(matches: &ArgMatches) -> Result<()> {
     let load_filename = matches.value_of("loadfile").unwrap();
-    let save_directory = match matches.value_of("save_directory") {
-        None => None,
-        Some(s) => Some(PathBuf::from(s)),
-    };
+    let paths = save_paths(matches, load_filename);
 
     let sdl = sdl2::init().unwrap();
 
-    let state: SmsState = save::deserialize_at(&load_filename)?;
+    let state: SmsState = save::read_state_at(&load_filename)?;
 
-    let sms = new_sms(&sdl, state, matches)?;
+    let (sms, window) = new_sms(&sdl, state, matches)?;
 
-    let mut user_interface = sms_user_interface::ui(sms, &sdl, save_directory, &[])?;
+    let mut user_interface = sms_user_interface::ui(sms, &sdl, paths, &[], window)?;
 
     user_interface.run()?;
 
@@ -186,18 +290,15 @@ fn run_load(matches: &ArgMatches) -> Result<()> {
 
 fn run_record(matches: &ArgMatches) -> Result<()> {
     let load_filename = matches.value_of("loadfile").unwrap();
-    let save_directory = match matches.value_of("save_directory") {
-        None => None,
-        Some(s) => Some(PathBuf::from(s)),
-    };
+    let paths = save_paths(matches, load_filename);
 
     let sdl = sdl2::init().unwrap();
 
-    let recording: Recording<SmsState> = save::deserialize_at(&load_filename)?;
-    let sms = new_sms(&sdl, recording.state, matches)?;
+    let recording: Recording<SmsState> = sms::read_recording_at(&load_filename)?;
+    let (sms, window) = new_sms(&sdl, recording.state, matches)?;
 
     let mut user_interface =
-        sms_user_interface::ui(sms, &sdl, save_directory, &recording.player_statuses)?;
+        sms_user_interface::ui(sms, &sdl, paths, &recording.player_statuses, window)?;
 
     user_interface.run()?;
 
@@ -207,11 +308,22 @@ fn run_record(matches: &ArgMatches) -> Result<()> {
 fn run() -> Result<()> {
     let memory_map_arg = Arg::with_name("memory_map")
         .long("memory_map")
-        .value_name("(sega|codemasters|sg1000_1|sg1000_2|sg1000_4)")
-        .help("Specify the sega, codemasters, or sg1000 memory map.")
+        .value_name("(auto|sega|codemasters|korean|multicart|sg1000_1|sg1000_2|sg1000_4)")
+        .help(
+            "Specify the sega, codemasters, korean, multicart, or sg1000 memory map, or guess it.",
+        )
         .takes_value(true)
-        .possible_values(&["sega", "codemasters", "sg1000_1", "sg1000_2", "sg1000_4"])
-        .default_value("sega");
+        .possible_values(&[
+            "auto",
+            "sega",
+            "codemasters",
+            "korean",
+            "multicart",
+            "sg1000_1",
+            "sg1000_2",
+            "sg1000_4",
+        ])
+        .default_value("auto");
     let save_directory_arg = Arg::with_name("save_directory")
         .long("save_directory")
         .value_name("DIRECTORY")
@@ -257,9 +369,8 @@ fn run() -> Result<()> {
         .long("frequency")
         .value_name("(unlimited|ntsc|pal|number)")
         .takes_value(true)
-        .default_value("ntsc")
         .validator(frequency_validator)
-        .help("Frequency of the Z80 processor");
+        .help("Frequency of the Z80 processor [default: that of the TV system]");
 
     let sound_arg = Arg::with_name("sound")
         .long("sound")
@@ -277,6 +388,123 @@ fn run() -> Result<()> {
         .possible_values(&["true", "false"])
         .help("Should graphics be displayed?");
 
+    let verify_checksum_arg = Arg::with_name("verify_checksum")
+        .long("verify_checksum")
+        .value_name("BOOL")
+        .takes_value(true)
+        .default_value("true")
+        .possible_values(&["true", "false"])
+        .help("Should the ROM's header checksum be checked?");
+
+    let port_a_arg = Arg::with_name("port_a")
+        .long("port_a")
+        .value_name("(joypad|light_phaser|paddle|none)")
+        .takes_value(true)
+        .default_value("joypad")
+        .possible_values(&["joypad", "light_phaser", "paddle", "none"])
+        .help("What's plugged into controller port A? The mouse aims a light phaser.");
+
+    let fm_arg = Arg::with_name("fm")
+        .long("fm")
+        .value_name("BOOL")
+        .takes_value(true)
+        .default_value("false")
+        .possible_values(&["true", "false"])
+        .help("Should there be a YM2413 FM sound chip, as in a Japanese Master System?");
+
+    let overscan_arg = Arg::with_name("overscan")
+        .long("overscan")
+        .value_name("BOOL")
+        .takes_value(true)
+        .default_value("false")
+        .possible_values(&["true", "false"])
+        .help("Should the border around the picture be shown, as on a TV?");
+
+    let keyboard_arg = Arg::with_name("keyboard")
+        .long("keyboard")
+        .value_name("BOOL")
+        .takes_value(true)
+        .default_value("false")
+        .possible_values(&["true", "false"])
+        .help(
+            "Should there be an SC-3000 keyboard in place of the joypads? Every key types on it.",
+        );
+
+    let scale_validator = |s: String| {
+        if s == "auto" {
+            return Ok(());
+        }
+        match s.parse::<u32>() {
+            Ok(x) if x > 0 => Ok(()),
+            _ => Err("scale must be auto or a positive integer".to_owned()),
+        }
+    };
+
+    let scale_arg = Arg::with_name("scale")
+        .long("scale")
+        .value_name("(auto|N)")
+        .takes_value(true)
+        .default_value("auto")
+        .validator(scale_validator)
+        .help("Draw each pixel N window pixels tall, or at the largest integer scale that fits.");
+
+    let aspect_correction_arg = Arg::with_name("aspect_correction")
+        .long("aspect_correction")
+        .value_name("BOOL")
+        .takes_value(true)
+        .default_value("false")
+        .possible_values(&["true", "false"])
+        .help("Should pixels be drawn wider than they are tall, as on a TV?");
+
+    let scanlines_validator = |s: String| match s.parse::<f32>() {
+        Ok(x) if (0.0..=1.0).contains(&x) => Ok(()),
+        _ => Err("scanline intensity must be between 0 and 1".to_owned()),
+    };
+
+    let scanlines_arg = Arg::with_name("scanlines")
+        .long("scanlines")
+        .value_name("INTENSITY")
+        .takes_value(true)
+        .validator(scanlines_validator)
+        .help("Darken every other row, like a CRT, by INTENSITY from 0 to 1. C toggles them.");
+
+    let audio_rate_validator = |s: String| {
+        if s == "native" {
+            return Ok(());
+        }
+        match s.parse::<u32>() {
+            Ok(x) if x > 0 => Ok(()),
+            _ => Err("audio rate must be native or a positive integer".to_owned()),
+        }
+    };
+    let audio_rate_arg = Arg::with_name("audio_rate")
+        .long("audio_rate")
+        .value_name("(native|number)")
+        .takes_value(true)
+        .default_value("native")
+        .validator(audio_rate_validator)
+        .help("Sample rate to resample audio to, like 44100");
+
+    let interpolation_arg = Arg::with_name("interpolation")
+        .long("interpolation")
+        .value_name("(linear|sinc)")
+        .takes_value(true)
+        .default_value("linear")
+        .possible_values(&["linear", "sinc"])
+        .help("How to resample audio, if --audio_rate isn't native");
+
+    let speed_validator = |s: String| match s.parse::<f64>() {
+        Ok(x) if x > 0.0 => Ok(()),
+        _ => Err("speed must be a positive number".to_owned()),
+    };
+    let speed_arg = Arg::with_name("speed")
+        .long("speed")
+        .value_name("number")
+        .takes_value(true)
+        .default_value("1")
+        .validator(speed_validator)
+        .help("How many times faster than normal to run, like 2 or 0.5; no audio unless 1");
+
     let app = App::new("Euphrates")
         .version("0.1.0")
         .author("Michael Benfield")
@@ -299,7 +527,40 @@ fn run() -> Result<()> {
                 .arg(kind_arg.clone())
                 .arg(sound_arg.clone())
                 .arg(graphics_arg.clone())
-                .arg(frequency_arg.clone()),
+                .arg(frequency_arg.clone())
+                .arg(verify_checksum_arg.clone())
+                .arg(
+                    Arg::with_name("bios")
+                        .long("bios")
+                        .value_name("FILE")
+                        .help("Boot from the BIOS in this file before the ROM")
+                        .takes_value(true),
+                )
+                .arg(port_a_arg.clone())
+                .arg(fm_arg.clone())
+                .arg(keyboard_arg.clone())
+                .arg(overscan_arg.clone())
+                .arg(scale_arg.clone())
+                .arg(aspect_correction_arg.clone())
+                .arg(scanlines_arg.clone())
+                .arg(audio_rate_arg.clone())
+                .arg(interpolation_arg.clone())
+                .arg(speed_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Debug a ROM image with commands from standard input")
+                .arg(
+                    Arg::with_name("rom")
+                        .long("rom")
+                        .value_name("FILE")
+                        .help("Specify the filename containing a ROM image")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(tv_arg.clone())
+                .arg(memory_map_arg.clone())
+                .arg(kind_arg.clone()),
         )
         .subcommand(
             SubCommand::with_name("load")
@@ -316,7 +577,17 @@ fn run() -> Result<()> {
                 )
                 .arg(frequency_arg.clone())
                 .arg(sound_arg.clone())
-                .arg(graphics_arg.clone()),
+                .arg(graphics_arg.clone())
+                .arg(port_a_arg.clone())
+                .arg(fm_arg.clone())
+                .arg(keyboard_arg.clone())
+                .arg(overscan_arg.clone())
+                .arg(scale_arg.clone())
+                .arg(aspect_correction_arg.clone())
+                .arg(scanlines_arg.clone())
+                .arg(audio_rate_arg.clone())
+                .arg(interpolation_arg.clone())
+                .arg(speed_arg.clone()),
         )
         .subcommand(
             SubCommand::with_name("loadrecord")
@@ -333,7 +604,17 @@ fn run() -> Result<()> {
                 )
                 .arg(frequency_arg.clone())
                 .arg(sound_arg.clone())
-                .arg(graphics_arg.clone()),
+                .arg(graphics_arg.clone())
+                .arg(port_a_arg.clone())
+                .arg(fm_arg.clone())
+                .arg(keyboard_arg.clone())
+                .arg(overscan_arg.clone())
+                .arg(scale_arg.clone())
+                .arg(aspect_correction_arg.clone())
+                .arg(scanlines_arg.clone())
+                .arg(audio_rate_arg.clone())
+                .arg(interpolation_arg.clone())
+                .arg(speed_arg.clone()),
         )
         .subcommand(
             SubCommand::with_name("playback")
@@ -349,12 +630,23 @@ fn run() -> Result<()> {
                 )
                 .arg(frequency_arg.clone())
                 .arg(sound_arg.clone())
-                .arg(graphics_arg.clone()),
+                .arg(graphics_arg.clone())
+                .arg(port_a_arg.clone())
+                .arg(fm_arg.clone())
+                .arg(keyboard_arg.clone())
+                .arg(overscan_arg.clone())
+                .arg(scale_arg.clone())
+                .arg(aspect_correction_arg.clone())
+                .arg(scanlines_arg.clone())
+                .arg(audio_rate_arg.clone())
+                .arg(interpolation_arg.clone())
+                .arg(speed_arg.clone()),
         );
     let matches = app.get_matches();
 
     return match matches.subcommand() {
         ("rom", Some(sub)) => run_rom(&sub),
+        ("repl", Some(sub)) => run_repl(&sub),
         ("load", Some(sub)) => run_load(&sub),
         ("loadrecord", Some(sub)) => run_record(&sub),
         ("playback", Some(sub)) => run_playback(&sub),
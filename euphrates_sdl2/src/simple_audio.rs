@@ -28,14 +28,14 @@ impl Audio {
                 None,
                 &sdl2::audio::AudioSpecDesired {
                     freq: Some(DEFAULT_FREQUENCY as i32),
-                    channels: Some(1),
+                    channels: Some(2),
                     samples: Some(DEFAULT_BUFFER_SIZE as u16),
                 },
             )
             .map_err(|s| format_err!("Unable to create SDL audio subsystem: {}", s))?;
 
         Ok(Audio {
-            buffer: vec![0i16; DEFAULT_BUFFER_SIZE as usize].into_boxed_slice(),
+            buffer: vec![0i16; 2 * DEFAULT_BUFFER_SIZE as usize].into_boxed_slice(),
             queue,
             audio_subsystem,
         })
@@ -50,13 +50,13 @@ impl SimpleAudio for Audio {
                 None,
                 &sdl2::audio::AudioSpecDesired {
                     freq: Some(frequency as i32),
-                    channels: Some(1),
+                    channels: Some(2),
                     samples: Some(buffer_size as u16),
                 },
             )
             .map_err(|s| format_err!("SDL audio error {}", s))?;
 
-        self.buffer = vec![0i16; buffer_size as usize].into_boxed_slice();
+        self.buffer = vec![0i16; 2 * buffer_size as usize].into_boxed_slice();
 
         Ok(())
     }
@@ -72,13 +72,15 @@ impl SimpleAudio for Audio {
     }
 
     #[inline]
-    fn buffer_set(&mut self, i: usize, value: i16) {
-        self.buffer[i] = value
+    fn buffer_set(&mut self, i: usize, left: i16, right: i16) {
+        // SDL wants interleaved stereo samples
+        self.buffer[2 * i] = left;
+        self.buffer[2 * i + 1] = right;
     }
 
     #[inline]
     fn buffer_len(&self) -> usize {
-        self.buffer.len()
+        self.buffer.len() / 2
     }
 
     fn queue_buffer(&mut self) -> std::result::Result<(), Error> {
@@ -1,12 +1,123 @@
 use std;
+use std::cell::Cell;
+use std::rc::Rc;
 
 use failure::Error;
 use sdl2;
 
-use euphrates::host_multimedia::{SimpleColor, SimpleGraphics};
+use euphrates::host_multimedia::{self, SimpleColor, SimpleGraphics};
 
 const DEFAULT_SIZE: usize = 256;
 
+/// The width of the Master System's pixels over their height.
+///
+/// On an NTSC TV, the VDP's pixels are a little wider than they are tall.
+pub const SMS_PIXEL_ASPECT_RATIO: f64 = 8.0 / 7.0;
+
+/// Where in a window a texture is drawn.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Placement {
+    /// How many window pixels tall each texture pixel is.
+    pub scale: u32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub texture_width: u32,
+    pub texture_height: u32,
+}
+
+impl Placement {
+    /// Center a texture of size `texture` in a window of size `window`.
+    ///
+    /// Each texture pixel is drawn `scale` window pixels tall and
+    /// `aspect_ratio` times that wide. If `scale` is `None`, it's the largest
+    /// integer at which the texture fits in the window, or 1 if it doesn't fit
+    /// at all.
+    pub fn new(
+        window: (u32, u32),
+        texture: (u32, u32),
+        scale: Option<u32>,
+        aspect_ratio: f64,
+    ) -> Self {
+        let (window_width, window_height) = window;
+        let (texture_width, texture_height) = texture;
+        let scale = scale.unwrap_or_else(|| {
+            let fit_width = window_width as f64 / (texture_width as f64 * aspect_ratio);
+            let fit_height = window_height / texture_height.max(1);
+            (fit_width as u32).min(fit_height).max(1)
+        });
+        let width = (texture_width as f64 * aspect_ratio * scale as f64).round() as u32;
+        let height = texture_height * scale;
+        Placement {
+            scale,
+            x: (window_width as i32 - width as i32) / 2,
+            y: (window_height as i32 - height as i32) / 2,
+            width,
+            height,
+            texture_width,
+            texture_height,
+        }
+    }
+
+    /// The texture pixel at the window point `(x, y)`.
+    ///
+    /// Points in the letterboxing give pixels outside the texture.
+    pub fn to_texture(&self, x: i32, y: i32) -> (i32, i32) {
+        let map = |z: i32, offset: i32, size: u32, texture_size: u32| {
+            ((z - offset) as f64 * texture_size as f64 / size.max(1) as f64).floor() as i32
+        };
+        (
+            map(x, self.x, self.width, self.texture_width),
+            map(y, self.y, self.height, self.texture_height),
+        )
+    }
+}
+
+/// Scanlines drawn over a `Window`'s texture, like on a CRT.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Scanlines {
+    pub on: bool,
+
+    /// From 0 (invisible) to 1 (black). See `host_multimedia::darken`.
+    pub intensity: f32,
+}
+
+/// Scanlines are this intense unless told otherwise.
+pub const DEFAULT_SCANLINE_INTENSITY: f32 = 0.3;
+
+/// Some of a `Window`'s state, shared with the `Window`, for a UI to use once
+/// the `Window` has been moved into an `Sms`.
+#[derive(Clone, Debug, Default)]
+pub struct WindowHandle {
+    placement: Rc<Cell<Placement>>,
+    scanlines: Rc<Cell<Scanlines>>,
+}
+
+impl WindowHandle {
+    /// Where the `Window` last drew its texture, for translating mouse
+    /// positions into texture pixels.
+    pub fn placement(&self) -> Placement {
+        self.placement.get()
+    }
+
+    pub fn scanlines(&self) -> Scanlines {
+        self.scanlines.get()
+    }
+
+    /// See `Window::set_scanlines`.
+    pub fn set_scanlines(&self, scanlines: Scanlines) {
+        self.scanlines.set(scanlines);
+    }
+
+    /// Turn scanlines on or off, keeping their intensity.
+    pub fn toggle_scanlines(&self) {
+        let mut scanlines = self.scanlines();
+        scanlines.on = !scanlines.on;
+        self.set_scanlines(scanlines);
+    }
+}
+
 pub struct Window {
     // Fields are dropped in the same order they are declared, so the order of
     // the first three fields here shouldn't change.
@@ -22,6 +133,9 @@ pub struct Window {
     height: usize,
     texture_width: usize,
     texture_height: usize,
+    scale: Option<u32>,
+    aspect_ratio: f64,
+    handle: WindowHandle,
 }
 
 impl Window {
@@ -68,6 +182,15 @@ impl Window {
             height: DEFAULT_SIZE,
             texture_width: DEFAULT_SIZE,
             texture_height: DEFAULT_SIZE,
+            scale: None,
+            aspect_ratio: 1.0,
+            handle: WindowHandle {
+                placement: Default::default(),
+                scanlines: Rc::new(Cell::new(Scanlines {
+                    on: false,
+                    intensity: DEFAULT_SCANLINE_INTENSITY,
+                })),
+            },
         })
     }
 
@@ -108,6 +231,57 @@ impl Window {
         self.height = use_height;
     }
 
+    /// Draw each texture pixel `scale` window pixels tall, resizing the
+    /// window to fit, or, if `scale` is `None`, at the largest integer scale
+    /// that fits the window. Either way the texture is centered, with black
+    /// letterboxing.
+    ///
+    /// `None` by default.
+    pub fn set_scale(&mut self, scale: Option<u32>) {
+        self.scale = scale;
+        self.fit_scale();
+    }
+
+    #[inline]
+    pub fn scale(&self) -> Option<u32> {
+        self.scale
+    }
+
+    /// Draw texture pixels `SMS_PIXEL_ASPECT_RATIO` times as wide as they are
+    /// tall, as on a TV, rather than square.
+    ///
+    /// Off by default.
+    pub fn set_aspect_correction(&mut self, x: bool) {
+        self.aspect_ratio = if x { SMS_PIXEL_ASPECT_RATIO } else { 1.0 };
+        self.fit_scale();
+    }
+
+    /// Darken every other row of the window where the texture is drawn, after
+    /// scaling, to look like a CRT's scanlines.
+    ///
+    /// Off by default. This can also be changed through a `WindowHandle`.
+    pub fn set_scanlines(&mut self, scanlines: Scanlines) {
+        self.handle.set_scanlines(scanlines);
+    }
+
+    #[inline]
+    pub fn scanlines(&self) -> Scanlines {
+        self.handle.scanlines()
+    }
+
+    pub fn handle(&self) -> WindowHandle {
+        self.handle.clone()
+    }
+
+    // With a fixed scale, resize the window to the scaled texture
+    fn fit_scale(&mut self) {
+        if let Some(scale) = self.scale {
+            let texture_size = (self.texture_width as u32, self.texture_height as u32);
+            let placement = Placement::new((0, 0), texture_size, Some(scale), self.aspect_ratio);
+            self.set_size(placement.width as usize, placement.height as usize);
+        }
+    }
+
     #[inline]
     pub fn texture_size(&self) -> (usize, usize) {
         (self.texture_width, self.texture_height)
@@ -191,14 +365,78 @@ impl SimpleGraphics for Window {
 
     #[inline]
     fn render(&mut self) -> Result<(), Error> {
+        let placement = Placement::new(
+            self.canvas.window().size(),
+            self.resolution(),
+            self.scale,
+            self.aspect_ratio,
+        );
+        self.handle.placement.set(placement);
+        let destination =
+            sdl2::rect::Rect::new(placement.x, placement.y, placement.width, placement.height);
+
+        self.canvas
+            .set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
         self.canvas.clear();
         self.texture
             .update(None, &self.pixels, self.texture_width * 4)
             .map_err(|e| format_err!("SDL rendering error {}", e))?;
         self.canvas
-            .copy(&self.texture, None, None)
+            .copy(&self.texture, None, destination)
             .map_err(|s| format_err!("SDL rendering error {}", s))?;
+
+        // Blending black over these rows with this alpha is the same as
+        // `host_multimedia::apply_scanlines` on the output
+        let scanlines = self.scanlines();
+        if scanlines.on {
+            let rows: Vec<sdl2::rect::Rect> = host_multimedia::scanline_rows(placement.height)
+                .map(|y| {
+                    sdl2::rect::Rect::new(placement.x, placement.y + y as i32, placement.width, 1)
+                })
+                .collect();
+            let alpha = host_multimedia::scanline_alpha(scanlines.intensity);
+            self.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+            self.canvas
+                .set_draw_color(sdl2::pixels::Color::RGBA(0, 0, 0, alpha));
+            self.canvas
+                .fill_rects(&rows)
+                .map_err(|s| format_err!("SDL rendering error {}", s))?;
+            self.canvas.set_blend_mode(sdl2::render::BlendMode::None);
+        }
         self.canvas.present();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placement() {
+        // the largest integer scale that fits, centered
+        let p = Placement::new((800, 600), (256, 192), None, 1.0);
+        assert_eq!(p.scale, 3);
+        assert_eq!((p.x, p.y, p.width, p.height), (16, 12, 768, 576));
+
+        // with aspect correction, the width is what limits it
+        let p = Placement::new((800, 600), (256, 192), None, SMS_PIXEL_ASPECT_RATIO);
+        assert_eq!(p.scale, 2);
+        assert_eq!((p.x, p.y, p.width, p.height), (107, 108, 585, 384));
+
+        // a fixed scale, even if it doesn't fit
+        let p = Placement::new((320, 240), (256, 192), Some(2), 1.0);
+        assert_eq!((p.x, p.y, p.width, p.height), (-96, -72, 512, 384));
+
+        // too small a window still gets a scale of 1
+        let p = Placement::new((100, 100), (160, 144), None, 1.0);
+        assert_eq!(p.scale, 1);
+
+        // window points to texture pixels
+        let p = Placement::new((800, 600), (256, 192), None, 1.0);
+        assert_eq!(p.to_texture(16, 12), (0, 0));
+        assert_eq!(p.to_texture(16 + 767, 12 + 575), (255, 191));
+        assert_eq!(p.to_texture(16 + 3 * 100 + 2, 12 + 3 * 50), (100, 50));
+        assert_eq!(p.to_texture(0, 0), (-6, -4));
+    }
+}
@@ -1,14 +1,16 @@
-use std::path::PathBuf;
-
 use failure::Error;
 
 use sdl2;
 
+use euphrates::save::PathPolicy;
 use euphrates::systems::sms::{
-    joypad_a_bits, joypad_b_bits, Command, PlaybackStatus, Query, Sms, SmsEmulationError,
-    SmsPlayerInput, Ui, UiHelper, UiStatus, UserMessage, Z80Display,
+    joypad_a_bits, joypad_b_bits, sc3000_keys, Command, ControllerPort, PlaybackStatus, PortDevice,
+    Query, Sc3000Key, Sms, SmsEmulationError, SmsPlayerInput, Ui, UiHelper, UiStatus, UserMessage,
+    Z80Display,
 };
 
+use simple_graphics::WindowHandle;
+
 struct PlaybackHelper(PlaybackStatus);
 
 impl UiHelper for PlaybackHelper {
@@ -25,17 +27,100 @@ impl UiHelper for PlaybackHelper {
     }
 }
 
-pub fn playback_ui(master_system: Box<dyn Sms>, player_statuses: &[SmsPlayerInput]) -> Ui {
-    let helper = Box::new(PlaybackHelper(PlaybackStatus::from_recorded(
-        player_statuses,
-    )));
+/// Play back `player_statuses`, run-length encoded as in `Recording`.
+pub fn playback_ui(
+    master_system: Box<dyn Sms>,
+    player_statuses: &[(u32, SmsPlayerInput)],
+) -> Ui {
+    let helper = Box::new(PlaybackHelper(PlaybackStatus::from_delta(player_statuses)));
 
     Ui::new(master_system, helper, None)
 }
 
+/// The SC-3000 key at the position of the host key `scancode`, if there is
+/// one.
+///
+/// Keys are matched by position on a US layout, except for the SC-3000's keys
+/// with no counterpart: `@` is backtick, `YEN` is backslash, `PI` is End,
+/// `GRAPH` is Alt, `ENG DIER'S` is Right Alt, and `FUNC` is Tab.
+pub fn sc3000_key(scancode: sdl2::keyboard::Scancode) -> Option<Sc3000Key> {
+    use sdl2::keyboard::Scancode::*;
+
+    let key = match scancode {
+        Num1 => sc3000_keys::DIGIT_1,
+        Num2 => sc3000_keys::DIGIT_2,
+        Num3 => sc3000_keys::DIGIT_3,
+        Num4 => sc3000_keys::DIGIT_4,
+        Num5 => sc3000_keys::DIGIT_5,
+        Num6 => sc3000_keys::DIGIT_6,
+        Num7 => sc3000_keys::DIGIT_7,
+        Num8 => sc3000_keys::DIGIT_8,
+        Num9 => sc3000_keys::DIGIT_9,
+        Num0 => sc3000_keys::DIGIT_0,
+        A => sc3000_keys::A,
+        B => sc3000_keys::B,
+        C => sc3000_keys::C,
+        D => sc3000_keys::D,
+        E => sc3000_keys::E,
+        F => sc3000_keys::F,
+        G => sc3000_keys::G,
+        H => sc3000_keys::H,
+        I => sc3000_keys::I,
+        J => sc3000_keys::J,
+        K => sc3000_keys::K,
+        L => sc3000_keys::L,
+        M => sc3000_keys::M,
+        N => sc3000_keys::N,
+        O => sc3000_keys::O,
+        P => sc3000_keys::P,
+        Q => sc3000_keys::Q,
+        R => sc3000_keys::R,
+        S => sc3000_keys::S,
+        T => sc3000_keys::T,
+        U => sc3000_keys::U,
+        V => sc3000_keys::V,
+        W => sc3000_keys::W,
+        X => sc3000_keys::X,
+        Y => sc3000_keys::Y,
+        Z => sc3000_keys::Z,
+        Minus => sc3000_keys::MINUS,
+        Equals => sc3000_keys::CARET,
+        Backslash => sc3000_keys::YEN,
+        Grave => sc3000_keys::AT,
+        LeftBracket => sc3000_keys::LEFT_BRACKET,
+        RightBracket => sc3000_keys::RIGHT_BRACKET,
+        Semicolon => sc3000_keys::SEMICOLON,
+        Apostrophe => sc3000_keys::COLON,
+        Comma => sc3000_keys::COMMA,
+        Period => sc3000_keys::PERIOD,
+        Slash => sc3000_keys::SLASH,
+        Space => sc3000_keys::SPACE,
+        Return => sc3000_keys::CR,
+        Backspace | Delete | Insert => sc3000_keys::INS_DEL,
+        Home => sc3000_keys::HOME_CLR,
+        End => sc3000_keys::PI,
+        Up => sc3000_keys::UP,
+        Down => sc3000_keys::DOWN,
+        Left => sc3000_keys::LEFT,
+        Right => sc3000_keys::RIGHT,
+        LShift | RShift => sc3000_keys::SHIFT,
+        LCtrl | RCtrl => sc3000_keys::CTRL,
+        LAlt => sc3000_keys::GRAPH,
+        RAlt => sc3000_keys::ENG_DIERS,
+        Tab => sc3000_keys::FUNC,
+        _ => return None,
+    };
+    Some(key)
+}
+
 struct SdlUiHelper {
     event_pump: sdl2::EventPump,
     playback_status: PlaybackStatus,
+    // Where the mouse is, once it's been in the window, for the light phaser
+    mouse: Option<(i32, i32)>,
+    mouse_button: bool,
+    // The window, to aim the phaser at the right pixel and toggle scanlines
+    window: Option<WindowHandle>,
 }
 
 impl UiHelper for SdlUiHelper {
@@ -68,13 +153,17 @@ impl UiHelper for SdlUiHelper {
         }
 
         fn do_query(status: &mut UiStatus, query: Query) {
-            if let Some(d) = status.master_system_mut().debugger() {
-                println!("{}", d.query(query));
+            if let Some(s) = status.master_system_mut().query(query) {
+                println!("{}", s);
             } else {
                 eprintln!("Unsupported query {:?}", query);
             }
         }
 
+        // with an SC-3000 keyboard, every key is typed on it, and there are
+        // no hotkeys or joypads
+        let keyboard = status.master_system().sc3000_keyboard();
+
         for event in self.event_pump.poll_iter() {
             match event {
                 sdl2::event::Event::Quit { .. } => return Ok(None),
@@ -82,7 +171,7 @@ impl UiHelper for SdlUiHelper {
                     scancode: Some(k),
                     keymod,
                     ..
-                } => match (
+                } if !keyboard => match (
                     k,
                     keymod.contains(sdl2::keyboard::LSHIFTMOD)
                         || keymod.contains(sdl2::keyboard::RSHIFTMOD),
@@ -93,7 +182,8 @@ impl UiHelper for SdlUiHelper {
                     (R, true) => status.save_recording(None),
                     (X, _) => status.save_state(None),
                     (M, false) => do_query(status, Query::RecentMemos),
-                    (Y, _) => do_command(status, Command::Step),
+                    (Y, false) => do_command(status, Command::Step),
+                    (Y, true) => do_command(status, Command::StepOver),
                     (N, false) => {
                         use euphrates::hardware::z80::Reg16::PC;
                         let pc = status.master_system().z80().reg16(PC);
@@ -102,46 +192,97 @@ impl UiHelper for SdlUiHelper {
                     (N, true) => do_query(status, Query::Disassembly),
                     (H, false) => status.master_system_mut().hold()?,
                     (H, true) => status.master_system_mut().resume()?,
+                    (C, _) => {
+                        if let Some(ref window) = self.window {
+                            window.toggle_scanlines();
+                        }
+                    }
                     _ => {}
                 },
+                sdl2::event::Event::MouseMotion { x, y, .. } => self.mouse = Some((x, y)),
+                sdl2::event::Event::MouseButtonDown {
+                    mouse_btn: sdl2::mouse::MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    self.mouse = Some((x, y));
+                    self.mouse_button = true;
+                }
+                sdl2::event::Event::MouseButtonUp {
+                    mouse_btn: sdl2::mouse::MouseButton::Left,
+                    ..
+                } => self.mouse_button = false,
                 _ => {}
             }
         }
 
         let keyboard_state = self.event_pump.keyboard_state();
 
-        let mut joypad_a = 0xFF;
-        let array_a = [
-            (W, joypad_a_bits::JOYPAD1_UP),
-            (A, joypad_a_bits::JOYPAD1_LEFT),
-            (S, joypad_a_bits::JOYPAD1_DOWN),
-            (D, joypad_a_bits::JOYPAD1_RIGHT),
-            (F, joypad_a_bits::JOYPAD1_A),
-            (G, joypad_a_bits::JOYPAD1_B),
-            (I, joypad_a_bits::JOYPAD2_UP),
-            (K, joypad_a_bits::JOYPAD2_DOWN),
-        ];
-        array_a
-            .iter()
-            .filter(|(scancode, _)| keyboard_state.is_scancode_pressed(*scancode))
-            .for_each(|(_, bit)| joypad_a &= !*bit);
-        player_status.set_joypad_a(joypad_a);
-
-        let mut joypad_b = 0xFF;
-        let array_b = [
-            (J, joypad_b_bits::JOYPAD2_LEFT),
-            (L, joypad_b_bits::JOYPAD2_RIGHT),
-            (Semicolon, joypad_b_bits::JOYPAD2_A),
-            (Apostrophe, joypad_b_bits::JOYPAD2_B),
-            (Space, joypad_b_bits::RESET),
-        ];
-        array_b
-            .iter()
-            .filter(|(scancode, _)| keyboard_state.is_scancode_pressed(*scancode))
-            .for_each(|(_, bit)| joypad_b &= !*bit);
-        player_status.set_joypad_b(joypad_b);
-
-        if player_status != Default::default() {
+        if keyboard {
+            for scancode in keyboard_state.pressed_scancodes() {
+                if let Some(key) = sc3000_key(scancode) {
+                    player_status.set_key_pressed(key, true);
+                }
+            }
+        } else {
+            // fast forward while tab is held
+            status
+                .master_system_mut()
+                .set_turbo(keyboard_state.is_scancode_pressed(Tab));
+
+            let mut joypad_a = 0xFF;
+            let array_a = [
+                (W, joypad_a_bits::JOYPAD1_UP),
+                (A, joypad_a_bits::JOYPAD1_LEFT),
+                (S, joypad_a_bits::JOYPAD1_DOWN),
+                (D, joypad_a_bits::JOYPAD1_RIGHT),
+                (F, joypad_a_bits::JOYPAD1_A),
+                (G, joypad_a_bits::JOYPAD1_B),
+                (I, joypad_a_bits::JOYPAD2_UP),
+                (K, joypad_a_bits::JOYPAD2_DOWN),
+            ];
+            array_a
+                .iter()
+                .filter(|(scancode, _)| keyboard_state.is_scancode_pressed(*scancode))
+                .for_each(|(_, bit)| joypad_a &= !*bit);
+            player_status.set_joypad_a(joypad_a);
+
+            let mut joypad_b = 0xFF;
+            let array_b = [
+                (J, joypad_b_bits::JOYPAD2_LEFT),
+                (L, joypad_b_bits::JOYPAD2_RIGHT),
+                (Semicolon, joypad_b_bits::JOYPAD2_A),
+                (Apostrophe, joypad_b_bits::JOYPAD2_B),
+                (Space, joypad_b_bits::RESET),
+            ];
+            array_b
+                .iter()
+                .filter(|(scancode, _)| keyboard_state.is_scancode_pressed(*scancode))
+                .for_each(|(_, bit)| joypad_b &= !*bit);
+            player_status.set_joypad_b(joypad_b);
+
+            // the mouse aims a light phaser in port A, if there is one
+            let sms = status.master_system();
+            if let (PortDevice::LightPhaser, Some((x, y))) =
+                (sms.port_device(ControllerPort::A), self.mouse)
+            {
+                let (x, y) = match self.window {
+                    Some(ref window) => window.placement().to_texture(x, y),
+                    None => (x, y),
+                };
+                let phaser = sms.light_phaser_at(x, y, self.mouse_button);
+                player_status.set_light_phaser(ControllerPort::A, phaser);
+            }
+        }
+
+        // merely aiming the phaser, without pulling the trigger, doesn't
+        // count as input interrupting playback
+        let mut input = player_status;
+        if !input.light_phaser(ControllerPort::A).trigger {
+            input.set_light_phaser(ControllerPort::A, Default::default());
+        }
+        if input != Default::default() {
             self.playback_status.end_playback();
         } else if let Some(ps) = self.playback_status.pop() {
             player_status = ps;
@@ -152,11 +293,16 @@ impl UiHelper for SdlUiHelper {
 }
 
 /// May return an error if there are problems with SDL
+///
+/// If the `Sms` draws to a `Window`, `window` should be its `Window::handle`,
+/// so the mouse aims the light phaser at the right pixel and C toggles
+/// scanlines.
 pub fn ui(
     master_system: Box<dyn Sms>,
     sdl: &sdl2::Sdl,
-    save_directory: Option<PathBuf>,
-    player_statuses: &[SmsPlayerInput],
+    paths: Option<PathPolicy>,
+    player_statuses: &[(u32, SmsPlayerInput)],
+    window: Option<WindowHandle>,
 ) -> Result<Ui, Error> {
     sdl.event()
         .map_err(|s| format_err!("Error initializing the SDL event subsystem {}", s))?;
@@ -167,8 +313,11 @@ pub fn ui(
 
     let helper = Box::new(SdlUiHelper {
         event_pump,
-        playback_status: PlaybackStatus::from_recorded(player_statuses),
+        playback_status: PlaybackStatus::from_delta(player_statuses),
+        mouse: None,
+        mouse_button: false,
+        window,
     });
 
-    Ok(Ui::new(master_system, helper, save_directory))
+    Ok(Ui::new(master_system, helper, paths))
 }
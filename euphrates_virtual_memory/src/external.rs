@@ -4,7 +4,8 @@ use std::sync::Arc;
 
 use euphrates::hardware::memory16::Memory16;
 use euphrates::hardware::sms_memory::{
-    self, MemoryPage, SmsMemory, SmsMemoryLoad, SmsMemoryLoadError, SmsMemoryMapper, SmsMemoryState,
+    self, MemoryPage, RamFill, SmsMemory, SmsMemoryLoad, SmsMemoryLoadError, SmsMemoryMapper,
+    SmsMemoryState,
 };
 
 use super::*;
@@ -79,23 +80,33 @@ pub struct SmsVirtualMemory {
     mapper: SmsMemoryMapper,
     write_mem: Logical<u8>,
     read_mem: Logical<u8>,
+
+    /// What newly allocated cartridge RAM is filled with
+    ram_fill: RamFill,
 }
 
 impl SmsVirtualMemory {
+    /// Newly allocated cartridge RAM of `len` bytes, filled with `ram_fill`.
+    fn new_cartridge_ram(&self, len: usize) -> Physical<u8> {
+        let mut contents = vec![0u8; len];
+        self.ram_fill.fill(&mut contents);
+        unsafe {
+            let mut ram = physical(len, true, false);
+            ptr::copy_nonoverlapping(contents.as_ptr(), ram.as_mut_ptr(), len);
+            ram
+        }
+    }
+
     fn ensure_half_page(&mut self) {
         if let None = self.half_cartridge_ram {
-            unsafe {
-                self.half_cartridge_ram = Some(physical(0x2000, true, false));
-            }
+            self.half_cartridge_ram = Some(self.new_cartridge_ram(0x2000));
         }
     }
 
     fn ensure_one_page(&mut self) {
         use self::MainCartridgeRam::*;
         if let Zero = self.main_cartridge_ram {
-            unsafe {
-                self.main_cartridge_ram = One(physical(0x4000, true, false));
-            }
+            self.main_cartridge_ram = One(self.new_cartridge_ram(0x4000));
         }
     }
 
@@ -103,10 +114,12 @@ impl SmsVirtualMemory {
         use self::MainCartridgeRam::*;
         use std::mem::swap;
         match &self.main_cartridge_ram {
-            Zero => unsafe {
-                self.main_cartridge_ram =
-                    Two(physical(0x4000, true, false), physical(0x4000, true, false));
-            },
+            Zero => {
+                self.main_cartridge_ram = Two(
+                    self.new_cartridge_ram(0x4000),
+                    self.new_cartridge_ram(0x4000),
+                );
+            }
             One(_) => {
                 let mut fake_ram = Zero;
                 swap(&mut fake_ram, &mut self.main_cartridge_ram);
@@ -114,9 +127,7 @@ impl SmsVirtualMemory {
                     One(x) => x,
                     _ => unreachable!(),
                 };
-                unsafe {
-                    self.main_cartridge_ram = Two(first_page, physical(0x4000, true, false));
-                }
+                self.main_cartridge_ram = Two(first_page, self.new_cartridge_ram(0x4000));
             }
             _ => {}
         }
@@ -374,6 +385,24 @@ impl SmsMemory for SmsVirtualMemory {
         }
     }
 
+    fn allocate_main_cartridge_ram(&mut self, len: usize) {
+        if len > 0x4000 {
+            self.ensure_two_pages();
+        } else if len > 0 {
+            self.ensure_one_page();
+        }
+        self.remap();
+    }
+
+    fn allocate_half_cartridge_ram(&mut self) {
+        self.ensure_half_page();
+        self.remap();
+    }
+
+    fn set_cartridge_ram_fill(&mut self, fill: RamFill) {
+        self.ram_fill = fill;
+    }
+
     #[inline]
     fn main_cartridge_ram_len(&self) -> usize {
         use self::MainCartridgeRam::*;
@@ -493,14 +522,18 @@ impl SmsMemory for SmsVirtualMemory {
             }
         };
 
-        SmsMemoryState {
+        let mut state = SmsMemoryState {
             rom: Arc::new(create_slice(&self.rom.0)),
             system_ram: create_slice(&self.system_ram),
             main_cartridge_ram,
             half_cartridge_ram,
             pages: self.pages.clone(),
             mapper: self.mapper,
-        }
+            ram_fill: self.ram_fill,
+            first_slot: Default::default(),
+        };
+        state.fix_first_slot();
+        state
     }
 }
 
@@ -544,6 +577,7 @@ impl SmsMemoryLoad for SmsVirtualMemory {
                 mapper: state.mapper,
                 write_mem: logical(0x10000, true, false),
                 read_mem: logical(0x10000, false, false),
+                ram_fill: state.ram_fill,
             };
 
             vm.remap();
@@ -618,6 +652,8 @@ pub mod tests {
             half_cartridge_ram: None,
             pages: Default::default(),
             mapper: Default::default(),
+            ram_fill: Default::default(),
+            first_slot: Default::default(),
         }
     }
 